@@ -0,0 +1,75 @@
+use pyo3::{create_exception, exceptions::PyException, prelude::*};
+use serde::Serialize;
+use std::path::PathBuf;
+
+create_exception!(apwm, ApwmError, PyException);
+
+fn to_py_err(error: apwm::Error) -> PyErr {
+    ApwmError::new_err(format!("{:#}", anyhow::Error::from(error)))
+}
+
+// Results are handed over through the `json` module, so Python tools see the
+// same fields as in the JSON output of the CLI
+fn to_python<'py>(py: Python<'py>, value: &impl Serialize) -> PyResult<Bound<'py, PyAny>> {
+    let json = serde_json::to_string(value).map_err(|e| ApwmError::new_err(e.to_string()))?;
+    py.import("json")?.call_method1("loads", (json,))
+}
+
+// Checks the content of an index file without reading the files it refers to
+#[pyfunction]
+fn validate_index(content: &str) -> PyResult<()> {
+    apwm::validate_index(content).map_err(to_py_err)
+}
+
+#[pyclass(frozen)]
+struct Index {
+    inner: apwm::Index,
+}
+
+#[pymethods]
+impl Index {
+    #[new]
+    fn new(path: PathBuf) -> PyResult<Self> {
+        let inner = apwm::Index::new(&path).map_err(to_py_err)?;
+        Ok(Self { inner })
+    }
+
+    #[getter]
+    fn archipelago_version(&self) -> String {
+        self.inner.common.archipelago_version.clone()
+    }
+
+    // The keys of the worlds of the index, in order
+    fn worlds(&self) -> Vec<String> {
+        self.inner.worlds.keys().cloned().collect()
+    }
+
+    // The whole index, as a dict
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        to_python(py, &self.inner)
+    }
+
+    // What the world `key` would be fetched and patched with
+    fn resolve_world<'py>(&self, py: Python<'py>, key: &str) -> PyResult<Bound<'py, PyAny>> {
+        let resolved = self.inner.resolve_world(key).map_err(to_py_err)?;
+        to_python(py, &resolved)
+    }
+
+    // Refreshes `destination`, without holding the GIL, and returns the
+    // summary of the refresh
+    fn refresh<'py>(&self, py: Python<'py>, destination: PathBuf) -> PyResult<Bound<'py, PyAny>> {
+        let summary = py
+            .allow_threads(|| self.inner.refresh_into_blocking(&destination))
+            .map_err(to_py_err)?;
+        to_python(py, &summary)
+    }
+}
+
+#[pymodule]
+#[pyo3(name = "apwm")]
+fn apwm_python(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("ApwmError", m.py().get_type::<ApwmError>())?;
+    m.add_class::<Index>()?;
+    m.add_function(wrap_pyfunction!(validate_index, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,237 @@
+#[cfg(feature = "refresh")]
+use anyhow::anyhow;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+#[cfg(feature = "refresh")]
+use crate::{bail, junk, sandbox, WorkDirs};
+use crate::{Error, Result};
+
+#[cfg(feature = "zip")]
+pub use crate::archive::{archive_file_sizes, archive_files, entry_names};
+#[cfg(all(feature = "zip", feature = "refresh"))]
+pub use crate::archive::{extract, pack, read_entry, verify};
+#[cfg(not(feature = "zip"))]
+pub use no_archive::{archive_file_sizes, archive_files, entry_names};
+#[cfg(all(not(feature = "zip"), feature = "refresh"))]
+pub use no_archive::{extract, pack, read_entry, verify};
+
+#[cfg(feature = "refresh")]
+pub fn package_dir(root: &Path) -> Result<PathBuf> {
+    let mut dirs = vec![];
+    for entry in std::fs::read_dir(root).map_err(|e| Error::io(root, e))? {
+        let entry = entry.map_err(|e| Error::io(root, e))?;
+        if entry
+            .file_type()
+            .map_err(|e| Error::io(entry.path(), e))?
+            .is_dir()
+        {
+            dirs.push(entry.path());
+        }
+    }
+
+    if dirs.len() != 1 {
+        bail!(
+            "Expected exactly one top-level directory in {}, found {}",
+            root.to_string_lossy(),
+            dirs.len()
+        );
+    }
+
+    Ok(dirs.remove(0))
+}
+
+pub(crate) fn collect_entries(
+    root: &Path,
+    dir: &Path,
+    entries: &mut Vec<(String, PathBuf)>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let path = entry.path();
+        let name = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+
+        if entry
+            .file_type()
+            .map_err(|e| Error::io(entry.path(), e))?
+            .is_dir()
+        {
+            entries.push((format!("{}/", name), path.clone()));
+            collect_entries(root, &path, entries)?;
+        } else {
+            entries.push((name, path));
+        }
+    }
+
+    Ok(())
+}
+
+// Packs a world's package directory into a clean `.apworld`, leaving junk
+// like `__pycache__` or `.git` out. With `version`, the `world_version` of
+// its `archipelago.json` gets stamped, creating the file if needed. The
+// package gets staged in the temporary directory of `dirs`.
+#[cfg(feature = "refresh")]
+pub fn pack_world(
+    package: &Path,
+    destination: &Path,
+    version: Option<&str>,
+    dirs: &WorkDirs,
+) -> Result<()> {
+    let name = package
+        .file_name()
+        .ok_or_else(|| anyhow!("{} isn't a directory", package.to_string_lossy()))?;
+    sandbox::ensure_no_symlinks(package)?;
+
+    let staging = dirs.tempdir()?;
+    let staged_package = staging.path().join(name);
+    for (path, content) in package_files(package, |path| !junk::is_junk(path))? {
+        let target = staged_package.join(path);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        std::fs::write(&target, content).map_err(|e| Error::io(target, e))?;
+    }
+
+    if let Some(version) = version {
+        let manifest_path = staged_package.join("archipelago.json");
+        let mut manifest = match std::fs::read(&manifest_path) {
+            Ok(content) => serde_json::from_slice::<serde_json::Value>(&content)?,
+            Err(_) => serde_json::json!({}),
+        };
+        manifest
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("archipelago.json isn't a JSON object"))?
+            .insert("world_version".into(), version.into());
+        std::fs::write(&manifest_path, serde_json::to_string_pretty(&manifest)?)
+            .map_err(|e| Error::io(&manifest_path, e))?;
+    }
+
+    pack(staging.path(), destination)
+}
+
+fn read_dir_files(
+    root: &Path,
+    dir: &Path,
+    filter: &impl Fn(&str) -> bool,
+    files: &mut BTreeMap<String, Vec<u8>>,
+) -> Result<()> {
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let path = entry.path();
+        if entry
+            .file_type()
+            .map_err(|e| Error::io(entry.path(), e))?
+            .is_dir()
+        {
+            read_dir_files(root, &path, filter, files)?;
+            continue;
+        }
+
+        let name = path
+            .strip_prefix(root)?
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if filter(&name) {
+            files.insert(name, std::fs::read(&path).map_err(|e| Error::io(&path, e))?);
+        }
+    }
+
+    Ok(())
+}
+
+// Reads the files matching `filter` from a world, either an `.apworld` file
+// or a package directory. Names are relative to the package directory.
+pub fn package_files(
+    path: &Path,
+    filter: impl Fn(&str) -> bool,
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    if path.is_dir() {
+        let mut files = BTreeMap::new();
+        read_dir_files(path, path, &filter, &mut files)?;
+        return Ok(files);
+    }
+
+    archive_files(path, &filter)
+}
+
+// Lists the files of a world with their uncompressed size. Names are relative
+// to the package directory.
+pub fn package_file_sizes(path: &Path) -> Result<BTreeMap<String, u64>> {
+    if path.is_dir() {
+        let mut entries = vec![];
+        collect_entries(path, path, &mut entries)?;
+        return entries
+            .into_iter()
+            .filter(|(name, _)| !name.ends_with('/'))
+            .map(|(name, path)| {
+                Ok((
+                    name,
+                    std::fs::metadata(&path)
+                        .map_err(|e| Error::io(path, e))?
+                        .len(),
+                ))
+            })
+            .collect();
+    }
+
+    archive_file_sizes(path)
+}
+
+// Without the zip feature, worlds can only be read from package directories
+#[cfg(not(feature = "zip"))]
+mod no_archive {
+    use std::{collections::BTreeMap, path::Path};
+
+    use crate::{Error, Result};
+
+    fn unsupported(apworld: &Path) -> Error {
+        Error::Other(anyhow::anyhow!(
+            "Can't read {}, apwm was built without the zip feature",
+            apworld.to_string_lossy()
+        ))
+    }
+
+    #[cfg(feature = "refresh")]
+    pub fn extract(apworld: &Path, _destination: &Path) -> Result<()> {
+        Err(unsupported(apworld))
+    }
+
+    #[cfg(feature = "refresh")]
+    pub fn verify(apworld: &Path) -> Result<()> {
+        Err(unsupported(apworld))
+    }
+
+    pub fn entry_names(apworld: &Path) -> Result<Vec<String>> {
+        Err(unsupported(apworld))
+    }
+
+    #[cfg(feature = "refresh")]
+    pub fn read_entry(apworld: &Path, _name: &str) -> Result<Vec<u8>> {
+        Err(unsupported(apworld))
+    }
+
+    #[cfg(feature = "refresh")]
+    pub fn pack(_source: &Path, destination: &Path) -> Result<()> {
+        Err(unsupported(destination))
+    }
+
+    pub fn archive_files(
+        path: &Path,
+        _filter: &impl Fn(&str) -> bool,
+    ) -> Result<BTreeMap<String, Vec<u8>>> {
+        Err(unsupported(path))
+    }
+
+    pub fn archive_file_sizes(path: &Path) -> Result<BTreeMap<String, u64>> {
+        Err(unsupported(path))
+    }
+}
@@ -0,0 +1,166 @@
+use std::{collections::BTreeMap, fs::File, io::Read, path::Path};
+use zip::ZipArchive;
+#[cfg(feature = "refresh")]
+use zip::{write::SimpleFileOptions, CompressionMethod, DateTime, ZipWriter};
+
+#[cfg(feature = "refresh")]
+use crate::{apworld, bail};
+use crate::{Error, Result};
+
+// Extracts `apworld` into `destination`, refusing entries that would end up
+// outside of it and symlinks.
+#[cfg(feature = "refresh")]
+pub fn extract(apworld: &Path, destination: &Path) -> Result<()> {
+    let mut archive = ZipArchive::new(File::open(apworld).map_err(|e| Error::io(apworld, e))?)?;
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        let Some(relative_path) = entry.enclosed_name() else {
+            bail!(
+                "{} contains an entry outside of the archive: {}",
+                apworld.to_string_lossy(),
+                entry.name()
+            );
+        };
+        if entry.is_symlink() {
+            bail!(
+                "{} contains a symlink: {}",
+                apworld.to_string_lossy(),
+                entry.name()
+            );
+        }
+
+        let path = destination.join(relative_path);
+        if entry.is_dir() {
+            std::fs::create_dir_all(&path).map_err(|e| Error::io(&path, e))?;
+            continue;
+        }
+
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        let mut file = File::create(&path).map_err(|e| Error::io(&path, e))?;
+        std::io::copy(&mut entry, &mut file).map_err(|e| Error::io(&path, e))?;
+    }
+
+    Ok(())
+}
+
+// Reads every entry of `apworld` without writing anything to disk so that
+// truncated or corrupted archives get caught by their central directory or
+// CRC checks
+#[cfg(feature = "refresh")]
+pub fn verify(apworld: &Path) -> Result<()> {
+    let mut archive = match ZipArchive::new(File::open(apworld).map_err(|e| Error::io(apworld, e))?)
+    {
+        Ok(archive) => archive,
+        Err(e) => bail!(
+            "{} is corrupted, its central directory can't be read: {}",
+            apworld.to_string_lossy(),
+            e
+        ),
+    };
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if let Err(e) = std::io::copy(&mut entry, &mut std::io::sink()) {
+            bail!(
+                "{} is corrupted, {} can't be read: {}",
+                apworld.to_string_lossy(),
+                entry.name(),
+                e
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub fn entry_names(apworld: &Path) -> Result<Vec<String>> {
+    let archive = ZipArchive::new(File::open(apworld).map_err(|e| Error::io(apworld, e))?)?;
+    Ok(archive.file_names().map(str::to_string).collect())
+}
+
+#[cfg(feature = "refresh")]
+pub fn read_entry(apworld: &Path, name: &str) -> Result<Vec<u8>> {
+    let mut archive = ZipArchive::new(File::open(apworld).map_err(|e| Error::io(apworld, e))?)?;
+    let mut content = vec![];
+    archive
+        .by_name(name)?
+        .read_to_end(&mut content)
+        .map_err(|e| Error::io(apworld, e))?;
+    Ok(content)
+}
+
+// Packs `source` into a zip at `destination`. Entries are sorted and stamped
+// with a fixed date and permissions so packing the same tree twice yields
+// byte-identical archives.
+#[cfg(feature = "refresh")]
+pub fn pack(source: &Path, destination: &Path) -> Result<()> {
+    let mut entries = vec![];
+    apworld::collect_entries(source, source, &mut entries)?;
+    entries.sort();
+
+    let file_options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        .last_modified_time(DateTime::default())
+        .unix_permissions(0o644);
+    let dir_options = file_options.unix_permissions(0o755);
+
+    let mut zip = ZipWriter::new(File::create(destination).map_err(|e| Error::io(destination, e))?);
+    for (name, path) in entries {
+        if name.ends_with('/') {
+            zip.add_directory(name, dir_options)?;
+        } else {
+            zip.start_file(name, file_options)?;
+            let mut file = File::open(&path).map_err(|e| Error::io(&path, e))?;
+            std::io::copy(&mut file, &mut zip).map_err(|e| Error::io(path, e))?;
+        }
+    }
+    zip.finish()?;
+
+    Ok(())
+}
+
+pub fn archive_files(
+    path: &Path,
+    filter: &impl Fn(&str) -> bool,
+) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut archive = ZipArchive::new(File::open(path).map_err(|e| Error::io(path, e))?)?;
+    let mut files = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+
+        let Some((_, name)) = entry.name().split_once('/') else {
+            continue;
+        };
+        let name = name.to_string();
+        if filter(&name) {
+            let mut content = vec![];
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| Error::io(path, e))?;
+            files.insert(name, content);
+        }
+    }
+
+    Ok(files)
+}
+
+pub fn archive_file_sizes(path: &Path) -> Result<BTreeMap<String, u64>> {
+    let mut archive = ZipArchive::new(File::open(path).map_err(|e| Error::io(path, e))?)?;
+    let mut sizes = BTreeMap::new();
+    for i in 0..archive.len() {
+        let entry = archive.by_index_raw(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        if let Some((_, name)) = entry.name().split_once('/') {
+            sizes.insert(name.to_string(), entry.size());
+        }
+    }
+
+    Ok(sizes)
+}
@@ -1,6 +1,21 @@
-use anyhow::Result;
-use clap::Parser;
-use std::path::{Path, PathBuf};
+use anyhow::{bail, Result};
+use apwm::{
+    DestinationChange, DiagnosticStatus, DiffOptions, DiffOutcome, DriftKind, HtmlLayout,
+    HtmlRenderer, ImportStatus, IndexDiff, LicensePolicy, LinkKind, LinkStatus, OriginKind,
+    PatchStatus, PlannedChange, RefreshAction, TextRenderer, UpstreamStatus, UrlStatus,
+    VendoredStatus, WorldFilter,
+};
+use clap::{CommandFactory, FromArgMatches};
+use serde::Serialize;
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Display,
+    io::{IsTerminal, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
 
 #[derive(clap::Subcommand)]
 enum Command {
@@ -9,41 +24,2718 @@ enum Command {
         index_path: PathBuf,
         #[clap(short = 'd')]
         apworlds_path: PathBuf,
+        // Print what the refresh would do instead of doing it
+        #[clap(long)]
+        plan: bool,
+        // Go through the refresh but print the changes it would make to the
+        // destination, file by file, instead of making them
+        #[clap(long, conflicts_with = "plan")]
+        dry_run: bool,
+    },
+    CheckPatches {
+        #[clap(short)]
+        index_path: PathBuf,
+    },
+    Junk {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+    },
+    CheckSyntax {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+    },
+    Options {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+        #[clap(short)]
+        output: PathBuf,
+    },
+    DataPackage {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+        #[clap(short)]
+        output: PathBuf,
+    },
+    PythonVersion {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+    },
+    Scan {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+    },
+    Sizes {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+        #[clap(short, default_value_t = 5)]
+        top: usize,
+    },
+    Stats {
+        #[clap(short)]
+        index_path: Option<PathBuf>,
+        // Sizes are only known with a refreshed destination. Without an
+        // index, its manifest gets summarized instead.
+        #[clap(short = 'd')]
+        apworlds_path: Option<PathBuf>,
+        #[clap(short, long, default_value_t = 10)]
+        top: usize,
+    },
+    Vendored {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+    },
+    Drift {
+        #[clap(short)]
+        index_path: PathBuf,
+    },
+    CheckLinks {
+        #[clap(short)]
+        index_path: PathBuf,
+    },
+    CheckUrls {
+        #[clap(short)]
+        index_path: PathBuf,
+        // How many urls get checked at the same time
+        #[clap(short, long, default_value_t = 8)]
+        jobs: usize,
+    },
+    Diff {
+        // Each side is a git revision of the index, like `HEAD~1`, an index
+        // directory or file, or a lockfile. Without a new side, the old one
+        // gets compared with the working tree, and without either HEAD does.
+        old: Option<String>,
+        new: Option<String>,
+        // The index whose git history revisions are looked up in
+        #[clap(short, default_value = ".")]
+        index_path: PathBuf,
+        #[clap(short, requires = "new_index_path", conflicts_with = "old")]
+        old_index_path: Option<PathBuf>,
+        #[clap(short, requires = "old_index_path")]
+        new_index_path: Option<PathBuf>,
+        #[clap(flatten)]
+        output: DiffOutput,
+        #[clap(flatten)]
+        args: DiffArgs,
+        #[clap(long)]
+        metadata_only: bool,
+    },
+    Changelog {
+        // Git revisions of the index, like a tag or `HEAD@{1.week.ago}`
+        from: String,
+        #[clap(default_value = "HEAD")]
+        to: String,
+        #[clap(short, default_value = ".")]
+        index_path: PathBuf,
+        // Download the worlds whose version changed to include their release
+        // notes
+        #[clap(long)]
+        release_notes: bool,
+    },
+    DiffDestinations {
+        #[clap(short)]
+        old_destination: PathBuf,
+        #[clap(short)]
+        new_destination: PathBuf,
+        #[clap(flatten)]
+        output: DiffOutput,
+        #[clap(flatten)]
+        args: DiffArgs,
+    },
+    DiffLockfile {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        lockfile: PathBuf,
+        #[clap(flatten)]
+        output: DiffOutput,
+        #[clap(flatten)]
+        args: DiffArgs,
+    },
+    Add {
+        #[clap(short)]
+        index_path: PathBuf,
+        // A direct link to an apworld or a github repository
+        #[clap(short)]
+        url: String,
+        // The world key, guessed from the apworld if omitted
+        #[clap(short)]
+        key: Option<String>,
+        // The world version, read from the apworld if omitted
+        #[clap(short = 'V')]
+        version: Option<String>,
+    },
+    Update {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        world: String,
+        // The version to update to, the latest github release if omitted
+        #[clap(short = 'V')]
+        version: Option<String>,
+    },
+    BumpArchipelago {
+        #[clap(short)]
+        index_path: PathBuf,
+        // The archipelago tag to move to
+        #[clap(short = 'V')]
+        version: String,
+        // Only check the new version, without changing the index
+        #[clap(long)]
+        dry_run: bool,
+    },
+    Outdated {
+        #[clap(short)]
+        index_path: PathBuf,
+    },
+    List {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(flatten)]
+        filter: ListFilter,
+        #[clap(long, value_enum, default_value_t = ListSort::Key)]
+        sort: ListSort,
+    },
+    Show {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        world: String,
+        // A refreshed destination to read the deployed world from
+        #[clap(short = 'd')]
+        apworlds_path: Option<PathBuf>,
+    },
+    Verify {
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+        // What the destination should contain, its own manifest if omitted
+        #[clap(short)]
+        lockfile: Option<PathBuf>,
+    },
+    Lock {
+        #[clap(short)]
+        index_path: PathBuf,
+        // Defaults to index.lock next to the index file
+        #[clap(short)]
+        lockfile: Option<PathBuf>,
+    },
+    Sync {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: PathBuf,
+        // Defaults to index.lock next to the index file
+        #[clap(short)]
+        lockfile: Option<PathBuf>,
+    },
+    Import {
+        #[clap(short)]
+        index_path: PathBuf,
+        // An existing `worlds` or `custom_worlds` directory
+        #[clap(short)]
+        source: PathBuf,
+        #[clap(long)]
+        dry_run: bool,
+    },
+    Mirror {
+        #[clap(short)]
+        index_path: PathBuf,
+        // The mirror's root, created if needed
+        #[clap(short)]
+        output: PathBuf,
+    },
+    Doctor {
+        #[clap(short)]
+        index_path: Option<PathBuf>,
+        #[clap(short = 'd')]
+        apworlds_path: Option<PathBuf>,
+    },
+    Search {
+        // Can be repeated to search several indexes
+        #[clap(short, required = true)]
+        index_path: Vec<PathBuf>,
+        #[clap(required = true)]
+        query: Vec<String>,
+    },
+    Rm {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        world: String,
+        // Only print what would be removed
+        #[clap(long)]
+        dry_run: bool,
+    },
+    Fmt {
+        #[clap(short)]
+        index_path: PathBuf,
+        // Fail instead of rewriting the index file if it isn't formatted
+        #[clap(long)]
+        check: bool,
+    },
+    Pack {
+        // The world's package directory, the one with its `__init__.py`
+        #[clap(short)]
+        source: PathBuf,
+        // Defaults to `<package name>.apworld` in the current directory
+        #[clap(short)]
+        output: Option<PathBuf>,
+        // Stamped as `world_version` in the apworld's `archipelago.json`
+        #[clap(short = 'V')]
+        version: Option<String>,
+    },
+    Unpack {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        world: String,
+        #[clap(short)]
+        output: PathBuf,
+    },
+    MakePatch {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short)]
+        world: String,
+        #[clap(short)]
+        modified: PathBuf,
+        #[clap(short)]
+        output: PathBuf,
+    },
+    // Serves the index as JSON along with the files of a refreshed destination
+    // and of a mirror
+    Serve {
+        #[clap(short)]
+        index_path: PathBuf,
+        #[clap(short = 'd')]
+        apworlds_path: Option<PathBuf>,
+        #[clap(short)]
+        mirror: Option<PathBuf>,
+        #[clap(short, long, default_value = "127.0.0.1:8080")]
+        bind: String,
+    },
+    // Prints a completion script, world names get completed from the index
+    // given with `-i`
+    Completions {
+        #[clap(value_enum)]
+        shell: Shell,
+    },
+    // Prints the world keys of an index, one per line, for completion scripts
+    #[clap(hide = true)]
+    CompleteWorlds {
+        #[clap(short, default_value = ".")]
+        index_path: PathBuf,
     },
 }
 
 #[derive(clap::Parser)]
 struct Args {
+    // Only print results and errors, without progress messages
+    #[clap(short, long, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+    // Print what's being done, `-vv` for the details
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+    // Print a JSON document instead of text, the same envelope for every
+    // command
+    #[clap(long, global = true)]
+    json: bool,
     #[command(subcommand)]
     command: Command,
 }
 
+// Makes the index and destination of the configuration the defaults of the
+// options taking them
+fn with_config_defaults(mut command: clap::Command, config: &apwm::Config) -> clap::Command {
+    let defaults = [
+        ("index_path", &config.index),
+        ("apworlds_path", &config.destination),
+    ];
+    let subcommands = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_string())
+        .collect::<Vec<_>>();
+
+    for name in subcommands {
+        command = command.mut_subcommand(name, |mut subcommand| {
+            for (id, default) in defaults {
+                let Some(default) = default else {
+                    continue;
+                };
+                if subcommand.get_arguments().any(|arg| arg.get_id() == id) {
+                    let default = default.to_string_lossy().into_owned();
+                    subcommand =
+                        subcommand.mut_arg(id, |arg| arg.default_value(default).required(false));
+                }
+            }
+            subcommand
+        });
+    }
+
+    command
+}
+
+// Errors exit with 1 unless they're one of these, usage errors exit with 2
+// and `diff --exit-code` outcomes with 20 and up
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FailureKind {
+    // The index or another input is invalid, or a check found problems
+    Validation = 10,
+    // A server couldn't be reached or answered with an error
+    Network = 11,
+    // Some worlds went through and others didn't
+    Partial = 12,
+}
+
+#[derive(Debug)]
+struct Failure {
+    kind: FailureKind,
+    message: String,
+}
+
+impl Failure {
+    fn validation(message: impl Into<String>) -> Self {
+        Self {
+            kind: FailureKind::Validation,
+            message: message.into(),
+        }
+    }
+
+    fn network(message: impl Into<String>) -> Self {
+        Self {
+            kind: FailureKind::Network,
+            message: message.into(),
+        }
+    }
+
+    fn partial(message: impl Into<String>) -> Self {
+        Self {
+            kind: FailureKind::Partial,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+// Library errors get classified by what caused them
+fn failure_kind(error: &anyhow::Error) -> Option<FailureKind> {
+    for cause in error.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return Some(failure.kind);
+        }
+        if let Some(e) = cause.downcast_ref::<apwm::Error>() {
+            // Worlds failing during a refresh don't stop the others
+            if matches!(e, apwm::Error::Refresh { .. }) {
+                return Some(FailureKind::Partial);
+            }
+            if e.is_network() {
+                return Some(FailureKind::Network);
+            }
+            if matches!(
+                e,
+                apwm::Error::IndexParse { .. } | apwm::Error::Patch { .. }
+            ) {
+                return Some(FailureKind::Validation);
+            }
+        }
+        if cause.is::<reqwest::Error>() {
+            return Some(FailureKind::Network);
+        }
+        if let Some(e) = cause.downcast_ref::<git2::Error>() {
+            if matches!(
+                e.class(),
+                git2::ErrorClass::Net
+                    | git2::ErrorClass::Http
+                    | git2::ErrorClass::Ssl
+                    | git2::ErrorClass::Ssh
+            ) {
+                return Some(FailureKind::Network);
+            }
+        }
+        if cause.is::<toml::de::Error>()
+            || cause.is::<serde_path_to_error::Error<toml::de::Error>>()
+            || cause.is::<toml_edit::TomlError>()
+        {
+            return Some(FailureKind::Validation);
+        }
+    }
+
+    None
+}
+
+// Library messages go to stderr, warnings only unless asked for more
+struct Logger {
+    level: log::LevelFilter,
+}
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= self.level && metadata.target().starts_with("apwm")
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}: {}",
+                record.level().as_str().to_lowercase(),
+                record.args()
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+// Bumped whenever a field of a `--json` document gets removed or changes
+// meaning, new fields can appear without a bump
+const JSON_SCHEMA_VERSION: u32 = 1;
+
+// What `--json` prints, whatever the command. `data` is missing when the
+// command failed before producing anything, `error` when it succeeded.
+#[derive(Serialize)]
+struct JsonDocument<'a> {
+    schema_version: u32,
+    command: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Where commands send their results. Humans get them printed right away, with
+// `--json` they're kept for the single document printed once the command is
+// done, so failures still produce valid JSON.
+struct Printer {
+    json: bool,
+    quiet: bool,
+    command: String,
+    data: RefCell<Option<serde_json::Value>>,
+}
+
+impl Printer {
+    fn emit<T: Serialize>(&self, data: &T, human: impl FnOnce(&T)) -> Result<()> {
+        if self.json {
+            *self.data.borrow_mut() = Some(serde_json::to_value(data)?);
+        } else {
+            human(data);
+        }
+
+        Ok(())
+    }
+
+    // Progress messages, which have no place in JSON documents
+    fn note(&self, message: impl Display) {
+        if !self.json && !self.quiet {
+            println!("{}", message);
+        }
+    }
+
+    fn document(&self, error: Option<&anyhow::Error>) -> JsonDocument<'_> {
+        JsonDocument {
+            schema_version: JSON_SCHEMA_VERSION,
+            command: &self.command,
+            data: self.data.take(),
+            error: error.map(|e| format!("{:#}", e)),
+        }
+    }
+
+    fn finish(&self, error: Option<&anyhow::Error>) -> Result<()> {
+        if !self.json {
+            return Ok(());
+        }
+
+        println!("{}", serde_json::to_string_pretty(&self.document(error))?);
+        Ok(())
+    }
+}
+
+// What the configuration changes in every index commands open
+#[derive(Default)]
+struct IndexSettings {
+    temp_dir: Option<PathBuf>,
+    github_token: Option<String>,
+}
+
+static INDEX_SETTINGS: OnceLock<IndexSettings> = OnceLock::new();
+
+fn open_index(path: &Path) -> apwm::Result<apwm::Index> {
+    let mut index = apwm::Index::new(path)?;
+    let settings = INDEX_SETTINGS.get_or_init(IndexSettings::default);
+    if let Some(temp_dir) = &settings.temp_dir {
+        index.set_temp_dir(temp_dir);
+    }
+    if let Some(token) = &settings.github_token {
+        index.set_github_token(token.as_str());
+    }
+
+    Ok(index)
+}
+
+// Where commands working without an index make their temporary directories
+fn work_dirs() -> apwm::WorkDirs {
+    let mut dirs = apwm::WorkDirs::default();
+    if let Some(temp_dir) = INDEX_SETTINGS
+        .get()
+        .and_then(|settings| settings.temp_dir.as_ref())
+    {
+        dirs.set_temp_dir(temp_dir);
+    }
+
+    dirs
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    let cli = Args::parse();
-    match cli.command {
-        Command::Refresh {
-            index_path,
-            apworlds_path,
-        } => {
-            refresh(&index_path, &apworlds_path).await?;
+    let config = apwm::Config::load(&std::env::current_dir()?)?;
+    let mut settings = IndexSettings::default();
+    if let Some(temp_dir) = &config.temp_dir {
+        std::fs::create_dir_all(temp_dir)?;
+        settings.temp_dir = Some(temp_dir.clone());
+    }
+    // `GITHUB_TOKEN` still wins over the configuration
+    if let Some(reference) = &config.credentials.github_token {
+        if std::env::var_os("GITHUB_TOKEN").is_none() {
+            match apwm::resolve_secret(reference) {
+                Ok(token) => settings.github_token = Some(token),
+                Err(e) => eprintln!("warning: couldn't read the github token: {}", e),
+            }
         }
     }
+    let _ = INDEX_SETTINGS.set(settings);
+
+    let matches = with_config_defaults(Args::command(), &config).get_matches();
+    let mut cli = Args::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Command::Diff { output, .. }
+    | Command::DiffDestinations { output, .. }
+    | Command::DiffLockfile { output, .. } = &mut cli.command
+    {
+        output.webhooks = config.notifications.discord_webhooks.clone();
+    }
+
+    let level = match (cli.quiet, cli.verbose) {
+        (true, _) => log::LevelFilter::Error,
+        (false, 0) => log::LevelFilter::Warn,
+        (false, 1) => log::LevelFilter::Info,
+        (false, 2) => log::LevelFilter::Debug,
+        (false, _) => log::LevelFilter::Trace,
+    };
+    log::set_boxed_logger(Box::new(Logger { level }))?;
+    log::set_max_level(level);
+
+    let printer = Printer {
+        json: cli.json,
+        quiet: cli.quiet,
+        command: matches.subcommand_name().unwrap_or_default().to_string(),
+        data: RefCell::default(),
+    };
+    let result = run(cli.command, &printer).await;
+    printer.finish(result.as_ref().err())?;
+    if let Err(e) = result {
+        eprintln!("Error: {:?}", e);
+        std::process::exit(failure_kind(&e).map_or(1, |kind| kind as i32));
+    }
 
     Ok(())
 }
 
-async fn refresh(index_path: &Path, destination: &Path) -> Result<()> {
-    let index_toml = index_path.join("index.toml");
-    let index = apwm::Index::new(&index_toml)?;
+#[derive(Serialize)]
+struct WorldAdded {
+    key: String,
+}
 
-    if !index.should_refresh(&destination) {
-        println!("The index hasn't been changed since the last refresh, nothing to do.");
-        return Ok(());
-    }
+#[derive(Serialize)]
+struct WorldUpdated {
+    world: String,
+    #[serde(flatten)]
+    update: apwm::WorldUpdate,
+}
 
-    println!("Refreshing apworlds into {}", destination.to_string_lossy());
-    index.refresh_into(destination).await?;
+#[derive(Serialize)]
+struct ArchipelagoBumped {
+    dry_run: bool,
+    #[serde(flatten)]
+    bump: apwm::ArchipelagoBump,
+}
 
-    Ok(())
+#[derive(Serialize)]
+struct WorldRemoved {
+    world: String,
+    dry_run: bool,
+    // Files next to the index nothing else uses anymore
+    orphans: Vec<PathBuf>,
+}
+
+#[derive(Serialize)]
+struct WorldUnpacked {
+    world: String,
+    package: PathBuf,
+}
+
+#[derive(Serialize)]
+struct PatchMade {
+    world: String,
+    patch: PathBuf,
+}
+
+#[derive(Serialize)]
+struct IndexFormatted {
+    index: PathBuf,
+    // Whether the file wasn't formatted, and got rewritten unless checking
+    changed: bool,
+}
+
+#[derive(Serialize)]
+struct Completions {
+    script: String,
+}
+
+async fn run(command: Command, printer: &Printer) -> Result<()> {
+    match command {
+        Command::Refresh {
+            index_path,
+            apworlds_path,
+            plan,
+            dry_run,
+        } => {
+            if plan {
+                refresh_plan(&index_path, &apworlds_path, printer)?;
+            } else if dry_run {
+                refresh_dry_run(&index_path, &apworlds_path, printer).await?;
+            } else {
+                refresh(&index_path, &apworlds_path, printer).await?;
+            }
+        }
+        Command::CheckPatches { index_path } => {
+            check_patches(&index_path, printer).await?;
+        }
+        Command::Junk {
+            index_path,
+            apworlds_path,
+        } => {
+            junk(&index_path, &apworlds_path, printer)?;
+        }
+        Command::CheckSyntax {
+            index_path,
+            apworlds_path,
+        } => {
+            check_syntax(&index_path, &apworlds_path, printer)?;
+        }
+        Command::Options {
+            index_path,
+            apworlds_path,
+            output,
+        } => {
+            options(&index_path, &apworlds_path, &output, printer)?;
+        }
+        Command::DataPackage {
+            index_path,
+            apworlds_path,
+            output,
+        } => {
+            data_package(&index_path, &apworlds_path, &output, printer)?;
+        }
+        Command::PythonVersion {
+            index_path,
+            apworlds_path,
+        } => {
+            python_version(&index_path, &apworlds_path, printer)?;
+        }
+        Command::Scan {
+            index_path,
+            apworlds_path,
+        } => {
+            scan(&index_path, &apworlds_path, printer)?;
+        }
+        Command::Sizes {
+            index_path,
+            apworlds_path,
+            top,
+        } => {
+            sizes(&index_path, &apworlds_path, top, printer)?;
+        }
+        Command::Stats {
+            index_path,
+            apworlds_path,
+            top,
+        } => {
+            stats(
+                index_path.as_deref(),
+                apworlds_path.as_deref(),
+                top,
+                printer,
+            )?;
+        }
+        Command::Vendored {
+            index_path,
+            apworlds_path,
+        } => {
+            vendored(&index_path, &apworlds_path, printer)?;
+        }
+        Command::Drift { index_path } => {
+            drift(&index_path, printer).await?;
+        }
+        Command::CheckLinks { index_path } => {
+            check_links(&index_path, printer).await?;
+        }
+        Command::CheckUrls { index_path, jobs } => {
+            check_urls(&index_path, jobs, printer).await?;
+        }
+        Command::Diff {
+            old,
+            new,
+            index_path,
+            old_index_path,
+            new_index_path,
+            output,
+            args,
+            metadata_only,
+        } => {
+            let to_string = |path: PathBuf| path.to_string_lossy().into_owned();
+            let (old, new) = match (old_index_path, new_index_path) {
+                (Some(old), Some(new)) => (to_string(old), Some(to_string(new))),
+                _ => (old.unwrap_or_else(|| "HEAD".into()), new),
+            };
+            diff(
+                &index_path,
+                &old,
+                new.as_deref(),
+                &output,
+                &args.options(),
+                metadata_only,
+                printer,
+            )
+            .await?;
+        }
+        Command::Changelog {
+            from,
+            to,
+            index_path,
+            release_notes,
+        } => {
+            let changelog = apwm::changelog(
+                &index_file(&index_path),
+                &from,
+                &to,
+                release_notes,
+                &work_dirs(),
+            )
+            .await?;
+            printer.emit(&changelog, |changelog| {
+                print!("{}", changelog.to_markdown())
+            })?;
+        }
+        Command::DiffDestinations {
+            old_destination,
+            new_destination,
+            output,
+            args,
+        } => {
+            output
+                .print_destinations(&old_destination, &new_destination, &args.options(), printer)
+                .await?;
+        }
+        Command::DiffLockfile {
+            index_path,
+            lockfile,
+            output,
+            args,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            output
+                .print(&index.diff_lockfile(&lockfile, &args.options())?, printer)
+                .await?;
+        }
+        Command::Add {
+            index_path,
+            url,
+            key,
+            version,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let key = index
+                .add_world(&url, key.as_deref(), version.as_deref())
+                .await?;
+            printer.emit(&WorldAdded { key }, |added| {
+                println!("Added {} to the index", added.key)
+            })?;
+        }
+        Command::Update {
+            index_path,
+            world,
+            version,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let update = index.update_world(&world, version.as_deref()).await?;
+            printer.emit(&WorldUpdated { world, update }, |updated| {
+                for check in &updated.update.patches {
+                    if let PatchStatus::Fuzzy(hunks) = &check.status {
+                        println!(
+                            "{}: warning: {} applies with fuzz",
+                            updated.world, check.patch
+                        );
+                        for hunk in hunks {
+                            println!("    {}", hunk);
+                        }
+                    }
+                }
+                println!(
+                    "Updated {} from {} to {}",
+                    updated.world,
+                    updated.update.old_version.as_deref().unwrap_or("Unknown"),
+                    updated.update.new_version
+                );
+            })?;
+        }
+        Command::BumpArchipelago {
+            index_path,
+            version,
+            dry_run,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let bump = index.bump_archipelago(&version, dry_run).await?;
+            printer.emit(&ArchipelagoBumped { dry_run, bump }, |bumped| {
+                let bump = &bumped.bump;
+                for (world, issue) in &bump.incompatible {
+                    println!(
+                        "{}: warning: {}, it won't load on {}",
+                        world, issue, bump.new_version
+                    );
+                }
+                if bumped.dry_run {
+                    println!(
+                        "The index can move from archipelago {} to {}",
+                        bump.old_version, bump.new_version
+                    );
+                } else {
+                    println!(
+                        "Moved the index from archipelago {} to {}",
+                        bump.old_version, bump.new_version
+                    );
+                }
+            })?;
+        }
+        Command::Outdated { index_path } => {
+            outdated(&index_path, printer).await?;
+        }
+        Command::List {
+            index_path,
+            filter,
+            sort,
+        } => {
+            list(&index_path, &filter.filter(), sort, printer)?;
+        }
+        Command::Show {
+            index_path,
+            world,
+            apworlds_path,
+        } => {
+            show(&index_path, &world, apworlds_path.as_deref(), printer)?;
+        }
+        Command::Verify {
+            apworlds_path,
+            lockfile,
+        } => {
+            verify(&apworlds_path, lockfile.as_deref(), printer)?;
+        }
+        Command::Lock {
+            index_path,
+            lockfile,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let lockfile = lockfile.unwrap_or_else(|| index_path.join("index.lock"));
+            index.lock(&lockfile).await?;
+            let locked = apwm::Manifest::load_from(&lockfile)?;
+            printer.emit(&locked, |_| {
+                println!("Wrote {}", lockfile.to_string_lossy())
+            })?;
+        }
+        Command::Sync {
+            index_path,
+            apworlds_path,
+            lockfile,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let lockfile = lockfile.unwrap_or_else(|| index_path.join("index.lock"));
+            index.sync(&lockfile, &apworlds_path).await?;
+            let manifest = apwm::Manifest::load(&apworlds_path)?;
+            printer.emit(&manifest, |_| {
+                println!(
+                    "Installed {} into {}",
+                    lockfile.to_string_lossy(),
+                    apworlds_path.to_string_lossy()
+                )
+            })?;
+        }
+        Command::Import {
+            index_path,
+            source,
+            dry_run,
+        } => {
+            import(&index_path, &source, dry_run, printer)?;
+        }
+        Command::Mirror { index_path, output } => {
+            mirror(&index_path, &output, printer).await?;
+        }
+        Command::Doctor {
+            index_path,
+            apworlds_path,
+        } => {
+            doctor(index_path.as_deref(), apworlds_path.as_deref(), printer)?;
+        }
+        Command::Search { index_path, query } => {
+            search(&index_path, &query.join(" "), printer)?;
+        }
+        Command::Rm {
+            index_path,
+            world,
+            dry_run,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let orphans = index.remove_world(&world, dry_run)?;
+            let removed = WorldRemoved {
+                world,
+                dry_run,
+                orphans,
+            };
+            printer.emit(&removed, |removed| {
+                let verb = if removed.dry_run {
+                    "Would remove"
+                } else {
+                    "Removed"
+                };
+                for orphan in &removed.orphans {
+                    println!("{} {}", verb, orphan.to_string_lossy());
+                }
+                println!("{} {} from the index", verb, removed.world);
+            })?;
+        }
+        Command::Fmt { index_path, check } => {
+            let index_toml = index_path.join("index.toml");
+            let content = std::fs::read_to_string(&index_toml)?;
+            let formatted = apwm::format_index(&content)?;
+            let changed = formatted != content;
+            if changed && !check {
+                std::fs::write(&index_toml, formatted)?;
+            }
+            let result = IndexFormatted {
+                index: index_toml,
+                changed,
+            };
+            printer.emit(&result, |_| {})?;
+            if changed && check {
+                bail!(Failure::validation(format!(
+                    "{} isn't formatted",
+                    result.index.to_string_lossy()
+                )));
+            }
+        }
+        Command::Pack {
+            source,
+            output,
+            version,
+        } => {
+            pack(&source, output, version.as_deref(), printer)?;
+        }
+        Command::Unpack {
+            index_path,
+            world,
+            output,
+        } => {
+            let index = open_index(&index_path.join("index.toml"))?;
+            let package = index.unpack_world(&world, &output).await?;
+            printer.emit(&WorldUnpacked { world, package }, |unpacked| {
+                println!(
+                    "Unpacked {} into {}",
+                    unpacked.world,
+                    unpacked.package.to_string_lossy()
+                )
+            })?;
+        }
+        Command::MakePatch {
+            index_path,
+            world,
+            modified,
+            output,
+        } => {
+            let index_toml = index_path.join("index.toml");
+            let index = open_index(&index_toml)?;
+            index.make_patch(&world, &modified, &output).await?;
+            let made = PatchMade {
+                world,
+                patch: output,
+            };
+            printer.emit(&made, |made| {
+                println!("Added {} to {}", made.patch.to_string_lossy(), made.world)
+            })?;
+        }
+        Command::Serve {
+            index_path,
+            apworlds_path,
+            mirror,
+            bind,
+        } => {
+            let server = apwm::Server::new(
+                &index_path.join("index.toml"),
+                apworlds_path.as_deref(),
+                mirror.as_deref(),
+            )?;
+            let listener = tokio::net::TcpListener::bind(&bind).await?;
+            printer.note(format_args!("Serving on http://{}", listener.local_addr()?));
+            server.run(listener).await?;
+        }
+        Command::Completions { shell } => {
+            let completions = Completions {
+                script: completions(shell),
+            };
+            printer.emit(&completions, |completions| print!("{}", completions.script))?;
+        }
+        Command::CompleteWorlds { index_path } => {
+            // Completion scripts can't show errors, there's just nothing to
+            // complete
+            let keys = open_index(&index_path.join("index.toml"))
+                .map(|index| index.worlds.into_keys().collect::<Vec<_>>())
+                .unwrap_or_default();
+            printer.emit(&keys, |keys| {
+                for key in keys {
+                    println!("{}", key);
+                }
+            })?;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct VersionMismatch {
+    declared: String,
+    found: String,
+}
+
+// Worlds refreshed in a way they may not work, only checked after refreshing
+#[derive(Serialize, Default)]
+struct RefreshWarnings {
+    version_mismatches: BTreeMap<String, VersionMismatch>,
+    unlicensed: Vec<String>,
+    incompatible: BTreeMap<String, apwm::CompatibilityIssue>,
+    python_incompatible: BTreeMap<String, apwm::PythonRequirement>,
+}
+
+#[derive(Serialize)]
+struct Refreshed {
+    // False when the index hasn't changed since the last refresh
+    refreshed: bool,
+    archipelago_version: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<apwm::RefreshSummary>,
+    #[serde(flatten)]
+    warnings: RefreshWarnings,
+}
+
+async fn refresh(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let mut result = Refreshed {
+        refreshed: index.should_refresh(destination),
+        archipelago_version: index.common.archipelago_version.clone(),
+        summary: None,
+        warnings: RefreshWarnings::default(),
+    };
+    if result.refreshed {
+        printer.note(format_args!(
+            "Refreshing apworlds into {}",
+            destination.to_string_lossy()
+        ));
+        let summary = index.refresh_into(destination).await?;
+        printer.note(format_args!(
+            "Refreshed {} worlds in {:.1}s",
+            summary.installed.len(),
+            summary.duration.as_secs_f64()
+        ));
+        result.summary = Some(summary);
+        result.warnings = refresh_warnings(&index, destination)?;
+    }
+
+    printer.emit(&result, |result| {
+        if !result.refreshed {
+            println!("The index hasn't been changed since the last refresh, nothing to do.");
+            return;
+        }
+
+        let warnings = &result.warnings;
+        for (world_name, mismatch) in &warnings.version_mismatches {
+            println!(
+                "warning: {} is declared as version {} in the index but the apworld says {}",
+                world_name, mismatch.declared, mismatch.found
+            );
+        }
+        for world_name in &warnings.unlicensed {
+            println!("warning: {} doesn't contain a license", world_name);
+        }
+        for (world_name, issue) in &warnings.incompatible {
+            println!(
+                "warning: {} {} but the index uses {}",
+                world_name, issue, result.archipelago_version
+            );
+        }
+        for (world_name, requirement) in &warnings.python_incompatible {
+            println!(
+                "warning: {} requires python {} ({} in {}:{}), newer than the oldest one archipelago {} supports",
+                world_name,
+                requirement.version,
+                requirement.feature,
+                requirement.file,
+                requirement.line,
+                result.archipelago_version
+            );
+        }
+    })
+}
+
+async fn refresh_dry_run(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let mut index = open_index(&index_path.join("index.toml"))?;
+    let fs = Arc::new(apwm::DryRunFs::new());
+    index.set_filesystem(fs.clone());
+    printer.note(format_args!(
+        "Refreshing apworlds without touching {}",
+        destination.to_string_lossy()
+    ));
+    index.refresh_into(destination).await?;
+
+    printer.emit(&fs.actions(), |actions| {
+        for action in actions {
+            println!("{}", action);
+        }
+    })
+}
+
+fn refresh_plan(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index = open_index(&index_path.join("index.toml"))?;
+    let plan = index.plan_refresh(destination)?;
+    printer.emit(&plan, |plan| {
+        for action in plan.actions() {
+            match action {
+                RefreshAction::CheckoutArchipelago { repo, version } => {
+                    println!("checkout archipelago {} from {}", version, repo)
+                }
+                RefreshAction::InstallWorld(world) => match world.change {
+                    PlannedChange::Add => println!("add {} {}", world.key, world.version),
+                    PlannedChange::Update => match &world.deployed_version {
+                        Some(deployed) if deployed.as_str() != world.version.as_str() => {
+                            println!("update {} {} -> {}", world.key, deployed, world.version)
+                        }
+                        _ => println!(
+                            "update {} {} (origin or patches changed)",
+                            world.key, world.version
+                        ),
+                    },
+                    PlannedChange::Downgrade => println!(
+                        "downgrade {} {} -> {}",
+                        world.key,
+                        world.deployed_version.as_ref().unwrap_or(&world.version),
+                        world.version
+                    ),
+                    PlannedChange::Unchanged => {
+                        println!("refresh {} {} (unchanged)", world.key, world.version)
+                    }
+                },
+                RefreshAction::RemoveWorld { key, version } => {
+                    println!("remove {} {}", key, version)
+                }
+                RefreshAction::CopyGlobalFile { path } => println!("copy global file {}", path),
+                RefreshAction::WriteTemplates { dir } => {
+                    println!("write templates to {}", dir.to_string_lossy())
+                }
+                RefreshAction::ReplaceDestination { destination } => {
+                    println!("replace {}", destination.to_string_lossy())
+                }
+            }
+        }
+    })
+}
+
+fn refresh_warnings(index: &apwm::Index, destination: &Path) -> Result<RefreshWarnings> {
+    let mut warnings = RefreshWarnings::default();
+    for (world_name, world) in &index.worlds {
+        if let Some(found) = world.version_mismatch() {
+            warnings.version_mismatches.insert(
+                world_name.clone(),
+                VersionMismatch {
+                    declared: world.version().to_string(),
+                    found: found.to_string(),
+                },
+            );
+        }
+    }
+
+    if index.common.license_policy == LicensePolicy::Warn {
+        warnings.unlicensed = index
+            .unlicensed_worlds()
+            .into_iter()
+            .map(str::to_string)
+            .collect();
+    }
+
+    warnings.incompatible = index
+        .incompatible_worlds()
+        .into_iter()
+        .map(|(world_name, issue)| (world_name.to_string(), issue))
+        .collect();
+    warnings.python_incompatible = index.python_incompatible_worlds(destination)?;
+
+    Ok(warnings)
+}
+
+fn junk(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.find_junk(destination)?;
+    printer.emit(&report, |report| {
+        if report.is_empty() {
+            println!("No junk found");
+            return;
+        }
+
+        let mut total = 0;
+        for (world_name, junk) in report {
+            let size = junk.iter().map(|file| file.size).sum::<u64>();
+            total += size;
+            println!("{}: {} junk files, {} bytes", world_name, junk.len(), size);
+            for file in junk {
+                println!("    {} ({} bytes)", file.path, file.size);
+            }
+        }
+        println!("Total: {} bytes of junk", total);
+    })
+}
+
+fn check_syntax(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.check_syntax(destination)?;
+    printer.emit(&report, |report| {
+        for (world_name, errors) in report {
+            for error in errors {
+                println!(
+                    "{}: {}:{}:{}: {}",
+                    world_name, error.file, error.line, error.column, error.message
+                );
+            }
+        }
+    })?;
+
+    if !report.is_empty() {
+        bail!(Failure::validation(format!(
+            "Found syntax errors in {} worlds",
+            report.len()
+        )));
+    }
+
+    Ok(())
+}
+
+#[derive(Serialize)]
+struct OptionsWritten {
+    path: PathBuf,
+    options: usize,
+}
+
+fn options(index_path: &Path, destination: &Path, output: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    std::fs::create_dir_all(output)?;
+    let mut written = BTreeMap::new();
+    for (world_name, options) in index.options_schemas(destination)? {
+        let options_path = output.join(format!("{}.json", world_name));
+        std::fs::write(&options_path, serde_json::to_string_pretty(&options)?)?;
+        written.insert(
+            world_name,
+            OptionsWritten {
+                path: options_path,
+                options: options.len(),
+            },
+        );
+    }
+
+    printer.emit(&written, |written| {
+        for file in written.values() {
+            println!(
+                "Wrote {} options to {}",
+                file.options,
+                file.path.to_string_lossy()
+            );
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct DataPackageWritten {
+    path: PathBuf,
+    items: usize,
+    locations: usize,
+}
+
+fn data_package(
+    index_path: &Path,
+    destination: &Path,
+    output: &Path,
+    printer: &Printer,
+) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    std::fs::create_dir_all(output)?;
+    let mut written = BTreeMap::new();
+    for (world_name, package) in index.data_packages(destination)? {
+        let package_path = output.join(format!("{}.json", world_name));
+        std::fs::write(&package_path, serde_json::to_string_pretty(&package)?)?;
+        written.insert(
+            world_name,
+            DataPackageWritten {
+                path: package_path,
+                items: package.items.len(),
+                locations: package.locations.len(),
+            },
+        );
+    }
+
+    printer.emit(&written, |written| {
+        for file in written.values() {
+            println!(
+                "Wrote {} items and {} locations to {}",
+                file.items,
+                file.locations,
+                file.path.to_string_lossy()
+            );
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct PythonVersions {
+    archipelago_version: String,
+    // The oldest python the deployed archipelago release runs on
+    python_version: Option<String>,
+    worlds: BTreeMap<String, WorldPython>,
+}
+
+#[derive(Serialize)]
+struct WorldPython {
+    #[serde(flatten)]
+    requirement: apwm::PythonRequirement,
+    supported: bool,
+}
+
+fn python_version(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let manifest = apwm::Manifest::load(destination)?;
+    let incompatible = index.python_incompatible_worlds(destination)?;
+    let versions = PythonVersions {
+        archipelago_version: manifest.archipelago_version,
+        python_version: manifest.python_version,
+        worlds: index
+            .python_requirements(destination)?
+            .into_iter()
+            .map(|(world_name, requirement)| {
+                let supported = !incompatible.contains_key(&world_name);
+                (
+                    world_name,
+                    WorldPython {
+                        requirement,
+                        supported,
+                    },
+                )
+            })
+            .collect(),
+    };
+
+    printer.emit(&versions, |versions| {
+        if let Some(python_version) = &versions.python_version {
+            println!(
+                "archipelago {} supports python {} and newer",
+                versions.archipelago_version, python_version
+            );
+        }
+        for (world_name, world) in &versions.worlds {
+            let requirement = &world.requirement;
+            println!(
+                "{}: python {} ({} in {}:{}){}",
+                world_name,
+                requirement.version,
+                requirement.feature,
+                requirement.file,
+                requirement.line,
+                if world.supported {
+                    ""
+                } else {
+                    ", NEWER THAN SUPPORTED"
+                }
+            );
+        }
+    })
+}
+
+fn scan(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.scan_code(destination)?;
+    printer.emit(&report, |report| {
+        for (world_name, findings) in report {
+            println!("{}: {} findings", world_name, findings.len());
+            for finding in findings {
+                println!(
+                    "    [{}] {}:{}: {}",
+                    finding.kind, finding.file, finding.line, finding.snippet
+                );
+            }
+        }
+    })
+}
+
+fn sizes(index_path: &Path, destination: &Path, top: usize, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.size_report(destination, top)?;
+    printer.emit(&report, |report| {
+        for (world_name, sizes) in report {
+            match sizes.compressed_size {
+                Some(compressed_size) => println!(
+                    "{}: {} bytes installed, {} bytes compressed",
+                    world_name, sizes.installed_size, compressed_size
+                ),
+                None => println!("{}: {} bytes installed", world_name, sizes.installed_size),
+            }
+            for file in &sizes.largest_files {
+                println!("    {} ({} bytes)", file.path, file.size);
+            }
+        }
+    })
+}
+
+fn stats(
+    index_path: Option<&Path>,
+    destination: Option<&Path>,
+    top: usize,
+    printer: &Printer,
+) -> Result<()> {
+    let stats = match (index_path, destination) {
+        (Some(index_path), destination) => {
+            open_index(&index_path.join("index.toml"))?.stats(destination, top)?
+        }
+        (None, Some(destination)) => apwm::destination_stats(destination, top)?,
+        (None, None) => bail!("Either an index or a destination is needed"),
+    };
+
+    printer.emit(&stats, |stats| {
+        println!("Worlds: {}", stats.worlds);
+        for (origin, count) in &stats.by_origin {
+            println!("    {}: {}", origin, count);
+        }
+        println!("Patched: {}", stats.patched);
+        println!(
+            "Supported: {}, external: {} ({:.0}% supported)",
+            stats.supported,
+            stats.external,
+            stats.supported_ratio() * 100.
+        );
+
+        if let Some(sizes) = &stats.sizes {
+            println!(
+                "Deployed: {} worlds, {} total, {} on average",
+                sizes.deployed,
+                apwm::human_size(sizes.total),
+                apwm::human_size(sizes.average)
+            );
+            if !sizes.largest.is_empty() {
+                println!("Largest worlds:");
+            }
+            for world in &sizes.largest {
+                println!("    {}: {}", world.world, apwm::human_size(world.size));
+            }
+        }
+    })
+}
+
+fn vendored(index_path: &Path, destination: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.find_vendored(destination)?;
+    printer.emit(&report, |report| {
+        for (world_name, libraries) in report {
+            for library in libraries {
+                let status = match &library.status {
+                    VendoredStatus::Known(version) => format!("matches {}", version),
+                    VendoredStatus::Modified => "MODIFIED, matches no known version".into(),
+                    VendoredStatus::Unregistered => "no known versions to compare with".into(),
+                };
+                println!(
+                    "{}: {} in {} (declares {}), {}",
+                    world_name,
+                    library.library,
+                    library.path,
+                    library.declared_version.as_deref().unwrap_or("no version"),
+                    status
+                );
+                println!("    sha256: {}", library.hash);
+            }
+        }
+    })
+}
+
+async fn drift(index_path: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.local_drift().await?;
+    printer.emit(&report, |report| {
+        for (world_name, files) in report {
+            println!("{}: {} files differ from upstream", world_name, files.len());
+            for file in files {
+                let (kind, sha256) = match file.kind {
+                    DriftKind::Added => ("added", &file.local_sha256),
+                    DriftKind::Removed => ("removed", &file.upstream_sha256),
+                    DriftKind::Changed => ("changed", &file.local_sha256),
+                };
+                println!(
+                    "    {} {} (sha256: {})",
+                    kind,
+                    file.path,
+                    sha256.as_deref().unwrap_or_default()
+                );
+            }
+        }
+    })
+}
+
+async fn check_links(index_path: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let links = index.check_links().await?;
+    printer.emit(&links, |links| {
+        for link in links {
+            let kind = match link.kind {
+                LinkKind::Origin => "origin",
+                LinkKind::Home => "home",
+                LinkKind::Upstream => "upstream",
+            };
+            let status = match &link.status {
+                LinkStatus::Alive { .. } => continue,
+                LinkStatus::Redirected { code, location } => format!(
+                    "redirected ({}) to {}",
+                    code,
+                    location.as_deref().unwrap_or("nowhere")
+                ),
+                LinkStatus::Dead { code } => format!("dead ({})", code),
+                LinkStatus::Unreachable { error } => format!("unreachable: {}", error),
+            };
+            println!("{}: {} {} is {}", link.world, kind, link.url, status);
+        }
+    })?;
+
+    let broken = links
+        .iter()
+        .filter(|link| !matches!(link.status, LinkStatus::Alive { .. }))
+        .count();
+    if broken != 0 {
+        bail!(Failure::validation(format!(
+            "{} links need attention",
+            broken
+        )));
+    }
+
+    Ok(())
+}
+
+async fn check_urls(index_path: &Path, jobs: usize, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = index.check_urls(jobs).await?;
+    printer.emit(&report, |report| {
+        for audit in report {
+            let kind = match audit.kind {
+                LinkKind::Origin => "origin",
+                LinkKind::Home => "home",
+                LinkKind::Upstream => "upstream",
+            };
+            let status = match &audit.status {
+                UrlStatus::Alive { .. } => continue,
+                UrlStatus::Moved { location } => format!("moved to {}", location),
+                UrlStatus::Dead { code } => format!("dead ({})", code),
+                UrlStatus::Unreachable { error } => format!("unreachable: {}", error),
+            };
+            println!("{}: {} {} is {}", audit.world, kind, audit.url, status);
+            if let Some(suggestion) = &audit.suggestion {
+                println!("  fix: {}", suggestion);
+            }
+        }
+        let alive = report
+            .iter()
+            .filter(|audit| audit.status.is_alive())
+            .count();
+        println!("{} of {} urls are alive", alive, report.len());
+    })?;
+
+    let rotten = report
+        .iter()
+        .filter(|audit| !audit.status.is_alive())
+        .count();
+    if rotten != 0 {
+        bail!(Failure::validation(format!(
+            "{} urls need attention",
+            rotten
+        )));
+    }
+
+    Ok(())
+}
+
+async fn outdated(index_path: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+    let report = index.outdated().await?;
+
+    printer.emit(&report, |report| {
+        let mut up_to_date = 0;
+        for check in report {
+            let version = check.version.as_deref().unwrap_or("Unknown");
+            match &check.status {
+                UpstreamStatus::UpToDate { .. } => up_to_date += 1,
+                UpstreamStatus::Outdated { latest, bump } => println!(
+                    "{}: {} -> {} ({}), {}",
+                    check.world, version, latest, bump, check.url
+                ),
+                UpstreamStatus::Unversioned {
+                    last_modified,
+                    etag,
+                } => println!(
+                    "{}: {} can't be compared, {} was last modified {}{}",
+                    check.world,
+                    version,
+                    check.url,
+                    last_modified.as_deref().unwrap_or("at an unknown date"),
+                    etag.as_ref()
+                        .map(|etag| format!(" (etag {})", etag))
+                        .unwrap_or_default()
+                ),
+                UpstreamStatus::Unknown { error } => {
+                    println!("{}: couldn't check {}: {}", check.world, check.url, error)
+                }
+            }
+        }
+        println!("{} worlds are up to date", up_to_date);
+    })
+}
+
+#[derive(clap::Args)]
+struct ListFilter {
+    #[clap(long, value_enum)]
+    origin: Option<Origin>,
+    #[clap(long)]
+    tag: Option<String>,
+    #[clap(long, conflicts_with = "no_patches")]
+    has_patches: bool,
+    #[clap(long)]
+    no_patches: bool,
+    #[clap(long, conflicts_with = "unsupported")]
+    supported: bool,
+    #[clap(long)]
+    unsupported: bool,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Origin {
+    Url,
+    Supported,
+    Local,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum ListSort {
+    Key,
+    Name,
+    // Oldest first, worlds without a version before the others
+    Version,
+}
+
+impl ListFilter {
+    fn filter(&self) -> WorldFilter {
+        let flag = |yes: bool, no: bool| (yes || no).then_some(yes);
+        WorldFilter {
+            origin: self.origin.map(|origin| match origin {
+                Origin::Url => OriginKind::Url,
+                Origin::Supported => OriginKind::Supported,
+                Origin::Local => OriginKind::Local,
+            }),
+            tag: self.tag.clone(),
+            has_patches: flag(self.has_patches, self.no_patches),
+            supported: flag(self.supported, self.unsupported),
+        }
+    }
+}
+
+fn list(index_path: &Path, filter: &WorldFilter, sort: ListSort, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+    let mut worlds = index.list(filter);
+    match sort {
+        ListSort::Key => {}
+        ListSort::Name => worlds.sort_by_cached_key(|world| world.name.to_lowercase()),
+        ListSort::Version => worlds.sort_by(|a, b| a.version.cmp(&b.version)),
+    }
+
+    printer.emit(&worlds, |worlds| {
+        let rows = worlds
+            .iter()
+            .map(|world| {
+                [
+                    world.key.clone(),
+                    world.name.clone(),
+                    world
+                        .version
+                        .as_ref()
+                        .map(ToString::to_string)
+                        .unwrap_or_default(),
+                    world.origin.to_string(),
+                    world.patches.len().to_string(),
+                    world.tags.join(","),
+                ]
+            })
+            .collect::<Vec<_>>();
+        let header = ["KEY", "NAME", "VERSION", "ORIGIN", "PATCHES", "TAGS"].map(str::to_string);
+        let widths = (0..header.len())
+            .map(|i| {
+                std::iter::once(&header)
+                    .chain(&rows)
+                    .map(|row| row[i].chars().count())
+                    .max()
+                    .unwrap_or(0)
+            })
+            .collect::<Vec<_>>();
+        for row in std::iter::once(&header).chain(&rows) {
+            let line = row
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{:width$}", cell, width = width))
+                .collect::<Vec<_>>();
+            println!("{}", line.join("  ").trim_end());
+        }
+    })
+}
+
+#[derive(Serialize)]
+struct WorldPacked {
+    world: String,
+    output: PathBuf,
+    // Problems with the packed apworld, which is still written
+    warnings: Vec<String>,
+}
+
+fn pack(
+    source: &Path,
+    output: Option<PathBuf>,
+    version: Option<&str>,
+    printer: &Printer,
+) -> Result<()> {
+    let source = std::fs::canonicalize(source)?;
+    let Some(name) = source
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+    else {
+        bail!("{} isn't a world directory", source.to_string_lossy());
+    };
+    let output = output.unwrap_or_else(|| PathBuf::from(format!("{}.apworld", name)));
+
+    apwm::pack_world(&source, &output, version, &work_dirs())?;
+    let packed = WorldPacked {
+        warnings: apwm::validate_structure(&output, &name)?
+            .iter()
+            .map(ToString::to_string)
+            .collect(),
+        world: name,
+        output,
+    };
+
+    printer.emit(&packed, |packed| {
+        for warning in &packed.warnings {
+            println!("warning: {}", warning);
+        }
+        println!(
+            "Packed {} into {}",
+            packed.world,
+            packed.output.to_string_lossy()
+        );
+    })
+}
+
+fn search(index_paths: &[PathBuf], query: &str, printer: &Printer) -> Result<()> {
+    let mut hits = vec![];
+    for index_path in index_paths {
+        let index = open_index(&index_path.join("index.toml"))?;
+        hits.extend(index.search(query)?);
+    }
+    hits.sort_by_key(|hit| std::cmp::Reverse(hit.score));
+
+    printer.emit(&hits, |hits| {
+        for hit in hits {
+            let location = if index_paths.len() > 1 {
+                format!("{}: ", hit.index.to_string_lossy())
+            } else {
+                String::new()
+            };
+            println!("{}{} ({})", location, hit.key, hit.name);
+            if let Some(description) = &hit.description {
+                println!("    {}", description);
+            }
+        }
+    })
+}
+
+fn show(
+    index_path: &Path,
+    world: &str,
+    destination: Option<&Path>,
+    printer: &Printer,
+) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+    let details = index.show(world, destination)?;
+
+    printer.emit(&details, |details| {
+        println!("{} ({})", details.key, details.name);
+        println!(
+            "  version: {}",
+            details.version.as_deref().unwrap_or("Unknown")
+        );
+        println!("  origin: {}", details.origin);
+        println!("  resolved: {}", details.resolved);
+        if let Some(home) = &details.home {
+            println!("  home: {}", home);
+        }
+        if let Some(upstream) = &details.upstream {
+            println!("  upstream: {}", upstream);
+        }
+        if !details.tags.is_empty() {
+            println!("  tags: {}", details.tags.join(", "));
+        }
+        if !details.dependencies.is_empty() {
+            println!("  dependencies: {}", details.dependencies.join(", "));
+        }
+        if let Some(license_file) = &details.license_file {
+            println!("  license file: {}", license_file);
+        }
+        if !details.patches.is_empty() {
+            println!("  patches:");
+            for patch in &details.patches {
+                match (&patch.before, patch.active) {
+                    (Some(before), true) => println!("    {} (before {})", patch.path, before),
+                    (Some(before), false) => {
+                        println!("    {} (upstreamed in {}, inactive)", patch.path, before)
+                    }
+                    (None, _) => println!("    {}", patch.path),
+                }
+            }
+        }
+
+        let Some(deployed) = &details.deployed else {
+            if destination.is_some() {
+                println!("  not deployed");
+            }
+            return;
+        };
+        println!("  deployed: {}", deployed.path);
+        if let Some(version) = &deployed.version {
+            println!("    version: {}", version);
+        }
+        if let Some(sha256) = &deployed.sha256 {
+            println!("    sha256: {}", sha256);
+        }
+        for patch in &deployed.patches {
+            println!("    patch: {} ({:.12})", patch.path, patch.sha256);
+        }
+        if let Some(last_updated) = deployed.last_updated {
+            println!("    last updated: {}", apwm::format_timestamp(last_updated));
+        }
+    })
+}
+
+fn verify(destination: &Path, lockfile: Option<&Path>, printer: &Printer) -> Result<()> {
+    let expected = apwm::Manifest::load_from(lockfile.unwrap_or(destination))?;
+    let issues = apwm::verify_destination(destination, &expected)?;
+
+    printer.emit(&issues, |issues| {
+        for issue in issues {
+            println!("{}", issue);
+        }
+    })?;
+
+    if !issues.is_empty() {
+        bail!(Failure::validation(format!(
+            "{} doesn't match what was deployed",
+            destination.to_string_lossy()
+        )));
+    }
+
+    Ok(())
+}
+
+fn import(index_path: &Path, source: &Path, dry_run: bool, printer: &Printer) -> Result<()> {
+    let index = open_index(&index_path.join("index.toml"))?;
+    let report = index.import_worlds(source, dry_run)?;
+
+    printer.emit(&report, |report| {
+        for world in report {
+            let version = world.version.as_deref().unwrap_or("unknown version");
+            match &world.status {
+                ImportStatus::Known { key, version: None } => {
+                    println!("{}: already in the index as {}", world.file, key)
+                }
+                ImportStatus::Known {
+                    key,
+                    version: Some(index_version),
+                } => println!(
+                    "{}: already in the index as {} ({}, the index has {})",
+                    world.file, key, version, index_version
+                ),
+                ImportStatus::New { key, local } if dry_run => println!(
+                    "{}: would be added as {} ({}) from {}",
+                    world.file, key, version, local
+                ),
+                ImportStatus::New { key, local } => println!(
+                    "{}: added as {} ({}) from {}",
+                    world.file, key, version, local
+                ),
+            }
+        }
+    })
+}
+
+async fn mirror(index_path: &Path, mirror: &Path, printer: &Printer) -> Result<()> {
+    let index = open_index(&index_path.join("index.toml"))?;
+    let report = index.mirror(mirror).await?;
+
+    printer.emit(&report, |report| {
+        for (world, version) in &report.mirrored {
+            println!("{}: mirrored {}", world, version);
+        }
+        for failure in &report.failed {
+            println!(
+                "{}: couldn't mirror {}: {}",
+                failure.world, failure.url, failure.error
+            );
+        }
+        println!(
+            "{} worlds mirrored, {} already present",
+            report.mirrored.len(),
+            report.present.len()
+        );
+    })?;
+
+    if !report.failed.is_empty() {
+        bail!(Failure::partial("Some worlds couldn't be mirrored"));
+    }
+
+    Ok(())
+}
+
+fn doctor(index_path: Option<&Path>, destination: Option<&Path>, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.map(|index_path| index_path.join("index.toml"));
+    let diagnostics = apwm::diagnose(index_toml.as_deref(), destination);
+
+    printer.emit(&diagnostics, |diagnostics| {
+        for diagnostic in diagnostics {
+            let status = match diagnostic.status {
+                DiagnosticStatus::Ok => "ok",
+                DiagnosticStatus::Warning => "warning",
+                DiagnosticStatus::Error => "error",
+            };
+            println!("{}: {}: {}", diagnostic.check, status, diagnostic.message);
+            if let Some(fix) = &diagnostic.fix {
+                println!("  fix: {}", fix);
+            }
+        }
+    })?;
+
+    if diagnostics
+        .iter()
+        .any(|diagnostic| diagnostic.status == DiagnosticStatus::Error)
+    {
+        bail!(Failure::validation(
+            "Some checks failed, apwm won't work properly until they're fixed"
+        ));
+    }
+
+    Ok(())
+}
+
+#[derive(clap::Args)]
+struct DiffOutput {
+    #[clap(long, conflicts_with_all = ["json", "html"])]
+    markdown: bool,
+    #[clap(long, conflicts_with = "json")]
+    html: bool,
+    #[clap(long, requires = "html")]
+    side_by_side: bool,
+    // Print discord webhook payloads, as a JSON array of messages
+    #[clap(long, conflicts_with_all = ["json", "markdown", "html"])]
+    discord: bool,
+    #[clap(long, value_enum, default_value_t = Color::Auto)]
+    color: Color,
+    // Exit with a code telling what kind of changes the diff contains
+    #[clap(long)]
+    exit_code: bool,
+    // Post the discord payloads to the webhooks of the configuration
+    #[clap(long)]
+    notify: bool,
+    #[clap(skip)]
+    webhooks: Vec<String>,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Color {
+    // Only when printing to a terminal and `NO_COLOR` isn't set
+    Auto,
+    Always,
+    Never,
+}
+
+impl DiffOutput {
+    async fn print(&self, diff: &IndexDiff, printer: &Printer) -> Result<()> {
+        if printer.json {
+            printer.emit(diff, |_| {})?;
+        } else {
+            self.render(diff)?;
+        }
+        self.notify_and_exit(diff, printer).await
+    }
+
+    // Text and HTML diffs of destinations get printed one file at a time as
+    // they're computed, other outputs need the whole diff. Markdown and
+    // discord payloads only use the paths and line counts of the files.
+    async fn print_destinations(
+        &self,
+        old: &Path,
+        new: &Path,
+        options: &DiffOptions,
+        printer: &Printer,
+    ) -> Result<()> {
+        if printer.json {
+            let diff = apwm::diff_destinations(old, new, options, &work_dirs())?;
+            return self.print(&diff, printer).await;
+        }
+        if self.markdown || self.discord {
+            let diff =
+                apwm::visit_destination_changes(old, new, options, &work_dirs(), |_| Ok(()))?;
+            return self.print(&diff, printer).await;
+        }
+
+        let mut stdout = std::io::stdout().lock();
+        let diff = if self.html {
+            let renderer = HtmlRenderer::new(if self.side_by_side {
+                HtmlLayout::SideBySide
+            } else {
+                HtmlLayout::Unified
+            });
+            write!(stdout, "{}", renderer.header())?;
+            let diff =
+                apwm::visit_destination_changes(old, new, options, &work_dirs(), |change| {
+                    let html = match change {
+                        DestinationChange::Archipelago(_) => return Ok(()),
+                        DestinationChange::World { key, diff } => renderer.world(key, diff),
+                        DestinationChange::File { change, .. } => renderer.file(&change),
+                        DestinationChange::WorldStats { stats, .. } => renderer.stats(&stats),
+                    };
+                    stdout.write_all(html.as_bytes()).map_err(apwm::Error::from)
+                })?;
+            write!(stdout, "{}", renderer.footer())?;
+            diff
+        } else {
+            let renderer = TextRenderer::new(self.colored());
+            let diff =
+                apwm::visit_destination_changes(old, new, options, &work_dirs(), |change| {
+                    let text = match change {
+                        DestinationChange::Archipelago(version) => renderer.archipelago(version),
+                        DestinationChange::World { key, diff } => renderer.world(key, diff),
+                        DestinationChange::File { change, .. } => renderer.file(&change),
+                        DestinationChange::WorldStats { key, stats } => renderer.stats(key, &stats),
+                    };
+                    stdout.write_all(text.as_bytes()).map_err(apwm::Error::from)
+                })?;
+            if diff.is_empty() {
+                writeln!(stdout, "No changes")?;
+            }
+            diff
+        };
+        drop(stdout);
+
+        self.notify_and_exit(&diff, printer).await
+    }
+
+    async fn notify_and_exit(&self, diff: &IndexDiff, printer: &Printer) -> Result<()> {
+        if self.notify {
+            self.post(diff).await?;
+        }
+        if self.exit_code {
+            printer.finish(None)?;
+            std::io::stdout().flush()?;
+            std::process::exit(match diff.outcome() {
+                DiffOutcome::NoChanges => 0,
+                DiffOutcome::Additions => 20,
+                DiffOutcome::Updates => 21,
+                DiffOutcome::Removals => 22,
+                DiffOutcome::Downgrades => 23,
+            });
+        }
+
+        Ok(())
+    }
+
+    // Webhooks can be written as is or as secret references
+    async fn post(&self, diff: &IndexDiff) -> Result<()> {
+        if self.webhooks.is_empty() {
+            bail!("No discord webhooks are configured in notifications.discord_webhooks");
+        }
+
+        let client = reqwest::Client::new();
+        for webhook in &self.webhooks {
+            let url = if webhook.starts_with("https://") {
+                webhook.clone()
+            } else {
+                apwm::resolve_secret(webhook)?
+            };
+            for message in diff.to_discord() {
+                let response = client
+                    .post(&url)
+                    .header(reqwest::header::CONTENT_TYPE, "application/json")
+                    .body(serde_json::to_vec(&message)?)
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    bail!(Failure::network(format!(
+                        "Discord refused a notification: {}",
+                        response.status()
+                    )));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn render(&self, diff: &IndexDiff) -> Result<()> {
+        if self.discord {
+            println!("{}", serde_json::to_string_pretty(&diff.to_discord())?);
+            return Ok(());
+        }
+        if self.markdown {
+            print!("{}", diff.to_markdown());
+            return Ok(());
+        }
+        if self.html {
+            let layout = if self.side_by_side {
+                HtmlLayout::SideBySide
+            } else {
+                HtmlLayout::Unified
+            };
+            print!("{}", diff.to_html(layout));
+            return Ok(());
+        }
+
+        print!("{}", diff.to_text(self.colored()));
+        Ok(())
+    }
+
+    fn colored(&self) -> bool {
+        match self.color {
+            Color::Auto => {
+                std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none()
+            }
+            Color::Always => true,
+            Color::Never => false,
+        }
+    }
+}
+
+#[derive(clap::Args)]
+struct DiffArgs {
+    // Glob patterns of files to leave out of content diffs
+    #[clap(long)]
+    ignore: Vec<String>,
+    // Names or glob patterns of the worlds to compare
+    #[clap(short, long)]
+    world: Vec<String>,
+    // Only show how many lines changed in each file
+    #[clap(long)]
+    stat: bool,
+    // Unchanged lines shown around every change
+    #[clap(short = 'U', long = "unified", default_value_t = 3)]
+    context_lines: usize,
+}
+
+impl DiffArgs {
+    fn options(&self) -> DiffOptions {
+        DiffOptions {
+            ignore: self.ignore.clone(),
+            worlds: self.world.clone(),
+            stat_only: self.stat,
+            context_lines: self.context_lines,
+        }
+    }
+}
+
+// Index paths given to `diff` can either be index directories or index files,
+// to compare two revisions of the same index
+fn index_file(index_path: &Path) -> PathBuf {
+    if index_path.is_file() {
+        return index_path.into();
+    }
+
+    index_path.join("index.toml")
+}
+
+enum DiffSide {
+    Index(Box<apwm::Index>),
+    Lockfile(PathBuf),
+}
+
+// Resolves a side of `diff`. Paths that exist are index directories or files,
+// or lockfiles and destinations if they can't be loaded as an index.
+// Anything else is a git revision of the index, exported to `scratch`.
+fn diff_side(index_path: &Path, side: &str, scratch: &Path) -> Result<DiffSide> {
+    let path = Path::new(side);
+    if !path.exists() {
+        let index_toml = apwm::export_revision(&index_file(index_path), side, scratch)?;
+        return Ok(DiffSide::Index(Box::new(open_index(&index_toml)?)));
+    }
+
+    match open_index(&index_file(path)) {
+        Ok(index) => Ok(DiffSide::Index(Box::new(index))),
+        Err(e) => match apwm::Manifest::load_from(path) {
+            Ok(_) => Ok(DiffSide::Lockfile(path.into())),
+            Err(_) => Err(e.into()),
+        },
+    }
+}
+
+async fn diff(
+    index_path: &Path,
+    old: &str,
+    new: Option<&str>,
+    output: &DiffOutput,
+    options: &DiffOptions,
+    metadata_only: bool,
+    printer: &Printer,
+) -> Result<()> {
+    // Exported revisions must outlive the diff, it reads their patches and
+    // local worlds
+    let scratch = work_dirs().tempdir()?;
+    let old = diff_side(index_path, old, &scratch.path().join("old"))?;
+    let new = match new {
+        Some(new) => diff_side(index_path, new, &scratch.path().join("new"))?,
+        None => DiffSide::Index(Box::new(open_index(&index_file(index_path))?)),
+    };
+
+    let diff = match (&old, &new) {
+        (DiffSide::Index(old), DiffSide::Index(new)) if metadata_only => {
+            old.diff_metadata(new, options)
+        }
+        (DiffSide::Index(old), DiffSide::Index(new)) => old.diff(new, options).await?,
+        (DiffSide::Lockfile(old), DiffSide::Index(new)) => new.diff_lockfile(old, options)?,
+        (DiffSide::Lockfile(old), DiffSide::Lockfile(new)) => apwm::diff_manifests(
+            &apwm::Manifest::load_from(old)?,
+            &apwm::Manifest::load_from(new)?,
+            options,
+        ),
+        (DiffSide::Index(_), DiffSide::Lockfile(_)) => {
+            bail!("A lockfile can only be compared with a newer index, give it as the old side")
+        }
+    };
+
+    output.print(&diff, printer).await
+}
+
+fn describe_lines(lines: &Range<usize>) -> String {
+    if lines.end == usize::MAX {
+        return "whole file".into();
+    }
+
+    format!("lines {}-{}", lines.start, lines.end - 1)
+}
+
+#[derive(Serialize)]
+struct PatchReport {
+    conflicts: BTreeMap<String, Vec<apwm::PatchConflict>>,
+    checks: BTreeMap<String, Vec<apwm::PatchCheck>>,
+}
+
+async fn check_patches(index_path: &Path, printer: &Printer) -> Result<()> {
+    let index_toml = index_path.join("index.toml");
+    let index = open_index(&index_toml)?;
+
+    let report = PatchReport {
+        conflicts: index.patch_conflicts()?,
+        checks: index.check_patches().await?,
+    };
+    printer.emit(&report, |report| {
+        for (world_name, conflicts) in &report.conflicts {
+            for conflict in conflicts {
+                println!(
+                    "{}: warning: {} ({}) overlaps with {} ({}) in {}",
+                    world_name,
+                    conflict.first,
+                    describe_lines(&conflict.first_lines),
+                    conflict.second,
+                    describe_lines(&conflict.second_lines),
+                    conflict.file,
+                );
+            }
+        }
+
+        for (world_name, checks) in &report.checks {
+            for check in checks {
+                match &check.status {
+                    PatchStatus::Clean => {
+                        println!("{}: {} applies cleanly", world_name, check.patch);
+                    }
+                    PatchStatus::Fuzzy(hunks) => {
+                        println!("{}: {} applies with fuzz", world_name, check.patch);
+                        for hunk in hunks {
+                            println!("    {}", hunk);
+                        }
+                    }
+                    PatchStatus::Failed(output) => {
+                        println!("{}: {} fails to apply", world_name, check.patch);
+                        for line in output.lines() {
+                            println!("    {}", line);
+                        }
+                    }
+                }
+            }
+        }
+    })?;
+
+    if report
+        .checks
+        .values()
+        .flatten()
+        .any(|check| !check.status.is_clean())
+    {
+        bail!(Failure::validation("Some patches don't apply cleanly"));
+    }
+
+    Ok(())
+}
+
+#[derive(clap::ValueEnum, Clone, Copy)]
+enum Shell {
+    Bash,
+    Zsh,
+    Fish,
+}
+
+// How the value of an option gets completed
+enum ValueCompletion {
+    Worlds,
+    Choices(Vec<String>),
+    Files,
+}
+
+struct CompletedOption {
+    short: Option<char>,
+    long: Option<String>,
+    value: Option<ValueCompletion>,
+}
+
+impl CompletedOption {
+    fn flags(&self) -> Vec<String> {
+        self.short
+            .map(|short| format!("-{}", short))
+            .into_iter()
+            .chain(self.long.iter().map(|long| format!("--{}", long)))
+            .collect()
+    }
+}
+
+// The visible subcommands of the CLI along with their options
+fn completed_commands() -> Vec<(String, Vec<CompletedOption>)> {
+    let mut command = <Args as clap::CommandFactory>::command();
+    command.build();
+
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set())
+        .map(|subcommand| {
+            let options = subcommand
+                .get_arguments()
+                .filter(|arg| !arg.is_positional() && !arg.is_hide_set())
+                .map(|arg| {
+                    let value = arg.get_action().takes_values().then(|| {
+                        let choices = arg.get_possible_values();
+                        if arg.get_id() == "world" {
+                            ValueCompletion::Worlds
+                        } else if !choices.is_empty() {
+                            ValueCompletion::Choices(
+                                choices
+                                    .iter()
+                                    .map(|choice| choice.get_name().to_string())
+                                    .collect(),
+                            )
+                        } else {
+                            ValueCompletion::Files
+                        }
+                    });
+                    CompletedOption {
+                        short: arg.get_short(),
+                        long: arg.get_long().map(str::to_string),
+                        value,
+                    }
+                })
+                .collect();
+            (subcommand.get_name().to_string(), options)
+        })
+        .collect()
+}
+
+const BASH_WORLDS: &str = r#"_apwm_worlds() {
+    local index=. i
+    for ((i = 1; i < COMP_CWORD - 1; i++)); do
+        if [[ ${COMP_WORDS[i]} == -i ]]; then
+            index=${COMP_WORDS[i + 1]}
+        fi
+    done
+    apwm complete-worlds -i "$index" 2>/dev/null
+}
+"#;
+
+const FISH_WORLDS: &str = r#"function __apwm_worlds
+    set -l tokens (commandline -opc)
+    set -l index .
+    for i in (seq (math (count $tokens) - 1))
+        if test "$tokens[$i]" = -i
+            set index $tokens[(math $i + 1)]
+        end
+    end
+    apwm complete-worlds -i $index 2>/dev/null
+end
+"#;
+
+// Options taking a value other than a world or a choice fall back to the
+// shell's own file completion
+fn bash_completions() -> String {
+    let commands = completed_commands();
+    let names = commands
+        .iter()
+        .map(|(name, _)| name.as_str())
+        .collect::<Vec<_>>();
+
+    let mut script = String::from(BASH_WORLDS);
+    script.push_str("\n_apwm() {\n");
+    script.push_str("    local cur=${COMP_WORDS[COMP_CWORD]} prev=${COMP_WORDS[COMP_CWORD - 1]}\n");
+    script.push_str("    if ((COMP_CWORD == 1)); then\n");
+    script.push_str(&format!(
+        "        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n",
+        names.join(" ")
+    ));
+    script.push_str("        return\n    fi\n\n    case ${COMP_WORDS[1]} in\n");
+    for (name, options) in &commands {
+        script.push_str(&format!("    {})\n        case $prev in\n", name));
+        for option in options {
+            let reply = match &option.value {
+                None => continue,
+                Some(ValueCompletion::Worlds) => {
+                    "COMPREPLY=($(compgen -W \"$(_apwm_worlds)\" -- \"$cur\"))".to_string()
+                }
+                Some(ValueCompletion::Choices(choices)) => format!(
+                    "COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))",
+                    choices.join(" ")
+                ),
+                Some(ValueCompletion::Files) => "COMPREPLY=()".to_string(),
+            };
+            script.push_str(&format!(
+                "        {}) {}; return ;;\n",
+                option.flags().join(" | "),
+                reply
+            ));
+        }
+        let flags = options
+            .iter()
+            .flat_map(CompletedOption::flags)
+            .collect::<Vec<_>>();
+        script.push_str(&format!(
+            "        esac\n        COMPREPLY=($(compgen -W \"{}\" -- \"$cur\"))\n        ;;\n",
+            flags.join(" ")
+        ));
+    }
+    script.push_str("    esac\n}\n\ncomplete -o default -F _apwm apwm\n");
+
+    script
+}
+
+fn fish_completions() -> String {
+    let mut script = String::from(FISH_WORLDS);
+    script.push_str("\ncomplete -c apwm -f\n");
+    for (name, options) in completed_commands() {
+        script.push_str(&format!(
+            "complete -c apwm -n __fish_use_subcommand -a {}\n",
+            name
+        ));
+        for option in options {
+            let mut line = format!(
+                "complete -c apwm -n \"__fish_seen_subcommand_from {}\"",
+                name
+            );
+            if let Some(short) = option.short {
+                line.push_str(&format!(" -s {}", short));
+            }
+            if let Some(long) = &option.long {
+                line.push_str(&format!(" -l {}", long));
+            }
+            match &option.value {
+                None => {}
+                Some(ValueCompletion::Worlds) => line.push_str(" -x -a \"(__apwm_worlds)\""),
+                Some(ValueCompletion::Choices(choices)) => {
+                    line.push_str(&format!(" -x -a \"{}\"", choices.join(" ")))
+                }
+                Some(ValueCompletion::Files) => line.push_str(" -r -F"),
+            }
+            script.push_str(&line);
+            script.push('\n');
+        }
+    }
+
+    script
+}
+
+fn completions(shell: Shell) -> String {
+    match shell {
+        Shell::Bash => bash_completions(),
+        // zsh runs the bash script through its compatibility layer
+        Shell::Zsh => format!(
+            "#compdef apwm\n\nautoload -U +X bashcompinit && bashcompinit\n\n{}",
+            bash_completions()
+        ),
+        Shell::Fish => fish_completions(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn printer(json: bool) -> Printer {
+        Printer {
+            json,
+            quiet: false,
+            command: "list".into(),
+            data: RefCell::default(),
+        }
+    }
+
+    #[test]
+    fn wraps_results_in_a_json_document() {
+        let printer = printer(true);
+        printer
+            .emit(&["ok"], |_| panic!("Printed for humans"))
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(printer.document(None)).unwrap(),
+            serde_json::json!({"schema_version": 1, "command": "list", "data": ["ok"]})
+        );
+
+        let error = anyhow::anyhow!("No world named ok").context("Couldn't list worlds");
+        assert_eq!(
+            serde_json::to_value(printer.document(Some(&error))).unwrap(),
+            serde_json::json!({
+                "schema_version": 1,
+                "command": "list",
+                "error": "Couldn't list worlds: No world named ok"
+            })
+        );
+    }
+
+    #[test]
+    fn prints_for_humans_without_json() {
+        let mut printed = vec![];
+        printer(false)
+            .emit(&["ok"], |data| printed.extend_from_slice(data))
+            .unwrap();
+        assert_eq!(printed, ["ok"]);
+    }
+
+    #[test]
+    fn parses_the_same_flags_everywhere() {
+        Args::command().debug_assert();
+        let args = Args::try_parse_from(["apwm", "doctor", "-vv", "--json"]).unwrap();
+        assert_eq!((args.quiet, args.verbose, args.json), (false, 2, true));
+        let args = Args::try_parse_from(["apwm", "-q", "doctor"]).unwrap();
+        assert!(args.quiet);
+        assert!(Args::try_parse_from(["apwm", "doctor", "-q", "-v"]).is_err());
+    }
+
+    #[test]
+    fn exits_with_the_kind_of_failure() {
+        let exit_code = |error: anyhow::Error| failure_kind(&error).map_or(1, |kind| kind as i32);
+        assert_eq!(exit_code(anyhow::anyhow!("Something broke")), 1);
+        assert_eq!(exit_code(Failure::validation("Invalid").into()), 10);
+        assert_eq!(exit_code(Failure::network("Unreachable").into()), 11);
+        assert_eq!(exit_code(Failure::partial("Some failed").into()), 12);
+
+        let invalid = apwm::Error::IndexParse {
+            path: "index.toml".into(),
+            source: anyhow::anyhow!("Invalid"),
+        };
+        assert_eq!(
+            exit_code(anyhow::Error::from(invalid).context("Couldn't load")),
+            10
+        );
+        let download = || apwm::Error::Download {
+            world: "ok".into(),
+            url: "https://example.com/ok.apworld".into(),
+            source: anyhow::anyhow!("Unreachable"),
+        };
+        assert_eq!(exit_code(download().into()), 11);
+        // Some worlds got refreshed anyway
+        let refresh = apwm::Error::Refresh {
+            summary: Default::default(),
+            source: Box::new(download()),
+        };
+        assert_eq!(exit_code(refresh.into()), 12);
+        assert_eq!(
+            exit_code(toml::from_str::<toml::Value>("=").unwrap_err().into()),
+            10
+        );
+    }
 }
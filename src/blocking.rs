@@ -0,0 +1,85 @@
+use std::{
+    future::Future,
+    path::Path,
+    pin::pin,
+    sync::Arc,
+    task::{Context, Poll, Wake, Waker},
+    thread::Thread,
+};
+
+use crate::{resolver::Downloader, Index, MirrorReport, RefreshSummary, Result};
+
+// Wakes up the thread blocked in `block_on`
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+// Runs `future` to completion on the calling thread. Downloads go through a
+// blocking client so nothing here needs an async runtime, resolvers
+// registered with `Index::register_resolver` still have to bring their own if
+// they rely on one.
+fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+            return output;
+        }
+        std::thread::park();
+    }
+}
+
+fn blocking_downloader(mut downloader: Downloader) -> Result<Downloader> {
+    downloader.set_blocking_client(reqwest::blocking::Client::builder().build()?);
+    Ok(downloader)
+}
+
+// Downloads the apworld at `url` to `destination` and checks it's a valid
+// apworld. `world` is only used in errors.
+pub fn download_apworld_blocking(world: &str, url: &str, destination: &Path) -> Result<()> {
+    let downloader = blocking_downloader(Default::default())?;
+    block_on(crate::download_apworld(
+        &downloader,
+        world,
+        url,
+        destination,
+    ))
+}
+
+// Synchronous versions of the async methods of `Index`, for callers without
+// an async runtime. The client given to `Index::set_http_client` isn't used,
+// downloads go through a default blocking one.
+impl Index {
+    // A copy of the index downloading on the calling thread, sharing its
+    // caches with this one
+    fn blocking(&self) -> Result<Index> {
+        let mut index = self.clone();
+        index.downloader = blocking_downloader(index.downloader)?;
+        Ok(index)
+    }
+
+    pub fn refresh_into_blocking(&self, destination: &Path) -> Result<RefreshSummary> {
+        let index = self.blocking()?;
+        block_on(index.refresh_into(destination))
+    }
+
+    pub fn lock_blocking(&self, lockfile: &Path) -> Result<()> {
+        let index = self.blocking()?;
+        block_on(index.lock(lockfile))
+    }
+
+    pub fn sync_blocking(&self, lockfile: &Path, destination: &Path) -> Result<()> {
+        let index = self.blocking()?;
+        block_on(index.sync(lockfile, destination))
+    }
+
+    pub fn mirror_blocking(&self, mirror: &Path) -> Result<MirrorReport> {
+        let index = self.blocking()?;
+        block_on(index.mirror(mirror))
+    }
+}
@@ -0,0 +1,283 @@
+use http::Uri;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Common, Error, Index, LicensePolicy, Patch, Result, SyntaxPolicy, Transform,
+    VendoredLibraryDefinition, World, WorldOrigin,
+};
+
+const ARCHIPELAGO_REPO: &str = "https://github.com/ArchipelagoMW/Archipelago.git";
+
+// Builds an index in code rather than from an index file. Local worlds,
+// patches and other files of the index are looked for in `dir`, like they
+// would be next to an index file. Methods editing the index file, like
+// `add_world`, need one to exist there.
+#[derive(Debug)]
+pub struct IndexBuilder {
+    dir: PathBuf,
+    common: Common,
+    patch_sets: BTreeMap<String, Vec<Patch>>,
+    vendored: BTreeMap<String, VendoredLibraryDefinition>,
+    worlds: BTreeMap<String, World>,
+}
+
+impl IndexBuilder {
+    pub fn new(dir: &Path, archipelago_version: &str) -> Self {
+        Self {
+            dir: dir.into(),
+            common: Common {
+                archipelago_repo: Uri::from_static(ARCHIPELAGO_REPO),
+                archipelago_version: archipelago_version.into(),
+                homepage: String::new(),
+                required_global_files: vec![],
+                strip_junk: false,
+                fix_layout: false,
+                license_policy: LicensePolicy::default(),
+                syntax_policy: SyntaxPolicy::default(),
+                templates_dir: None,
+                diff_ignore: vec![],
+            },
+            patch_sets: BTreeMap::new(),
+            vendored: BTreeMap::new(),
+            worlds: BTreeMap::new(),
+        }
+    }
+
+    pub fn archipelago_repo(mut self, repo: Uri) -> Self {
+        self.common.archipelago_repo = repo;
+        self
+    }
+
+    pub fn homepage(mut self, homepage: &str) -> Self {
+        self.common.homepage = homepage.into();
+        self
+    }
+
+    pub fn required_global_file(mut self, path: &str) -> Self {
+        self.common.required_global_files.push(path.into());
+        self
+    }
+
+    pub fn strip_junk(mut self, strip_junk: bool) -> Self {
+        self.common.strip_junk = strip_junk;
+        self
+    }
+
+    pub fn fix_layout(mut self, fix_layout: bool) -> Self {
+        self.common.fix_layout = fix_layout;
+        self
+    }
+
+    pub fn license_policy(mut self, policy: LicensePolicy) -> Self {
+        self.common.license_policy = policy;
+        self
+    }
+
+    pub fn syntax_policy(mut self, policy: SyntaxPolicy) -> Self {
+        self.common.syntax_policy = policy;
+        self
+    }
+
+    pub fn templates_dir(mut self, dir: &Path) -> Self {
+        self.common.templates_dir = Some(dir.into());
+        self
+    }
+
+    pub fn diff_ignore(mut self, pattern: &str) -> Self {
+        self.common.diff_ignore.push(pattern.into());
+        self
+    }
+
+    pub fn patch_set(mut self, name: &str, patches: Vec<Patch>) -> Self {
+        self.patch_sets.insert(name.into(), patches);
+        self
+    }
+
+    pub fn vendored(mut self, name: &str, library: VendoredLibraryDefinition) -> Self {
+        self.vendored.insert(name.into(), library);
+        self
+    }
+
+    pub fn world(mut self, key: &str, world: WorldBuilder) -> Self {
+        self.worlds.insert(key.into(), world.world);
+        self
+    }
+
+    // Checks the index the same way loading an index file would, including
+    // that the files it refers to stay inside `dir`. An invalid index is an
+    // `Error::IndexParse`, like an invalid index file.
+    pub fn build(self) -> Result<Index> {
+        let path = self.dir.join("index.toml");
+        let invalid = |message: &str| Error::IndexParse {
+            path: path.clone(),
+            source: anyhow::anyhow!("{}", message),
+        };
+        if self.common.archipelago_version.is_empty() {
+            return Err(invalid("The archipelago version can't be empty"));
+        }
+        if self
+            .worlds
+            .iter()
+            .any(|(key, world)| key.is_empty() || world.name.is_empty())
+        {
+            return Err(invalid("Worlds need a key and a name"));
+        }
+
+        let mut index = Index {
+            path: path.clone(),
+            common: self.common,
+            patch_sets: self.patch_sets,
+            vendored: self.vendored,
+            worlds: self.worlds,
+            downloader: Default::default(),
+            fs: crate::default_fs(),
+            clock: crate::default_clock(),
+            resolutions: Default::default(),
+            dirs: Default::default(),
+        };
+        index.resolve_worlds().map_err(|source| Error::IndexParse {
+            path,
+            source: source.into(),
+        })?;
+
+        Ok(index)
+    }
+}
+
+// A world to add to an `IndexBuilder`
+#[derive(Debug)]
+pub struct WorldBuilder {
+    world: World,
+}
+
+impl WorldBuilder {
+    pub fn new(name: &str, origin: WorldOrigin) -> Self {
+        Self {
+            world: World {
+                name: name.into(),
+                origin,
+                version: None,
+                patches: vec![],
+                patch_sets: vec![],
+                transforms: vec![],
+                home: None,
+                dependencies: vec![],
+                tags: vec![],
+                description: None,
+                license_file: None,
+                upstream: None,
+                info: Default::default(),
+                inherited_patches: 0,
+                url_template: None,
+            },
+        }
+    }
+
+    pub fn version(mut self, version: &str) -> Self {
+        self.world.version = Some(version.into());
+        self
+    }
+
+    pub fn patch(mut self, patch: Patch) -> Self {
+        self.world.patches.push(patch);
+        self
+    }
+
+    pub fn patch_set(mut self, name: &str) -> Self {
+        self.world.patch_sets.push(name.into());
+        self
+    }
+
+    pub fn transform(mut self, transform: Transform) -> Self {
+        self.world.transforms.push(transform);
+        self
+    }
+
+    pub fn home(mut self, home: &str) -> Self {
+        self.world.home = Some(home.into()).filter(|home: &String| !home.is_empty());
+        self
+    }
+
+    pub fn dependency(mut self, dependency: &str) -> Self {
+        self.world.dependencies.push(dependency.into());
+        self
+    }
+
+    pub fn tag(mut self, tag: &str) -> Self {
+        self.world.tags.push(tag.into());
+        self
+    }
+
+    pub fn description(mut self, description: &str) -> Self {
+        self.world.description = Some(description.into());
+        self
+    }
+
+    pub fn license_file(mut self, path: &Path) -> Self {
+        self.world.license_file = Some(path.into());
+        self
+    }
+
+    pub fn upstream(mut self, upstream: Uri) -> Self {
+        self.world.upstream = Some(upstream);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn builder() -> IndexBuilder {
+        IndexBuilder::new(Path::new("/srv/index"), "0.5.0")
+            .homepage("https://example.com")
+            .patch_set(
+                "common",
+                vec![Patch {
+                    path: "patches/common.patch".into(),
+                    before: None,
+                }],
+            )
+    }
+
+    #[test]
+    fn builds_what_an_index_file_would_load() {
+        let world = WorldBuilder::new("Ok Game", WorldOrigin::Local("worlds/ok".into()))
+            .version("1.0")
+            .patch_set("common")
+            .tag("puzzle")
+            .home("");
+        let index = builder().world("ok", world).build().unwrap();
+        assert_eq!(index.path, Path::new("/srv/index/index.toml"));
+        let ok = &index.worlds["ok"];
+        assert_eq!(ok.version.as_deref(), Some("1.0"));
+        assert_eq!(ok.home, None);
+        // Resolved like an index file would be
+        assert_eq!(ok.patches.len(), 1);
+
+        let loaded = Index::from_str(&index.to_toml().unwrap(), Path::new("/srv/index")).unwrap();
+        assert_eq!(loaded.to_toml().unwrap(), index.to_toml().unwrap());
+    }
+
+    #[test]
+    fn rejects_what_an_index_file_couldnt_have() {
+        let local = |path: &str| WorldBuilder::new("Ok Game", WorldOrigin::Local(path.into()));
+        for builder in [
+            IndexBuilder::new(Path::new("/srv/index"), ""),
+            builder().world("", local("worlds/ok")),
+            builder().world("ok", WorldBuilder::new("", WorldOrigin::Local("ok".into()))),
+            builder().world("ok", local("../ok")),
+            builder().world("ok", local("worlds/ok").patch_set("missing")),
+        ] {
+            let error = builder.build().unwrap_err();
+            assert!(
+                matches!(error, Error::IndexParse { ref path, .. } if path == Path::new("/srv/index/index.toml")),
+                "{:?}",
+                error
+            );
+        }
+    }
+}
@@ -0,0 +1,174 @@
+#[cfg(feature = "refresh")]
+use std::{future::Future, pin::pin};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+};
+
+#[cfg(feature = "refresh")]
+use crate::{Error, Result};
+
+// Aborts the operations it's given to, like `Index::refresh_into_with`.
+// Clones share the same state, so one can be kept to cancel from another task.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    // The tasks waiting for a cancellation, woken up by `cancel`
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *self.inner.wakers.lock().unwrap());
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Resolves once `cancel` has been called
+    pub async fn cancelled(&self) {
+        std::future::poll_fn(|cx| self.poll_cancelled(cx)).await
+    }
+
+    fn poll_cancelled(&self, cx: &mut Context) -> Poll<()> {
+        if self.is_cancelled() {
+            return Poll::Ready(());
+        }
+        let mut wakers = self.inner.wakers.lock().unwrap();
+        // Checked again with the lock held so a cancellation in between isn't
+        // missed
+        if self.is_cancelled() {
+            return Poll::Ready(());
+        }
+        if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+            wakers.push(cx.waker().clone());
+        }
+        Poll::Pending
+    }
+
+    #[cfg(feature = "refresh")]
+    pub(crate) fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled);
+        }
+        Ok(())
+    }
+
+    // Runs `future` until it completes or the token gets cancelled, in which
+    // case `future` is dropped
+    #[cfg(feature = "refresh")]
+    pub(crate) async fn run<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        let mut future = pin!(future);
+        std::future::poll_fn(|cx| {
+            if let Poll::Ready(result) = future.as_mut().poll(cx) {
+                return Poll::Ready(result);
+            }
+            self.poll_cancelled(cx).map(|()| Err(Error::Cancelled))
+        })
+        .await
+    }
+}
+
+#[cfg(all(test, feature = "refresh"))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    // Counts how many times it got dropped
+    struct Dropped(Arc<AtomicUsize>);
+
+    impl Drop for Dropped {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[tokio::test]
+    async fn wakes_up_tasks_waiting_for_a_cancellation() {
+        let token = CancellationToken::new();
+        let waiting = tokio::spawn({
+            let token = token.clone();
+            async move { token.cancelled().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiting.is_finished());
+        token.cancel();
+        waiting.await.unwrap();
+        assert!(token.is_cancelled());
+        assert!(matches!(token.check(), Err(Error::Cancelled)));
+    }
+
+    #[tokio::test]
+    async fn drops_what_it_runs_when_cancelled() {
+        let token = CancellationToken::new();
+        assert_eq!(token.run(async { Ok(1) }).await.unwrap(), 1);
+
+        let drops = Arc::new(AtomicUsize::new(0));
+        let running = {
+            let dropped = Dropped(drops.clone());
+            token.run(async move {
+                let _dropped = dropped;
+                std::future::pending::<Result<()>>().await
+            })
+        };
+        let cancelling = async {
+            tokio::task::yield_now().await;
+            assert_eq!(drops.load(Ordering::SeqCst), 0);
+            token.cancel();
+        };
+        let (result, ()) = tokio::join!(running, cancelling);
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(drops.load(Ordering::SeqCst), 1);
+    }
+
+    #[cfg(feature = "git")]
+    #[tokio::test]
+    async fn leaves_the_destination_alone_when_cancelled() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index_file = tmp.path().join("index.toml");
+        std::fs::write(
+            &index_file,
+            format!(
+                "[common]\narchipelago_repo = \"file://localhost{}\"\n\
+                 archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+                 required_global_files = []\n\n[worlds]\n",
+                tmp.path().join("archipelago").to_string_lossy()
+            ),
+        )
+        .unwrap();
+        let mut index = crate::Index::new(&index_file).unwrap();
+        index.set_temp_dir(&tmp.path().join("tmp"));
+        let destination = tmp.path().join("worlds");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("old.txt"), "old").unwrap();
+
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = index.refresh_into_with(&destination, &token).await;
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(std::fs::read(destination.join("old.txt")).unwrap(), b"old");
+        let mut left = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["index.toml", "tmp", "worlds"]);
+    }
+}
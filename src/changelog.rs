@@ -0,0 +1,97 @@
+use crate::Result;
+use regex::Regex;
+use std::path::Path;
+
+use crate::{
+    apworld,
+    version::{self, VersionBump},
+};
+
+// Files worlds keep their release notes in, by order of preference. They're
+// only looked for at the root of the package.
+const CANDIDATES: &[&str] = &["changelog", "changes", "history", "news", "readme"];
+
+fn stem(name: &str) -> String {
+    name.split('.').next().unwrap_or(name).to_lowercase()
+}
+
+struct Headings {
+    version: Regex,
+    plain: Regex,
+}
+
+impl Headings {
+    fn new() -> Self {
+        Self {
+            version: Regex::new(r"\bv?(\d+(?:\.\d+)+(?:-?[0-9A-Za-z]+(?:\.[0-9A-Za-z]+)*)?)")
+                .unwrap(),
+            plain: Regex::new(r"(?i)^\s*(?:\[|version\s+)?v?\d+(?:\.\d+)+\b").unwrap(),
+        }
+    }
+
+    // The version a line announces if it's a heading: a markdown heading, a
+    // line underlined with `=` or `-`, or a line starting with a version
+    fn version(&self, line: &str, next: Option<&str>) -> Option<String> {
+        let is_underlined = next.is_some_and(|next| {
+            let next = next.trim();
+            next.len() >= 3 && (next.chars().all(|c| c == '=') || next.chars().all(|c| c == '-'))
+        });
+        if !line.trim_start().starts_with('#') && !is_underlined && !self.plain.is_match(line) {
+            return None;
+        }
+
+        Some(self.version.captures(line)?[1].to_string())
+    }
+}
+
+// Extracts the release notes between `old_version` and `new_version` from a
+// changelog: everything from the heading of `new_version` up to the heading
+// of `old_version` or of any older version
+pub fn section(content: &str, old_version: &str, new_version: &str) -> Option<String> {
+    let lines = content.lines().collect::<Vec<_>>();
+    let matcher = Headings::new();
+    let headings = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| Some((i, matcher.version(line, lines.get(i + 1).copied())?)))
+        .collect::<Vec<_>>();
+
+    let start = headings.iter().position(|(_, version)| {
+        version::classify(new_version, version) == VersionBump::Equivalent
+    })?;
+    let end = headings[start + 1..]
+        .iter()
+        .find(|(_, version)| {
+            matches!(
+                version::classify(old_version, version),
+                VersionBump::Equivalent | VersionBump::Downgrade
+            )
+        })
+        .map_or(lines.len(), |(i, _)| *i);
+
+    let section = lines[headings[start].0..end].join("\n");
+    let section = section.trim_end();
+    (!section.is_empty()).then(|| section.to_string())
+}
+
+// Looks for a changelog in a world's package directory and extracts the notes
+// for the versions between `old_version` and `new_version`
+pub fn extract(package_dir: &Path, old_version: &str, new_version: &str) -> Result<Option<String>> {
+    let files = apworld::package_files(package_dir, |name| {
+        !name.contains('/') && CANDIDATES.contains(&stem(name).as_str())
+    })?;
+
+    for candidate in CANDIDATES {
+        for (name, content) in &files {
+            if stem(name) != *candidate {
+                continue;
+            }
+            let content = String::from_utf8_lossy(content);
+            if let Some(section) = section(&content, old_version, new_version) {
+                return Ok(Some(section));
+            }
+        }
+    }
+
+    Ok(None)
+}
@@ -0,0 +1,82 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::{Result, Vfs};
+
+// Where freshness checks get the current time and the modification time of
+// files from, set with `Index::set_clock`. Refreshes stamp `.last_refresh`
+// with `now` and filesystems like `MemoryFs` stamp what they write with it,
+// so every time compared comes from the same clock. Tests use a
+// `ManualClock` to decide what's stale without touching files or waiting.
+pub trait Clock: Send + Sync + std::fmt::Debug {
+    fn now(&self) -> SystemTime;
+
+    // When `path` of `fs` was last modified
+    fn modified(&self, path: &Path, fs: &dyn Vfs) -> Result<SystemTime> {
+        fs.modified(path)
+    }
+}
+
+// The clock of the machine, used unless another one is set
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+// A clock that only moves when told to. Files it wasn't given a modification
+// time for are looked up on their filesystem.
+#[derive(Debug)]
+pub struct ManualClock {
+    now: Mutex<SystemTime>,
+    modified: Mutex<BTreeMap<PathBuf, SystemTime>>,
+}
+
+impl ManualClock {
+    pub fn new(now: SystemTime) -> Self {
+        Self {
+            now: Mutex::new(now),
+            modified: Mutex::default(),
+        }
+    }
+
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock().unwrap() = now;
+    }
+
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+
+    // Makes `path` look modified at `time`, like the index file to force or
+    // skip a refresh
+    pub fn set_modified(&self, path: impl Into<PathBuf>, time: SystemTime) {
+        self.modified.lock().unwrap().insert(path.into(), time);
+    }
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock().unwrap()
+    }
+
+    fn modified(&self, path: &Path, fs: &dyn Vfs) -> Result<SystemTime> {
+        match self.modified.lock().unwrap().get(path) {
+            Some(time) => Ok(*time),
+            None => fs.modified(path),
+        }
+    }
+}
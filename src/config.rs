@@ -0,0 +1,141 @@
+use anyhow::{anyhow, Context};
+
+use crate::{bail, Error, Result};
+use serde::Deserialize;
+use std::path::{Path, PathBuf};
+
+// Name of the project configuration, looked for in the current directory and
+// its parents
+pub const PROJECT_CONFIG: &str = "apwm.toml";
+
+// Defaults for the CLI, from `~/.config/apwm/config.toml` and from the
+// closest `apwm.toml`, which takes precedence. Relative paths are relative to
+// the file they're in.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    // The index directory used when a command isn't given one
+    pub index: Option<PathBuf>,
+    // The destination used when a command isn't given one
+    pub destination: Option<PathBuf>,
+    // Where temporary files like archipelago checkouts go, instead of the
+    // system's temporary directory
+    pub temp_dir: Option<PathBuf>,
+    #[serde(default)]
+    pub credentials: Credentials,
+    #[serde(default)]
+    pub notifications: Notifications,
+}
+
+// Secrets are never written in the configuration, only where to find them,
+// see `resolve_secret`
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Credentials {
+    // Used for github API requests, like finding the latest release of a
+    // world, which are heavily rate limited without it
+    pub github_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Notifications {
+    // Webhooks diffs get posted to with `--notify`
+    #[serde(default)]
+    pub discord_webhooks: Vec<String>,
+}
+
+// Reads a secret from where a reference points: `env:NAME` for an
+// environment variable, `file:path` for the trimmed content of a file
+pub fn resolve_secret(reference: &str) -> Result<String> {
+    if let Some(name) = reference.strip_prefix("env:") {
+        return Ok(std::env::var(name).with_context(|| format!("{} isn't set", name))?);
+    }
+    if let Some(path) = reference.strip_prefix("file:") {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Couldn't read the secret in {}", path))?;
+        return Ok(content.trim().to_string());
+    }
+
+    bail!(
+        "Unknown secret reference {}, use `env:NAME` or `file:path`",
+        reference
+    )
+}
+
+fn global_config_path() -> Option<PathBuf> {
+    if let Some(path) = std::env::var_os("APWM_CONFIG") {
+        return Some(path.into());
+    }
+    let config_dir = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+
+    Some(config_dir.join("apwm").join("config.toml"))
+}
+
+fn project_config_path(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|ancestor| ancestor.join(PROJECT_CONFIG))
+        .find(|path| path.is_file())
+}
+
+impl Config {
+    pub fn load_from(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        let mut config: Config = toml::from_str(&content)
+            .with_context(|| format!("Invalid configuration in {}", path.to_string_lossy()))?;
+
+        let base = path
+            .parent()
+            .ok_or_else(|| anyhow!("Config file doesn't have a parent dir"))?;
+        for dir in [
+            &mut config.index,
+            &mut config.destination,
+            &mut config.temp_dir,
+        ]
+        .into_iter()
+        .flatten()
+        {
+            *dir = base.join(&*dir);
+        }
+
+        Ok(config)
+    }
+
+    // Loads the global configuration, `$APWM_CONFIG` if set, and the project
+    // one found from `dir`. Missing files are fine.
+    pub fn load(dir: &Path) -> Result<Self> {
+        let global = match global_config_path() {
+            Some(path) if path.is_file() => Self::load_from(&path)?,
+            _ => Self::default(),
+        };
+        let project = match project_config_path(dir) {
+            Some(path) => Self::load_from(&path)?,
+            None => Self::default(),
+        };
+
+        Ok(global.merge(project))
+    }
+
+    // Values of `other` win over the ones of `self`
+    fn merge(self, other: Self) -> Self {
+        Self {
+            index: other.index.or(self.index),
+            destination: other.destination.or(self.destination),
+            temp_dir: other.temp_dir.or(self.temp_dir),
+            credentials: Credentials {
+                github_token: other
+                    .credentials
+                    .github_token
+                    .or(self.credentials.github_token),
+            },
+            notifications: if other.notifications.discord_webhooks.is_empty() {
+                self.notifications
+            } else {
+                other.notifications
+            },
+        }
+    }
+}
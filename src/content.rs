@@ -0,0 +1,67 @@
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{bail, sandbox, DiffOptions, Index, IndexDiff, Result, World};
+
+// Where errors say the index comes from, there's no file
+const INDEX_PATH: &str = "index.toml";
+
+// Files of the index the worlds refer to have to stay inside its directory
+pub(crate) fn check_contained(worlds: &BTreeMap<String, World>) -> Result<()> {
+    for (key, world) in worlds {
+        if let Some(path) = world
+            .index_files()
+            .into_iter()
+            .find(|path| !sandbox::is_contained(path))
+        {
+            bail!(
+                "{} refers to {}, which isn't inside the index directory",
+                key,
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Whether `content` is a valid index file, as far as can be told without the
+// files it refers to. Like `diff_indexes`, it needs no filesystem, git or
+// network, for the index website to run it in the browser.
+pub fn validate_index(content: &str) -> Result<()> {
+    Index::parse(content, Path::new(INDEX_PATH)).map(|_| ())
+}
+
+// Compares the worlds of two index files, like `Index::diff_metadata`
+pub fn diff_indexes(old: &str, new: &str, options: &DiffOptions) -> Result<IndexDiff> {
+    let old = Index::parse(old, Path::new(INDEX_PATH))?;
+    let new = Index::parse(new, Path::new(INDEX_PATH))?;
+    Ok(old.diff_metadata(&new, options))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rejects_files_outside_of_the_index() {
+        let index = |world: &str| {
+            format!(
+                "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+                 archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+                 required_global_files = []\n\n[worlds.escaping]\nname = \"Escaping\"\n{}\n",
+                world
+            )
+        };
+        validate_index(&index("local = \"worlds/escaping\"")).unwrap();
+        for world in [
+            "local = \"../escaping\"",
+            "local = \"/srv/escaping\"",
+            "local = \"worlds/escaping\"\npatches = [\"../escaping.patch\"]",
+            "local = \"worlds/escaping\"\nlicense_file = \"/etc/passwd\"",
+        ] {
+            let error = validate_index(&index(world)).unwrap_err();
+            assert!(format!("{:?}", error).contains("isn't inside"), "{}", world);
+            assert!(Index::from_str(&index(world), Path::new("/srv/index")).is_err());
+        }
+    }
+}
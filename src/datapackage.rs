@@ -0,0 +1,163 @@
+use rustpython_parser::{
+    ast::{self, Expr, Stmt},
+    parse, Mode,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{apworld, junk, options::expr_value, Result};
+
+// Item and location names of a world, with their id when it could be found
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DataPackage {
+    pub items: BTreeMap<String, Option<i64>>,
+    pub locations: BTreeMap<String, Option<i64>>,
+}
+
+enum Table {
+    Items,
+    Locations,
+}
+
+fn table_kind(name: &str) -> Option<Table> {
+    let name = name.to_lowercase();
+    // Groups map group names to item names, not items themselves
+    if name.contains("group") {
+        return None;
+    }
+
+    if name.contains("location") {
+        Some(Table::Locations)
+    } else if name.contains("item") {
+        Some(Table::Items)
+    } else {
+        None
+    }
+}
+
+// The id of an entry, either the value itself or an `id`/`code` field
+fn entry_id(value: &Value) -> Option<i64> {
+    match value {
+        Value::Number(n) => n.as_i64(),
+        Value::Object(fields) => ["id", "code"]
+            .iter()
+            .find_map(|key| fields.get(*key)?.as_i64()),
+        _ => None,
+    }
+}
+
+fn python_entries(value: &Expr) -> Vec<(String, Option<i64>)> {
+    let no_attrs = BTreeMap::new();
+    match value {
+        Expr::Dict(dict) => dict
+            .keys
+            .iter()
+            .zip(dict.values.iter())
+            .filter_map(|(key, value)| {
+                let Some(Value::String(name)) = expr_value(key.as_ref()?, &no_attrs) else {
+                    return None;
+                };
+                Some((
+                    name,
+                    expr_value(value, &no_attrs).as_ref().and_then(entry_id),
+                ))
+            })
+            .collect(),
+        Expr::List(ast::ExprList { elts, .. })
+        | Expr::Tuple(ast::ExprTuple { elts, .. })
+        | Expr::Set(ast::ExprSet { elts, .. }) => elts
+            .iter()
+            .filter_map(|elt| match expr_value(elt, &no_attrs)? {
+                Value::String(name) => Some((name, None)),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// Module level dict and list literals named after items or locations
+fn python_tables(source: &str, file: &str, package: &mut DataPackage) {
+    let Ok(ast::Mod::Module(module)) = parse(source, Mode::Module, file) else {
+        return;
+    };
+
+    for stmt in module.body {
+        let (targets, value) = match &stmt {
+            Stmt::Assign(assign) => (assign.targets.iter().collect::<Vec<_>>(), &*assign.value),
+            Stmt::AnnAssign(ast::StmtAnnAssign {
+                target,
+                value: Some(value),
+                ..
+            }) => (vec![target.as_ref()], value.as_ref()),
+            _ => continue,
+        };
+
+        for target in targets {
+            let Expr::Name(name) = target else {
+                continue;
+            };
+            let table = match table_kind(&name.id) {
+                Some(Table::Items) => &mut package.items,
+                Some(Table::Locations) => &mut package.locations,
+                None => continue,
+            };
+            table.extend(python_entries(value));
+        }
+    }
+}
+
+fn json_entries(value: &Value) -> Vec<(String, Option<i64>)> {
+    match value {
+        Value::Object(entries) => entries
+            .iter()
+            .map(|(name, value)| (name.clone(), entry_id(value)))
+            .collect(),
+        Value::Array(entries) => entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Value::String(name) => Some((name.clone(), None)),
+                Value::Object(fields) => {
+                    Some((fields.get("name")?.as_str()?.to_string(), entry_id(entry)))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    }
+}
+
+// JSON data files named after items or locations, like the ones of manual
+// worlds
+fn json_tables(content: &[u8], file: &str, package: &mut DataPackage) {
+    let stem = file.rsplit('/').next().unwrap_or(file);
+    let table = match table_kind(stem) {
+        Some(Table::Items) => &mut package.items,
+        Some(Table::Locations) => &mut package.locations,
+        None => return,
+    };
+
+    if let Ok(value) = serde_json::from_slice::<Value>(content) {
+        table.extend(json_entries(&value));
+    }
+}
+
+// Extracts the item and location names of a world from its data files and
+// python tables. This is a best effort: tables built at runtime are missed.
+pub fn extract_data_package(path: &Path) -> Result<DataPackage> {
+    let files = apworld::package_files(path, |name| {
+        (name.ends_with(".py") || name.ends_with(".json")) && !junk::is_junk(name)
+    })?;
+
+    let mut package = DataPackage::default();
+    for (file, content) in &files {
+        if file.ends_with(".json") {
+            json_tables(content, file, &mut package);
+        } else {
+            python_tables(&String::from_utf8_lossy(content), file, &mut package);
+        }
+    }
+
+    Ok(package)
+}
@@ -0,0 +1,38 @@
+// Splits seconds since the unix epoch into a UTC year, month and day, with
+// Howard Hinnant's days to civil date conversion
+fn civil_date(timestamp: u64) -> (i64, i64, i64) {
+    let days = (timestamp / 86400) as i64;
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+// Formats seconds since the unix epoch as a UTC date
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_date(timestamp);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+// Formats seconds since the unix epoch as a UTC date and time
+pub fn format_timestamp(timestamp: u64) -> String {
+    let seconds = timestamp % 86400;
+    format!(
+        "{} {:02}:{:02}:{:02} UTC",
+        format_date(timestamp),
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,39 @@
+use serde::Serialize;
+
+use crate::{AppliedPatch, WorldOrigin};
+
+#[derive(Serialize, Debug)]
+pub struct PatchDetails {
+    pub path: String,
+    pub before: Option<String>,
+    // Whether the patch applies to the world's current version
+    pub active: bool,
+}
+
+// What a refreshed destination contains for a world
+#[derive(Serialize, Debug)]
+pub struct DeployedWorld {
+    pub path: String,
+    pub version: Option<String>,
+    pub sha256: Option<String>,
+    pub patches: Vec<AppliedPatch>,
+    // When the world was last written, in seconds since the unix epoch
+    pub last_updated: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct WorldDetails {
+    pub key: String,
+    pub name: String,
+    pub version: Option<String>,
+    pub origin: WorldOrigin,
+    // Where the world actually gets fetched from
+    pub resolved: String,
+    pub home: Option<String>,
+    pub upstream: Option<String>,
+    pub tags: Vec<String>,
+    pub dependencies: Vec<String>,
+    pub patches: Vec<PatchDetails>,
+    pub license_file: Option<String>,
+    pub deployed: Option<DeployedWorld>,
+}
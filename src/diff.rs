@@ -0,0 +1,1013 @@
+use crate::{Error, Result};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use similar::{ChangeTag, TextDiff};
+#[cfg(feature = "refresh")]
+use std::path::PathBuf;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    path::Path,
+};
+
+#[cfg(feature = "refresh")]
+use crate::{apworld, changelog, manifest, size, WorkDirs};
+use crate::{
+    glob, junk,
+    manifest::{AppliedPatch, Manifest},
+    patch,
+    version::{self, VersionBump},
+    WorldOrigin,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FileChangeKind {
+    Added,
+    Removed,
+    Modified,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileChange {
+    pub path: String,
+    pub kind: FileChangeKind,
+    // Unified diff of the file, `None` for binary files and when only stats
+    // were asked for
+    pub diff: Option<String>,
+    // Changed lines, both 0 for binary files
+    pub insertions: usize,
+    pub deletions: usize,
+    // Sizes and hashes of binary files, `None` for text files
+    pub binary: Option<BinarySummary>,
+}
+
+// How a binary file changed. Sizes and hashes are `None` on the side the file
+// doesn't exist.
+#[derive(Serialize, Debug, Clone)]
+pub struct BinarySummary {
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub old_sha256: Option<String>,
+    pub new_sha256: Option<String>,
+}
+
+impl BinarySummary {
+    fn new(old: Option<&[u8]>, new: Option<&[u8]>) -> Self {
+        let sha256 = |content: &[u8]| format!("{:x}", Sha256::digest(content));
+        Self {
+            old_size: old.map(|content| content.len() as u64),
+            new_size: new.map(|content| content.len() as u64),
+            old_sha256: old.map(sha256),
+            new_sha256: new.map(sha256),
+        }
+    }
+}
+
+impl std::fmt::Display for BinarySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.old_size, self.new_size) {
+            (Some(old), Some(new)) => write!(f, "{} -> {}", human_size(old), human_size(new))?,
+            (None, Some(new)) => write!(f, "added, {}", human_size(new))?,
+            (Some(old), None) => write!(f, "removed, was {}", human_size(old))?,
+            (None, None) => {}
+        }
+        if let (Some(old), Some(new)) = (&self.old_sha256, &self.new_sha256) {
+            write!(f, ", sha256 {:.12} -> {:.12}", old, new)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum WorldChange {
+    Added {
+        version: String,
+        origin: WorldOrigin,
+    },
+    Removed {
+        version: String,
+        origin: WorldOrigin,
+    },
+    VersionChanged {
+        old: String,
+        new: String,
+        bump: VersionBump,
+    },
+    OriginChanged {
+        old: WorldOrigin,
+        new: WorldOrigin,
+    },
+    // The patches applied to the world, with patch sets expanded
+    PatchesChanged {
+        old: Vec<String>,
+        new: Vec<String>,
+    },
+    // The version, origin and patches didn't change but the deployed world
+    // did, like when an author re-uploads a release
+    Reuploaded {
+        version: String,
+        old_sha256: String,
+        new_sha256: String,
+    },
+    // The deployed files of the world differ
+    Updated {
+        files: Vec<FileChange>,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VersionChange {
+    pub old: String,
+    pub new: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WorldDiff {
+    // The display name and homepage of the world, from the new index unless
+    // the world got removed
+    pub name: String,
+    pub home: Option<String>,
+    // Deployed size of the world, when it had to be downloaded
+    pub old_size: Option<u64>,
+    pub new_size: Option<u64>,
+    pub changes: Vec<WorldChange>,
+    // Release notes from the world's changelog for the versions in between,
+    // when its version changed and it ships one
+    pub changelog: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct DiffStats {
+    pub files_changed: usize,
+    pub insertions: usize,
+    pub deletions: usize,
+    // Growth of the deployed world in bytes, when both sizes are known
+    pub size_delta: Option<i64>,
+}
+
+impl std::fmt::Display for DiffStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} files changed, {} insertions(+), {} deletions(-)",
+            self.files_changed, self.insertions, self.deletions
+        )?;
+        if let Some(delta) = self.size_delta {
+            let sign = if delta < 0 { "-" } else { "+" };
+            write!(f, ", {}{}", sign, human_size(delta.unsigned_abs()))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl WorldDiff {
+    pub fn stats(&self) -> DiffStats {
+        let files = self
+            .changes
+            .iter()
+            .flat_map(|change| match change {
+                WorldChange::Updated { files } => files.as_slice(),
+                _ => &[],
+            })
+            .collect::<Vec<_>>();
+
+        DiffStats {
+            files_changed: files.len(),
+            insertions: files.iter().map(|file| file.insertions).sum(),
+            deletions: files.iter().map(|file| file.deletions).sum(),
+            size_delta: match (self.old_size, self.new_size) {
+                (Some(old), Some(new)) => Some(new as i64 - old as i64),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiffOptions {
+    // Glob patterns of files left out of content diffs, on top of junk files
+    // and of the ones the indexes ignore
+    pub ignore: Vec<String>,
+    // Names or glob patterns of the worlds to compare, all of them if empty
+    pub worlds: Vec<String>,
+    // Only count changed lines instead of keeping the content diffs around,
+    // for worlds too big to hold all of their diffs in memory
+    pub stat_only: bool,
+    // Unchanged lines shown around every change of the content diffs
+    pub context_lines: usize,
+}
+
+impl Default for DiffOptions {
+    fn default() -> Self {
+        Self {
+            ignore: vec![],
+            worlds: vec![],
+            stat_only: false,
+            context_lines: 3,
+        }
+    }
+}
+
+impl DiffOptions {
+    pub fn includes(&self, world: &str) -> bool {
+        self.worlds.is_empty()
+            || self
+                .worlds
+                .iter()
+                .any(|pattern| glob::matches(pattern, world))
+    }
+}
+
+// The most notable kind of change in a diff, from the least to the most likely
+// to need a manual review
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOutcome {
+    NoChanges,
+    Additions,
+    Updates,
+    Removals,
+    Downgrades,
+}
+
+// What changed between two indexes, world by world
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct IndexDiff {
+    pub archipelago_version: Option<VersionChange>,
+    pub worlds: BTreeMap<String, WorldDiff>,
+}
+
+impl IndexDiff {
+    pub fn is_empty(&self) -> bool {
+        self.archipelago_version.is_none() && self.worlds.is_empty()
+    }
+
+    // Worlds the new side added, in key order like every list of worlds
+    pub fn added(&self) -> impl Iterator<Item = (&str, &WorldDiff)> {
+        self.worlds_where(|change| matches!(change, Some(WorldChange::Added { .. })))
+    }
+
+    // Worlds both sides have that changed, in key order
+    pub fn updated(&self) -> impl Iterator<Item = (&str, &WorldDiff)> {
+        self.worlds_where(|change| {
+            !matches!(
+                change,
+                Some(WorldChange::Added { .. } | WorldChange::Removed { .. })
+            )
+        })
+    }
+
+    // Worlds the new side removed, in key order
+    pub fn removed(&self) -> impl Iterator<Item = (&str, &WorldDiff)> {
+        self.worlds_where(|change| matches!(change, Some(WorldChange::Removed { .. })))
+    }
+
+    fn worlds_where(
+        &self,
+        first_change: impl Fn(Option<&WorldChange>) -> bool,
+    ) -> impl Iterator<Item = (&str, &WorldDiff)> {
+        self.worlds
+            .iter()
+            .filter(move |(_, world)| first_change(world.changes.first()))
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    pub fn outcome(&self) -> DiffOutcome {
+        let mut outcome = match self.archipelago_version {
+            Some(_) => DiffOutcome::Updates,
+            None => DiffOutcome::NoChanges,
+        };
+        for change in self.worlds.values().flat_map(|world| &world.changes) {
+            outcome = outcome.max(match change {
+                WorldChange::Added { .. } => DiffOutcome::Additions,
+                WorldChange::Removed { .. } => DiffOutcome::Removals,
+                WorldChange::VersionChanged {
+                    bump: VersionBump::Downgrade,
+                    ..
+                } => DiffOutcome::Downgrades,
+                _ => DiffOutcome::Updates,
+            });
+        }
+
+        outcome
+    }
+
+    // Renders the diff as a Markdown summary, grouping worlds by whether they
+    // got added, updated or removed. Content diffs are left out.
+    pub fn to_markdown(&self) -> String {
+        fn line<'a>((key, world): (&str, &'a WorldDiff)) -> (String, Option<&'a str>) {
+            (markdown_line(key, world), world.changelog.as_deref())
+        }
+        let added = self.added().map(line).collect::<Vec<_>>();
+        let updated = self.updated().map(line).collect::<Vec<_>>();
+        let removed = self.removed().map(line).collect::<Vec<_>>();
+
+        let mut markdown = String::new();
+        if let Some(version) = &self.archipelago_version {
+            let _ = writeln!(
+                markdown,
+                "Archipelago updated from {} to {}\n",
+                version.old, version.new
+            );
+        }
+        for (title, lines) in [("Added", added), ("Updated", updated), ("Removed", removed)] {
+            if lines.is_empty() {
+                continue;
+            }
+            let _ = writeln!(markdown, "## {}\n", title);
+            for (line, changelog) in lines {
+                let _ = writeln!(markdown, "- {}", line);
+                if let Some(changelog) = changelog {
+                    let _ = writeln!(markdown, "  <details><summary>Changelog</summary>\n");
+                    for line in changelog.lines() {
+                        let _ = writeln!(markdown, "{}", format!("  {}", line).trim_end());
+                    }
+                    let _ = writeln!(markdown, "\n  </details>");
+                }
+            }
+            let _ = writeln!(markdown);
+        }
+
+        markdown
+    }
+}
+
+pub fn human_size(size: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", size, UNITS[unit])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+fn markdown_line(key: &str, world: &WorldDiff) -> String {
+    let mut parts = vec![format!("**{}** (`{}`)", world.name, key)];
+    for change in &world.changes {
+        parts.push(match change {
+            WorldChange::Added { version, .. } | WorldChange::Removed { version, .. } => {
+                version.clone()
+            }
+            WorldChange::VersionChanged { old, new, bump } => match bump {
+                VersionBump::Downgrade | VersionBump::Major | VersionBump::Stabilized => {
+                    format!("{} → {} (**{}**)", old, new, bump)
+                }
+                _ => format!("{} → {}", old, new),
+            },
+            WorldChange::OriginChanged { new, .. } => format!("now from {}", new),
+            WorldChange::PatchesChanged { new, .. } => format!("{} patches", new.len()),
+            WorldChange::Reuploaded { .. } => "**content changed without a version bump**".into(),
+            WorldChange::Updated { .. } => {
+                let stats = world.stats();
+                format!(
+                    "{} files changed (+{} -{})",
+                    stats.files_changed, stats.insertions, stats.deletions
+                )
+            }
+        });
+    }
+    if let Some(home) = &world.home {
+        parts.push(format!("[homepage]({})", home));
+    }
+    match (world.old_size, world.new_size) {
+        (Some(old), Some(new)) if old != new => {
+            parts.push(format!("{} → {}", human_size(old), human_size(new)))
+        }
+        (_, Some(size)) => parts.push(human_size(size)),
+        _ => {}
+    }
+
+    parts.join(", ")
+}
+
+fn read(path: &Path) -> Result<Option<Vec<u8>>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    Ok(Some(std::fs::read(path).map_err(|e| Error::io(path, e))?))
+}
+
+// Diffs two package directories one file at a time, handing every changed
+// file to `visit` so that only one file is held in memory at once. Junk files
+// and files matching the ignore patterns are left out. The diffs of text
+// files are `patch -p1` compatible.
+pub fn visit_dir_changes(
+    old: &Path,
+    new: &Path,
+    options: &DiffOptions,
+    mut visit: impl FnMut(FileChange) -> Result<()>,
+) -> Result<()> {
+    let mut files = BTreeSet::new();
+    patch::collect_files(old, old, &mut files)?;
+    patch::collect_files(new, new, &mut files)?;
+
+    for file in &files {
+        let name = file
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        if junk::is_junk(&name) || glob::matches_path(&options.ignore, &name) {
+            continue;
+        }
+
+        let old_content = read(&old.join(file))?;
+        let new_content = read(&new.join(file))?;
+        let kind = match (&old_content, &new_content) {
+            (None, _) => FileChangeKind::Added,
+            (_, None) => FileChangeKind::Removed,
+            (old_content, new_content) if old_content == new_content => continue,
+            _ => FileChangeKind::Modified,
+        };
+
+        let binary = BinarySummary::new(old_content.as_deref(), new_content.as_deref());
+        let old_text = String::from_utf8(old_content.unwrap_or_default());
+        let new_text = String::from_utf8(new_content.unwrap_or_default());
+        let (mut insertions, mut deletions) = (0, 0);
+        let (is_text, diff) = match (old_text, new_text) {
+            (Ok(old_text), Ok(new_text)) => {
+                let text_diff = TextDiff::from_lines(&old_text, &new_text);
+                for change in text_diff.iter_all_changes() {
+                    match change.tag() {
+                        ChangeTag::Insert => insertions += 1,
+                        ChangeTag::Delete => deletions += 1,
+                        ChangeTag::Equal => {}
+                    }
+                }
+                let old_header = match kind {
+                    FileChangeKind::Added => "/dev/null".to_string(),
+                    _ => format!("a/{}", name),
+                };
+                let new_header = match kind {
+                    FileChangeKind::Removed => "/dev/null".to_string(),
+                    _ => format!("b/{}", name),
+                };
+                let diff = (!options.stat_only).then(|| {
+                    text_diff
+                        .unified_diff()
+                        .context_radius(options.context_lines)
+                        .header(&old_header, &new_header)
+                        .to_string()
+                });
+                (true, diff)
+            }
+            _ => (false, None),
+        };
+
+        visit(FileChange {
+            path: name,
+            kind,
+            diff,
+            insertions,
+            deletions,
+            binary: (!is_text).then_some(binary),
+        })?;
+    }
+
+    Ok(())
+}
+
+// Diffs two package directories file by file, see `visit_dir_changes`
+#[cfg(feature = "refresh")]
+pub fn diff_dirs(old: &Path, new: &Path, options: &DiffOptions) -> Result<Vec<FileChange>> {
+    let mut changes = vec![];
+    visit_dir_changes(old, new, options, |change| {
+        changes.push(change);
+        Ok(())
+    })?;
+
+    Ok(changes)
+}
+
+// The package directory of a deployed world, extracting it into `tmp_dir` if
+// it's an `.apworld`
+#[cfg(feature = "refresh")]
+fn unpacked(path: &Path, tmp_dir: &Path) -> Result<PathBuf> {
+    if path.is_file() {
+        apworld::extract(path, tmp_dir)?;
+        return apworld::package_dir(tmp_dir);
+    }
+
+    Ok(path.into())
+}
+
+// The patches of two manifests as listed in a diff. When only their content
+// changed, patches get suffixed with the start of their hash to tell them apart.
+fn patch_labels(old: &[AppliedPatch], new: &[AppliedPatch]) -> (Vec<String>, Vec<String>) {
+    let paths = |patches: &[AppliedPatch]| {
+        patches
+            .iter()
+            .map(|patch| patch.path.clone())
+            .collect::<Vec<_>>()
+    };
+    let labels = |patches: &[AppliedPatch]| {
+        patches
+            .iter()
+            .map(|patch| format!("{}@{:.8}", patch.path, patch.sha256))
+            .collect::<Vec<_>>()
+    };
+
+    if paths(old) == paths(new) {
+        return (labels(old), labels(new));
+    }
+
+    (paths(old), paths(new))
+}
+
+fn world_diff(name: &str) -> WorldDiff {
+    WorldDiff {
+        name: name.to_string(),
+        home: None,
+        old_size: None,
+        new_size: None,
+        changes: vec![],
+        changelog: None,
+    }
+}
+
+// Compares two manifests without looking at any file: the worlds that got
+// added or removed and the ones whose version, origin or patches changed
+pub fn diff_manifests(old: &Manifest, new: &Manifest, options: &DiffOptions) -> IndexDiff {
+    let mut diff = IndexDiff::default();
+    if old.archipelago_version != new.archipelago_version {
+        diff.archipelago_version = Some(VersionChange {
+            old: old.archipelago_version.clone(),
+            new: new.archipelago_version.clone(),
+        });
+    }
+
+    let names = old
+        .worlds
+        .keys()
+        .chain(new.worlds.keys())
+        .filter(|name| options.includes(name))
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        let (old_world, new_world) = (old.worlds.get(name), new.worlds.get(name));
+        let mut world_diff = world_diff(new_world.or(old_world).map_or("", |world| &world.name));
+
+        match (old_world, new_world) {
+            (None, Some(new_world)) => world_diff.changes.push(WorldChange::Added {
+                version: new_world.version.clone(),
+                origin: new_world.origin.clone(),
+            }),
+            (Some(old_world), None) => world_diff.changes.push(WorldChange::Removed {
+                version: old_world.version.clone(),
+                origin: old_world.origin.clone(),
+            }),
+            (Some(old_world), Some(new_world)) => {
+                if old_world.version != new_world.version {
+                    world_diff.changes.push(WorldChange::VersionChanged {
+                        old: old_world.version.clone(),
+                        new: new_world.version.clone(),
+                        bump: version::classify(&old_world.version, &new_world.version),
+                    });
+                }
+                if old_world.origin != new_world.origin {
+                    world_diff.changes.push(WorldChange::OriginChanged {
+                        old: old_world.origin.clone(),
+                        new: new_world.origin.clone(),
+                    });
+                }
+                if old_world.patches != new_world.patches {
+                    let (old, new) = patch_labels(&old_world.patches, &new_world.patches);
+                    world_diff
+                        .changes
+                        .push(WorldChange::PatchesChanged { old, new });
+                }
+                if let (true, Some(old_sha256), Some(new_sha256)) = (
+                    world_diff.changes.is_empty(),
+                    &old_world.sha256,
+                    &new_world.sha256,
+                ) {
+                    if old_sha256 != new_sha256 {
+                        world_diff.changes.push(WorldChange::Reuploaded {
+                            version: new_world.version.clone(),
+                            old_sha256: old_sha256.clone(),
+                            new_sha256: new_sha256.clone(),
+                        });
+                    }
+                }
+            }
+            (None, None) => {}
+        }
+
+        if !world_diff.changes.is_empty() {
+            diff.worlds.insert(name.clone(), world_diff);
+        }
+    }
+
+    diff
+}
+
+// What `visit_destination_changes` hands over, in world key order. A changed
+// world comes with the changes known without looking at its files, followed
+// by the files that changed in it and their stats when there are any.
+#[derive(Debug)]
+pub enum DestinationChange<'a> {
+    Archipelago(&'a VersionChange),
+    World { key: &'a str, diff: &'a WorldDiff },
+    File { key: &'a str, change: FileChange },
+    WorldStats { key: &'a str, stats: DiffStats },
+}
+
+// Compares two refreshed destinations using their manifests, diffing the
+// files of every world deployed in both. `.apworld`s get unpacked in the
+// temporary directory of `dirs`.
+#[cfg(feature = "refresh")]
+pub fn diff_destinations(
+    old: &Path,
+    new: &Path,
+    options: &DiffOptions,
+    dirs: &WorkDirs,
+) -> Result<IndexDiff> {
+    let mut files = BTreeMap::<String, Vec<FileChange>>::new();
+    let mut diff = visit_destination_changes(old, new, options, dirs, |change| {
+        if let DestinationChange::File { key, change } = change {
+            files.entry(key.to_string()).or_default().push(change);
+        }
+        Ok(())
+    })?;
+    for (key, world_diff) in &mut diff.worlds {
+        for change in &mut world_diff.changes {
+            if let (WorldChange::Updated { files: stats }, Some(files)) =
+                (change, files.remove(key))
+            {
+                *stats = files;
+            }
+        }
+    }
+
+    Ok(diff)
+}
+
+// Like `diff_destinations`, but handing every changed file to `visit` as soon
+// as it's diffed, so only one file is held in memory at once. The returned
+// diff has the paths and line counts of the files without their content
+// diffs, which is enough for summaries and `IndexDiff::outcome`.
+#[cfg(feature = "refresh")]
+pub fn visit_destination_changes(
+    old: &Path,
+    new: &Path,
+    options: &DiffOptions,
+    dirs: &WorkDirs,
+    mut visit: impl FnMut(DestinationChange) -> Result<()>,
+) -> Result<IndexDiff> {
+    let mut old_manifest = Manifest::load(old)?;
+    let mut new_manifest = Manifest::load(new)?;
+    // Manifests written before hashes got recorded
+    for (manifest, destination) in [(&mut old_manifest, old), (&mut new_manifest, new)] {
+        for (name, world) in &mut manifest.worlds {
+            if world.sha256.is_none() {
+                let path = world.origin.deployed_path(name, destination)?;
+                world.sha256 = Some(manifest::content_sha256(&path)?);
+            }
+        }
+    }
+    let mut diff = diff_manifests(&old_manifest, &new_manifest, options);
+    if let Some(version) = &diff.archipelago_version {
+        visit(DestinationChange::Archipelago(version))?;
+    }
+
+    let names = old_manifest
+        .worlds
+        .keys()
+        .chain(new_manifest.worlds.keys())
+        .filter(|name| options.includes(name))
+        .cloned()
+        .collect::<BTreeSet<_>>();
+    for name in names {
+        let (old_world, new_world) = (
+            old_manifest.worlds.get(&name),
+            new_manifest.worlds.get(&name),
+        );
+        let mut world_diff = diff
+            .worlds
+            .remove(&name)
+            .unwrap_or_else(|| world_diff(new_world.or(old_world).map_or("", |world| &world.name)));
+        let mut visited = false;
+
+        match (old_world, new_world) {
+            (None, Some(new_world)) => {
+                let path = new_world.origin.deployed_path(&name, new)?;
+                world_diff.new_size = Some(size::deployed_size(&path)?);
+            }
+            (Some(old_world), None) => {
+                let path = old_world.origin.deployed_path(&name, old)?;
+                world_diff.old_size = Some(size::deployed_size(&path)?);
+            }
+            (Some(old_world), Some(new_world)) => {
+                let old_path = old_world.origin.deployed_path(&name, old)?;
+                let new_path = new_world.origin.deployed_path(&name, new)?;
+                let old_tmp = dirs.tempdir()?;
+                let new_tmp = dirs.tempdir()?;
+                let old_dir = unpacked(&old_path, old_tmp.path())?;
+                let new_dir = unpacked(&new_path, new_tmp.path())?;
+
+                world_diff.old_size = Some(size::deployed_size(&old_path)?);
+                world_diff.new_size = Some(size::deployed_size(&new_path)?);
+                if old_world.version != new_world.version {
+                    world_diff.changelog =
+                        changelog::extract(&new_dir, &old_world.version, &new_world.version)?;
+                }
+
+                // The world is only visited once it's known to have changed
+                let mut stats = vec![];
+                visit_dir_changes(&old_dir, &new_dir, options, |change| {
+                    if !visited {
+                        visit(DestinationChange::World {
+                            key: &name,
+                            diff: &world_diff,
+                        })?;
+                        visited = true;
+                    }
+                    stats.push(FileChange {
+                        path: change.path.clone(),
+                        kind: change.kind,
+                        diff: None,
+                        insertions: change.insertions,
+                        deletions: change.deletions,
+                        binary: change.binary.clone(),
+                    });
+                    visit(DestinationChange::File { key: &name, change })
+                })?;
+                if !stats.is_empty() {
+                    world_diff
+                        .changes
+                        .push(WorldChange::Updated { files: stats });
+                    visit(DestinationChange::WorldStats {
+                        key: &name,
+                        stats: world_diff.stats(),
+                    })?;
+                }
+            }
+            (None, None) => {}
+        }
+
+        if world_diff.changes.is_empty() {
+            continue;
+        }
+        if !visited {
+            visit(DestinationChange::World {
+                key: &name,
+                diff: &world_diff,
+            })?;
+        }
+        diff.worlds.insert(name, world_diff);
+    }
+
+    Ok(diff)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn write(dir: &Path, path: &str, content: impl AsRef<[u8]>) {
+        let path = dir.join(path);
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(path, content).unwrap();
+    }
+
+    // Two versions of a package, with a file of each kind of change, a junk
+    // file and a file the options ignore
+    fn packages() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let (old, new) = (tmp.path().join("old"), tmp.path().join("new"));
+        for dir in [&old, &new] {
+            write(dir, "same.py", "same\n");
+            write(dir, "__pycache__/same.cpython-311.pyc", [0, 1, 2, 3]);
+        }
+        write(&old, "a.py", "one\ntwo\nthree\n");
+        write(&new, "a.py", "one\n2\nthree\nfour\n");
+        write(&old, "removed.py", "gone\n");
+        write(&new, "data/added.py", "new\n");
+        write(&old, "data.bin", [0xff, 0, 1]);
+        write(&new, "data.bin", [0xff, 0, 1, 2]);
+        write(&new, "notes.txt", "ignored\n");
+        (tmp, old, new)
+    }
+
+    fn options() -> DiffOptions {
+        DiffOptions {
+            ignore: vec!["*.txt".into()],
+            ..Default::default()
+        }
+    }
+
+    fn changes(old: &Path, new: &Path, options: &DiffOptions) -> Vec<FileChange> {
+        let mut changes = vec![];
+        visit_dir_changes(old, new, options, |change| {
+            changes.push(change);
+            Ok(())
+        })
+        .unwrap();
+        changes
+    }
+
+    // Files come in path order, component by component
+    #[test]
+    fn visits_changed_files_in_order() {
+        let (_tmp, old, new) = packages();
+        let changes = changes(&old, &new, &options());
+        let kinds = changes
+            .iter()
+            .map(|change| (change.path.as_str(), change.kind))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            [
+                ("a.py", FileChangeKind::Modified),
+                ("data/added.py", FileChangeKind::Added),
+                ("data.bin", FileChangeKind::Modified),
+                ("removed.py", FileChangeKind::Removed),
+            ]
+        );
+
+        let a = &changes[0];
+        assert_eq!((a.insertions, a.deletions), (2, 1));
+        let diff = a.diff.as_deref().unwrap();
+        assert!(diff.starts_with("--- a/a.py\n+++ b/a.py\n"), "{}", diff);
+        assert!(diff.contains("\n-two\n+2\n"), "{}", diff);
+        assert!(a.binary.is_none());
+
+        let added = changes[1].diff.as_deref().unwrap();
+        assert!(added.starts_with("--- /dev/null\n+++ b/data/added.py\n"));
+        let removed = changes[3].diff.as_deref().unwrap();
+        assert!(removed.starts_with("--- a/removed.py\n+++ /dev/null\n"));
+    }
+
+    #[test]
+    fn summarizes_binary_files() {
+        let (_tmp, old, new) = packages();
+        let changes = changes(&old, &new, &options());
+        let binary = &changes[2];
+        assert!(binary.diff.is_none());
+        assert_eq!((binary.insertions, binary.deletions), (0, 0));
+        let summary = binary.binary.as_ref().unwrap();
+        assert_eq!((summary.old_size, summary.new_size), (Some(3), Some(4)));
+        assert_ne!(summary.old_sha256, summary.new_sha256);
+    }
+
+    #[test]
+    fn only_counts_lines_for_stats() {
+        let (_tmp, old, new) = packages();
+        let options = DiffOptions {
+            stat_only: true,
+            ..options()
+        };
+        let changes = changes(&old, &new, &options);
+        assert!(changes.iter().all(|change| change.diff.is_none()));
+        assert_eq!((changes[0].insertions, changes[0].deletions), (2, 1));
+    }
+
+    #[test]
+    fn honours_ignore_patterns() {
+        let (_tmp, old, new) = packages();
+        let changes = changes(&old, &new, &DiffOptions::default());
+        assert!(changes.iter().any(|change| change.path == "notes.txt"));
+        assert!(!changes
+            .iter()
+            .any(|change| change.path.contains("__pycache__")));
+    }
+
+    #[cfg(feature = "refresh")]
+    #[test]
+    fn produces_diffs_patch_applies() {
+        let (tmp, old, new) = packages();
+        std::fs::remove_file(new.join("data.bin")).unwrap();
+        std::fs::remove_file(old.join("data.bin")).unwrap();
+        let diff = changes(&old, &new, &options())
+            .into_iter()
+            .filter_map(|change| change.diff)
+            .collect::<String>();
+        let patch_file = tmp.path().join("packages.patch");
+        std::fs::write(&patch_file, diff).unwrap();
+
+        patch::apply(&old, &patch_file).unwrap();
+        std::fs::remove_file(new.join("notes.txt")).unwrap();
+        assert!(changes(&old, &new, &DiffOptions::default()).is_empty());
+    }
+
+    #[test]
+    fn formats_sizes() {
+        assert_eq!(human_size(512), "512 B");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(5 * 1024 * 1024), "5.0 MiB");
+        assert_eq!(human_size(3 * 1024 * 1024 * 1024 * 1024), "3072.0 GiB");
+    }
+
+    fn manifest(content: &str) -> Manifest {
+        toml::from_str(content).unwrap()
+    }
+
+    const OLD: &str = r#"
+        archipelago_version = "0.5.0"
+
+        [worlds.bumped]
+        name = "Bumped"
+        version = "1.0"
+        url = "https://example.com/bumped.apworld"
+
+        [worlds.removed]
+        name = "Removed"
+        version = "1.0"
+        supported = "removed"
+
+        [worlds.reuploaded]
+        name = "Reuploaded"
+        version = "1.0"
+        url = "https://example.com/reuploaded.apworld"
+        sha256 = "aaaa"
+    "#;
+
+    const NEW: &str = r#"
+        archipelago_version = "0.5.1"
+
+        [worlds.added]
+        name = "Added"
+        version = "0.1"
+        local = "worlds/added"
+
+        [worlds.bumped]
+        name = "Bumped"
+        version = "1.1"
+        url = "https://example.com/bumped-1.1.apworld"
+
+        [worlds.reuploaded]
+        name = "Reuploaded"
+        version = "1.0"
+        url = "https://example.com/reuploaded.apworld"
+        sha256 = "bbbb"
+    "#;
+
+    #[test]
+    fn diffs_manifests() {
+        let diff = diff_manifests(&manifest(OLD), &manifest(NEW), &DiffOptions::default());
+        let version = diff.archipelago_version.as_ref().unwrap();
+        assert_eq!(
+            (version.old.as_str(), version.new.as_str()),
+            ("0.5.0", "0.5.1")
+        );
+
+        let added = diff.added().map(|(key, _)| key).collect::<Vec<_>>();
+        let updated = diff.updated().map(|(key, _)| key).collect::<Vec<_>>();
+        let removed = diff.removed().map(|(key, _)| key).collect::<Vec<_>>();
+        assert_eq!(added, ["added"]);
+        assert_eq!(updated, ["bumped", "reuploaded"]);
+        assert_eq!(removed, ["removed"]);
+
+        assert!(matches!(
+            diff.worlds["bumped"].changes[..],
+            [
+                WorldChange::VersionChanged {
+                    bump: VersionBump::Minor,
+                    ..
+                },
+                WorldChange::OriginChanged { .. }
+            ]
+        ));
+        assert!(matches!(
+            diff.worlds["reuploaded"].changes[..],
+            [WorldChange::Reuploaded { .. }]
+        ));
+        assert_eq!(diff.outcome(), DiffOutcome::Removals);
+    }
+
+    #[test]
+    fn ranks_outcomes() {
+        let same = diff_manifests(&manifest(OLD), &manifest(OLD), &DiffOptions::default());
+        assert!(same.is_empty());
+        assert_eq!(same.outcome(), DiffOutcome::NoChanges);
+
+        let downgraded = diff_manifests(&manifest(NEW), &manifest(OLD), &DiffOptions::default());
+        assert_eq!(downgraded.outcome(), DiffOutcome::Downgrades);
+    }
+
+    #[test]
+    fn only_diffs_the_worlds_asked_for() {
+        let options = DiffOptions {
+            worlds: vec!["re*".into()],
+            ..Default::default()
+        };
+        let diff = diff_manifests(&manifest(OLD), &manifest(NEW), &options);
+        assert_eq!(
+            diff.worlds.keys().collect::<Vec<_>>(),
+            ["removed", "reuploaded"]
+        );
+    }
+}
@@ -0,0 +1,150 @@
+use serde::Serialize;
+
+use crate::{
+    diff::{human_size, IndexDiff, WorldChange, WorldDiff},
+    version::VersionBump,
+};
+
+// Limits of discord's webhook API, in characters
+const MAX_TITLE: usize = 256;
+const MAX_DESCRIPTION: usize = 4096;
+const MAX_CONTENT: usize = 2000;
+const MAX_EMBEDS_SIZE: usize = 6000;
+const MAX_EMBEDS: usize = 10;
+
+const GREEN: u32 = 0x2ea043;
+const BLUE: u32 = 0x0969da;
+const ORANGE: u32 = 0xd29922;
+const RED: u32 = 0xcf222e;
+
+// A message ready to be POSTed to a discord webhook
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DiscordMessage {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    pub embeds: Vec<DiscordEmbed>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct DiscordEmbed {
+    pub title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    pub description: String,
+    pub color: u32,
+}
+
+impl DiscordEmbed {
+    fn size(&self) -> usize {
+        self.title.chars().count() + self.description.chars().count()
+    }
+}
+
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+
+    let mut truncated = text.chars().take(max - 1).collect::<String>();
+    truncated.push('…');
+    truncated
+}
+
+fn color(world: &WorldDiff) -> u32 {
+    let mut color = BLUE;
+    for change in &world.changes {
+        match change {
+            WorldChange::Added { .. } => color = GREEN,
+            WorldChange::Removed { .. }
+            | WorldChange::VersionChanged {
+                bump: VersionBump::Downgrade,
+                ..
+            } => return RED,
+            WorldChange::Reuploaded { .. } => color = ORANGE,
+            _ => {}
+        }
+    }
+
+    color
+}
+
+fn description(world: &WorldDiff) -> String {
+    let mut lines = vec![];
+    for change in &world.changes {
+        lines.push(match change {
+            WorldChange::Added { version, origin } => {
+                format!("Added, version {} from {}", version, origin)
+            }
+            WorldChange::Removed { version, .. } => format!("Removed, was version {}", version),
+            WorldChange::VersionChanged { old, new, bump } => {
+                format!("Version {} → {} ({})", old, new, bump)
+            }
+            WorldChange::OriginChanged { new, .. } => format!("Now from {}", new),
+            WorldChange::PatchesChanged { new, .. } => format!("{} patches applied", new.len()),
+            WorldChange::Reuploaded { .. } => "Content changed without a version bump".into(),
+            WorldChange::Updated { .. } => world.stats().to_string(),
+        });
+    }
+    match (world.old_size, world.new_size) {
+        (Some(old), Some(new)) if old != new => {
+            lines.push(format!("Size {} → {}", human_size(old), human_size(new)))
+        }
+        (_, Some(size)) => lines.push(format!("Size {}", human_size(size))),
+        _ => {}
+    }
+
+    let mut description = lines.join("\n");
+    if let Some(changelog) = &world.changelog {
+        description.push_str("\n\n**Changelog**\n");
+        description.push_str(changelog);
+    }
+
+    truncate(&description, MAX_DESCRIPTION)
+}
+
+impl IndexDiff {
+    // Renders the diff as discord webhook payloads, one embed per world. Embeds
+    // are split across as many messages as needed to stay within discord's
+    // limits, and overly long changelogs get truncated.
+    pub fn to_discord(&self) -> Vec<DiscordMessage> {
+        let mut messages = vec![];
+        let mut message = DiscordMessage {
+            content: self.archipelago_version.as_ref().map(|version| {
+                truncate(
+                    &format!(
+                        "Archipelago updated from {} to {}",
+                        version.old, version.new
+                    ),
+                    MAX_CONTENT,
+                )
+            }),
+            embeds: vec![],
+        };
+        let mut size = 0;
+
+        for (key, world) in &self.worlds {
+            let embed = DiscordEmbed {
+                title: truncate(&format!("{} ({})", world.name, key), MAX_TITLE),
+                // Discord rejects embeds whose URL isn't a web link
+                url: world
+                    .home
+                    .clone()
+                    .filter(|home| home.starts_with("https://") || home.starts_with("http://")),
+                description: description(world),
+                color: color(world),
+            };
+
+            if message.embeds.len() == MAX_EMBEDS || size + embed.size() > MAX_EMBEDS_SIZE {
+                messages.push(std::mem::take(&mut message));
+                size = 0;
+            }
+            size += embed.size();
+            message.embeds.push(embed);
+        }
+        if message.content.is_some() || !message.embeds.is_empty() {
+            messages.push(message);
+        }
+
+        messages
+    }
+}
@@ -0,0 +1,267 @@
+use git2::{Direction, Remote};
+use serde::Serialize;
+use std::{path::Path, process::Command};
+
+use crate::{sandbox, Index, Result};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DiagnosticStatus {
+    Ok,
+    // Some commands won't work, refreshing still does
+    Warning,
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Diagnostic {
+    pub check: String,
+    pub status: DiagnosticStatus,
+    pub message: String,
+    // What to do about it, when it isn't ok
+    pub fix: Option<String>,
+}
+
+impl Diagnostic {
+    fn ok(check: &str, message: impl Into<String>) -> Self {
+        Self {
+            check: check.into(),
+            status: DiagnosticStatus::Ok,
+            message: message.into(),
+            fix: None,
+        }
+    }
+
+    fn failed(
+        check: &str,
+        status: DiagnosticStatus,
+        message: impl Into<String>,
+        fix: impl Into<String>,
+    ) -> Self {
+        Self {
+            check: check.into(),
+            status,
+            message: message.into(),
+            fix: Some(fix.into()),
+        }
+    }
+
+    pub fn is_ok(&self) -> bool {
+        self.status == DiagnosticStatus::Ok
+    }
+}
+
+// Patches are applied with the `patch` tool, everything git related goes
+// through libgit2 so git itself isn't needed. Without patches in the index a
+// missing tool only matters to `make-patch` users.
+fn check_patch_tool(needed: bool) -> Diagnostic {
+    let check = "patch tool";
+    let status = if needed {
+        DiagnosticStatus::Error
+    } else {
+        DiagnosticStatus::Warning
+    };
+    match Command::new("patch").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            let version = String::from_utf8_lossy(&output.stdout);
+            Diagnostic::ok(check, version.lines().next().unwrap_or_default().trim())
+        }
+        Ok(output) => Diagnostic::failed(
+            check,
+            status,
+            format!("patch --version exited with {}", output.status),
+            "Install GNU patch, BSD patch doesn't support the options apwm uses",
+        ),
+        Err(e) => Diagnostic::failed(
+            check,
+            status,
+            format!("Couldn't run patch: {}", e),
+            "Install GNU patch and make sure it's in PATH, worlds with patches can't be refreshed without it",
+        ),
+    }
+}
+
+// Directories that don't exist yet only need their closest existing parent
+// to be writable, refreshes create them
+fn check_writable(check: &str, dir: &Path) -> Diagnostic {
+    let Some(existing) = dir.ancestors().find(|ancestor| ancestor.exists()) else {
+        return Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("{} doesn't exist", dir.to_string_lossy()),
+            "Create it or pass another directory",
+        );
+    };
+    if !existing.is_dir() {
+        return Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("{} isn't a directory", existing.to_string_lossy()),
+            "Remove it or pass another directory",
+        );
+    }
+
+    match tempfile::tempfile_in(existing) {
+        Ok(_) if existing == dir => {
+            Diagnostic::ok(check, format!("{} is writable", dir.to_string_lossy()))
+        }
+        Ok(_) => Diagnostic::ok(
+            check,
+            format!(
+                "{} doesn't exist yet, {} is writable",
+                dir.to_string_lossy(),
+                existing.to_string_lossy()
+            ),
+        ),
+        Err(e) => Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("Can't write to {}: {}", existing.to_string_lossy(), e),
+            format!(
+                "Fix the permissions of {} or run apwm as a user owning it",
+                existing.to_string_lossy()
+            ),
+        ),
+    }
+}
+
+// Files the index refers to, like patches and local worlds, that aren't there
+fn missing_files(index: &Index) -> Result<Vec<String>> {
+    let index_dir = index.index_dir()?;
+    let mut missing = vec![];
+    for (name, world) in &index.worlds {
+        for path in world.index_files() {
+            if !sandbox::is_contained(&path) || !index_dir.join(&path).exists() {
+                missing.push(format!("{} ({})", path.to_string_lossy(), name));
+            }
+        }
+    }
+
+    Ok(missing)
+}
+
+fn check_index(index_toml: &Path) -> (Diagnostic, Option<Index>) {
+    let check = "index";
+    if !index_toml.is_file() {
+        return (
+            Diagnostic::failed(
+                check,
+                DiagnosticStatus::Error,
+                format!("{} doesn't exist", index_toml.to_string_lossy()),
+                "Pass the directory containing index.toml",
+            ),
+            None,
+        );
+    }
+
+    let index = match Index::new(index_toml) {
+        Ok(index) => index,
+        Err(e) => {
+            return (
+                Diagnostic::failed(
+                    check,
+                    DiagnosticStatus::Error,
+                    format!("Couldn't load {}: {}", index_toml.to_string_lossy(), e),
+                    "Fix the index, `apwm fmt --check` points at syntax errors",
+                ),
+                None,
+            )
+        }
+    };
+
+    let diagnostic = match missing_files(&index) {
+        Ok(missing) if missing.is_empty() => Diagnostic::ok(
+            check,
+            format!(
+                "{} loads with {} worlds",
+                index_toml.to_string_lossy(),
+                index.worlds.len()
+            ),
+        ),
+        Ok(missing) => Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("Missing files: {}", missing.join(", ")),
+            "Add the files next to the index or remove them from it, paths are relative to the index directory",
+        ),
+        Err(e) => Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            e.to_string(),
+            "Pass the path of an index directory",
+        ),
+    };
+
+    (diagnostic, Some(index))
+}
+
+// Lists the refs of the archipelago repository, which also tells whether the
+// pinned version exists
+fn check_archipelago(index: &Index) -> Diagnostic {
+    let check = "archipelago repository";
+    let url = index.common.archipelago_repo.to_string();
+    let version = &index.common.archipelago_version;
+
+    let refs = Remote::create_detached(url.as_str()).and_then(|mut remote| {
+        remote.connect(Direction::Fetch)?;
+        let refs = remote
+            .list()?
+            .iter()
+            .map(|head| head.name().to_string())
+            .collect::<Vec<_>>();
+        Ok(refs)
+    });
+    let refs = match refs {
+        Ok(refs) => refs,
+        Err(e) => return Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("Couldn't reach {}: {}", url, e.message()),
+            "Check the network connection and proxy settings, and that archipelago_repo is right",
+        ),
+    };
+
+    let known = refs.iter().any(|name| {
+        name == &format!("refs/tags/{}", version) || name == &format!("refs/heads/{}", version)
+    });
+    if !known {
+        return Diagnostic::failed(
+            check,
+            DiagnosticStatus::Error,
+            format!("{} has no tag or branch named {}", url, version),
+            "Fix archipelago_version in the index",
+        );
+    }
+
+    Diagnostic::ok(check, format!("{} is reachable and has {}", url, version))
+}
+
+// Checks everything apwm needs from its environment. The index and the
+// network are only checked when an index is given, the destination when one
+// is given.
+pub fn diagnose(index_toml: Option<&Path>, destination: Option<&Path>) -> Vec<Diagnostic> {
+    let mut diagnostics = vec![];
+    let mut index = None;
+    if let Some(index_toml) = index_toml {
+        let (diagnostic, loaded) = check_index(index_toml);
+        diagnostics.push(diagnostic);
+        index = loaded;
+    }
+
+    let needs_patch = index
+        .as_ref()
+        .is_none_or(|index| index.worlds.values().any(|world| world.has_patches()));
+    diagnostics.insert(0, check_patch_tool(needs_patch));
+    diagnostics.insert(
+        1,
+        check_writable("temporary directory", &std::env::temp_dir()),
+    );
+    if let Some(destination) = destination {
+        diagnostics.push(check_writable("destination", destination));
+    }
+    if let Some(index) = &index {
+        diagnostics.push(check_archipelago(index));
+    }
+
+    diagnostics
+}
@@ -0,0 +1,62 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{apworld, junk, Result};
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DriftKind {
+    // Only in the vendored copy
+    Added,
+    // Only in the upstream release
+    Removed,
+    Changed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileDrift {
+    pub path: String,
+    pub kind: DriftKind,
+    pub local_sha256: Option<String>,
+    pub upstream_sha256: Option<String>,
+}
+
+fn hashes(path: &Path) -> Result<BTreeMap<String, String>> {
+    Ok(apworld::package_files(path, |name| !junk::is_junk(name))?
+        .into_iter()
+        .map(|(name, content)| (name, format!("{:x}", Sha256::digest(content))))
+        .collect())
+}
+
+// Compares a vendored world against its upstream release. Both can either be
+// `.apworld` files or package directories. Junk files are ignored.
+pub fn compare(local: &Path, upstream: &Path) -> Result<Vec<FileDrift>> {
+    let mut local = hashes(local)?;
+    let upstream = hashes(upstream)?;
+
+    let mut drift = vec![];
+    for (path, upstream_sha256) in upstream {
+        let local_sha256 = local.remove(&path);
+        let kind = match &local_sha256 {
+            None => DriftKind::Removed,
+            Some(sha256) if *sha256 != upstream_sha256 => DriftKind::Changed,
+            Some(_) => continue,
+        };
+        drift.push(FileDrift {
+            path,
+            kind,
+            local_sha256,
+            upstream_sha256: Some(upstream_sha256),
+        });
+    }
+    drift.extend(local.into_iter().map(|(path, sha256)| FileDrift {
+        path,
+        kind: DriftKind::Added,
+        local_sha256: Some(sha256),
+        upstream_sha256: None,
+    }));
+    drift.sort_by(|a, b| a.path.cmp(&b.path));
+
+    Ok(drift)
+}
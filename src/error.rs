@@ -0,0 +1,204 @@
+use std::path::PathBuf;
+
+use crate::RefreshSummary;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+// Errors of the library. The failures worth telling apart get their own
+// variant, everything else, like an invalid world or a failed check, ends up
+// in `Other` with its message.
+#[derive(thiserror::Error, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("Invalid index {}", path.to_string_lossy())]
+    IndexParse {
+        path: PathBuf,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("Couldn't download {world} from {url}")]
+    Download {
+        world: String,
+        url: String,
+        #[source]
+        source: anyhow::Error,
+    },
+    #[error("Couldn't apply {file} to {world}")]
+    Patch {
+        world: String,
+        file: String,
+        #[source]
+        source: anyhow::Error,
+    },
+    // Every world gets a chance before a refresh fails, the summary has all
+    // the ones that didn't make it
+    #[error("Couldn't refresh {}", summary.failed_keys())]
+    Refresh {
+        summary: Box<RefreshSummary>,
+        // The error of the first world that failed
+        #[source]
+        source: Box<Error>,
+    },
+    #[error("Cancelled")]
+    Cancelled,
+    #[cfg(feature = "git")]
+    #[error(transparent)]
+    Git(#[from] git2::Error),
+    #[cfg(any(feature = "http", feature = "blocking"))]
+    #[error(transparent)]
+    Http(#[from] reqwest::Error),
+    #[error("{}", io_message(path, source))]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error(transparent)]
+    Other(anyhow::Error),
+}
+
+fn io_message(path: &Option<PathBuf>, source: &std::io::Error) -> String {
+    match path {
+        Some(path) => format!("{}: {}", path.to_string_lossy(), source),
+        None => source.to_string(),
+    }
+}
+
+impl Error {
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        Self::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    // Whether a server couldn't be reached or answered with an error, which
+    // may go away by trying again later
+    pub fn is_network(&self) -> bool {
+        match self {
+            Error::Download { .. } => true,
+            Error::Refresh { source, .. } => source.is_network(),
+            #[cfg(any(feature = "http", feature = "blocking"))]
+            Error::Http(_) => true,
+            #[cfg(feature = "git")]
+            Error::Git(e) => matches!(
+                e.class(),
+                git2::ErrorClass::Net
+                    | git2::ErrorClass::Http
+                    | git2::ErrorClass::Ssl
+                    | git2::ErrorClass::Ssh
+            ),
+            _ => false,
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(source: std::io::Error) -> Self {
+        Self::Io { path: None, source }
+    }
+}
+
+// Errors raised deeper down keep their variant through `anyhow`
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        let error = match error.downcast::<Error>() {
+            Ok(error) => return error,
+            Err(error) => error,
+        };
+        #[cfg(feature = "git")]
+        let error = match error.downcast::<git2::Error>() {
+            Ok(error) => return Self::Git(error),
+            Err(error) => error,
+        };
+        #[cfg(any(feature = "http", feature = "blocking"))]
+        let error = match error.downcast::<reqwest::Error>() {
+            Ok(error) => return Self::Http(error),
+            Err(error) => error,
+        };
+        match error.downcast::<std::io::Error>() {
+            Ok(error) => error.into(),
+            Err(error) => Self::Other(error),
+        }
+    }
+}
+
+// Errors with nothing more to them than their message
+macro_rules! other_errors {
+    ($($error:ty),* $(,)?) => {
+        $(
+            impl From<$error> for Error {
+                fn from(error: $error) -> Self {
+                    Self::Other(error.into())
+                }
+            }
+        )*
+    };
+}
+
+other_errors!(
+    serde_json::Error,
+    toml::de::Error,
+    toml::ser::Error,
+    toml_edit::TomlError,
+    std::path::StripPrefixError,
+    std::string::FromUtf8Error,
+    std::str::Utf8Error,
+    std::time::SystemTimeError,
+    regex::Error,
+    http::uri::InvalidUri,
+);
+
+#[cfg(feature = "http")]
+other_errors!(tokio::task::JoinError);
+
+#[cfg(feature = "zip")]
+other_errors!(zip::result::ZipError);
+
+// `bail!` of anyhow, for functions returning our `Result`
+macro_rules! bail {
+    ($($arg:tt)*) => {
+        return Err($crate::Error::Other(anyhow::anyhow!($($arg)*)))
+    };
+}
+
+pub(crate) use bail;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Index, IndexBuilder, WorldBuilder, WorldOrigin};
+    use std::path::Path;
+
+    #[test]
+    fn says_which_file_failed() {
+        let path = Path::new("/nonexistent/index.toml");
+        match Index::new(path) {
+            Err(Error::Io {
+                path: Some(failed), ..
+            }) => assert_eq!(failed, path),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn reports_invalid_indexes_as_such() {
+        let dir = Path::new("/srv/index");
+        let world = WorldBuilder::new("Escaping", WorldOrigin::Local("../escaping".into()));
+        let built = IndexBuilder::new(dir, "0.5.0")
+            .world("escaping", world)
+            .build();
+        assert!(matches!(built, Err(Error::IndexParse { .. })));
+        let built = IndexBuilder::new(dir, "").build();
+        assert!(matches!(built, Err(Error::IndexParse { .. })));
+    }
+
+    #[test]
+    fn keeps_variants_through_anyhow() {
+        let io = std::io::Error::new(std::io::ErrorKind::NotFound, "gone");
+        let error = Error::from(anyhow::Error::from(Error::io("/a", io)));
+        assert!(matches!(error, Error::Io { path: Some(_), .. }));
+        let error = Error::from(anyhow::anyhow!("something else"));
+        assert!(matches!(error, Error::Other(_)));
+    }
+}
@@ -0,0 +1,139 @@
+use crate::Result;
+use std::cmp::Ordering;
+use toml_edit::{DocumentMut, Item, Key, Table};
+
+// Canonical key order of each part of the index. Other keys come after
+// those, in alphabetical order.
+const SECTIONS: &[&str] = &["common", "patch_sets", "vendored", "worlds"];
+const COMMON_KEYS: &[&str] = &[
+    "archipelago_repo",
+    "archipelago_version",
+    "homepage",
+    "required_global_files",
+];
+const WORLD_KEYS: &[&str] = &[
+    "name",
+    "supported",
+    "url",
+    "local",
+    "version",
+    "home",
+    "upstream",
+    "description",
+    "tags",
+    "dependencies",
+    "license_file",
+    "patch_sets",
+    "patches",
+    "transforms",
+];
+
+fn compare_keys(order: &[&str], a: &Key, b: &Key) -> Ordering {
+    let rank = |key: &Key| {
+        order
+            .iter()
+            .position(|known| *known == key.get())
+            .unwrap_or(order.len())
+    };
+    rank(a).cmp(&rank(b)).then_with(|| a.get().cmp(b.get()))
+}
+
+// Turns the inline and dotted tables of `table` into standard
+// `[section.key]` tables, with no spaces around their key in the header
+fn standardize(table: &mut Table) {
+    let keys = table
+        .iter()
+        .filter(|(_, item)| item.is_inline_table() || item.is_table())
+        .map(|(key, _)| key.to_string())
+        .collect::<Vec<_>>();
+    for key in keys {
+        let Some(item) = table.get_mut(&key) else {
+            continue;
+        };
+        *item = std::mem::take(item)
+            .into_table()
+            .map_or_else(|item| item, Item::Table);
+        if let Item::Table(child) = item {
+            child.set_dotted(false);
+        }
+        if let Some(mut key) = table.key_mut(&key) {
+            key.leaf_decor_mut().clear();
+        }
+    }
+}
+
+// Sorts the tables of `table` by rewriting their position in the document,
+// depth first in the order of its keys
+fn assign_positions(table: &mut Table, position: &mut usize) {
+    table.set_position(*position);
+    *position += 1;
+    for (_, item) in table.iter_mut() {
+        match item {
+            Item::Table(child) => assign_positions(child, position),
+            Item::ArrayOfTables(array) => {
+                for child in array.iter_mut() {
+                    assign_positions(child, position);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+// Every table gets separated from the previous one by a single blank line.
+// Tables with comments above them are left as they are.
+fn space_tables(table: &mut Table, first: &mut bool) {
+    for (_, item) in table.iter_mut() {
+        let Item::Table(child) = item else {
+            continue;
+        };
+        if !child.is_implicit() {
+            let decor = child.decor_mut();
+            let is_blank = decor
+                .prefix()
+                .and_then(|prefix| prefix.as_str())
+                .is_none_or(|prefix| prefix.trim().is_empty());
+            if is_blank {
+                decor.set_prefix(if *first { "" } else { "\n" });
+            }
+            *first = false;
+        }
+        space_tables(child, first);
+    }
+}
+
+// Canonicalizes an index file: sections and world keys come in a fixed
+// order, worlds, patch sets and vendored libraries are sorted by key and all
+// of them are written as standard tables. Comments are kept along with the
+// key or table they're above.
+pub fn format_index(content: &str) -> Result<String> {
+    let mut doc = content.parse::<DocumentMut>()?;
+    let root = doc.as_table_mut();
+    root.sort_values_by(|a, _, b, _| compare_keys(SECTIONS, a, b));
+
+    if let Some(common) = root.get_mut("common").and_then(Item::as_table_mut) {
+        common.sort_values_by(|a, _, b, _| compare_keys(COMMON_KEYS, a, b));
+    }
+    standardize(root);
+    for section in ["patch_sets", "vendored", "worlds"] {
+        let Some(section) = root.get_mut(section).and_then(Item::as_table_mut) else {
+            continue;
+        };
+        standardize(section);
+        section.sort_values();
+        if section.get_values().is_empty() {
+            section.set_implicit(true);
+        }
+    }
+    if let Some(worlds) = root.get_mut("worlds").and_then(Item::as_table_mut) {
+        for (_, world) in worlds.iter_mut() {
+            if let Some(world) = world.as_table_mut() {
+                world.sort_values_by(|a, _, b, _| compare_keys(WORLD_KEYS, a, b));
+            }
+        }
+    }
+
+    assign_positions(root, &mut 0);
+    space_tables(root, &mut true);
+    Ok(doc.to_string())
+}
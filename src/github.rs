@@ -0,0 +1,94 @@
+use crate::{bail, Result};
+use reqwest::{header, Client};
+use serde::Deserialize;
+
+#[derive(Debug, Clone)]
+pub struct Release {
+    pub tag: String,
+    pub url: String,
+    // Download URL of the `.apworld` asset, if the release has one
+    pub apworld_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RawRelease {
+    tag_name: String,
+    html_url: String,
+    assets: Vec<RawAsset>,
+}
+
+#[derive(Deserialize)]
+struct RawAsset {
+    name: String,
+    browser_download_url: String,
+}
+
+// The owner and name of the repository `url` points to, when it's the page
+// of a github repository rather than a file
+pub fn repository(url: &str) -> Option<(String, String)> {
+    let path = url
+        .strip_prefix("https://github.com/")?
+        .trim_end_matches('/')
+        .trim_end_matches(".git");
+    match path.split('/').collect::<Vec<_>>().as_slice() {
+        [owner, repo] if !owner.is_empty() && !repo.is_empty() => {
+            Some((owner.to_string(), repo.to_string()))
+        }
+        _ => None,
+    }
+}
+
+pub async fn latest_release(
+    client: &Client,
+    token: Option<&str>,
+    owner: &str,
+    repo: &str,
+) -> Result<Release> {
+    let url = format!(
+        "https://api.github.com/repos/{}/{}/releases/latest",
+        owner, repo
+    );
+    // The API refuses requests without a user agent, which the client may
+    // not set
+    let mut request = client.get(&url).header(
+        header::USER_AGENT,
+        concat!("apwm/", env!("CARGO_PKG_VERSION")),
+    );
+    // Only sent to the API, downloads can be anywhere
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let response = request.send().await?;
+    if !response.status().is_success() {
+        bail!(
+            "Couldn't get the latest release of {}/{}: {}",
+            owner,
+            repo,
+            response.status()
+        );
+    }
+
+    let release = serde_json::from_slice::<RawRelease>(&response.bytes().await?)?;
+    Ok(Release {
+        apworld_url: release
+            .assets
+            .into_iter()
+            .find(|asset| asset.name.ends_with(".apworld"))
+            .map(|asset| asset.browser_download_url),
+        tag: release.tag_name,
+        url: release.html_url,
+    })
+}
+
+// The repository any github link points to, be it the repository page, one
+// of its releases or one of their assets
+pub fn owning_repository(url: &str) -> Option<(String, String)> {
+    let mut path = url.strip_prefix("https://github.com/")?.split('/');
+    let owner = path.next().filter(|owner| !owner.is_empty())?;
+    let repo = path.next()?.trim_end_matches(".git");
+    if repo.is_empty() {
+        return None;
+    }
+
+    Some((owner.to_string(), repo.to_string()))
+}
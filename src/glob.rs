@@ -0,0 +1,38 @@
+// Matches `text` against a shell style pattern. `?` matches any character
+// and `*` any run of characters except `/`, `**` also matches `/`.
+pub fn matches(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let text = text.chars().collect::<Vec<_>>();
+    matches_from(&pattern, &text)
+}
+
+fn matches_from(pattern: &[char], text: &[char]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        // `**/` also matches no directory at all
+        ['*', '*', '/', rest @ ..] if matches_from(rest, text) => true,
+        ['*', '*', rest @ ..] => (0..=text.len()).any(|i| matches_from(rest, &text[i..])),
+        ['*', rest @ ..] => {
+            let run = text.iter().take_while(|&&c| c != '/').count();
+            (0..=run).any(|i| matches_from(rest, &text[i..]))
+        }
+        ['?', rest @ ..] => !text.is_empty() && text[0] != '/' && matches_from(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && matches_from(rest, &text[1..]),
+    }
+}
+
+// Whether a `/` separated path is matched by one of `patterns`, gitignore
+// style: patterns without a `/` are matched against every component of the
+// path, others against the path and its parent directories
+pub fn matches_path(patterns: &[String], path: &str) -> bool {
+    let components = path.split('/').collect::<Vec<_>>();
+    patterns.iter().any(|pattern| {
+        let pattern = pattern.trim_end_matches('/');
+        if !pattern.contains('/') {
+            return components.iter().any(|c| matches(pattern, c));
+        }
+
+        let pattern = pattern.trim_start_matches('/');
+        (1..=components.len()).any(|i| matches(pattern, &components[..i].join("/")))
+    })
+}
@@ -0,0 +1,86 @@
+use crate::Result;
+use serde::Serialize;
+use std::{fmt::Write, path::Path};
+
+use crate::{
+    date,
+    revision::{self, RevisionInfo},
+    DiffOptions, Index, IndexDiff, WorkDirs,
+};
+
+// What changed in an index between two revisions of its git repository
+#[derive(Serialize, Debug)]
+pub struct IndexChangelog {
+    pub from: RevisionInfo,
+    pub to: RevisionInfo,
+    // Commits that changed the index in between, oldest first
+    pub commits: Vec<RevisionInfo>,
+    pub diff: IndexDiff,
+}
+
+impl IndexChangelog {
+    // A post announcing the world updates, titled with the dates of both
+    // revisions
+    pub fn to_markdown(&self) -> String {
+        let mut markdown = format!(
+            "# World updates from {} to {}\n\n",
+            date::format_date(self.from.time),
+            date::format_date(self.to.time)
+        );
+        if self.diff.is_empty() {
+            markdown.push_str("No worlds changed.\n\n");
+        }
+        markdown.push_str(&self.diff.to_markdown());
+        let _ = writeln!(
+            markdown,
+            "_{} commit{}, {:.8}..{:.8}_",
+            self.commits.len(),
+            if self.commits.len() == 1 { "" } else { "s" },
+            self.from.id,
+            self.to.id
+        );
+
+        markdown
+    }
+}
+
+// Builds the changelog of an index between the `from` and `to` revisions of
+// its git repository. With `release_notes`, worlds whose version changed get
+// downloaded from both revisions to include their release notes, otherwise
+// nothing gets downloaded. Revisions get exported in, and download into, the
+// temporary directory of `dirs`.
+pub async fn changelog(
+    index_file: &Path,
+    from: &str,
+    to: &str,
+    release_notes: bool,
+    dirs: &WorkDirs,
+) -> Result<IndexChangelog> {
+    let old_dir = dirs.tempdir()?;
+    let mut old = Index::new(&revision::export_revision(
+        index_file,
+        from,
+        old_dir.path(),
+    )?)?;
+    old.dirs = dirs.clone();
+    let new_dir = dirs.tempdir()?;
+    let mut new = Index::new(&revision::export_revision(index_file, to, new_dir.path())?)?;
+    new.dirs = dirs.clone();
+
+    let options = DiffOptions {
+        stat_only: true,
+        ..Default::default()
+    };
+    let diff = if release_notes {
+        old.diff(&new, &options).await?
+    } else {
+        old.diff_metadata(&new, &options)
+    };
+
+    Ok(IndexChangelog {
+        from: revision::describe_revision(index_file, from)?,
+        to: revision::describe_revision(index_file, to)?,
+        commits: revision::commits_between(index_file, from, to)?,
+        diff,
+    })
+}
@@ -0,0 +1,362 @@
+use regex::Regex;
+use std::fmt::Write;
+
+use crate::diff::{DiffStats, FileChange, IndexDiff, WorldChange, WorldDiff};
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum HtmlLayout {
+    #[default]
+    Unified,
+    SideBySide,
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; }
+table.diff { border-collapse: collapse; width: 100%; font-family: monospace; font-size: 13px; }
+table.diff td { padding: 0 6px; white-space: pre-wrap; vertical-align: top; }
+td.num { color: #888; text-align: right; user-select: none; width: 1%; }
+tr.hunk td { background: #eef4ff; color: #555; }
+.add { background: #e6ffec; }
+.del { background: #ffebe9; }
+.kw { color: #cf222e; }
+.str { color: #0a3069; }
+.com { color: #6e7781; font-style: italic; }
+.num-lit { color: #0550ae; }
+";
+
+const PYTHON_KEYWORDS: &str = "False|None|True|and|as|assert|async|await|break|class|continue|def|del|elif|else|except|finally|for|from|global|if|import|in|is|lambda|match|case|nonlocal|not|or|pass|raise|return|try|while|with|yield";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Highlighter {
+    python: Regex,
+}
+
+impl Highlighter {
+    fn new() -> Self {
+        let python = format!(
+            r#"(?P<com>#.*)|(?P<str>"(?:\\.|[^"\\])*"?|'(?:\\.|[^'\\])*'?)|(?P<kw>\b(?:{})\b)|(?P<num>\b\d[\d_.]*\b)"#,
+            PYTHON_KEYWORDS
+        );
+        Self {
+            python: Regex::new(&python).unwrap(),
+        }
+    }
+
+    // Escapes a line of `file`, highlighting it if it's python. Lines are
+    // highlighted on their own so multiline strings aren't handled.
+    fn line(&self, file: &str, line: &str) -> String {
+        if !file.ends_with(".py") {
+            return escape(line);
+        }
+
+        let mut html = String::new();
+        let mut last = 0;
+        for captures in self.python.captures_iter(line) {
+            let Some(token) = captures.get(0) else {
+                continue;
+            };
+            let class = ["com", "str", "kw", "num"]
+                .into_iter()
+                .find(|name| captures.name(name).is_some())
+                .map(|name| if name == "num" { "num-lit" } else { name })
+                .unwrap_or_default();
+            html.push_str(&escape(&line[last..token.start()]));
+            let _ = write!(
+                html,
+                "<span class=\"{}\">{}</span>",
+                class,
+                escape(token.as_str())
+            );
+            last = token.end();
+        }
+        html.push_str(&escape(&line[last..]));
+
+        html
+    }
+}
+
+enum DiffLine<'a> {
+    Hunk(&'a str),
+    Context(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+fn parse_unified(diff: &str) -> Vec<DiffLine<'_>> {
+    diff.lines()
+        .filter(|line| !line.starts_with("--- ") && !line.starts_with("+++ "))
+        .filter_map(|line| {
+            if line.starts_with("@@") {
+                return Some(DiffLine::Hunk(line));
+            }
+            let (marker, content) = line.split_at(line.len().min(1));
+            match marker {
+                "+" => Some(DiffLine::Added(content)),
+                "-" => Some(DiffLine::Removed(content)),
+                " " | "" => Some(DiffLine::Context(content)),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// Old and new line numbers from a `@@ -a,b +c,d @@` hunk header
+fn hunk_start(header: &str) -> (usize, usize) {
+    let mut numbers = header.split(' ').skip(1).take(2).map(|range| {
+        range[1..]
+            .split(',')
+            .next()
+            .and_then(|start| start.parse().ok())
+            .unwrap_or(1)
+    });
+    (numbers.next().unwrap_or(1), numbers.next().unwrap_or(1))
+}
+
+fn render_unified(html: &mut String, highlighter: &Highlighter, file: &str, diff: &str) {
+    let (mut old, mut new) = (1, 1);
+    for line in parse_unified(diff) {
+        let (class, old_num, new_num, marker, content) = match line {
+            DiffLine::Hunk(header) => {
+                (old, new) = hunk_start(header);
+                let _ = writeln!(
+                    html,
+                    "<tr class=\"hunk\"><td class=\"num\"></td><td class=\"num\"></td><td>{}</td></tr>",
+                    escape(header)
+                );
+                continue;
+            }
+            DiffLine::Context(content) => {
+                old += 1;
+                new += 1;
+                ("", Some(old - 1), Some(new - 1), " ", content)
+            }
+            DiffLine::Removed(content) => {
+                old += 1;
+                ("del", Some(old - 1), None, "-", content)
+            }
+            DiffLine::Added(content) => {
+                new += 1;
+                ("add", None, Some(new - 1), "+", content)
+            }
+        };
+        let _ = writeln!(
+            html,
+            "<tr class=\"{}\"><td class=\"num\">{}</td><td class=\"num\">{}</td><td>{}{}</td></tr>",
+            class,
+            old_num.map(|n| n.to_string()).unwrap_or_default(),
+            new_num.map(|n| n.to_string()).unwrap_or_default(),
+            marker,
+            highlighter.line(file, content)
+        );
+    }
+}
+
+fn side_cell(
+    highlighter: &Highlighter,
+    file: &str,
+    line: Option<(usize, &str)>,
+    class: &str,
+) -> String {
+    match line {
+        Some((number, content)) => format!(
+            "<td class=\"num\">{}</td><td class=\"{}\">{}</td>",
+            number,
+            class,
+            highlighter.line(file, content)
+        ),
+        None => "<td class=\"num\"></td><td></td>".to_string(),
+    }
+}
+
+// Pairs up the removed and added lines of a change on the same rows
+fn flush_changes(
+    html: &mut String,
+    highlighter: &Highlighter,
+    file: &str,
+    removed: &mut Vec<(usize, &str)>,
+    added: &mut Vec<(usize, &str)>,
+) {
+    for i in 0..removed.len().max(added.len()) {
+        let _ = writeln!(
+            html,
+            "<tr>{}{}</tr>",
+            side_cell(highlighter, file, removed.get(i).copied(), "del"),
+            side_cell(highlighter, file, added.get(i).copied(), "add")
+        );
+    }
+    removed.clear();
+    added.clear();
+}
+
+fn render_side_by_side(html: &mut String, highlighter: &Highlighter, file: &str, diff: &str) {
+    let (mut old, mut new) = (1, 1);
+    let mut removed = vec![];
+    let mut added = vec![];
+
+    for line in parse_unified(diff) {
+        match line {
+            DiffLine::Removed(content) => {
+                removed.push((old, content));
+                old += 1;
+            }
+            DiffLine::Added(content) => {
+                added.push((new, content));
+                new += 1;
+            }
+            DiffLine::Hunk(header) => {
+                flush_changes(html, highlighter, file, &mut removed, &mut added);
+                (old, new) = hunk_start(header);
+                let _ = writeln!(
+                    html,
+                    "<tr class=\"hunk\"><td class=\"num\"></td><td colspan=\"3\">{}</td></tr>",
+                    escape(header)
+                );
+            }
+            DiffLine::Context(content) => {
+                flush_changes(html, highlighter, file, &mut removed, &mut added);
+                let _ = writeln!(
+                    html,
+                    "<tr>{}{}</tr>",
+                    side_cell(highlighter, file, Some((old, content)), ""),
+                    side_cell(highlighter, file, Some((new, content)), "")
+                );
+                old += 1;
+                new += 1;
+            }
+        }
+    }
+    flush_changes(html, highlighter, file, &mut removed, &mut added);
+}
+
+fn render_file(
+    html: &mut String,
+    highlighter: &Highlighter,
+    file: &FileChange,
+    layout: HtmlLayout,
+) {
+    let _ = writeln!(html, "<h3>{}</h3>", escape(&file.path));
+    if let Some(binary) = &file.binary {
+        let _ = writeln!(
+            html,
+            "<p>Binary file changed: {}</p>",
+            escape(&binary.to_string())
+        );
+    }
+    let Some(diff) = &file.diff else {
+        return;
+    };
+
+    let _ = writeln!(html, "<table class=\"diff\">");
+    match layout {
+        HtmlLayout::Unified => render_unified(html, highlighter, &file.path, diff),
+        HtmlLayout::SideBySide => render_side_by_side(html, highlighter, &file.path, diff),
+    }
+    let _ = writeln!(html, "</table>");
+}
+
+impl IndexDiff {
+    // Renders the content changes of the diff as a standalone HTML page, with
+    // python files syntax highlighted
+    pub fn to_html(&self, layout: HtmlLayout) -> String {
+        let renderer = HtmlRenderer::new(layout);
+
+        let mut html = renderer.header();
+        for (key, world) in &self.worlds {
+            html.push_str(&renderer.world(key, world));
+        }
+        html.push_str(&renderer.footer());
+
+        html
+    }
+}
+
+// Renders the page of `IndexDiff::to_html` one piece at a time, for
+// destination diffs streamed through `visit_destination_changes`
+pub struct HtmlRenderer {
+    highlighter: Highlighter,
+    layout: HtmlLayout,
+}
+
+impl HtmlRenderer {
+    pub fn new(layout: HtmlLayout) -> Self {
+        Self {
+            highlighter: Highlighter::new(),
+            layout,
+        }
+    }
+
+    pub fn header(&self) -> String {
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<style>{}</style>\n</head>\n<body>\n",
+            STYLE
+        )
+    }
+
+    // The changes of a world, with the content diffs of its files
+    pub fn world(&self, key: &str, world: &WorldDiff) -> String {
+        let mut html = String::new();
+        let _ = writeln!(html, "<h2>{} ({})</h2>", escape(&world.name), escape(key));
+        for change in &world.changes {
+            let description = match change {
+                WorldChange::Added { version, origin } => {
+                    format!("Added, version {} from {}", version, origin)
+                }
+                WorldChange::Removed { version, .. } => {
+                    format!("Removed, was version {}", version)
+                }
+                WorldChange::VersionChanged { old, new, bump } => {
+                    format!("Version {} → {} ({})", old, new, bump)
+                }
+                WorldChange::OriginChanged { old, new } => format!("Origin {} → {}", old, new),
+                WorldChange::PatchesChanged { old, new } => {
+                    format!("Patches [{}] → [{}]", old.join(", "), new.join(", "))
+                }
+                WorldChange::Reuploaded {
+                    version,
+                    old_sha256,
+                    new_sha256,
+                } => format!(
+                    "Content changed without a version bump, still {} ({:.12} → {:.12})",
+                    version, old_sha256, new_sha256
+                ),
+                WorldChange::Updated { files } => {
+                    html.push_str(&self.stats(&world.stats()));
+                    for file in files {
+                        html.push_str(&self.file(file));
+                    }
+                    continue;
+                }
+            };
+            let _ = writeln!(html, "<p>{}</p>", escape(&description));
+        }
+        if let Some(changelog) = &world.changelog {
+            let _ = writeln!(
+                html,
+                "<details><summary>Changelog</summary><pre>{}</pre></details>",
+                escape(changelog)
+            );
+        }
+
+        html
+    }
+
+    pub fn file(&self, file: &FileChange) -> String {
+        let mut html = String::new();
+        render_file(&mut html, &self.highlighter, file, self.layout);
+        html
+    }
+
+    pub fn stats(&self, stats: &DiffStats) -> String {
+        format!("<p>{}</p>\n", escape(&stats.to_string()))
+    }
+
+    pub fn footer(&self) -> String {
+        "</body>\n</html>\n".to_string()
+    }
+}
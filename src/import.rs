@@ -0,0 +1,156 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::{apworld, inspect, junk, manifest, ApworldInfo, Error, Result};
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ImportStatus {
+    // Already in the index under `key`, by key or by game name
+    Known {
+        key: String,
+        version: Option<String>,
+    },
+    // Added to the index under `key`, as a local world at `local`
+    New {
+        key: String,
+        local: String,
+    },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportedWorld {
+    // The name of the world in the scanned directory
+    pub file: String,
+    // See `content_sha256`
+    pub sha256: String,
+    pub game: Option<String>,
+    pub version: Option<String>,
+    pub status: ImportStatus,
+}
+
+// A world found in a worlds directory, before matching it with the index
+pub struct FoundWorld {
+    pub path: PathBuf,
+    pub file: String,
+    // The name of its python package
+    pub package: String,
+    pub sha256: String,
+    pub info: ApworldInfo,
+}
+
+// Finds the worlds of a `worlds` or `custom_worlds` directory: `.apworld`
+// files and package directories with an `__init__.py`. Anything else, like
+// junk or archipelago's own modules, is skipped.
+pub fn scan(dir: &Path) -> Result<Vec<FoundWorld>> {
+    let mut worlds = vec![];
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let path = entry.path();
+        let file = entry.file_name().to_string_lossy().into_owned();
+        if file.starts_with(['.', '_']) || junk::is_junk(&file) {
+            continue;
+        }
+
+        let package = if path.is_dir() {
+            if !path.join("__init__.py").is_file() {
+                continue;
+            }
+            file.clone()
+        } else if let Some(stem) = file.strip_suffix(".apworld") {
+            apworld::entry_names(&path)?
+                .iter()
+                .find_map(|name| Some(name.split_once('/')?.0.to_string()))
+                .unwrap_or_else(|| stem.to_string())
+        } else {
+            continue;
+        };
+
+        worlds.push(FoundWorld {
+            sha256: manifest::content_sha256(&path)?,
+            info: inspect::inspect(&path)?,
+            path,
+            file,
+            package,
+        });
+    }
+    worlds.sort_by(|a, b| a.file.cmp(&b.file));
+
+    Ok(worlds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Index;
+
+    fn world(dir: &Path, game: &str) {
+        std::fs::create_dir_all(dir).unwrap();
+        std::fs::write(
+            dir.join("__init__.py"),
+            format!(
+                "from worlds.AutoWorld import World\n\nclass GameWorld(World):\n    game = \"{}\"\n",
+                game
+            ),
+        )
+        .unwrap();
+    }
+
+    fn statuses(imported: &[ImportedWorld]) -> Vec<String> {
+        imported
+            .iter()
+            .map(|world| match &world.status {
+                ImportStatus::Known { key, .. } => format!("{} is {}", world.file, key),
+                ImportStatus::New { key, local } => {
+                    format!("{} as {} in {}", world.file, key, local)
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn matches_worlds_with_the_index_and_adds_the_others() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index_dir = tmp.path().join("index");
+        world(&index_dir.join("ok"), "Ok Game");
+        let index_file = index_dir.join("index.toml");
+        std::fs::write(
+            &index_file,
+            "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+             archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+             required_global_files = []\n\n\
+             [worlds.ok]\nname = \"Ok Game\"\nlocal = \"ok\"\nversion = \"1.0\"\n\n\
+             [worlds.super_metroid]\nname = \"Super Metroid\"\nsupported = \"sm\"\n",
+        )
+        .unwrap();
+
+        let custom_worlds = tmp.path().join("custom_worlds");
+        world(&custom_worlds.join("ok"), "Ok Game");
+        world(&custom_worlds.join("ok_fork"), "Ok Game");
+        world(&custom_worlds.join("sm"), "Super Metroid");
+        world(&custom_worlds.join("new_world"), "New Game");
+        std::fs::create_dir_all(custom_worlds.join("__pycache__")).unwrap();
+        std::fs::create_dir_all(custom_worlds.join("not_a_world")).unwrap();
+        std::fs::write(custom_worlds.join("notes.txt"), "").unwrap();
+
+        let index = Index::new(&index_file).unwrap();
+        let expected = [
+            "new_world as new_world in worlds/new_world",
+            "ok is ok",
+            "ok_fork is ok",
+            "sm is super_metroid",
+        ];
+        let imported = index.import_worlds(&custom_worlds, true).unwrap();
+        assert_eq!(statuses(&imported), expected);
+        assert!(!index_dir.join("worlds").exists());
+
+        let imported = index.import_worlds(&custom_worlds, false).unwrap();
+        assert_eq!(statuses(&imported), expected);
+        assert!(index_dir.join("worlds/new_world/__init__.py").is_file());
+        let index = Index::new(&index_file).unwrap();
+        assert_eq!(index.worlds["new_world"].name, "New Game");
+
+        let imported = index.import_worlds(&custom_worlds, false).unwrap();
+        assert_eq!(statuses(&imported)[0], "new_world is new_world");
+    }
+}
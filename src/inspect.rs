@@ -0,0 +1,141 @@
+use crate::Result;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{
+    apworld,
+    license::{self, LicenseInfo},
+    WorldVersion,
+};
+
+// What an apworld says about itself, as opposed to what the index says
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct ApworldInfo {
+    pub game: Option<String>,
+    pub version: Option<String>,
+    pub minimum_ap_version: Option<String>,
+    pub maximum_ap_version: Option<String>,
+    pub license: Option<LicenseInfo>,
+}
+
+#[derive(Deserialize)]
+struct ApworldManifest {
+    game: Option<String>,
+    world_version: Option<String>,
+    minimum_ap_version: Option<String>,
+    maximum_ap_version: Option<String>,
+}
+
+fn find_game(source: &str) -> Option<String> {
+    let re = Regex::new(r#"(?m)^\s+game\s*(?::\s*[\w\[\]]+\s*)?=\s*["']([^"']+)["']"#).unwrap();
+    re.captures(source).map(|captures| captures[1].to_string())
+}
+
+// `required_client_version = (0, 5, 0)` on the World subclass
+fn find_required_version(source: &str) -> Option<String> {
+    let re = Regex::new(r"(?m)^\s+required_client_version\s*=\s*\(([\d,\s]+)\)").unwrap();
+    let captures = re.captures(source)?;
+    let components = captures[1]
+        .split(',')
+        .map(str::trim)
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+
+    Some(components.join("."))
+}
+
+fn find_version(source: &str) -> Option<String> {
+    let re = Regex::new(r#"(?m)^__version__\s*=\s*["']([^"']+)["']"#).unwrap();
+    re.captures(source).map(|captures| captures[1].to_string())
+}
+
+// Inspects an `.apworld` file or a world package directory. The
+// `archipelago.json` manifest takes precedence, then `__init__.py` and
+// finally every other python file of the world.
+pub fn inspect(path: &Path) -> Result<ApworldInfo> {
+    let files = apworld::package_files(path, |name| {
+        name == "archipelago.json" || name.ends_with(".py") || license::is_license_file(name)
+    })?;
+
+    let mut info = ApworldInfo {
+        license: files
+            .iter()
+            .find(|(name, _)| license::is_license_file(name))
+            .map(|(name, content)| license::identify(name, content)),
+        ..Default::default()
+    };
+    if let Some(manifest) = files.get("archipelago.json") {
+        if let Ok(manifest) = serde_json::from_slice::<ApworldManifest>(manifest) {
+            info.game = manifest.game;
+            info.version = manifest.world_version;
+            info.minimum_ap_version = manifest.minimum_ap_version;
+            info.maximum_ap_version = manifest.maximum_ap_version;
+        }
+    }
+
+    let init = files
+        .get("__init__.py")
+        .map(|content| String::from_utf8_lossy(content));
+    let sources = init.into_iter().chain(
+        files
+            .iter()
+            .filter(|(name, _)| name.ends_with(".py") && *name != "__init__.py")
+            .map(|(_, content)| String::from_utf8_lossy(content)),
+    );
+    for source in sources {
+        if info.game.is_none() {
+            info.game = find_game(&source);
+        }
+        if info.version.is_none() {
+            info.version = find_version(&source);
+        }
+        if info.minimum_ap_version.is_none() {
+            info.minimum_ap_version = find_required_version(&source);
+        }
+    }
+
+    Ok(info)
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum CompatibilityIssue {
+    RequiresNewer { minimum: String },
+    RequiresOlder { maximum: String },
+}
+
+impl std::fmt::Display for CompatibilityIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompatibilityIssue::RequiresNewer { minimum } => {
+                write!(f, "requires archipelago {} or newer", minimum)
+            }
+            CompatibilityIssue::RequiresOlder { maximum } => {
+                write!(f, "requires archipelago {} or older", maximum)
+            }
+        }
+    }
+}
+
+impl ApworldInfo {
+    pub fn compatibility_issue(&self, ap_version: &str) -> Option<CompatibilityIssue> {
+        if let Some(minimum) = &self.minimum_ap_version {
+            if WorldVersion::from(ap_version) < WorldVersion::from(minimum.as_str()) {
+                return Some(CompatibilityIssue::RequiresNewer {
+                    minimum: minimum.clone(),
+                });
+            }
+        }
+
+        if let Some(maximum) = &self.maximum_ap_version {
+            if WorldVersion::from(ap_version) > WorldVersion::from(maximum.as_str()) {
+                return Some(CompatibilityIssue::RequiresOlder {
+                    maximum: maximum.clone(),
+                });
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,67 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{apworld, Result};
+#[cfg(feature = "refresh")]
+use crate::{delete_file_or_dir, Error};
+
+const JUNK_DIRS: &[&str] = &[
+    "__pycache__",
+    ".git",
+    ".idea",
+    ".vscode",
+    ".mypy_cache",
+    ".pytest_cache",
+];
+const JUNK_FILES: &[&str] = &[".DS_Store", "Thumbs.db", "desktop.ini"];
+const JUNK_EXTENSIONS: &[&str] = &["pyc", "pyo", "swp", "swo", "orig", "rej"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct JunkFile {
+    pub path: String,
+    pub size: u64,
+}
+
+// Whether a `/` separated path points to, or is inside of, something that
+// has no business being shipped in an apworld
+pub fn is_junk(path: &str) -> bool {
+    let mut components = path.split('/').filter(|c| !c.is_empty());
+    let Some(file_name) = components.next_back() else {
+        return false;
+    };
+
+    components.any(|c| JUNK_DIRS.contains(&c))
+        || JUNK_DIRS.contains(&file_name)
+        || JUNK_FILES.contains(&file_name)
+        || file_name.ends_with('~')
+        || file_name
+            .rsplit_once('.')
+            .is_some_and(|(_, ext)| JUNK_EXTENSIONS.contains(&ext))
+}
+
+pub fn find_junk(path: &Path) -> Result<Vec<JunkFile>> {
+    Ok(apworld::package_file_sizes(path)?
+        .into_iter()
+        .filter(|(name, _)| is_junk(name))
+        .map(|(path, size)| JunkFile { path, size })
+        .collect())
+}
+
+#[cfg(feature = "refresh")]
+pub fn strip_junk(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if is_junk(&name) {
+            delete_file_or_dir(&entry.path())?;
+        } else if entry
+            .file_type()
+            .map_err(|e| Error::io(entry.path(), e))?
+            .is_dir()
+        {
+            strip_junk(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,102 @@
+use std::path::Path;
+
+use crate::{apworld, Result};
+#[cfg(feature = "refresh")]
+use crate::{bail, scratch_dir_beside, Error};
+
+// How many archives deep we're willing to dig when fixing nested apworlds
+#[cfg(feature = "refresh")]
+const MAX_NESTING: usize = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LayoutProblem {
+    // The apworld only contains another archive
+    NestedArchive(String),
+    // `__init__.py` sits directly at the root of the archive
+    MissingPackageDirectory,
+    // The python package isn't a top-level directory, `found` is where it is
+    PackageTooDeep { found: String },
+}
+
+impl std::fmt::Display for LayoutProblem {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LayoutProblem::NestedArchive(inner) => {
+                write!(f, "the apworld is an archive wrapping {}", inner)
+            }
+            LayoutProblem::MissingPackageDirectory => write!(
+                f,
+                "__init__.py is at the root of the archive instead of inside a directory"
+            ),
+            LayoutProblem::PackageTooDeep { found } => write!(
+                f,
+                "the python package is {}/ instead of a top-level directory",
+                found
+            ),
+        }
+    }
+}
+
+pub fn diagnose(apworld: &Path) -> Result<Option<LayoutProblem>> {
+    let names = apworld::entry_names(apworld)?;
+    let files = names
+        .iter()
+        .filter(|name| !name.ends_with('/'))
+        .collect::<Vec<_>>();
+
+    if let [inner] = files.as_slice() {
+        if inner.ends_with(".apworld") || inner.ends_with(".zip") {
+            return Ok(Some(LayoutProblem::NestedArchive(inner.to_string())));
+        }
+    }
+
+    let shallowest_init = files
+        .iter()
+        .filter(|name| name.as_str() == "__init__.py" || name.ends_with("/__init__.py"))
+        .min_by_key(|name| name.matches('/').count());
+    let Some(init) = shallowest_init else {
+        return Ok(None);
+    };
+
+    Ok(match init.matches('/').count() {
+        0 => Some(LayoutProblem::MissingPackageDirectory),
+        1 => None,
+        _ => Some(LayoutProblem::PackageTooDeep {
+            found: init.trim_end_matches("/__init__.py").to_string(),
+        }),
+    })
+}
+
+// Rewrites `apworld` in place so that its python package ends up as the
+// `name` top-level directory
+#[cfg(feature = "refresh")]
+pub fn fix(apworld: &Path, name: &str) -> Result<()> {
+    for _ in 0..MAX_NESTING {
+        match diagnose(apworld)? {
+            None => return Ok(()),
+            Some(LayoutProblem::NestedArchive(inner)) => {
+                let content = apworld::read_entry(apworld, &inner)?;
+                std::fs::write(apworld, content).map_err(|e| Error::io(apworld, e))?;
+            }
+            Some(LayoutProblem::MissingPackageDirectory) => {
+                let extracted = scratch_dir_beside(apworld)?;
+                apworld::extract(apworld, &extracted.path().join(name))?;
+                apworld::pack(extracted.path(), apworld)?;
+            }
+            Some(LayoutProblem::PackageTooDeep { found }) => {
+                let extracted = scratch_dir_beside(apworld)?;
+                apworld::extract(apworld, extracted.path())?;
+                let repacked = scratch_dir_beside(apworld)?;
+                std::fs::rename(extracted.path().join(found), repacked.path().join(name))
+                    .map_err(|e| Error::io(repacked.path().join(name), e))?;
+                apworld::pack(repacked.path(), apworld)?;
+            }
+        }
+    }
+
+    bail!(
+        "Couldn't fix the layout of {} after {} attempts",
+        apworld.to_string_lossy(),
+        MAX_NESTING
+    )
+}
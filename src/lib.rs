@@ -1,22 +1,177 @@
-use anyhow::{anyhow, Result};
-use git2::{build::RepoBuilder, AutotagOption, FetchOptions};
+use anyhow::anyhow;
+use error::bail;
 use http::Uri;
-use serde::{Deserialize, Deserializer};
+use pipeline::ResolutionCache;
+#[cfg(feature = "refresh")]
+use pipeline::{cache_artifact, cached_artifact};
+use resolver::Downloader;
+use serde::{Deserialize, Deserializer, Serialize};
 use std::{
-    collections::BTreeMap,
-    fs::{remove_dir_all, OpenOptions},
+    collections::{BTreeMap, BTreeSet},
     path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::{Duration, SystemTime},
+};
+#[cfg(feature = "refresh")]
+use std::{
+    fs::{remove_dir_all, OpenOptions},
+    time::Instant,
 };
+#[cfg(feature = "refresh")]
+use tracing::Instrument;
+
+mod apworld;
+#[cfg(feature = "zip")]
+mod archive;
+#[cfg(feature = "blocking")]
+mod blocking;
+mod builder;
+mod cancel;
+#[cfg(feature = "refresh")]
+mod changelog;
+mod clock;
+mod config;
+mod content;
+mod datapackage;
+mod date;
+mod details;
+mod diff;
+mod discord;
+#[cfg(feature = "git")]
+mod doctor;
+#[cfg(feature = "refresh")]
+mod drift;
+mod error;
+mod fmt;
+#[cfg(feature = "http")]
+mod github;
+mod glob;
+#[cfg(feature = "git")]
+mod history;
+mod html;
+mod import;
+mod inspect;
+mod junk;
+mod layout;
+mod license;
+#[cfg(feature = "http")]
+mod links;
+mod listing;
+mod manifest;
+mod mirror;
+mod options;
+#[cfg(all(feature = "git", feature = "http"))]
+mod outdated;
+mod patch;
+mod pipeline;
+#[cfg(feature = "refresh")]
+mod plan;
+mod pyversion;
+mod resolver;
+#[cfg(feature = "git")]
+mod revision;
+mod sandbox;
+mod scan;
+mod search;
+#[cfg(feature = "serve")]
+mod serve;
+mod size;
+mod stats;
+#[cfg(feature = "refresh")]
+mod suggest;
+mod summary;
+mod syntax;
+#[cfg(feature = "refresh")]
+mod template;
+mod terminal;
+mod transform;
+mod validate;
+mod vendored;
+mod verify;
+mod version;
+mod vfs;
+
+#[cfg(feature = "refresh")]
+pub use apworld::pack_world;
+#[cfg(feature = "blocking")]
+pub use blocking::download_apworld_blocking;
+pub use builder::{IndexBuilder, WorldBuilder};
+pub use cancel::CancellationToken;
+pub use clock::{Clock, ManualClock, SystemClock};
+pub use config::{resolve_secret, Config, Credentials, Notifications, PROJECT_CONFIG};
+pub use content::{diff_indexes, validate_index};
+pub use datapackage::DataPackage;
+pub use date::{format_date, format_timestamp};
+pub use details::{DeployedWorld, PatchDetails, WorldDetails};
+#[cfg(feature = "refresh")]
+pub use diff::{diff_destinations, visit_destination_changes};
+pub use diff::{
+    diff_manifests, human_size, visit_dir_changes, BinarySummary, DestinationChange, DiffOptions,
+    DiffOutcome, DiffStats, FileChange, FileChangeKind, IndexDiff, VersionChange, WorldChange,
+    WorldDiff,
+};
+pub use discord::{DiscordEmbed, DiscordMessage};
+#[cfg(feature = "git")]
+pub use doctor::{diagnose, Diagnostic, DiagnosticStatus};
+#[cfg(feature = "refresh")]
+pub use drift::{DriftKind, FileDrift};
+pub use error::{Error, Result};
+pub use fmt::format_index;
+#[cfg(feature = "git")]
+pub use history::{changelog, IndexChangelog};
+pub use html::{HtmlLayout, HtmlRenderer};
+pub use import::{ImportStatus, ImportedWorld};
+pub use inspect::{ApworldInfo, CompatibilityIssue};
+pub use junk::{is_junk, JunkFile};
+pub use layout::LayoutProblem;
+pub use license::{LicenseInfo, LicensePolicy};
+#[cfg(feature = "http")]
+pub use links::{LinkCheck, LinkKind, LinkStatus, UrlAudit, UrlStatus};
+pub use listing::{OriginKind, WorldFilter, WorldListing};
+pub use manifest::{AppliedPatch, Manifest, WorldManifest};
+pub use mirror::{MirrorFailure, MirrorManifest, MirrorReport, MirroredWorld};
+pub use options::OptionSchema;
+#[cfg(all(feature = "git", feature = "http"))]
+pub use outdated::{UpstreamCheck, UpstreamSource, UpstreamStatus};
+pub use patch::{Patch, PatchCheck, PatchConflict, PatchStatus};
+pub use pipeline::{FetchedArtifact, InstalledWorld, ResolvedWorld};
+#[cfg(feature = "refresh")]
+pub use plan::{PlannedChange, PlannedWorld, RefreshAction, RefreshPlan};
+pub use pyversion::PythonRequirement;
+pub use resolver::{DownloadProgress, OriginResolver, ResolveFuture};
+#[cfg(feature = "git")]
+pub use revision::{commits_between, describe_revision, export_revision, RevisionInfo};
+pub use scan::{Finding, FindingKind};
+pub use search::SearchHit;
+#[cfg(feature = "serve")]
+pub use serve::Server;
+pub use size::{FileSize, SizeReport};
+pub use stats::{destination_stats, IndexStats, SizeStats, WorldSize};
+pub use summary::{FailedWorld, RefreshSummary, RefreshedWorld};
+pub use syntax::{SyntaxError, SyntaxPolicy};
+pub use terminal::TextRenderer;
+pub use transform::Transform;
+pub use validate::{validate_structure, StructureIssue};
+pub use vendored::{VendoredLibrary, VendoredLibraryDefinition, VendoredStatus};
+pub use verify::{verify_destination, DeploymentIssue};
+pub use version::{VersionBump, WorldVersion};
+pub use vfs::{DryRunFs, FsAction, MemoryFs, RealFs, Vfs};
 
 fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
-    std::fs::create_dir_all(&dst)?;
-    for entry in std::fs::read_dir(src)? {
-        let entry = entry?;
-        let ty = entry.file_type()?;
-        if ty.is_dir() {
+    std::fs::create_dir_all(dst).map_err(|e| Error::io(dst, e))?;
+    for entry in std::fs::read_dir(src).map_err(|e| Error::io(src, e))? {
+        let entry = entry.map_err(|e| Error::io(src, e))?;
+        let ty = entry.file_type().map_err(|e| Error::io(entry.path(), e))?;
+        if ty.is_symlink() {
+            bail!(
+                "Refusing to copy symlink {}",
+                entry.path().to_string_lossy()
+            );
+        } else if ty.is_dir() {
             copy_dir_all(&entry.path(), &dst.join(entry.file_name()))?;
         } else {
-            std::fs::copy(entry.path(), &dst.join(entry.file_name()))?;
+            std::fs::copy(entry.path(), dst.join(entry.file_name()))
+                .map_err(|e| Error::io(dst.join(entry.file_name()), e))?;
         }
     }
     Ok(())
@@ -29,33 +184,131 @@ fn copy_file_or_dir(destination: &Path, index_dir: &Path, local_path: &Path) ->
 
     let path = index_dir.join(local_path);
     if path.is_dir() {
-        copy_dir_all(&path, &destination)?;
+        copy_dir_all(&path, destination)?;
     } else if path.is_file() {
-        std::fs::copy(&path, &destination)?;
+        std::fs::copy(&path, destination).map_err(|e| Error::io(destination, e))?;
     }
 
     Ok(())
 }
 
+// A directory to prepare the content of `destination` in before renaming it
+// over, on the same filesystem for the rename to work
+#[cfg(feature = "refresh")]
+pub(crate) fn staging_dir(destination: &Path) -> Result<tempfile::TempDir> {
+    let parent = match destination.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    staging_dir_in(parent)
+}
+
+#[cfg(feature = "refresh")]
+pub(crate) fn staging_dir_in(dir: &Path) -> Result<tempfile::TempDir> {
+    std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+    let staging = tempfile::tempdir_in(dir)?;
+    // Temporary directories are only accessible to their owner, which the
+    // destination shouldn't inherit
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(staging.path(), std::fs::Permissions::from_mode(0o755))
+            .map_err(|e| Error::io(staging.path(), e))?;
+    }
+
+    Ok(staging)
+}
+
+// A scratch directory next to `path`, so work on a world happens where the
+// world was put rather than in the system's temporary directory
+#[cfg(feature = "refresh")]
+pub(crate) fn scratch_dir_beside(path: &Path) -> Result<tempfile::TempDir> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok(tempfile::Builder::new()
+        .prefix(".apwm")
+        .tempdir_in(parent)?)
+}
+
+// Where an index makes its temporary, staging and cache directories, the
+// system's temporary directory and the destination's parent by default.
+// Functions working without an index, like `diff_destinations`, take one to
+// know where their temporary directories go.
+#[derive(Debug, Clone, Default)]
+pub struct WorkDirs {
+    temp: Option<PathBuf>,
+    staging: Option<PathBuf>,
+    cache: Option<PathBuf>,
+}
+
+impl WorkDirs {
+    // Where temporary directories go instead of the system's temporary
+    // directory
+    pub fn set_temp_dir(&mut self, dir: &Path) {
+        self.temp = Some(dir.into());
+    }
+
+    #[cfg(feature = "refresh")]
+    pub fn tempdir(&self) -> Result<tempfile::TempDir> {
+        match &self.temp {
+            Some(dir) => {
+                std::fs::create_dir_all(dir).map_err(|e| Error::io(dir, e))?;
+                Ok(tempfile::tempdir_in(dir)?)
+            }
+            None => Ok(tempfile::tempdir()?),
+        }
+    }
+
+    #[cfg(feature = "refresh")]
+    pub(crate) fn cache(&self) -> Option<&Path> {
+        self.cache.as_deref()
+    }
+}
+
 fn delete_file_or_dir(path: &Path) -> Result<()> {
     if path.is_dir() {
-        std::fs::remove_dir_all(path)?;
+        std::fs::remove_dir_all(path).map_err(|e| Error::io(path, e))?;
     } else if path.is_file() {
-        std::fs::remove_file(path)?;
+        std::fs::remove_file(path).map_err(|e| Error::io(path, e))?;
     }
     Ok(())
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Common {
     #[serde(with = "http_serde::uri")]
     pub archipelago_repo: Uri,
     pub archipelago_version: String,
     pub homepage: String,
     pub required_global_files: Vec<String>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub strip_junk: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub fix_layout: bool,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub license_policy: LicensePolicy,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub syntax_policy: SyntaxPolicy,
+    // Where to generate player YAML templates during refresh, relative to the
+    // index file
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub templates_dir: Option<PathBuf>,
+    // Glob patterns of files to leave out of content diffs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub diff_ignore: Vec<String>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub enum WorldOrigin {
     #[serde(rename = "url")]
     Url(#[serde(with = "http_serde::uri")] Uri),
@@ -65,7 +318,36 @@ pub enum WorldOrigin {
     Local(PathBuf),
 }
 
+impl std::fmt::Display for WorldOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WorldOrigin::Url(uri) => write!(f, "{}", uri),
+            WorldOrigin::Supported(dir_name) => write!(f, "supported ({})", dir_name),
+            WorldOrigin::Local(path) => write!(f, "local ({})", path.to_string_lossy()),
+        }
+    }
+}
+
 impl WorldOrigin {
+    // Where a world with this origin ends up in a refreshed destination,
+    // either an `.apworld` file or its package directory
+    fn deployed_path(&self, name: &str, destination: &Path) -> Result<PathBuf> {
+        Ok(match self {
+            WorldOrigin::Local(path) => {
+                let Some(dir_name) = path.file_name() else {
+                    bail!(
+                        "The local path of {}, {}, doesn't end with a directory name",
+                        name,
+                        path.to_string_lossy()
+                    );
+                };
+                destination.join(dir_name)
+            }
+            WorldOrigin::Supported(dir_name) => destination.join(dir_name),
+            WorldOrigin::Url(_) => destination.join(format!("{}.apworld", name)),
+        })
+    }
+
     pub fn is_supported(&self) -> bool {
         matches!(self, WorldOrigin::Supported(_))
     }
@@ -73,31 +355,228 @@ impl WorldOrigin {
     pub fn is_local(&self) -> bool {
         matches!(self, WorldOrigin::Local(_))
     }
+
+    pub fn url(&self) -> Option<&Uri> {
+        match self {
+            WorldOrigin::Url(uri) => Some(uri),
+            _ => None,
+        }
+    }
+
+    // The directory of the world in the archipelago repository
+    pub fn supported_dir(&self) -> Option<&str> {
+        match self {
+            WorldOrigin::Supported(dir_name) => Some(dir_name),
+            _ => None,
+        }
+    }
+
+    // Relative to the index file
+    pub fn local_path(&self) -> Option<&Path> {
+        match self {
+            WorldOrigin::Local(path) => Some(path),
+            _ => None,
+        }
+    }
 }
 
 impl World {
-    async fn download_to(&self, destination: &Path, ap_dir: &Path, index_dir: &Path) -> Result<()> {
+    #[cfg(feature = "refresh")]
+    async fn download_to(
+        &self,
+        downloader: &Downloader,
+        name: &str,
+        destination: &Path,
+        ap_dir: &Path,
+        index_dir: &Path,
+        common: &Common,
+    ) -> Result<()> {
+        self.fetch_verified(downloader, name, destination, ap_dir, index_dir)
+            .await?;
+        self.prepare(name, destination, index_dir, common)
+    }
+
+    // Fetches the world as its origin has it, untouched
+    #[cfg(feature = "refresh")]
+    async fn fetch_verified(
+        &self,
+        downloader: &Downloader,
+        name: &str,
+        destination: &Path,
+        ap_dir: &Path,
+        index_dir: &Path,
+    ) -> Result<()> {
+        let fetched = async {
+            self.fetch_to(downloader, destination, ap_dir, index_dir)
+                .await?;
+            if destination.is_file() {
+                apworld::verify(destination)?;
+            }
+            Ok::<_, Error>(())
+        };
+        fetched.await.map_err(|source| match &self.origin {
+            WorldOrigin::Url(uri) => Error::Download {
+                world: name.into(),
+                url: uri.to_string(),
+                source: source.into(),
+            },
+            _ => source,
+        })
+    }
+
+    // Turns a fetched world into the one the index describes: fixed layout,
+    // patches, transforms, license and junk
+    #[cfg(feature = "refresh")]
+    fn prepare(
+        &self,
+        name: &str,
+        destination: &Path,
+        index_dir: &Path,
+        common: &Common,
+    ) -> Result<()> {
+        if common.fix_layout && destination.is_file() {
+            layout::fix(destination, name)?;
+        }
+
+        if self.has_patches()
+            || self.has_transforms()
+            || self.license_file.is_some()
+            || common.strip_junk
+        {
+            self.apply_changes(name, destination, index_dir, common.strip_junk)?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(feature = "refresh")]
+    async fn fetch_to(
+        &self,
+        downloader: &Downloader,
+        destination: &Path,
+        ap_dir: &Path,
+        index_dir: &Path,
+    ) -> Result<()> {
         match &self.origin {
-            WorldOrigin::Url(uri) => self.download_uri(uri, destination).await,
+            WorldOrigin::Url(uri) => self.download_uri(downloader, uri, destination).await,
             WorldOrigin::Supported(apworld) => {
-                self.download_supported(destination, ap_dir, &apworld).await
+                self.download_supported(destination, ap_dir, apworld).await
             }
-            WorldOrigin::Local(path) => copy_file_or_dir(destination, index_dir, &path),
+            WorldOrigin::Local(path) => copy_file_or_dir(destination, index_dir, path),
         }
     }
 
-    async fn download_uri(&self, uri: &Uri, destination: &Path) -> Result<()> {
-        if destination.exists() {
-            std::fs::remove_file(destination)?;
+    // Runs `f` on the world's package directory. `.apworld` files get
+    // extracted first and repacked in place afterwards.
+    #[cfg(feature = "refresh")]
+    fn with_package_dir<T>(
+        &self,
+        destination: &Path,
+        f: impl FnOnce(&Path) -> Result<T>,
+    ) -> Result<T> {
+        match &self.origin {
+            WorldOrigin::Supported(dir_name) => {
+                let dir = destination.join(dir_name);
+                sandbox::ensure_no_symlinks(&dir)?;
+                f(&dir)
+            }
+            _ if destination.is_dir() => {
+                sandbox::ensure_no_symlinks(destination)?;
+                f(destination)
+            }
+            _ => {
+                let extracted = scratch_dir_beside(destination)?;
+                apworld::extract(destination, extracted.path())?;
+                let result = f(&apworld::package_dir(extracted.path())?)?;
+                apworld::pack(extracted.path(), destination)?;
+                Ok(result)
+            }
         }
+    }
 
-        let req = reqwest::get(&uri.to_string()).await?;
-        let body = req.bytes().await?;
-        std::fs::write(destination, body)?;
+    fn manifest(&self, index_dir: &Path) -> Result<WorldManifest> {
+        let patches = self
+            .active_patches()
+            .map(|patch| AppliedPatch::new(&patch.path, &index_dir.join(&patch.path)))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(WorldManifest {
+            name: self.name.clone(),
+            game: self.info().and_then(|info| info.game.clone()),
+            version: self.version().to_string(),
+            origin: self.origin.clone(),
+            dependencies: self.dependencies.clone(),
+            patches,
+            sha256: None,
+        })
+    }
+
+    #[cfg(feature = "refresh")]
+    fn apply_patches(&self, name: &str, dir: &Path, index_dir: &Path) -> Result<()> {
+        for patch in self.active_patches() {
+            let _span = tracing::info_span!("patch", world = name, file = %patch.path).entered();
+            patch::apply(dir, &index_dir.join(&patch.path)).map_err(|source| Error::Patch {
+                world: name.into(),
+                file: patch.path.clone(),
+                source: source.into(),
+            })?;
+        }
 
         Ok(())
     }
 
+    #[cfg(feature = "refresh")]
+    fn apply_changes(
+        &self,
+        name: &str,
+        destination: &Path,
+        index_dir: &Path,
+        strip_junk: bool,
+    ) -> Result<()> {
+        self.with_package_dir(destination, |dir| {
+            self.apply_patches(name, dir, index_dir)?;
+            transform::apply_all(dir, index_dir, &self.transforms)?;
+            if strip_junk {
+                junk::strip_junk(dir)?;
+            }
+            if let Some(license_file) = &self.license_file {
+                if !license::has_license(dir)? {
+                    copy_file_or_dir(&dir.join("LICENSE"), index_dir, license_file)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    #[cfg(feature = "refresh")]
+    fn check_patches(&self, destination: &Path, index_dir: &Path) -> Result<Vec<PatchCheck>> {
+        self.with_package_dir(destination, |dir| {
+            self.active_patches()
+                .map(|patch| {
+                    Ok(PatchCheck {
+                        patch: patch.path.clone(),
+                        status: patch::check(dir, &index_dir.join(&patch.path))?,
+                    })
+                })
+                .collect()
+        })
+    }
+
+    #[cfg(feature = "refresh")]
+    async fn download_uri(
+        &self,
+        downloader: &Downloader,
+        uri: &Uri,
+        destination: &Path,
+    ) -> Result<()> {
+        if destination.exists() {
+            std::fs::remove_file(destination).map_err(|e| Error::io(destination, e))?;
+        }
+
+        downloader.fetch(&uri.to_string(), destination).await
+    }
+
+    #[cfg(feature = "refresh")]
     async fn download_supported(
         &self,
         destination: &Path,
@@ -106,10 +585,25 @@ impl World {
     ) -> Result<()> {
         let world_destination = destination.join(dir_name);
         if world_destination.exists() {
-            std::fs::remove_dir_all(&world_destination)?;
+            std::fs::remove_dir_all(&world_destination)
+                .map_err(|e| Error::io(&world_destination, e))?;
         }
 
         let apworld_dir = ap_dir.join("worlds").join(dir_name);
+        if !apworld_dir.is_dir() {
+            let mut message = format!("{} isn't a world of the archipelago checkout", dir_name);
+            let available = std::fs::read_dir(ap_dir.join("worlds"))
+                .map_err(|e| Error::io(ap_dir.join("worlds"), e))?
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_dir())
+                .filter_map(|entry| entry.file_name().into_string().ok())
+                .collect::<Vec<_>>();
+            let suggestions = suggest::near_misses(dir_name, available.iter().map(String::as_str));
+            if !suggestions.is_empty() {
+                message.push_str(&format!(", did you mean {}?", suggestions.join(", ")));
+            }
+            bail!(message);
+        }
         copy_dir_all(&apworld_dir, &world_destination)?;
 
         for dependency in &self.dependencies {
@@ -117,24 +611,96 @@ impl World {
             let dep_destination = destination.join(dependency);
 
             if dep_destination.exists() {
-                std::fs::remove_dir_all(&dep_destination)?;
+                std::fs::remove_dir_all(&dep_destination)
+                    .map_err(|e| Error::io(&dep_destination, e))?;
             }
 
             if dep_path.is_dir() {
                 copy_dir_all(&dep_path, &dep_destination)?;
             } else if dep_path.is_file() {
-                std::fs::copy(&dep_path, &dep_destination)?;
+                std::fs::copy(&dep_path, &dep_destination)
+                    .map_err(|e| Error::io(&dep_destination, e))?;
             }
         }
 
         Ok(())
     }
 
-    pub fn version(&self) -> &str {
+    // The declared version, falling back to the one found in the apworld
+    // itself once it has been resolved
+    pub fn version(&self) -> WorldVersion {
         self.version
-            .as_ref()
-            .map(String::as_str)
-            .unwrap_or("Unknown")
+            .clone()
+            .or_else(|| self.info().and_then(|info| info.version.clone()))
+            .unwrap_or_else(|| "Unknown".into())
+            .into()
+    }
+
+    // The version as written in the index, supported worlds get the
+    // archipelago version
+    pub fn declared_version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    // What the apworld declares, as of the last time it was resolved
+    pub fn info(&self) -> Option<Arc<ApworldInfo>> {
+        self.info.read().unwrap().clone()
+    }
+
+    // The game the apworld declares once it has been resolved, the name of
+    // the world until then
+    pub fn game(&self) -> String {
+        self.info()
+            .and_then(|info| info.game.clone())
+            .unwrap_or_else(|| self.name.clone())
+    }
+
+    // The version found in the apworld when it disagrees with the index
+    pub fn version_mismatch(&self) -> Option<String> {
+        if self.is_supported() {
+            return None;
+        }
+
+        let declared = self.version.as_deref()?;
+        let found = self.info()?.version.clone()?;
+        if WorldVersion::from(declared) == WorldVersion::from(found.as_str()) {
+            return None;
+        }
+
+        Some(found)
+    }
+
+    pub fn license(&self) -> Option<LicenseInfo> {
+        self.info()?.license.clone()
+    }
+
+    fn package_path(&self, destination: &Path) -> PathBuf {
+        match &self.origin {
+            WorldOrigin::Supported(dir_name) => destination.join(dir_name),
+            _ => destination.into(),
+        }
+    }
+
+    #[cfg(feature = "refresh")]
+    fn validate(&self, name: &str, destination: &Path) -> Result<()> {
+        let issues = validate::validate_structure(&self.package_path(destination), name)?;
+        if !issues.is_empty() {
+            let issues = issues
+                .iter()
+                .map(|issue| format!("  - {}", issue))
+                .collect::<Vec<_>>();
+            bail!("{} isn't a valid apworld:\n{}", name, issues.join("\n"));
+        }
+
+        Ok(())
+    }
+
+    // Replaces what an earlier resolution found, for every clone of the index
+    #[cfg(feature = "refresh")]
+    fn resolve_info(&self, destination: &Path) -> Result<()> {
+        let info = inspect::inspect(&self.package_path(destination))?;
+        *self.info.write().unwrap() = Some(Arc::new(info));
+        Ok(())
     }
 
     pub fn url(&self) -> String {
@@ -144,8 +710,94 @@ impl World {
         }
     }
 
+    fn active_patch_names(&self) -> Vec<String> {
+        self.active_patches()
+            .map(|patch| patch.path.clone())
+            .collect()
+    }
+
+    fn empty_diff(&self) -> WorldDiff {
+        WorldDiff {
+            name: self.name.clone(),
+            home: self.home.clone(),
+            old_size: None,
+            new_size: None,
+            changes: vec![],
+            changelog: None,
+        }
+    }
+
+    // Every patch of the world, the ones of its patch sets first, including
+    // patches upstreamed in its current version
+    pub fn patches(&self) -> &[Patch] {
+        &self.patches
+    }
+
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+
+    // Patches that apply to the world's current version
+    pub fn active_patches(&self) -> impl Iterator<Item = &Patch> {
+        self.patches
+            .iter()
+            .filter(|patch| patch.applies_to(self.version.as_deref()))
+    }
+
+    // Urls can have `{version}`, `{major}`, `{minor}` and `{patch}` in them,
+    // replaced with the declared version
+    fn expand_url(&mut self, key: &str) -> Result<()> {
+        let WorldOrigin::Url(uri) = &self.origin else {
+            return Ok(());
+        };
+        let template = self
+            .url_template
+            .get_or_insert_with(|| uri.clone())
+            .to_string();
+        if !template.contains('{') {
+            self.url_template = None;
+            return Ok(());
+        }
+        let Some(version) = self.declared_version() else {
+            bail!("{} has placeholders in its url but no version", key);
+        };
+        let expanded = WorldVersion::from(version).expand(&template);
+        if expanded.contains('{') {
+            bail!(
+                "{} has placeholders in its url that can't be replaced with version {}",
+                key,
+                version
+            );
+        }
+        self.origin = WorldOrigin::Url(expanded.parse()?);
+
+        Ok(())
+    }
+
     pub fn has_patches(&self) -> bool {
-        !self.patches.is_empty()
+        self.active_patches().next().is_some()
+    }
+
+    // Files of the index the world uses, relative to the index file
+    fn index_files(&self) -> Vec<PathBuf> {
+        let local = match &self.origin {
+            WorldOrigin::Local(path) => Some(path.clone()),
+            _ => None,
+        };
+        local
+            .into_iter()
+            .chain(self.patches.iter().map(|patch| PathBuf::from(&patch.path)))
+            .chain(self.license_file.clone())
+            .chain(
+                self.transforms
+                    .iter()
+                    .filter_map(|transform| Some(transform.source()?.to_path_buf())),
+            )
+            .collect()
+    }
+
+    pub fn has_transforms(&self) -> bool {
+        !self.transforms.is_empty()
     }
 
     pub fn is_supported(&self) -> bool {
@@ -153,18 +805,95 @@ impl World {
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Debug, Clone)]
 pub struct World {
     pub name: String,
     #[serde(flatten)]
     pub origin: WorldOrigin,
     version: Option<String>,
     #[serde(default)]
-    patches: Vec<String>,
+    patches: Vec<Patch>,
+    #[serde(default)]
+    pub patch_sets: Vec<String>,
+    #[serde(default)]
+    transforms: Vec<Transform>,
     #[serde(deserialize_with = "empty_string_as_none", default)]
     pub home: Option<String>,
     #[serde(default)]
     pub dependencies: Vec<String>,
+    // Free form labels to group worlds by, only used for listing
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // A short summary of the world, only used for searching
+    pub description: Option<String>,
+    pub license_file: Option<PathBuf>,
+    // Where the author publishes the world, for local worlds vendoring it
+    #[serde(default, with = "http_serde::option::uri")]
+    pub upstream: Option<Uri>,
+    // Shared with the clones of the world, a refresh from any of them updates
+    // it for all
+    #[serde(skip)]
+    info: Arc<RwLock<Option<Arc<ApworldInfo>>>>,
+    // How many of `patches` come from patch sets
+    #[serde(skip)]
+    inherited_patches: usize,
+    // The url as written in the index when it has placeholders, `origin`
+    // has it expanded
+    #[serde(skip)]
+    url_template: Option<Uri>,
+}
+
+// Written back as it is in the index file, without the version supported
+// worlds get from the archipelago version or the patches of patch sets
+impl Serialize for World {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct RawWorld<'a> {
+            name: &'a str,
+            #[serde(flatten)]
+            origin: &'a WorldOrigin,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            version: Option<&'a str>,
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            patches: &'a [Patch],
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            patch_sets: &'a [String],
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            transforms: &'a [Transform],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            home: Option<&'a str>,
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            dependencies: &'a [String],
+            #[serde(skip_serializing_if = "<[_]>::is_empty")]
+            tags: &'a [String],
+            #[serde(skip_serializing_if = "Option::is_none")]
+            description: Option<&'a str>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            license_file: Option<&'a Path>,
+            #[serde(
+                skip_serializing_if = "Option::is_none",
+                with = "http_serde::option::uri"
+            )]
+            upstream: &'a Option<Uri>,
+        }
+
+        let template = self.url_template.clone().map(WorldOrigin::Url);
+        RawWorld {
+            name: &self.name,
+            origin: template.as_ref().unwrap_or(&self.origin),
+            version: self.version.as_deref().filter(|_| !self.is_supported()),
+            patches: &self.patches[self.inherited_patches..],
+            patch_sets: &self.patch_sets,
+            transforms: &self.transforms,
+            home: self.home.as_deref(),
+            dependencies: &self.dependencies,
+            tags: &self.tags,
+            description: self.description.as_deref(),
+            license_file: self.license_file.as_deref(),
+            upstream: &self.upstream,
+        }
+        .serialize(s)
+    }
 }
 
 fn empty_string_as_none<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String>, D::Error> {
@@ -172,106 +901,2221 @@ fn empty_string_as_none<'de, D: Deserializer<'de>>(d: D) -> Result<Option<String
     Ok(o.filter(|s| !s.is_empty()))
 }
 
-#[derive(Deserialize, Debug)]
+// The outcome of `Index::bump_archipelago`
+#[derive(Serialize, Debug)]
+pub struct ArchipelagoBump {
+    pub old_version: String,
+    pub new_version: String,
+    // External worlds whose apworld declares it can't run on the new version
+    pub incompatible: BTreeMap<String, CompatibilityIssue>,
+}
+
+// The outcome of `Index::update_world`
+#[derive(Serialize, Debug)]
+pub struct WorldUpdate {
+    pub old_version: Option<String>,
+    pub new_version: String,
+    pub url: String,
+    pub patches: Vec<PatchCheck>,
+}
+
+#[cfg(feature = "refresh")]
+async fn download_apworld(
+    downloader: &Downloader,
+    world: &str,
+    url: &str,
+    destination: &Path,
+) -> Result<()> {
+    tracing::info!("Downloading {}", url);
+    let download = async {
+        downloader.fetch(url, destination).await?;
+        apworld::verify(destination)
+    };
+
+    download.await.map_err(|source| Error::Download {
+        world: world.into(),
+        url: url.into(),
+        source: source.into(),
+    })
+}
+
+// Replaces a value of the index file, keeping the comments around it
+#[cfg(feature = "refresh")]
+fn set_string(table: &mut dyn toml_edit::TableLike, key: &str, value: &str) {
+    match table.get_mut(key).and_then(|item| item.as_value_mut()) {
+        Some(existing) => {
+            let decor = existing.decor().clone();
+            *existing = value.into();
+            *existing.decor_mut() = decor;
+        }
+        None => {
+            table.insert(key, toml_edit::value(value));
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Index {
     #[serde(skip)]
     path: PathBuf,
     pub common: Common,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub patch_sets: BTreeMap<String, Vec<Patch>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub vendored: BTreeMap<String, VendoredLibraryDefinition>,
     pub worlds: BTreeMap<String, World>,
+    #[serde(skip)]
+    downloader: Downloader,
+    #[serde(skip, default = "default_fs")]
+    fs: Arc<dyn Vfs>,
+    #[serde(skip, default = "default_clock")]
+    clock: Arc<dyn Clock>,
+    #[serde(skip)]
+    resolutions: Arc<ResolutionCache>,
+    #[serde(skip)]
+    dirs: WorkDirs,
+}
+
+// Services share an index between their tasks, behind an `Arc` or as clones
+// sharing their caches, loading it once and refreshing from it while serving
+// queries
+const _: fn() = || {
+    fn shared<T: Send + Sync>() {}
+    shared::<Index>();
+};
+
+fn default_fs() -> Arc<dyn Vfs> {
+    Arc::new(RealFs)
+}
+
+fn default_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
 }
 
 impl Index {
     pub fn new(index_path: &Path) -> Result<Self> {
-        let index_content = std::fs::read_to_string(index_path)?;
-        let deser = toml::Deserializer::new(&index_content);
+        let index_content =
+            std::fs::read_to_string(index_path).map_err(|e| Error::io(index_path, e))?;
+        Self::parse(&index_content, index_path)
+    }
 
-        let mut index: Index = serde_path_to_error::deserialize(deser)?;
+    // Loads an index that doesn't come from a file, like an HTTP response.
+    // Local worlds, patches and other files of the index are looked for in
+    // `index_dir`.
+    pub fn from_str(content: &str, index_dir: &Path) -> Result<Self> {
+        Self::parse(content, &index_dir.join("index.toml"))
+    }
+
+    pub fn from_reader(mut reader: impl std::io::Read, index_dir: &Path) -> Result<Self> {
+        let mut content = String::new();
+        reader
+            .read_to_string(&mut content)
+            .map_err(|e| Error::IndexParse {
+                path: index_dir.join("index.toml"),
+                source: e.into(),
+            })?;
+        Self::from_str(&content, index_dir)
+    }
+
+    // The index as TOML, without the comments and formatting of the file it
+    // was loaded from, `fmt` keeps those
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string(self)?)
+    }
+
+    fn parse(index_content: &str, index_path: &Path) -> Result<Self> {
+        let deser = toml::Deserializer::new(index_content);
+
+        let mut index: Index =
+            serde_path_to_error::deserialize(deser).map_err(|e| Error::IndexParse {
+                path: index_path.into(),
+                source: e.into(),
+            })?;
         index.path = index_path.into();
+        index.resolve_worlds().map_err(|source| Error::IndexParse {
+            path: index_path.into(),
+            source: source.into(),
+        })?;
+
+        Ok(index)
+    }
 
-        for (_, world) in index.worlds.iter_mut() {
+    // Pins supported worlds to the archipelago version, expands the patch
+    // sets of every world and the placeholders of their urls, and checks that
+    // the files they refer to stay inside the index directory
+    fn resolve_worlds(&mut self) -> Result<()> {
+        for (name, world) in self.worlds.iter_mut() {
             if world.origin.is_supported() {
-                world.version = Some(index.common.archipelago_version.clone());
+                world.version = Some(self.common.archipelago_version.clone());
+            }
+            world.expand_url(name)?;
+
+            let mut patches = vec![];
+            for set_name in &world.patch_sets {
+                let Some(set) = self.patch_sets.get(set_name) else {
+                    bail!("World {} references unknown patch set {}", name, set_name);
+                };
+                patches.extend(set.iter().cloned());
             }
+            world.inherited_patches = patches.len();
+            patches.append(&mut world.patches);
+            world.patches = patches;
         }
 
-        Ok(index)
+        content::check_contained(&self.worlds)
     }
 
-    pub async fn refresh_into(&self, destination: &Path) -> Result<()> {
-        let ap_tmp_dir = tempfile::tempdir()?;
-        let ap_tmp_dir = ap_tmp_dir.path();
+    // Worlds whose url has `scheme` get fetched by `resolver` instead of
+    // being downloaded over HTTP. Registering a resolver for `https` takes
+    // over every download.
+    pub fn register_resolver(&mut self, scheme: &str, resolver: impl OriginResolver + 'static) {
+        self.downloader.register(scheme, Arc::new(resolver));
+    }
 
-        {
-            let mut fetch_opts = FetchOptions::new();
-            fetch_opts.download_tags(AutotagOption::All);
+    // The client used for downloads and the github API, to configure TLS,
+    // proxies or middlewares. Link checks use their own client as they need
+    // to see redirects.
+    #[cfg(feature = "http")]
+    pub fn set_http_client(&mut self, client: reqwest::Client) {
+        self.downloader.set_client(client);
+    }
 
-            let repo = RepoBuilder::new()
-                .fetch_options(fetch_opts)
-                .clone(&self.common.archipelago_repo.to_string(), &ap_tmp_dir)?;
-            let git_ref =
-                repo.resolve_reference_from_short_name(&self.common.archipelago_version)?;
-            let tag = git_ref.peel_to_commit()?;
+    // The token sent to the github API when looking up releases, instead of
+    // the `GITHUB_TOKEN` environment variable
+    #[cfg(feature = "http")]
+    pub fn set_github_token(&mut self, token: impl Into<String>) {
+        self.downloader.set_github_token(token.into());
+    }
 
-            repo.checkout_tree(&tag.as_object(), None)?;
-        }
+    // Where checkouts, downloads and other temporary directories go instead
+    // of the system's temporary directory
+    pub fn set_temp_dir(&mut self, dir: &Path) {
+        self.dirs.set_temp_dir(dir);
+    }
 
-        if destination.exists() {
-            remove_dir_all(destination)?;
-        }
-        std::fs::create_dir_all(destination)?;
+    // Where refreshes and syncs prepare destinations instead of next to
+    // them. Destinations get copied over when it's on another filesystem.
+    pub fn set_staging_dir(&mut self, dir: &Path) {
+        self.dirs.staging = Some(dir.into());
+    }
 
-        let index_dir = self
-            .path
-            .parent()
-            .ok_or_else(|| anyhow::anyhow!("Index file doesn't have a parent dir"))?;
-        for (name, world) in &self.worlds {
-            let world_dest = match &world.origin {
-                WorldOrigin::Local(path) => destination.join(path.file_name().unwrap()),
-                WorldOrigin::Supported(_) => destination.into(),
-                WorldOrigin::Url(_) => destination.join(&format!("{}.apworld", name)),
-            };
+    // Where refreshes keep the worlds they download, to only download url
+    // worlds again when their url or version changed
+    pub fn set_cache_dir(&mut self, dir: &Path) {
+        self.dirs.cache = Some(dir.into());
+    }
 
-            world
-                .download_to(&world_dest, &ap_tmp_dir, &index_dir)
-                .await?
+    // Stages `built`, a destination prepared on disk by `refresh_staged`, on
+    // the filesystem of the index and moves it over `destination`
+    #[cfg(feature = "refresh")]
+    pub(crate) fn deploy(
+        &self,
+        built: &Path,
+        destination: &Path,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        let staging = vfs::StagingDir::new(self.fs.clone(), destination, &self.dirs)?;
+        self.fs.stage(built, staging.path())?;
+        cancel.check()?;
+        self.fs.install(staging.path(), destination)
+    }
+
+    // The directories set with `set_temp_dir` and friends, for functions
+    // working outside of the index
+    pub fn work_dirs(&self) -> &WorkDirs {
+        &self.dirs
+    }
+
+    // Where refreshes and syncs deploy to, the real filesystem by default
+    pub fn set_filesystem(&mut self, fs: Arc<dyn Vfs>) {
+        fs.set_clock(self.clock.clone());
+        self.fs = fs;
+    }
+
+    // What freshness checks like `should_refresh` tell the time with, the
+    // clock of the machine by default
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.fs.set_clock(clock.clone());
+        self.clock = clock;
+    }
+
+    // Calls `progress` as downloads go, to render progress bars or publish
+    // a status. Worlds fetched by a resolver don't report any progress.
+    pub fn on_download_progress(
+        &mut self,
+        progress: impl Fn(&DownloadProgress) + Send + Sync + 'static,
+    ) {
+        self.downloader.set_progress(Arc::new(progress));
+    }
+
+    // Rewrites the urls of worlds, whatever their origin, and of the
+    // archipelago repository right before fetching them, like to go through
+    // a caching proxy. `rewrite` returns the url to use instead, or `None` to
+    // keep it. Manifests and reports keep the urls of the index.
+    pub fn rewrite_urls(
+        &mut self,
+        rewrite: impl Fn(&str) -> Option<String> + Send + Sync + 'static,
+    ) {
+        self.downloader.set_rewrite(Arc::new(rewrite));
+    }
+
+    #[cfg(feature = "refresh")]
+    fn checkout_archipelago(&self, destination: &Path) -> Result<()> {
+        self.checkout_archipelago_at(
+            destination,
+            &self.common.archipelago_version,
+            &CancellationToken::new(),
+        )
+    }
+
+    #[cfg(feature = "refresh")]
+    fn checkout_archipelago_at(
+        &self,
+        destination: &Path,
+        version: &str,
+        cancel: &CancellationToken,
+    ) -> Result<()> {
+        tracing::info!(
+            "Checking out archipelago {} from {}",
+            version,
+            self.common.archipelago_repo
+        );
+        #[cfg(feature = "git")]
+        return revision::checkout(
+            &self
+                .downloader
+                .rewrite(&self.common.archipelago_repo.to_string()),
+            version,
+            destination,
+            cancel,
+        );
+        #[cfg(not(feature = "git"))]
+        {
+            let _ = (destination, cancel);
+            bail!("Can't check out archipelago, apwm was built without the git feature")
         }
+    }
 
-        for path in &self.common.required_global_files {
-            let file_path = Path::new("worlds").join(path);
-            let file_destination = destination.join(
-                Path::new(path)
-                    .file_name()
-                    .ok_or_else(|| anyhow!("Error while getting filename"))?,
+    // Makes sure every world's dependencies are in the archipelago checkout,
+    // reporting all the missing ones at once
+    #[cfg(feature = "refresh")]
+    fn check_dependencies(&self, ap_dir: &Path) -> Result<()> {
+        let missing = self
+            .worlds
+            .iter()
+            .flat_map(|(name, world)| {
+                world
+                    .dependencies
+                    .iter()
+                    .filter(|dependency| !ap_dir.join("worlds").join(dependency).exists())
+                    .map(move |dependency| format!("  - {} (needed by {})", dependency, name))
+            })
+            .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            bail!(
+                "Some dependencies are missing from archipelago {}:\n{}",
+                self.common.archipelago_version,
+                missing.join("\n")
             );
-            copy_file_or_dir(&file_destination, ap_tmp_dir, &file_path)?;
         }
 
-        let last_refreshed = destination.join(".last_refresh");
-        OpenOptions::new()
-            .create(true)
-            .write(true)
-            .open(last_refreshed)?;
-
         Ok(())
     }
 
-    pub fn should_refresh(&self, destination: &Path) -> bool {
-        let last_refreshed = destination.join(".last_refresh");
+    fn index_dir(&self) -> Result<&Path> {
+        Ok(self
+            .path
+            .parent()
+            .ok_or_else(|| anyhow!("Index file doesn't have a parent dir"))?)
+    }
 
-        let Ok(last_refreshed_metadata) = std::fs::metadata(last_refreshed) else {
-            return true;
-        };
-        let Ok(index_metadata) = std::fs::metadata(&self.path) else {
-            return true;
+    // Where a world gets installed into `destination`. Supported worlds are
+    // copied into their directory of it by the install.
+    fn world_destination(name: &str, world: &World, destination: &Path) -> Result<PathBuf> {
+        match &world.origin {
+            WorldOrigin::Supported(_) => Ok(destination.into()),
+            origin => origin.deployed_path(name, destination),
+        }
+    }
+
+    #[cfg(feature = "refresh")]
+    pub async fn refresh_into(&self, destination: &Path) -> Result<RefreshSummary> {
+        self.refresh_into_with(destination, &CancellationToken::new())
+            .await
+    }
+
+    // Refreshes into a staging directory next to `destination`, which only
+    // replaces it once every world went through. A failed or cancelled
+    // refresh, or dropping the future, leaves `destination` untouched and
+    // removes the staging directory. `destination` is on the filesystem set
+    // with `set_filesystem`. When worlds fail, the others still get refreshed
+    // to report all the failures at once in an `Error::Refresh`.
+    #[cfg(feature = "refresh")]
+    pub async fn refresh_into_with(
+        &self,
+        destination: &Path,
+        cancel: &CancellationToken,
+    ) -> Result<RefreshSummary> {
+        self.plan_refresh(destination)?.execute_with(cancel).await
+    }
+
+    // What refreshing into `destination` would do, compared with what it
+    // has, without doing anything yet
+    #[cfg(feature = "refresh")]
+    pub fn plan_refresh(&self, destination: &Path) -> Result<RefreshPlan<'_>> {
+        RefreshPlan::new(self, destination)
+    }
+
+    // Downloads, patches and checks the worlds in `keys` into `staged`, a
+    // directory of the real filesystem
+    #[cfg(feature = "refresh")]
+    async fn refresh_staged(
+        &self,
+        staged: &Path,
+        keys: &BTreeSet<String>,
+        cancel: &CancellationToken,
+    ) -> Result<RefreshSummary> {
+        let span = tracing::info_span!(
+            "refresh",
+            archipelago_version = %self.common.archipelago_version,
+            worlds = keys.len(),
+        );
+        self.stage_worlds(staged, keys, cancel)
+            .instrument(span)
+            .await
+    }
+
+    #[cfg(feature = "refresh")]
+    async fn stage_worlds(
+        &self,
+        staged: &Path,
+        keys: &BTreeSet<String>,
+        cancel: &CancellationToken,
+    ) -> Result<RefreshSummary> {
+        let started = Instant::now();
+        let ap_tmp_dir = self.dirs.tempdir()?;
+        let ap_tmp_dir = ap_tmp_dir.path();
+        self.checkout_archipelago_at(ap_tmp_dir, &self.common.archipelago_version, cancel)?;
+        self.check_dependencies(ap_tmp_dir)?;
+
+        let index_dir = self.index_dir()?;
+        let mut manifest = Manifest {
+            archipelago_version: self.common.archipelago_version.clone(),
+            python_version: pyversion::archipelago_minimum_python(ap_tmp_dir)?,
+            global_files: vec![],
+            worlds: BTreeMap::new(),
+        };
+        let mut summary = RefreshSummary {
+            archipelago_version: self.common.archipelago_version.clone(),
+            skipped: self
+                .worlds
+                .keys()
+                .filter(|key| !keys.contains(*key))
+                .cloned()
+                .collect(),
+            ..Default::default()
         };
+        let mut first_error = None;
+        let artifacts = self.dirs.tempdir()?;
+        for (name, world) in self.worlds.iter().filter(|(key, _)| keys.contains(*key)) {
+            cancel.check()?;
+            let world_started = Instant::now();
+            let span = tracing::info_span!(
+                "world",
+                key = %name,
+                origin = %world.origin,
+                version = %world.version(),
+            );
+            let installed = async {
+                tracing::info!("Refreshing {}", name);
+                let resolved = self.resolve_world(name)?;
+                let cache = self.artifact_cache(&resolved);
+                let cached = cache.and_then(|cache| cached_artifact(cache, &resolved));
+                let from_cache = cached.is_some();
+                let artifact = match cached {
+                    Some(artifact) => artifact,
+                    None => {
+                        let dir = cache.unwrap_or(artifacts.path());
+                        let artifact = cancel
+                            .run(self.fetch_in(&resolved, dir, ap_tmp_dir))
+                            .await?;
+                        if let Some(cache) = cache {
+                            cache_artifact(cache, &artifact)?;
+                        }
+                        artifact
+                    }
+                };
+                let installed = self.install_world(&artifact, staged)?;
+                if cache.is_none() {
+                    std::fs::remove_dir_all(&artifact.dir)
+                        .map_err(|e| Error::io(&artifact.dir, e))?;
+                }
+                Ok::<_, Error>((installed, from_cache))
+            };
+            match installed.instrument(span).await {
+                Ok((installed, cached)) => {
+                    summary.installed.push(RefreshedWorld {
+                        key: name.clone(),
+                        version: installed.manifest.version.as_str().into(),
+                        sha256: installed.manifest.sha256.clone(),
+                        cached,
+                        duration: world_started.elapsed(),
+                    });
+                    manifest.worlds.insert(name.clone(), installed.manifest);
+                }
+                Err(Error::Cancelled) => return Err(Error::Cancelled),
+                Err(e) => {
+                    tracing::error!("Couldn't refresh {}: {}", name, e);
+                    summary
+                        .failed
+                        .push(FailedWorld::new(name, world.version(), &e));
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        if let Some(source) = first_error {
+            summary.duration = started.elapsed();
+            return Err(Error::Refresh {
+                summary: Box::new(summary),
+                source: Box::new(source),
+            });
+        }
+
+        let duplicate_games = self
+            .duplicate_games()
+            .into_iter()
+            .map(|(game, worlds)| format!("  - {}: {}", game, worlds.join(", ")))
+            .collect::<Vec<_>>();
+        if !duplicate_games.is_empty() {
+            bail!(
+                "Several worlds declare the same game, archipelago won't load them:\n{}",
+                duplicate_games.join("\n")
+            );
+        }
+
+        for path in &self.common.required_global_files {
+            tracing::debug!("Copying global file {}", path);
+            let file_path = Path::new("worlds").join(path);
+            let file_name = Path::new(path)
+                .file_name()
+                .ok_or_else(|| anyhow!("Error while getting filename"))?;
+            copy_file_or_dir(&staged.join(file_name), ap_tmp_dir, &file_path)?;
+            manifest
+                .global_files
+                .push(file_name.to_string_lossy().into());
+        }
+
+        if let Some(templates_dir) = &self.common.templates_dir {
+            self.write_templates(staged, &index_dir.join(templates_dir))?;
+        }
+
+        manifest.write(staged)?;
+
+        // Stamped by the index's clock, which freshness checks compare with
+        let last_refreshed = staged.join(".last_refresh");
+        OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .open(&last_refreshed)?
+            .set_modified(self.clock.now())
+            .map_err(|e| Error::io(&last_refreshed, e))?;
+
+        summary.duration = started.elapsed();
+        Ok(summary)
+    }
+
+    // Applies every world's patches in a throwaway directory and reports how
+    // each of them went. Nothing gets deployed.
+    #[cfg(feature = "refresh")]
+    pub async fn check_patches(&self) -> Result<BTreeMap<String, Vec<PatchCheck>>> {
+        let ap_tmp_dir = self.dirs.tempdir()?;
+        let ap_tmp_dir = ap_tmp_dir.path();
+        if self
+            .worlds
+            .values()
+            .any(|world| world.is_supported() && world.has_patches())
+        {
+            self.checkout_archipelago(ap_tmp_dir)?;
+        }
+
+        let destination = self.dirs.tempdir()?;
+        let index_dir = self.index_dir()?;
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            if !world.has_patches() {
+                continue;
+            }
+
+            let world_dest = Self::world_destination(name, world, destination.path())?;
+            world
+                .fetch_verified(&self.downloader, name, &world_dest, ap_tmp_dir, index_dir)
+                .await?;
+            report.insert(name.clone(), world.check_patches(&world_dest, index_dir)?);
+        }
+
+        Ok(report)
+    }
+
+    // Worlds whose apworld declares they can't run on the index's archipelago
+    // version. Only meaningful once the worlds have been resolved.
+    pub fn incompatible_worlds(&self) -> BTreeMap<&str, CompatibilityIssue> {
+        self.worlds
+            .iter()
+            .filter(|(_, world)| !world.is_supported())
+            .filter_map(|(name, world)| {
+                let issue = world
+                    .info()?
+                    .compatibility_issue(&self.common.archipelago_version)?;
+                Some((name.as_str(), issue))
+            })
+            .collect()
+    }
+
+    // Game names declared by more than one resolved world, along with the
+    // worlds declaring them
+    pub fn duplicate_games(&self) -> BTreeMap<String, Vec<&str>> {
+        let mut games = BTreeMap::<String, Vec<&str>>::new();
+        for (name, world) in &self.worlds {
+            if let Some(game) = world.info().and_then(|info| info.game.clone()) {
+                games.entry(game).or_default().push(name);
+            }
+        }
+
+        games.retain(|_, worlds| worlds.len() > 1);
+        games
+    }
+
+    // Resolved non supported worlds without a license. Supported worlds are
+    // covered by archipelago's own license.
+    pub fn unlicensed_worlds(&self) -> Vec<&str> {
+        self.worlds
+            .iter()
+            .filter(|(_, world)| !world.is_supported() && world.info().is_some())
+            .filter(|(_, world)| world.license().is_none())
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+
+    // Sizes of the worlds of a refreshed destination, with their `top`
+    // largest files
+    pub fn size_report(
+        &self,
+        destination: &Path,
+        top: usize,
+    ) -> Result<BTreeMap<String, SizeReport>> {
+        self.worlds
+            .iter()
+            .map(|(name, world)| {
+                let world_dest = Self::world_destination(name, world, destination)?;
+                let report = size::size_report(&world.package_path(&world_dest), top)?;
+                Ok((name.clone(), report))
+            })
+            .collect()
+    }
+
+    // Summarizes the index, along with the sizes of its worlds in a refreshed
+    // destination when given
+    pub fn stats(&self, destination: Option<&Path>, top: usize) -> Result<IndexStats> {
+        stats::compute(
+            self.worlds
+                .iter()
+                .map(|(name, world)| (name.as_str(), &world.origin, world.has_patches())),
+            destination,
+            top,
+        )
+    }
+
+    // Scans the worlds of a refreshed destination for risky code patterns
+    pub fn scan_code(&self, destination: &Path) -> Result<BTreeMap<String, Vec<Finding>>> {
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let world_dest = Self::world_destination(name, world, destination)?;
+            let findings = scan::scan(&world.package_path(&world_dest))?;
+            if !findings.is_empty() {
+                report.insert(name.clone(), findings);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Writes a default player YAML template for every world of a refreshed
+    // destination. The templates directory is recreated from scratch.
+    #[cfg(feature = "refresh")]
+    fn write_templates(&self, destination: &Path, templates_dir: &Path) -> Result<()> {
+        if templates_dir.exists() {
+            remove_dir_all(templates_dir)?;
+        }
+        std::fs::create_dir_all(templates_dir).map_err(|e| Error::io(templates_dir, e))?;
+
+        for (name, options) in self.options_schemas(destination)? {
+            let world = &self.worlds[&name];
+            std::fs::write(
+                templates_dir.join(format!("{}.yaml", name)),
+                template::render(&world.game(), &self.common.archipelago_version, &options),
+            )
+            .map_err(|e| Error::io(templates_dir.join(format!("{}.yaml", name)), e))?;
+        }
+
+        Ok(())
+    }
+
+    // The minimum python version required by the worlds of a refreshed
+    // destination. Worlds not needing anything newer than 3.8 are left out.
+    pub fn python_requirements(
+        &self,
+        destination: &Path,
+    ) -> Result<BTreeMap<String, PythonRequirement>> {
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let world_dest = Self::world_destination(name, world, destination)?;
+            if let Some(requirement) = pyversion::minimum_python(&world.package_path(&world_dest))?
+            {
+                report.insert(name.clone(), requirement);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Worlds of a refreshed destination requiring a newer python than the
+    // oldest one the deployed archipelago release accepts
+    pub fn python_incompatible_worlds(
+        &self,
+        destination: &Path,
+    ) -> Result<BTreeMap<String, PythonRequirement>> {
+        let Some(python_version) = Manifest::load(destination)?.python_version else {
+            return Ok(BTreeMap::new());
+        };
+
+        let mut report = self.python_requirements(destination)?;
+        report.retain(|_, requirement| {
+            WorldVersion::from(requirement.version.as_str())
+                > WorldVersion::from(python_version.as_str())
+        });
+        Ok(report)
+    }
+
+    // Extracts the items and locations of the worlds of a refreshed destination
+    pub fn data_packages(&self, destination: &Path) -> Result<BTreeMap<String, DataPackage>> {
+        self.worlds
+            .iter()
+            .map(|(name, world)| {
+                let world_dest = Self::world_destination(name, world, destination)?;
+                let package = datapackage::extract_data_package(&world.package_path(&world_dest))?;
+                Ok((name.clone(), package))
+            })
+            .collect()
+    }
+
+    // Extracts the options of the worlds of a refreshed destination
+    pub fn options_schemas(
+        &self,
+        destination: &Path,
+    ) -> Result<BTreeMap<String, Vec<OptionSchema>>> {
+        self.worlds
+            .iter()
+            .map(|(name, world)| {
+                let world_dest = Self::world_destination(name, world, destination)?;
+                let options = options::extract_options(&world.package_path(&world_dest))?;
+                Ok((name.clone(), options))
+            })
+            .collect()
+    }
 
-        let Ok(last_refreshed_mtime) = last_refreshed_metadata.modified() else {
+    // Lists junk files in the worlds of a refreshed destination
+    pub fn find_junk(&self, destination: &Path) -> Result<BTreeMap<String, Vec<JunkFile>>> {
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let world_dest = Self::world_destination(name, world, destination)?;
+            let junk = junk::find_junk(&world.package_path(&world_dest))?;
+            if !junk.is_empty() {
+                report.insert(name.clone(), junk);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Parses the python sources of the worlds of a refreshed destination
+    pub fn check_syntax(&self, destination: &Path) -> Result<BTreeMap<String, Vec<SyntaxError>>> {
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let world_dest = Self::world_destination(name, world, destination)?;
+            let errors = syntax::check_syntax(&world.package_path(&world_dest))?;
+            if !errors.is_empty() {
+                report.insert(name.clone(), errors);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Lists the libraries vendored by the worlds of a refreshed destination.
+    // Libraries declared in the index take precedence over the builtin ones.
+    pub fn find_vendored(
+        &self,
+        destination: &Path,
+    ) -> Result<BTreeMap<String, Vec<VendoredLibrary>>> {
+        let mut definitions = vendored::builtin_definitions();
+        definitions.extend(self.vendored.clone());
+
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let world_dest = Self::world_destination(name, world, destination)?;
+            let found = vendored::find_vendored(&world.package_path(&world_dest), &definitions)?;
+            if !found.is_empty() {
+                report.insert(name.clone(), found);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // A summary line for each world matching `filter`, in key order
+    pub fn list(&self, filter: &WorldFilter) -> Vec<WorldListing> {
+        self.worlds_matching(filter)
+            .map(|(key, world)| WorldListing {
+                key: key.to_string(),
+                name: world.name.clone(),
+                version: world.declared_version().map(WorldVersion::from),
+                origin: OriginKind::from(&world.origin),
+                source: match &world.origin {
+                    WorldOrigin::Url(uri) => uri.to_string(),
+                    WorldOrigin::Supported(dir_name) => dir_name.clone(),
+                    WorldOrigin::Local(path) => path.to_string_lossy().into_owned(),
+                },
+                home: world.home.clone(),
+                tags: world.tags.clone(),
+                patches: world.active_patch_names(),
+            })
+            .collect()
+    }
+
+    // The worlds `filter` matches, with their key, in key order
+    pub fn worlds_matching<'a>(
+        &'a self,
+        filter: &'a WorldFilter,
+    ) -> impl Iterator<Item = (&'a str, &'a World)> {
+        self.worlds
+            .iter()
+            .filter(|(_, world)| filter.matches(world))
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    pub fn worlds_by_origin(&self, origin: OriginKind) -> impl Iterator<Item = (&str, &World)> {
+        self.worlds
+            .iter()
+            .filter(move |(_, world)| OriginKind::from(&world.origin) == origin)
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    // Worlds with patches applying to their current version
+    pub fn worlds_with_patches(&self) -> impl Iterator<Item = (&str, &World)> {
+        self.worlds
+            .iter()
+            .filter(|(_, world)| world.has_patches())
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    pub fn worlds_with_tag<'a>(
+        &'a self,
+        tag: &'a str,
+    ) -> impl Iterator<Item = (&'a str, &'a World)> {
+        self.worlds
+            .iter()
+            .filter(move |(_, world)| world.tags.iter().any(|world_tag| world_tag == tag))
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    // The first world, in key order, whose `game` is `game`, ignoring case
+    pub fn find_by_game_name(&self, game: &str) -> Option<(&str, &World)> {
+        self.worlds
+            .iter()
+            .find(|(_, world)| world.game().eq_ignore_ascii_case(game))
+            .map(|(key, world)| (key.as_str(), world))
+    }
+
+    fn deployed(key: &str, world: &World, destination: &Path) -> Result<Option<DeployedWorld>> {
+        let path = world.origin.deployed_path(key, destination)?;
+        let manifest = Manifest::load(destination)
+            .ok()
+            .and_then(|mut manifest| manifest.worlds.remove(key));
+        if !path.exists() && manifest.is_none() {
+            return Ok(None);
+        }
+
+        let sha256 = match manifest.as_ref().and_then(|m| m.sha256.clone()) {
+            Some(sha256) => Some(sha256),
+            None if path.exists() => Some(manifest::content_sha256(&path)?),
+            None => None,
+        };
+        let last_updated = std::fs::metadata(&path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|mtime| mtime.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|age| age.as_secs());
+
+        Ok(Some(DeployedWorld {
+            path: path.to_string_lossy().into(),
+            version: manifest.as_ref().map(|m| m.version.clone()),
+            sha256,
+            patches: manifest.map(|m| m.patches).unwrap_or_default(),
+            last_updated,
+        }))
+    }
+
+    // Looks for worlds matching every word of `query` in their key, name,
+    // tags or description, best matches first and by key among equals
+    pub fn search(&self, query: &str) -> Result<Vec<SearchHit>> {
+        let terms = search::terms(query);
+        let index_dir = self.index_dir()?;
+
+        let mut hits = self
+            .worlds
+            .iter()
+            .filter_map(|(key, world)| {
+                Some(SearchHit {
+                    index: index_dir.into(),
+                    key: key.clone(),
+                    name: world.name.clone(),
+                    description: world.description.clone(),
+                    tags: world.tags.clone(),
+                    score: search::score(key, world, &terms)?,
+                })
+            })
+            .collect::<Vec<_>>();
+        hits.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.key.cmp(&b.key)));
+
+        Ok(hits)
+    }
+
+    // Gathers everything known about a world from the index and, when given,
+    // from a refreshed destination
+    pub fn show(&self, key: &str, destination: Option<&Path>) -> Result<WorldDetails> {
+        let world = self
+            .worlds
+            .get(key)
+            .ok_or_else(|| anyhow!("Unknown world {}", key))?;
+
+        let resolved = match &world.origin {
+            WorldOrigin::Url(uri) => uri.to_string(),
+            WorldOrigin::Supported(dir_name) => format!(
+                "{} at {}, worlds/{}",
+                self.common.archipelago_repo, self.common.archipelago_version, dir_name
+            ),
+            WorldOrigin::Local(path) => self.index_dir()?.join(path).to_string_lossy().into(),
+        };
+
+        let deployed = match destination {
+            Some(destination) => Self::deployed(key, world, destination)?,
+            None => None,
+        };
+
+        Ok(WorldDetails {
+            key: key.to_string(),
+            name: world.name.clone(),
+            version: world.version.clone(),
+            origin: world.origin.clone(),
+            resolved,
+            home: world.home.clone(),
+            upstream: world.upstream.as_ref().map(Uri::to_string),
+            tags: world.tags.clone(),
+            dependencies: world.dependencies.clone(),
+            patches: world
+                .patches
+                .iter()
+                .map(|patch| PatchDetails {
+                    path: patch.path.clone(),
+                    before: patch.before.clone(),
+                    active: patch.applies_to(world.version.as_deref()),
+                })
+                .collect(),
+            license_file: world
+                .license_file
+                .as_ref()
+                .map(|path| path.to_string_lossy().into()),
+            deployed,
+        })
+    }
+
+    // Checks that every origin URL and homepage of the index is still alive.
+    // Links that are fine are reported too.
+    #[cfg(feature = "http")]
+    pub async fn check_links(&self) -> Result<Vec<LinkCheck>> {
+        let client = links::client()?;
+
+        let mut report = vec![];
+        for (name, world) in &self.worlds {
+            let origin = match &world.origin {
+                WorldOrigin::Url(uri) if !self.downloader.resolves(uri) => Some(uri.to_string()),
+                _ => None,
+            };
+            let urls = origin
+                .map(|url| (LinkKind::Origin, url))
+                .into_iter()
+                .chain(world.home.clone().map(|url| (LinkKind::Home, url)));
+
+            for (kind, url) in urls {
+                report.push(LinkCheck {
+                    world: name.clone(),
+                    kind,
+                    status: links::check(&client, &url).await,
+                    url,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Checks every url of the index, `concurrency` at a time, following
+    // redirects to suggest fixes. Patches live next to the index so they have
+    // no url to check.
+    #[cfg(feature = "http")]
+    pub async fn check_urls(&self, concurrency: usize) -> Result<Vec<UrlAudit>> {
+        let client = links::client()?;
+        let semaphore = Arc::new(tokio::sync::Semaphore::new(concurrency.max(1)));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        let mut position = 0;
+        for (name, world) in &self.worlds {
+            let origin = match &world.origin {
+                WorldOrigin::Url(uri) if !self.downloader.resolves(uri) => Some(uri.to_string()),
+                _ => None,
+            };
+            let urls = origin
+                .map(|url| (LinkKind::Origin, url))
+                .into_iter()
+                .chain(world.home.clone().map(|url| (LinkKind::Home, url)))
+                .chain(
+                    world
+                        .upstream
+                        .as_ref()
+                        .map(|url| (LinkKind::Upstream, url.to_string())),
+                );
+
+            for (kind, url) in urls {
+                let client = client.clone();
+                let semaphore = semaphore.clone();
+                let world = name.clone();
+                tasks.spawn(async move {
+                    let _permit = semaphore.acquire_owned().await;
+                    let (status, redirects) = links::audit(&client, &url).await;
+                    let audit = UrlAudit {
+                        suggestion: links::suggest_fix(&kind, &url, &status),
+                        world,
+                        kind,
+                        url,
+                        status,
+                        redirects,
+                    };
+                    (position, audit)
+                });
+                position += 1;
+            }
+        }
+
+        // Reported in index order, whichever finished first
+        let mut report = vec![];
+        while let Some(audit) = tasks.join_next().await {
+            report.push(audit?);
+        }
+        report.sort_by_key(|(position, _)| *position);
+
+        Ok(report.into_iter().map(|(_, audit)| audit).collect())
+    }
+
+    // Looks upstream for newer versions of the worlds than the ones the index
+    // pins. Supported worlds are left out as they follow archipelago, and so
+    // are worlds without any link to look at.
+    #[cfg(all(feature = "git", feature = "http"))]
+    pub async fn outdated(&self) -> Result<Vec<UpstreamCheck>> {
+        let client = self.downloader.client();
+        let token = self.downloader.github_token();
+
+        let mut report = vec![];
+        for (name, world) in &self.worlds {
+            let download = match &world.origin {
+                WorldOrigin::Url(uri) => Some(uri.to_string()),
+                WorldOrigin::Supported(_) => continue,
+                WorldOrigin::Local(_) => None,
+            };
+            let links = download
+                .iter()
+                .cloned()
+                .chain(world.upstream.as_ref().map(Uri::to_string))
+                .chain(world.home.clone())
+                .collect::<Vec<_>>();
+
+            let check = outdated::check(
+                client,
+                token.as_deref(),
+                name,
+                world.version.as_deref(),
+                &links,
+                download.as_deref(),
+            )
+            .await;
+            report.extend(check);
+        }
+
+        Ok(report)
+    }
+
+    // Compares local worlds declaring an `upstream` against the release they
+    // point to. Worlds matching their upstream are left out.
+    #[cfg(feature = "refresh")]
+    pub async fn local_drift(&self) -> Result<BTreeMap<String, Vec<FileDrift>>> {
+        let index_dir = self.index_dir()?;
+        let upstream_dir = self.dirs.tempdir()?;
+
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let (WorldOrigin::Local(path), Some(upstream)) = (&world.origin, &world.upstream)
+            else {
+                continue;
+            };
+
+            let upstream_path = upstream_dir.path().join(format!("{}.apworld", name));
+            world
+                .download_uri(&self.downloader, upstream, &upstream_path)
+                .await?;
+            let drift = drift::compare(&index_dir.join(path), &upstream_path)?;
+            if !drift.is_empty() {
+                report.insert(name.clone(), drift);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Statically looks for overlapping hunks between the patches of each
+    // world, without downloading anything.
+    pub fn patch_conflicts(&self) -> Result<BTreeMap<String, Vec<PatchConflict>>> {
+        let index_dir = self.index_dir()?;
+        let mut report = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            let patches = world
+                .active_patches()
+                .map(|patch| {
+                    let content = std::fs::read_to_string(index_dir.join(&patch.path))
+                        .map_err(|e| Error::io(index_dir.join(&patch.path), e))?;
+                    Ok((patch.path.clone(), content))
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            let conflicts = patch::find_conflicts(&patches);
+            if !conflicts.is_empty() {
+                report.insert(name.clone(), conflicts);
+            }
+        }
+
+        Ok(report)
+    }
+
+    // Downloads a world with its changes applied into `destination`. Returns
+    // the deployed world, either an `.apworld` or a directory, along with its
+    // package directory.
+    #[cfg(feature = "refresh")]
+    async fn stage_world(
+        &self,
+        name: &str,
+        world: &World,
+        ap_dir: &Path,
+        destination: &Path,
+    ) -> Result<(PathBuf, PathBuf)> {
+        let world_dest = Self::world_destination(name, world, destination)?;
+        world
+            .download_to(
+                &self.downloader,
+                name,
+                &world_dest,
+                ap_dir,
+                self.index_dir()?,
+                &self.common,
+            )
+            .await?;
+
+        if world_dest.is_file() {
+            let extracted = destination.join("extracted");
+            apworld::extract(&world_dest, &extracted)?;
+            let package_dir = apworld::package_dir(&extracted)?;
+            return Ok((world_dest, package_dir));
+        }
+
+        let package_dir = world.package_path(&world_dest);
+        Ok((package_dir.clone(), package_dir))
+    }
+
+    // Compares the worlds of this index with the ones of `new` without
+    // downloading anything: versions, origins and patches
+    pub fn diff_metadata(&self, new: &Index, options: &DiffOptions) -> IndexDiff {
+        let mut diff = IndexDiff::default();
+        if self.common.archipelago_version != new.common.archipelago_version {
+            diff.archipelago_version = Some(VersionChange {
+                old: self.common.archipelago_version.clone(),
+                new: new.common.archipelago_version.clone(),
+            });
+        }
+
+        for (name, new_world) in &new.worlds {
+            let mut world_diff = new_world.empty_diff();
+            let Some(old_world) = self.worlds.get(name) else {
+                world_diff.changes.push(WorldChange::Added {
+                    version: new_world.version().to_string(),
+                    origin: new_world.origin.clone(),
+                });
+                diff.worlds.insert(name.clone(), world_diff);
+                continue;
+            };
+
+            if old_world.version().as_str() != new_world.version().as_str() {
+                world_diff.changes.push(WorldChange::VersionChanged {
+                    old: old_world.version().to_string(),
+                    new: new_world.version().to_string(),
+                    bump: old_world.version().bump_to(&new_world.version()),
+                });
+            }
+            if old_world.origin != new_world.origin {
+                world_diff.changes.push(WorldChange::OriginChanged {
+                    old: old_world.origin.clone(),
+                    new: new_world.origin.clone(),
+                });
+            }
+            let old_patches = old_world.active_patch_names();
+            let new_patches = new_world.active_patch_names();
+            if old_patches != new_patches {
+                world_diff.changes.push(WorldChange::PatchesChanged {
+                    old: old_patches,
+                    new: new_patches,
+                });
+            }
+            if !world_diff.changes.is_empty() {
+                diff.worlds.insert(name.clone(), world_diff);
+            }
+        }
+        for (name, old_world) in &self.worlds {
+            if !new.worlds.contains_key(name) {
+                let mut world_diff = old_world.empty_diff();
+                world_diff.changes.push(WorldChange::Removed {
+                    version: old_world.version().to_string(),
+                    origin: old_world.origin.clone(),
+                });
+                diff.worlds.insert(name.clone(), world_diff);
+            }
+        }
+        diff.worlds.retain(|name, _| options.includes(name));
+
+        diff
+    }
+
+    // What a refresh of the index would deploy, as far as the index knows
+    // without downloading anything
+    fn current_manifest(&self) -> Result<Manifest> {
+        let index_dir = self.index_dir()?;
+        Ok(Manifest {
+            archipelago_version: self.common.archipelago_version.clone(),
+            python_version: None,
+            global_files: self
+                .common
+                .required_global_files
+                .iter()
+                .filter_map(|path| Some(Path::new(path).file_name()?.to_string_lossy().into()))
+                .collect(),
+            worlds: self
+                .worlds
+                .iter()
+                .map(|(name, world)| Ok((name.clone(), world.manifest(index_dir)?)))
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    // Compares a lockfile, or the manifest of a destination, with the current
+    // state of the index to know which worlds the next locked refresh would
+    // change. Worlds without a declared version keep the locked one as
+    // there's no telling whether it changed.
+    pub fn diff_lockfile(&self, lockfile: &Path, options: &DiffOptions) -> Result<IndexDiff> {
+        let locked = Manifest::load_from(lockfile)?;
+        let mut current = self.current_manifest()?;
+        for (name, world) in &mut current.worlds {
+            if let (None, Some(locked_world)) =
+                (&self.worlds[name].version, locked.worlds.get(name))
+            {
+                world.version = locked_world.version.clone();
+            }
+        }
+
+        Ok(diff::diff_manifests(&locked, &current, options))
+    }
+
+    // Moves the index to another archipelago version. Every supported world,
+    // dependency and required global file must still exist at that version.
+    // External worlds get downloaded to report the ones declaring they can't
+    // run on it, which doesn't prevent the bump. Nothing gets written with
+    // `dry_run`.
+    #[cfg(feature = "refresh")]
+    pub async fn bump_archipelago(&self, version: &str, dry_run: bool) -> Result<ArchipelagoBump> {
+        if version == self.common.archipelago_version {
+            bail!("The index is already at archipelago {}", version);
+        }
+
+        let ap_tmp_dir = self.dirs.tempdir()?;
+        let ap_tmp_dir = ap_tmp_dir.path();
+        self.checkout_archipelago_at(ap_tmp_dir, version, &CancellationToken::new())?;
+
+        let mut missing = vec![];
+        for (name, world) in &self.worlds {
+            if let WorldOrigin::Supported(dir_name) = &world.origin {
+                missing.push((dir_name, name));
+            }
+            missing.extend(
+                world
+                    .dependencies
+                    .iter()
+                    .map(|dependency| (dependency, name)),
+            );
+        }
+        let mut missing = missing
+            .into_iter()
+            .filter(|(path, _)| !ap_tmp_dir.join("worlds").join(path).exists())
+            .map(|(path, name)| format!("  - {} (needed by {})", path, name))
+            .collect::<Vec<_>>();
+        missing.extend(
+            self.common
+                .required_global_files
+                .iter()
+                .filter(|path| !ap_tmp_dir.join("worlds").join(path).exists())
+                .map(|path| format!("  - {} (required global file)", path)),
+        );
+        if !missing.is_empty() {
+            bail!(
+                "Some worlds and files are missing from archipelago {}:\n{}",
+                version,
+                missing.join("\n")
+            );
+        }
+
+        let destination = self.dirs.tempdir()?;
+        let index_dir = self.index_dir()?;
+        let mut incompatible = BTreeMap::new();
+        for (name, world) in &self.worlds {
+            if world.is_supported() {
+                continue;
+            }
+
+            let world_dest = Self::world_destination(name, world, destination.path())?;
+            world
+                .download_to(
+                    &self.downloader,
+                    name,
+                    &world_dest,
+                    ap_tmp_dir,
+                    index_dir,
+                    &self.common,
+                )
+                .await?;
+            let info = inspect::inspect(&world.package_path(&world_dest))?;
+            if let Some(issue) = info.compatibility_issue(version) {
+                incompatible.insert(name.clone(), issue);
+            }
+        }
+
+        if !dry_run {
+            let content =
+                std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+            let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+            let common = doc["common"]
+                .as_table_like_mut()
+                .ok_or_else(|| anyhow!("Couldn't find the common section in the index file"))?;
+            set_string(common, "archipelago_version", version);
+            std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+        }
+
+        Ok(ArchipelagoBump {
+            old_version: self.common.archipelago_version.clone(),
+            new_version: version.to_string(),
+            incompatible,
+        })
+    }
+
+    // Downloads the apworld of every world coming from a url into `mirror`,
+    // as `<world>/<version>/<world>.apworld`, and records them in its
+    // manifest. Worlds without a declared version are filed under the start
+    // of their sha256 and get downloaded on every run. Versions already in
+    // the mirror with the hash recorded for them aren't downloaded again, and
+    // a world failing to download doesn't stop the others. Supported and
+    // local worlds are archived by the archipelago and index repositories
+    // already.
+    #[cfg(feature = "refresh")]
+    pub async fn mirror(&self, mirror: &Path) -> Result<MirrorReport> {
+        std::fs::create_dir_all(mirror).map_err(|e| Error::io(mirror, e))?;
+        let mut manifest = MirrorManifest::load(mirror)?;
+        let mut report = MirrorReport::default();
+        let staging = tempfile::tempdir_in(mirror)?;
+
+        for (name, world) in &self.worlds {
+            let WorldOrigin::Url(uri) = &world.origin else {
+                continue;
+            };
+            let url = uri.to_string();
+
+            if let Some(version) = &world.version {
+                if let Some(mirrored) = manifest.get(name, version) {
+                    let path = mirror.join(&mirrored.path);
+                    if path.is_file() && manifest::sha256_file(&path)? == mirrored.sha256 {
+                        report.present.insert(name.clone(), version.clone());
+                        continue;
+                    }
+                }
+            }
+
+            let download = staging.path().join(format!("{}.apworld", name));
+            let mirrored = async {
+                download_apworld(&self.downloader, name, &url, &download).await?;
+                let sha256 = manifest::sha256_file(&download)?;
+                let version = world
+                    .version
+                    .clone()
+                    .unwrap_or_else(|| sha256[..12].to_string());
+                let path = Path::new(name)
+                    .join(&version)
+                    .join(format!("{}.apworld", name));
+                if !sandbox::is_contained(&path) {
+                    bail!("{} can't be used as a path", path.to_string_lossy());
+                }
+
+                std::fs::create_dir_all(mirror.join(&path).parent().unwrap_or(mirror))
+                    .map_err(|e| Error::io(mirror.join(&path).parent().unwrap_or(mirror), e))?;
+                std::fs::rename(&download, mirror.join(&path))
+                    .map_err(|e| Error::io(mirror.join(&path), e))?;
+                let mirrored = MirroredWorld {
+                    url: url.clone(),
+                    path: path.to_string_lossy().into(),
+                    size: std::fs::metadata(mirror.join(&path))
+                        .map_err(|e| Error::io(mirror.join(&path), e))?
+                        .len(),
+                    sha256,
+                    mirrored_at: self
+                        .clock
+                        .now()
+                        .duration_since(std::time::UNIX_EPOCH)?
+                        .as_secs(),
+                };
+                Ok((version, mirrored))
+            }
+            .await;
+
+            match mirrored {
+                Ok((version, mirrored)) => {
+                    manifest
+                        .worlds
+                        .entry(name.clone())
+                        .or_default()
+                        .insert(version.clone(), mirrored);
+                    report.mirrored.insert(name.clone(), version);
+                }
+                Err(e) => report.failed.push(MirrorFailure {
+                    world: name.clone(),
+                    url,
+                    error: e.to_string(),
+                }),
+            }
+        }
+
+        manifest.write(mirror)?;
+        Ok(report)
+    }
+
+    // Refreshes the index in a scratch directory and writes the resulting
+    // manifest to `lockfile`, pinning the version, patches and content hash of
+    // every world
+    #[cfg(feature = "refresh")]
+    pub async fn lock(&self, lockfile: &Path) -> Result<()> {
+        let destination = self.dirs.tempdir()?;
+        let keys = self.worlds.keys().cloned().collect();
+        self.refresh_staged(destination.path(), &keys, &CancellationToken::new())
+            .await?;
+        Manifest::load(destination.path())?.write_to(lockfile)
+    }
+
+    // Refreshes into `destination` only if the result is exactly what
+    // `lockfile` describes. The index must not have changed since it was
+    // locked and every world must still hash the same. The refresh happens in
+    // a staging directory so a mismatch leaves `destination` untouched.
+    #[cfg(feature = "refresh")]
+    pub async fn sync(&self, lockfile: &Path, destination: &Path) -> Result<()> {
+        let locked = Manifest::load_from(lockfile)?;
+        let changes = self.diff_lockfile(lockfile, &DiffOptions::default())?;
+        if !changes.is_empty() {
+            bail!(
+                "The index changed since {} was written:\n{}",
+                lockfile.to_string_lossy(),
+                changes.to_text(false).trim_end()
+            );
+        }
+
+        let built = self.dirs.tempdir()?;
+        let keys = self.worlds.keys().cloned().collect();
+        self.refresh_staged(built.path(), &keys, &CancellationToken::new())
+            .await?;
+
+        let issues = verify::verify_destination(built.path(), &locked)?
+            .iter()
+            .map(|issue| format!("  - {}", issue))
+            .collect::<Vec<_>>();
+        if !issues.is_empty() {
+            bail!(
+                "The refreshed worlds don't match {}:\n{}",
+                lockfile.to_string_lossy(),
+                issues.join("\n")
+            );
+        }
+
+        self.deploy(built.path(), destination, &CancellationToken::new())
+    }
+
+    // Compares this index with `new`. Added worlds get downloaded to know
+    // their size, and worlds whose version, origin or patches changed get
+    // downloaded from both indexes to diff their content.
+    #[cfg(feature = "refresh")]
+    pub async fn diff(&self, new: &Index, options: &DiffOptions) -> Result<IndexDiff> {
+        let mut diff = self.diff_metadata(new, options);
+        let content_options = DiffOptions {
+            ignore: options
+                .ignore
+                .iter()
+                .chain(&self.common.diff_ignore)
+                .chain(&new.common.diff_ignore)
+                .cloned()
+                .collect(),
+            ..options.clone()
+        };
+
+        let added = new
+            .worlds
+            .iter()
+            .filter(|(name, _)| !self.worlds.contains_key(*name) && options.includes(name))
+            .collect::<Vec<_>>();
+        let mut changed = vec![];
+        for (name, new_world) in &new.worlds {
+            let Some(old_world) = self.worlds.get(name) else {
+                continue;
+            };
+            if !options.includes(name) {
+                continue;
+            }
+            if old_world.manifest(self.index_dir()?)? != new_world.manifest(new.index_dir()?)? {
+                changed.push((name, old_world, new_world));
+            }
+        }
+
+        let old_ap_dir = self.dirs.tempdir()?;
+        if changed
+            .iter()
+            .any(|(_, old_world, _)| old_world.is_supported())
+        {
+            self.checkout_archipelago(old_ap_dir.path())?;
+        }
+        let new_ap_dir = self.dirs.tempdir()?;
+        if changed
+            .iter()
+            .map(|(_, _, new_world)| new_world)
+            .chain(added.iter().map(|(_, new_world)| new_world))
+            .any(|new_world| new_world.is_supported())
+        {
+            new.checkout_archipelago(new_ap_dir.path())?;
+        }
+
+        for (name, new_world) in added {
+            let new_dest = self.dirs.tempdir()?;
+            let (new_path, _) = new
+                .stage_world(name, new_world, new_ap_dir.path(), new_dest.path())
+                .await?;
+            if let Some(world_diff) = diff.worlds.get_mut(name) {
+                world_diff.new_size = Some(size::deployed_size(&new_path)?);
+            }
+        }
+
+        for (name, old_world, new_world) in changed {
+            let old_dest = self.dirs.tempdir()?;
+            let (old_path, old_dir) = self
+                .stage_world(name, old_world, old_ap_dir.path(), old_dest.path())
+                .await?;
+            let new_dest = self.dirs.tempdir()?;
+            let (new_path, new_dir) = new
+                .stage_world(name, new_world, new_ap_dir.path(), new_dest.path())
+                .await?;
+
+            let files = diff::diff_dirs(&old_dir, &new_dir, &content_options)?;
+            let world_diff = diff
+                .worlds
+                .entry(name.clone())
+                .or_insert_with(|| new_world.empty_diff());
+            world_diff.old_size = Some(size::deployed_size(&old_path)?);
+            world_diff.new_size = Some(size::deployed_size(&new_path)?);
+            if old_world.version().as_str() != new_world.version().as_str() {
+                world_diff.changelog = changelog::extract(
+                    &new_dir,
+                    old_world.version().as_str(),
+                    new_world.version().as_str(),
+                )?;
+            }
+            if !files.is_empty() {
+                world_diff.changes.push(WorldChange::Updated { files });
+            }
+        }
+        diff.worlds
+            .retain(|_, world_diff| !world_diff.changes.is_empty());
+
+        Ok(diff)
+    }
+
+    // Diffs `modified` against the world as it currently gets deployed (minus
+    // transforms), writes the result to `patch_path` and registers the new
+    // patch in the index file.
+    #[cfg(feature = "refresh")]
+    pub async fn make_patch(
+        &self,
+        world_name: &str,
+        modified: &Path,
+        patch_path: &Path,
+    ) -> Result<()> {
+        let world = self
+            .worlds
+            .get(world_name)
+            .ok_or_else(|| anyhow!("Unknown world {}", world_name))?;
+
+        let ap_tmp_dir = self.dirs.tempdir()?;
+        if world.is_supported() {
+            self.checkout_archipelago(ap_tmp_dir.path())?;
+        }
+
+        let destination = self.dirs.tempdir()?;
+        let index_dir = self.index_dir()?;
+        let world_dest = Self::world_destination(world_name, world, destination.path())?;
+        world
+            .fetch_verified(
+                &self.downloader,
+                world_name,
+                &world_dest,
+                ap_tmp_dir.path(),
+                index_dir,
+            )
+            .await?;
+
+        let diff = world.with_package_dir(&world_dest, |dir| {
+            world.apply_patches(world_name, dir, index_dir)?;
+            patch::generate(dir, modified)
+        })?;
+        if diff.is_empty() {
+            bail!(
+                "{} doesn't contain any change to {}",
+                modified.to_string_lossy(),
+                world_name
+            );
+        }
+
+        let patch_file = index_dir.join(patch_path);
+        if let Some(parent) = patch_file.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+        }
+        std::fs::write(&patch_file, diff).map_err(|e| Error::io(&patch_file, e))?;
+
+        self.add_patch_entry(world_name, &patch_path.to_string_lossy())
+    }
+
+    // Downloads a world and applies its patches and transforms like a refresh
+    // would, then extracts its package directory into `destination`, which
+    // must be empty. Returns the path of the extracted package.
+    #[cfg(feature = "refresh")]
+    pub async fn unpack_world(&self, key: &str, destination: &Path) -> Result<PathBuf> {
+        let world = self
+            .worlds
+            .get(key)
+            .ok_or_else(|| anyhow!("Unknown world {}", key))?;
+        if destination.exists()
+            && std::fs::read_dir(destination)
+                .map_err(|e| Error::io(destination, e))?
+                .next()
+                .is_some()
+        {
+            bail!("{} isn't empty", destination.to_string_lossy());
+        }
+
+        let ap_tmp_dir = self.dirs.tempdir()?;
+        if world.is_supported() {
+            self.checkout_archipelago(ap_tmp_dir.path())?;
+        }
+
+        let download = self.dirs.tempdir()?;
+        let world_dest = Self::world_destination(key, world, download.path())?;
+        world
+            .download_to(
+                &self.downloader,
+                key,
+                &world_dest,
+                ap_tmp_dir.path(),
+                self.index_dir()?,
+                &self.common,
+            )
+            .await?;
+
+        let package = world.package_path(&world_dest);
+        if package.is_file() {
+            apworld::extract(&package, destination)?;
+            return apworld::package_dir(destination);
+        }
+
+        let unpacked = destination.join(package.file_name().unwrap_or_default());
+        copy_dir_all(&package, &unpacked)?;
+        Ok(unpacked)
+    }
+
+    #[cfg(feature = "refresh")]
+    fn add_patch_entry(&self, world_name: &str, patch_path: &str) -> Result<()> {
+        let content = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+
+        let world = doc["worlds"][world_name]
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("Couldn't find {} in the index file", world_name))?;
+        if world.get("patches").is_none() {
+            world.insert("patches", toml_edit::value(toml_edit::Array::new()));
+        }
+        let patches = world
+            .get_mut("patches")
+            .and_then(|patches| patches.as_array_mut())
+            .ok_or_else(|| anyhow!("The patches of {} aren't an array", world_name))?;
+        patches.push(patch_path);
+
+        std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+
+        Ok(())
+    }
+
+    // Removes a world from the index file along with the files it was the
+    // only one to use: its local copy, patches, license file and transform
+    // sources. With `dry_run`, nothing gets changed. Returns the files that
+    // are, or would be, deleted, relative to the index file.
+    pub fn remove_world(&self, key: &str, dry_run: bool) -> Result<Vec<PathBuf>> {
+        let world = self
+            .worlds
+            .get(key)
+            .ok_or_else(|| anyhow!("Unknown world {}", key))?;
+
+        let still_used = self
+            .worlds
+            .iter()
+            .filter(|(other_key, _)| *other_key != key)
+            .flat_map(|(_, other)| other.index_files())
+            .chain(
+                self.patch_sets
+                    .values()
+                    .flatten()
+                    .map(|patch| PathBuf::from(&patch.path)),
+            )
+            .collect::<BTreeSet<_>>();
+        let index_dir = self.index_dir()?;
+        let orphans = world
+            .index_files()
+            .into_iter()
+            .filter(|path| !still_used.contains(path) && index_dir.join(path).exists())
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect::<Vec<_>>();
+        if dry_run {
+            return Ok(orphans);
+        }
+
+        let content = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+        doc["worlds"]
+            .as_table_like_mut()
+            .and_then(|worlds| worlds.remove(key))
+            .ok_or_else(|| anyhow!("Couldn't find {} in the index file", key))?;
+        std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+
+        for orphan in &orphans {
+            delete_file_or_dir(&index_dir.join(orphan))?;
+        }
+
+        Ok(orphans)
+    }
+
+    // Adds a world downloaded from `source` to the index file. `source` is
+    // either a direct link to an `.apworld` or a github repository, in which
+    // case the apworld of its latest release is used. The world key defaults
+    // to the name of the apworld's python package and its name and version
+    // are read from the apworld, or the release, unless `version` is given.
+    // Returns the world key.
+    #[cfg(feature = "http")]
+    pub async fn add_world(
+        &self,
+        source: &str,
+        key: Option<&str>,
+        version: Option<&str>,
+    ) -> Result<String> {
+        let (url, home, tag) = match github::repository(source) {
+            Some((owner, repo)) => {
+                let release = github::latest_release(
+                    self.downloader.client(),
+                    self.downloader.github_token().as_deref(),
+                    &owner,
+                    &repo,
+                )
+                .await?;
+                let Some(apworld_url) = release.apworld_url else {
+                    bail!(
+                        "The latest release of {} doesn't contain an apworld: {}",
+                        source,
+                        release.url
+                    );
+                };
+                (apworld_url, Some(source.to_string()), Some(release.tag))
+            }
+            None => (source.to_string(), None, None),
+        };
+        let uri = url.parse::<Uri>()?;
+
+        let tmp_dir = self.dirs.tempdir()?;
+        let apworld_path = tmp_dir.path().join("world.apworld");
+        let file_name = url.rsplit('/').next().unwrap_or(&url);
+        download_apworld(
+            &self.downloader,
+            key.unwrap_or(file_name),
+            &url,
+            &apworld_path,
+        )
+        .await?;
+
+        let package_name = apworld::entry_names(&apworld_path)?
+            .iter()
+            .find_map(|name| Some(name.split_once('/')?.0.to_string()));
+        let file_stem = uri
+            .path()
+            .rsplit('/')
+            .next()
+            .and_then(|file_name| file_name.strip_suffix(".apworld"))
+            .map(str::to_string);
+        let Some(key) = key.map(str::to_string).or(package_name).or(file_stem) else {
+            bail!("Couldn't guess a key for {}, pass one explicitly", url);
+        };
+        if self.worlds.contains_key(&key) {
+            bail!("{} is already in the index", key);
+        }
+
+        let info = inspect::inspect(&apworld_path)?;
+        let Some(version) = version.map(str::to_string).or(info.version).or(tag) else {
+            bail!("Couldn't find the version of {}, pass one explicitly", url);
+        };
+
+        let content = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+        let worlds = doc
+            .entry("worlds")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("worlds isn't a table in the index file"))?;
+        let mut world = toml_edit::Table::new();
+        world.insert(
+            "name",
+            toml_edit::value(info.game.unwrap_or_else(|| key.clone())),
+        );
+        world.insert("version", toml_edit::value(version));
+        world.insert("url", toml_edit::value(url));
+        if let Some(home) = home {
+            world.insert("home", toml_edit::value(home));
+        }
+        worlds.insert(&key, toml_edit::Item::Table(world));
+        std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+
+        Ok(key)
+    }
+
+    // Onboards an existing `worlds` or `custom_worlds` directory. Every world
+    // found in it gets matched with the index by key, game name or supported
+    // world directory. The others get copied next to the index under
+    // `worlds/` and added to it as local worlds, unless `dry_run` is set.
+    pub fn import_worlds(&self, dir: &Path, dry_run: bool) -> Result<Vec<ImportedWorld>> {
+        let index_dir = self.index_dir()?;
+        let mut added = BTreeSet::new();
+        let mut report = vec![];
+
+        for found in import::scan(dir)? {
+            // Keys win over game names, two forks of a world share one
+            let known = self
+                .worlds
+                .get_key_value(&found.package)
+                .or_else(|| {
+                    self.worlds.iter().find(|(_, world)| {
+                        matches!(&world.origin, WorldOrigin::Supported(dir_name) if *dir_name == found.package)
+                    })
+                })
+                .or_else(|| {
+                    self.worlds
+                        .iter()
+                        .find(|(_, world)| found.info.game.as_ref() == Some(&world.name))
+                });
+
+            let status = match known {
+                Some((key, world)) => ImportStatus::Known {
+                    key: key.clone(),
+                    version: world.version.clone(),
+                },
+                None => {
+                    let key = found.package.clone();
+                    if !added.insert(key.clone()) {
+                        bail!(
+                            "Several worlds of {} are named {}",
+                            dir.to_string_lossy(),
+                            key
+                        );
+                    }
+                    let local = if found.path.is_dir() {
+                        Path::new("worlds").join(&key)
+                    } else {
+                        Path::new("worlds").join(format!("{}.apworld", key))
+                    };
+                    if index_dir.join(&local).exists() {
+                        bail!(
+                            "{} already exists next to the index",
+                            local.to_string_lossy()
+                        );
+                    }
+
+                    ImportStatus::New {
+                        key,
+                        local: local.to_string_lossy().into(),
+                    }
+                }
+            };
+
+            report.push(ImportedWorld {
+                file: found.file,
+                sha256: found.sha256,
+                game: found.info.game,
+                version: found.info.version,
+                status,
+            });
+        }
+
+        if dry_run || added.is_empty() {
+            return Ok(report);
+        }
+
+        let content = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+        let worlds = doc
+            .entry("worlds")
+            .or_insert_with(toml_edit::table)
+            .as_table_mut()
+            .ok_or_else(|| anyhow!("worlds isn't a table in the index file"))?;
+        std::fs::create_dir_all(index_dir.join("worlds"))
+            .map_err(|e| Error::io(index_dir.join("worlds"), e))?;
+        for imported in &report {
+            let ImportStatus::New { key, local } = &imported.status else {
+                continue;
+            };
+            copy_file_or_dir(&index_dir.join(local), dir, Path::new(&imported.file))?;
+
+            let mut world = toml_edit::Table::new();
+            world.insert(
+                "name",
+                toml_edit::value(imported.game.clone().unwrap_or_else(|| key.clone())),
+            );
+            world.insert("local", toml_edit::value(local));
+            if let Some(version) = &imported.version {
+                world.insert("version", toml_edit::value(version));
+            }
+            worlds.insert(key, toml_edit::Item::Table(world));
+        }
+        std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+
+        Ok(report)
+    }
+
+    // Bumps a world downloaded from a url to `version`, or to the latest
+    // release of its github `home` if omitted. Worlds with a url template get
+    // it expanded with the new version, otherwise the old version is replaced
+    // with the new one where it's a whole token of the url. The new apworld
+    // is downloaded and verified and the world's patches are checked against
+    // it. The index file is only written if all the patches still apply.
+    #[cfg(feature = "http")]
+    pub async fn update_world(&self, key: &str, version: Option<&str>) -> Result<WorldUpdate> {
+        let world = self
+            .worlds
+            .get(key)
+            .ok_or_else(|| anyhow!("Unknown world {}", key))?;
+        let WorldOrigin::Url(old_url) = &world.origin else {
+            bail!("{} isn't downloaded from a url, it can't be updated", key);
+        };
+        let old_url = old_url.to_string();
+        let old_version = world.version.clone();
+
+        // The template stays in the index, only the version changes
+        let template = world.url_template.as_ref().map(ToString::to_string);
+        let (url, version, tag) = match (version, &template) {
+            (Some(version), Some(template)) => (
+                WorldVersion::from(version).expand(template),
+                Some(version.to_string()),
+                None,
+            ),
+            (Some(version), None) => {
+                let Some(old_version) = old_version.as_deref() else {
+                    bail!("{} doesn't declare a version, update its url by hand", key);
+                };
+                let Some(url) = version::replace_version(&old_url, old_version, version) else {
+                    bail!(
+                        "The url of {} doesn't contain version {} unambiguously, update it by hand",
+                        key,
+                        old_version
+                    );
+                };
+                (url, Some(version.to_string()), None)
+            }
+            (None, _) => {
+                let Some((owner, repo)) = world.home.as_deref().and_then(github::repository) else {
+                    bail!(
+                        "{} isn't hosted on github, pass the version to update to",
+                        key
+                    );
+                };
+                let release = github::latest_release(
+                    self.downloader.client(),
+                    self.downloader.github_token().as_deref(),
+                    &owner,
+                    &repo,
+                )
+                .await?;
+                match (&template, release.apworld_url) {
+                    // Tags are often the version with a `v` in front of it,
+                    // the one expanding to the release's apworld wins
+                    (Some(template), apworld_url) => {
+                        let candidates =
+                            [release.tag.as_str(), release.tag.trim_start_matches('v')];
+                        let version = candidates
+                            .iter()
+                            .find(|version| {
+                                apworld_url.as_deref()
+                                    == Some(WorldVersion::from(**version).expand(template).as_str())
+                            })
+                            .unwrap_or(&candidates[1])
+                            .to_string();
+                        (
+                            WorldVersion::from(version.as_str()).expand(template),
+                            Some(version),
+                            None,
+                        )
+                    }
+                    (None, Some(apworld_url)) => (apworld_url, None, Some(release.tag)),
+                    (None, None) => bail!(
+                        "The latest release of {} doesn't contain an apworld: {}",
+                        key,
+                        release.url
+                    ),
+                }
+            }
+        };
+        url.parse::<Uri>()?;
+
+        let tmp_dir = self.dirs.tempdir()?;
+        let apworld_path = tmp_dir.path().join(format!("{}.apworld", key));
+        download_apworld(&self.downloader, key, &url, &apworld_path).await?;
+        let info = inspect::inspect(&apworld_path)?;
+        let Some(new_version) = version.or(info.version).or(tag) else {
+            bail!("Couldn't find the version of {}, pass one explicitly", url);
+        };
+        if old_version.as_deref() == Some(new_version.as_str()) && old_url == url {
+            bail!("{} is already at version {}", key, new_version);
+        }
+
+        let index_dir = self.index_dir()?;
+        let patches = world
+            .patches
+            .iter()
+            .filter(|patch| patch.applies_to(Some(&new_version)))
+            .collect::<Vec<_>>();
+        let patches = world.with_package_dir(&apworld_path, |dir| {
+            patches
+                .iter()
+                .map(|patch| {
+                    Ok(PatchCheck {
+                        patch: patch.path.clone(),
+                        status: patch::check(dir, &index_dir.join(&patch.path))?,
+                    })
+                })
+                .collect::<Result<Vec<_>>>()
+        })?;
+        // Reported as a failure to patch, with the first patch that no longer
+        // applies
+        let failed = patches.iter().find_map(|check| match &check.status {
+            PatchStatus::Failed(output) => Some((&check.patch, output)),
+            _ => None,
+        });
+        if let Some((file, output)) = failed {
+            return Err(Error::Patch {
+                world: key.into(),
+                file: file.clone(),
+                source: anyhow!(
+                    "It no longer applies to version {}:\n{}",
+                    new_version,
+                    output
+                ),
+            });
+        }
+
+        let content = std::fs::read_to_string(&self.path).map_err(|e| Error::io(&self.path, e))?;
+        let mut doc = content.parse::<toml_edit::DocumentMut>()?;
+        let entry = doc["worlds"][key]
+            .as_table_like_mut()
+            .ok_or_else(|| anyhow!("Couldn't find {} in the index file", key))?;
+        set_string(entry, "version", &new_version);
+        set_string(entry, "url", template.as_deref().unwrap_or(&url));
+        std::fs::write(&self.path, doc.to_string()).map_err(|e| Error::io(&self.path, e))?;
+
+        Ok(WorldUpdate {
+            old_version,
+            new_version,
+            url,
+            patches,
+        })
+    }
+
+    // When `destination` was last refreshed, if it ever was
+    pub fn last_refreshed(&self, destination: &Path) -> Option<SystemTime> {
+        self.clock
+            .modified(&destination.join(".last_refresh"), &*self.fs)
+            .ok()
+    }
+
+    // How long ago `destination` was last refreshed, by the index's clock
+    pub fn refresh_age(&self, destination: &Path) -> Option<Duration> {
+        let last_refreshed = self.last_refreshed(destination)?;
+        Some(
+            self.clock
+                .now()
+                .duration_since(last_refreshed)
+                .unwrap_or_default(),
+        )
+    }
+
+    pub fn should_refresh(&self, destination: &Path) -> bool {
+        let Some(last_refreshed_mtime) = self.last_refreshed(destination) else {
             return true;
         };
-        let Ok(index_mtime) = index_metadata.modified() else {
+        // The index is always on disk, whatever the destination's on
+        let Ok(index_mtime) = self.clock.modified(&self.path, &RealFs) else {
             return true;
         };
 
         index_mtime > last_refreshed_mtime
     }
 }
+
+#[cfg(all(test, feature = "http", feature = "zip"))]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "--- a/a.py\n+++ b/a.py\n@@ -1,3 +1,3 @@\n one\n-two\n+deux\n three\n";
+
+    // Packs `dir` as whatever apworld it's asked for
+    struct Store {
+        dir: PathBuf,
+    }
+
+    impl OriginResolver for Store {
+        fn fetch<'a>(&'a self, _uri: &'a Uri, destination: &'a Path) -> ResolveFuture<'a> {
+            Box::pin(async move { archive::pack(&self.dir, destination) })
+        }
+    }
+
+    // An index with the url world `ok`, served by a store with its package
+    fn index(dir: &Path, world: &str) -> Index {
+        let store = dir.join("store");
+        std::fs::create_dir_all(store.join("ok")).unwrap();
+        std::fs::write(store.join("ok/__init__.py"), "").unwrap();
+        std::fs::write(store.join("ok/a.py"), "one\ntwo\nthree\n").unwrap();
+        std::fs::write(dir.join("ok.patch"), PATCH).unwrap();
+        std::fs::write(dir.join("broken.patch"), PATCH.replace("two", "zwei")).unwrap();
+        std::fs::write(
+            dir.join("index.toml"),
+            format!(
+                "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+                 archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+                 required_global_files = []\n\n\
+                 [worlds.ok]\nname = \"Ok\"\n{}\n",
+                world
+            ),
+        )
+        .unwrap();
+
+        let mut index = Index::new(&dir.join("index.toml")).unwrap();
+        index.set_temp_dir(&dir.join("tmp"));
+        index.register_resolver("store", Store { dir: store });
+        index
+    }
+
+    #[tokio::test]
+    async fn updates_the_version_in_urls() {
+        let tmp = tempfile::tempdir().unwrap();
+        let world = "# Kept\nurl = \"store://apworlds/ok-1.0.apworld\"\nversion = \"1.0\"\n\
+                     patches = [\"ok.patch\"]";
+        let update = index(tmp.path(), world)
+            .update_world("ok", Some("1.1"))
+            .await
+            .unwrap();
+        assert_eq!(update.old_version.as_deref(), Some("1.0"));
+        assert_eq!(update.url, "store://apworlds/ok-1.1.apworld");
+        assert_eq!(update.patches.len(), 1);
+        let content = std::fs::read_to_string(tmp.path().join("index.toml")).unwrap();
+        assert!(content
+            .contains("# Kept\nurl = \"store://apworlds/ok-1.1.apworld\"\nversion = \"1.1\"\n"));
+
+        let index = Index::new(&tmp.path().join("index.toml")).unwrap();
+        assert_eq!(index.worlds["ok"].version.as_deref(), Some("1.1"));
+    }
+
+    #[tokio::test]
+    async fn keeps_url_templates() {
+        let tmp = tempfile::tempdir().unwrap();
+        let world = "url = \"store://apworlds/v{version}/ok.apworld\"\nversion = \"1.0\"";
+        let update = index(tmp.path(), world)
+            .update_world("ok", Some("1.1"))
+            .await
+            .unwrap();
+        assert_eq!(update.url, "store://apworlds/v1.1/ok.apworld");
+        let content = std::fs::read_to_string(tmp.path().join("index.toml")).unwrap();
+        assert!(content
+            .contains("url = \"store://apworlds/v{version}/ok.apworld\"\nversion = \"1.1\"\n"));
+    }
+
+    #[tokio::test]
+    async fn leaves_the_index_alone_when_it_cant_update() {
+        for (world, error) in [
+            (
+                "url = \"store://apworlds/ok-1.0.1.apworld\"\nversion = \"1.0\"",
+                "doesn't contain version 1.0 unambiguously",
+            ),
+            (
+                "url = \"store://apworlds/ok.apworld\"",
+                "doesn't declare a version",
+            ),
+            (
+                "url = \"store://apworlds/ok-1.0.apworld\"\nversion = \"1.0\"\n\
+                 patches = [\"ok.patch\", \"broken.patch\"]",
+                "Couldn't apply broken.patch to ok",
+            ),
+        ] {
+            let tmp = tempfile::tempdir().unwrap();
+            let index = index(tmp.path(), world);
+            let before = std::fs::read_to_string(tmp.path().join("index.toml")).unwrap();
+            let e = index.update_world("ok", Some("1.1")).await.unwrap_err();
+            assert!(e.to_string().contains(error), "{}", e);
+            let after = std::fs::read_to_string(tmp.path().join("index.toml")).unwrap();
+            assert_eq!(after, before);
+        }
+    }
+}
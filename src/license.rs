@@ -0,0 +1,82 @@
+#[cfg(feature = "refresh")]
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "refresh")]
+use std::path::Path;
+
+const LICENSE_NAMES: &[&str] = &["license", "licence", "copying", "unlicense"];
+
+// Markers are checked in order, more specific licenses first
+const KNOWN_LICENSES: &[(&str, &str)] = &[
+    ("GNU AFFERO GENERAL PUBLIC LICENSE", "AGPL-3.0"),
+    ("GNU LESSER GENERAL PUBLIC LICENSE", "LGPL"),
+    (
+        "GNU GENERAL PUBLIC LICENSE\n                       Version 3",
+        "GPL-3.0",
+    ),
+    (
+        "GNU GENERAL PUBLIC LICENSE\n                       Version 2",
+        "GPL-2.0",
+    ),
+    ("GNU GENERAL PUBLIC LICENSE", "GPL"),
+    ("Apache License", "Apache-2.0"),
+    ("Mozilla Public License", "MPL-2.0"),
+    ("Permission is hereby granted, free of charge", "MIT"),
+    ("Redistribution and use in source and binary forms", "BSD"),
+    ("This is free and unencumbered software", "Unlicense"),
+];
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LicensePolicy {
+    #[default]
+    Ignore,
+    Warn,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LicenseInfo {
+    pub file: String,
+    pub kind: Option<String>,
+}
+
+// Whether a file at the root of a world looks like a license
+pub fn is_license_file(name: &str) -> bool {
+    if name.contains('/') {
+        return false;
+    }
+
+    let name = name.to_lowercase();
+    let stem = name.split('.').next().unwrap_or(&name);
+    LICENSE_NAMES.contains(&stem)
+}
+
+pub fn identify(file: &str, content: &[u8]) -> LicenseInfo {
+    let content = String::from_utf8_lossy(content);
+    let kind = KNOWN_LICENSES
+        .iter()
+        .find(|(marker, _)| content.contains(marker))
+        .map(|(_, kind)| kind.to_string());
+
+    LicenseInfo {
+        file: file.to_string(),
+        kind,
+    }
+}
+
+#[cfg(feature = "refresh")]
+pub fn has_license(dir: &Path) -> Result<bool> {
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        if is_license_file(
+            &entry
+                .map_err(|e| Error::io(dir, e))?
+                .file_name()
+                .to_string_lossy(),
+        ) {
+            return Ok(true);
+        }
+    }
+
+    Ok(false)
+}
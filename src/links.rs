@@ -0,0 +1,177 @@
+use reqwest::{redirect::Policy, Client, StatusCode, Url};
+use serde::Serialize;
+
+use crate::{github, Result};
+
+// Redirect chains longer than this are reported as unreachable
+const MAX_REDIRECTS: usize = 10;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LinkKind {
+    Origin,
+    Home,
+    Upstream,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum LinkStatus {
+    Alive { code: u16 },
+    Redirected { code: u16, location: Option<String> },
+    Dead { code: u16 },
+    Unreachable { error: String },
+}
+
+impl LinkStatus {
+    pub fn is_alive(&self) -> bool {
+        matches!(self, LinkStatus::Alive { .. })
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct LinkCheck {
+    pub world: String,
+    pub kind: LinkKind,
+    pub url: String,
+    pub status: LinkStatus,
+}
+
+pub fn client() -> Result<Client> {
+    Ok(Client::builder()
+        .redirect(Policy::none())
+        .user_agent(concat!("apwm/", env!("CARGO_PKG_VERSION")))
+        .build()?)
+}
+
+// HEADs `url`, falling back to a GET for servers that don't implement HEAD.
+// Redirects aren't followed so they can be reported.
+pub async fn check(client: &Client, url: &str) -> LinkStatus {
+    let mut response = client.head(url).send().await;
+    if let Ok(head) = &response {
+        if head.status() == StatusCode::METHOD_NOT_ALLOWED {
+            response = client.get(url).send().await;
+        }
+    }
+
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            return LinkStatus::Unreachable {
+                error: e.to_string(),
+            }
+        }
+    };
+
+    let code = response.status().as_u16();
+    if response.status().is_redirection() {
+        let location = response
+            .headers()
+            .get(reqwest::header::LOCATION)
+            .and_then(|location| location.to_str().ok())
+            .map(str::to_string);
+        LinkStatus::Redirected { code, location }
+    } else if response.status().is_success() {
+        LinkStatus::Alive { code }
+    } else {
+        LinkStatus::Dead { code }
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UrlStatus {
+    Alive { code: u16 },
+    // Permanently redirected to `location`, which is alive
+    Moved { location: String },
+    Dead { code: u16 },
+    Unreachable { error: String },
+}
+
+impl UrlStatus {
+    pub fn is_alive(&self) -> bool {
+        matches!(self, UrlStatus::Alive { .. })
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UrlAudit {
+    pub world: String,
+    pub kind: LinkKind,
+    pub url: String,
+    pub status: UrlStatus,
+    // Every url the request got redirected to, in order
+    pub redirects: Vec<String>,
+    // What to change in the index, when something can be guessed
+    pub suggestion: Option<String>,
+}
+
+// Follows the redirects of `url` and checks where they lead. Only leading
+// permanent redirects make it moved, a temporary one means the original url is
+// still the one to use, like github release assets redirecting to their CDN.
+pub async fn audit(client: &Client, url: &str) -> (UrlStatus, Vec<String>) {
+    let mut redirects = vec![];
+    let mut current = url.to_string();
+    let mut moved_to = None;
+    let mut permanent = true;
+    for _ in 0..=MAX_REDIRECTS {
+        let (code, location) = match check(client, &current).await {
+            LinkStatus::Alive { code } => {
+                let status = match moved_to {
+                    Some(location) => UrlStatus::Moved { location },
+                    None => UrlStatus::Alive { code },
+                };
+                return (status, redirects);
+            }
+            LinkStatus::Dead { code } => return (UrlStatus::Dead { code }, redirects),
+            LinkStatus::Unreachable { error } => {
+                return (UrlStatus::Unreachable { error }, redirects)
+            }
+            LinkStatus::Redirected { code, location } => (code, location),
+        };
+
+        let next = location.and_then(|location| Url::parse(&current).ok()?.join(&location).ok());
+        let Some(next) = next else {
+            let error = format!("{} redirected ({}) without a valid location", current, code);
+            return (UrlStatus::Unreachable { error }, redirects);
+        };
+        permanent &= code == 301 || code == 308;
+        if permanent {
+            moved_to = Some(next.to_string());
+        }
+        current = next.to_string();
+        redirects.push(current.clone());
+    }
+
+    let error = format!("more than {} redirects", MAX_REDIRECTS);
+    (UrlStatus::Unreachable { error }, redirects)
+}
+
+pub fn suggest_fix(kind: &LinkKind, url: &str, status: &UrlStatus) -> Option<String> {
+    match status {
+        UrlStatus::Alive { .. } => None,
+        UrlStatus::Moved { location } => {
+            let old = github::owning_repository(url);
+            let new = github::owning_repository(location);
+            Some(match (old, new) {
+                (Some(old), Some(new)) if old != new => format!(
+                    "The github repository {}/{} moved to {}/{}, use {}",
+                    old.0, old.1, new.0, new.1, location
+                ),
+                _ => format!("Use {}", location),
+            })
+        }
+        UrlStatus::Dead { code: 404 | 410 } => match github::owning_repository(url) {
+            Some((owner, repo)) if *kind == LinkKind::Origin => Some(format!(
+                "The release asset is gone, look for it in the releases of {}/{} and update the url and version",
+                owner, repo
+            )),
+            Some((owner, repo)) => Some(format!(
+                "{}/{} was deleted or made private, look for a fork or remove the link",
+                owner, repo
+            )),
+            None => None,
+        },
+        UrlStatus::Dead { .. } | UrlStatus::Unreachable { .. } => None,
+    }
+}
@@ -0,0 +1,68 @@
+use serde::Serialize;
+
+use crate::{World, WorldOrigin, WorldVersion};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum OriginKind {
+    Url,
+    Supported,
+    Local,
+}
+
+impl std::fmt::Display for OriginKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let kind = match self {
+            OriginKind::Url => "url",
+            OriginKind::Supported => "supported",
+            OriginKind::Local => "local",
+        };
+        write!(f, "{}", kind)
+    }
+}
+
+impl From<&WorldOrigin> for OriginKind {
+    fn from(origin: &WorldOrigin) -> Self {
+        match origin {
+            WorldOrigin::Url(_) => OriginKind::Url,
+            WorldOrigin::Supported(_) => OriginKind::Supported,
+            WorldOrigin::Local(_) => OriginKind::Local,
+        }
+    }
+}
+
+// Which worlds `Index::list` returns. Unset criteria match every world.
+#[derive(Debug, Clone, Default)]
+pub struct WorldFilter {
+    pub origin: Option<OriginKind>,
+    pub tag: Option<String>,
+    pub has_patches: Option<bool>,
+    pub supported: Option<bool>,
+}
+
+impl WorldFilter {
+    pub fn matches(&self, world: &World) -> bool {
+        self.origin
+            .is_none_or(|origin| OriginKind::from(&world.origin) == origin)
+            && self.tag.as_ref().is_none_or(|tag| world.tags.contains(tag))
+            && self
+                .has_patches
+                .is_none_or(|has_patches| world.has_patches() == has_patches)
+            && self
+                .supported
+                .is_none_or(|supported| world.is_supported() == supported)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WorldListing {
+    pub key: String,
+    pub name: String,
+    pub version: Option<WorldVersion>,
+    pub origin: OriginKind,
+    // The url, supported directory or local path the world comes from
+    pub source: String,
+    pub home: Option<String>,
+    pub tags: Vec<String>,
+    pub patches: Vec<String>,
+}
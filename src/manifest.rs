@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::Path};
+
+#[cfg(feature = "refresh")]
+use crate::Vfs;
+use crate::{apworld, junk, Error, Result, WorldOrigin};
+
+const MANIFEST_FILE: &str = ".manifest.toml";
+
+// Describes what got deployed by a refresh, so a destination can be traced
+// back to the exact index state that produced it.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Manifest {
+    pub archipelago_version: String,
+    // The oldest python version the deployed archipelago release accepts
+    #[serde(default)]
+    pub python_version: Option<String>,
+    // Files from the archipelago repository copied next to the worlds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub global_files: Vec<String>,
+    pub worlds: BTreeMap<String, WorldManifest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WorldManifest {
+    pub name: String,
+    pub game: Option<String>,
+    pub version: String,
+    #[serde(flatten)]
+    pub origin: WorldOrigin,
+    // Other directories of the archipelago repository deployed with the world
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<String>,
+    #[serde(default)]
+    pub patches: Vec<AppliedPatch>,
+    // Hash of the deployed world, see `content_sha256`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AppliedPatch {
+    pub path: String,
+    pub sha256: String,
+}
+
+impl AppliedPatch {
+    pub fn new(path: &str, patch_file: &Path) -> Result<Self> {
+        Ok(Self {
+            path: path.to_string(),
+            sha256: sha256_file(patch_file)?,
+        })
+    }
+}
+
+pub fn sha256_file(path: &Path) -> Result<String> {
+    let content = std::fs::read(path).map_err(|e| Error::io(path, e))?;
+    Ok(format!("{:x}", Sha256::digest(content)))
+}
+
+// Hashes a deployed world, either an `.apworld` file or a package directory
+// whose files get hashed along with their path. Junk files are left out.
+pub fn content_sha256(path: &Path) -> Result<String> {
+    if path.is_file() {
+        return sha256_file(path);
+    }
+
+    let mut hasher = Sha256::new();
+    for (name, content) in apworld::package_files(path, |name| !junk::is_junk(name))? {
+        hasher.update(name.as_bytes());
+        hasher.update([0]);
+        hasher.update((content.len() as u64).to_le_bytes());
+        hasher.update(content);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl Manifest {
+    pub fn load(destination: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(destination.join(MANIFEST_FILE))
+            .map_err(|e| Error::io(destination.join(MANIFEST_FILE), e))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    #[cfg(feature = "refresh")]
+    pub(crate) fn load_with(fs: &dyn Vfs, destination: &Path) -> Result<Self> {
+        let content = fs.read(&destination.join(MANIFEST_FILE))?;
+        Ok(toml::from_str(std::str::from_utf8(&content)?)?)
+    }
+
+    // Loads a manifest from a destination or from a manifest file, like a
+    // lockfile
+    pub fn load_from(path: &Path) -> Result<Self> {
+        if path.is_dir() {
+            return Self::load(path);
+        }
+
+        let content = std::fs::read_to_string(path).map_err(|e| Error::io(path, e))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub fn write(&self, destination: &Path) -> Result<()> {
+        self.write_to(&destination.join(MANIFEST_FILE))
+    }
+
+    // Writes the manifest to a file of its own, like a lockfile
+    pub fn write_to(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, toml::to_string(self)?).map_err(|e| Error::io(path, e))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,179 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+pub const MIRROR_MANIFEST: &str = "mirror.toml";
+
+// Everything a mirror contains, accumulated over every run so old versions
+// stay archived after the index moves on
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct MirrorManifest {
+    // World key to version to mirrored file
+    pub worlds: BTreeMap<String, BTreeMap<String, MirroredWorld>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MirroredWorld {
+    // Where the file was downloaded from
+    pub url: String,
+    // Relative to the mirror's root
+    pub path: String,
+    pub sha256: String,
+    pub size: u64,
+    // When the file was downloaded, in seconds since the unix epoch
+    pub mirrored_at: u64,
+}
+
+impl MirrorManifest {
+    pub fn load(mirror: &Path) -> Result<Self> {
+        let path = mirror.join(MIRROR_MANIFEST);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = std::fs::read_to_string(&path).map_err(|e| Error::io(path, e))?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    pub fn write(&self, mirror: &Path) -> Result<()> {
+        std::fs::write(mirror.join(MIRROR_MANIFEST), toml::to_string(self)?)
+            .map_err(|e| Error::io(mirror.join(MIRROR_MANIFEST), e))?;
+        Ok(())
+    }
+
+    pub fn get(&self, world: &str, version: &str) -> Option<&MirroredWorld> {
+        self.worlds.get(world)?.get(version)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct MirrorFailure {
+    pub world: String,
+    pub url: String,
+    pub error: String,
+}
+
+// What a run of `Index::mirror` did
+#[derive(Serialize, Debug, Default)]
+pub struct MirrorReport {
+    // World keys to the version downloaded by this run
+    pub mirrored: BTreeMap<String, String>,
+    // Already in the mirror with the expected content
+    pub present: BTreeMap<String, String>,
+    pub failed: Vec<MirrorFailure>,
+}
+
+#[cfg(all(test, feature = "refresh", feature = "zip"))]
+mod tests {
+    use super::*;
+    use crate::{Index, ManualClock, OriginResolver, ResolveFuture};
+    use http::Uri;
+    use std::{
+        sync::{Arc, Mutex},
+        time::{Duration, SystemTime},
+    };
+
+    // Packs `world` as whatever apworld it's asked for, except broken ones
+    struct Store {
+        world: std::path::PathBuf,
+        fetched: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl OriginResolver for Store {
+        fn fetch<'a>(&'a self, uri: &'a Uri, destination: &'a Path) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                self.fetched.lock().unwrap().push(uri.to_string());
+                if uri.to_string().contains("broken") {
+                    crate::bail!("No such apworld");
+                }
+                crate::archive::pack(&self.world, destination)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn archives_every_version_once() {
+        let tmp = tempfile::tempdir().unwrap();
+        let world = tmp.path().join("store/ok");
+        std::fs::create_dir_all(&world).unwrap();
+        std::fs::write(world.join("__init__.py"), "").unwrap();
+        let index_file = tmp.path().join("index.toml");
+        let write_index = |version: &str| {
+            std::fs::write(
+                &index_file,
+                format!(
+                    "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+                     archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+                     required_global_files = []\n\n\
+                     [worlds.broken]\nname = \"Broken\"\nurl = \"store://apworlds/broken.apworld\"\nversion = \"1.0\"\n\n\
+                     [worlds.ok]\nname = \"Ok\"\nurl = \"store://apworlds/ok.apworld\"\nversion = \"{}\"\n\n\
+                     [worlds.unversioned]\nname = \"Unversioned\"\nurl = \"store://apworlds/unversioned.apworld\"\n",
+                    version
+                ),
+            )
+            .unwrap()
+        };
+        let fetched = Arc::new(Mutex::new(vec![]));
+        let open = || {
+            let mut index = Index::new(&index_file).unwrap();
+            index.set_temp_dir(&tmp.path().join("tmp"));
+            index.set_clock(Arc::new(ManualClock::new(
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000),
+            )));
+            index.register_resolver(
+                "store",
+                Store {
+                    world: world.clone(),
+                    fetched: fetched.clone(),
+                },
+            );
+            index
+        };
+        let fetched_since = |count: &mut usize| {
+            let fetched = fetched.lock().unwrap();
+            let since = fetched[*count..]
+                .iter()
+                .map(|uri| uri.trim_start_matches("store://apworlds/").to_string())
+                .collect::<Vec<_>>();
+            *count = fetched.len();
+            since
+        };
+        let mut count = 0;
+
+        write_index("1.0");
+        let mirror = tmp.path().join("mirror");
+        let report = open().mirror(&mirror).await.unwrap();
+        assert_eq!(
+            fetched_since(&mut count),
+            ["broken.apworld", "ok.apworld", "unversioned.apworld"]
+        );
+        assert_eq!(report.mirrored["ok"], "1.0");
+        assert_eq!(report.failed.len(), 1);
+        assert_eq!(report.failed[0].world, "broken");
+        let manifest = MirrorManifest::load(&mirror).unwrap();
+        let ok = manifest.get("ok", "1.0").unwrap();
+        assert_eq!(ok.path, "ok/1.0/ok.apworld");
+        assert_eq!(ok.mirrored_at, 1_000_000);
+        assert!(mirror.join(&ok.path).is_file());
+        // Named after their hash
+        let unversioned = &report.mirrored["unversioned"];
+        assert_eq!(unversioned.len(), 12);
+        assert!(manifest.get("unversioned", unversioned).is_some());
+
+        // Only worlds without a version get downloaded again
+        let report = open().mirror(&mirror).await.unwrap();
+        assert_eq!(
+            fetched_since(&mut count),
+            ["broken.apworld", "unversioned.apworld"]
+        );
+        assert_eq!(report.present["ok"], "1.0");
+
+        // Old versions stay archived
+        write_index("1.1");
+        let report = open().mirror(&mirror).await.unwrap();
+        assert_eq!(report.mirrored["ok"], "1.1");
+        let manifest = MirrorManifest::load(&mirror).unwrap();
+        assert!(manifest.get("ok", "1.0").is_some());
+        assert!(manifest.get("ok", "1.1").is_some());
+    }
+}
@@ -0,0 +1,302 @@
+use rustpython_parser::{
+    ast::{self, Constant, Expr, Stmt, UnaryOp},
+    parse, Mode,
+};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{apworld, junk, Result};
+
+// Option classes from archipelago's `Options.py` that worlds build upon
+const BASE_KINDS: &[&str] = &[
+    "Toggle",
+    "DefaultOnToggle",
+    "Choice",
+    "TextChoice",
+    "Range",
+    "NamedRange",
+    "FreeText",
+    "OptionSet",
+    "OptionList",
+    "OptionDict",
+    "OptionCounter",
+    "ItemDict",
+    "ItemSet",
+    "LocationSet",
+    "StartInventory",
+    "StartInventoryPool",
+    "StartHints",
+    "StartLocationHints",
+    "ExcludeLocations",
+    "PriorityLocations",
+    "DeathLink",
+    "ProgressionBalancing",
+    "Accessibility",
+    "ItemsAccessibility",
+    "LocalItems",
+    "NonLocalItems",
+    "ItemLinks",
+    "PlandoConnections",
+    "PlandoTexts",
+    "PlandoItems",
+];
+
+const OPTIONS_CONTAINERS: &[&str] = &["PerGameCommonOptions", "CommonOptions"];
+
+#[derive(Serialize, Debug, Clone)]
+pub struct OptionSchema {
+    pub name: String,
+    pub class: String,
+    pub kind: String,
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub default: Option<Value>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub choices: BTreeMap<String, i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range_start: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub range_end: Option<i64>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub special_range_names: BTreeMap<String, i64>,
+}
+
+#[derive(Default, Clone)]
+struct Class {
+    bases: Vec<String>,
+    doc: Option<String>,
+    attrs: BTreeMap<String, Value>,
+    fields: Vec<(String, String)>,
+}
+
+fn expr_name(expr: &Expr) -> Option<String> {
+    match expr {
+        Expr::Name(name) => Some(name.id.to_string()),
+        Expr::Attribute(attribute) => Some(attribute.attr.to_string()),
+        Expr::Subscript(subscript) => expr_name(&subscript.value),
+        Expr::Constant(ast::ExprConstant {
+            value: Constant::Str(name),
+            ..
+        }) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+fn constant_value(constant: &Constant) -> Option<Value> {
+    Some(match constant {
+        Constant::None => Value::Null,
+        Constant::Bool(b) => Value::Bool(*b),
+        Constant::Str(s) => Value::String(s.clone()),
+        Constant::Int(i) => Value::from(i.to_string().parse::<i64>().ok()?),
+        Constant::Float(f) => Value::from(*f),
+        Constant::Tuple(items) => Value::Array(items.iter().filter_map(constant_value).collect()),
+        _ => return None,
+    })
+}
+
+// Evaluates simple literals, resolving names against the attributes of the
+// class being parsed
+pub fn expr_value(expr: &Expr, attrs: &BTreeMap<String, Value>) -> Option<Value> {
+    match expr {
+        Expr::Constant(constant) => constant_value(&constant.value),
+        Expr::UnaryOp(op) if matches!(op.op, UnaryOp::USub) => {
+            match expr_value(&op.operand, attrs)? {
+                Value::Number(n) if n.is_i64() => Some(Value::from(-n.as_i64()?)),
+                Value::Number(n) => Some(Value::from(-n.as_f64()?)),
+                _ => None,
+            }
+        }
+        Expr::List(ast::ExprList { elts, .. })
+        | Expr::Tuple(ast::ExprTuple { elts, .. })
+        | Expr::Set(ast::ExprSet { elts, .. }) => Some(Value::Array(
+            elts.iter().filter_map(|e| expr_value(e, attrs)).collect(),
+        )),
+        Expr::Dict(dict) => Some(Value::Object(
+            dict.keys
+                .iter()
+                .zip(dict.values.iter())
+                .filter_map(|(key, value)| {
+                    let key = match expr_value(key.as_ref()?, attrs)? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    Some((key, expr_value(value, attrs)?))
+                })
+                .collect(),
+        )),
+        // frozenset({...}), set(), dict(...)
+        Expr::Call(call) => match call.args.first() {
+            Some(arg) => expr_value(arg, attrs),
+            None => Some(Value::Array(vec![])),
+        },
+        Expr::Name(name) => attrs.get(name.id.as_str()).cloned(),
+        _ => None,
+    }
+}
+
+fn parse_classes(source: &str, file: &str, classes: &mut BTreeMap<String, Class>) {
+    let Ok(ast::Mod::Module(module)) = parse(source, Mode::Module, file) else {
+        return;
+    };
+
+    for stmt in module.body {
+        let Stmt::ClassDef(class_def) = stmt else {
+            continue;
+        };
+
+        let mut class = Class {
+            bases: class_def.bases.iter().filter_map(expr_name).collect(),
+            ..Default::default()
+        };
+        for (i, stmt) in class_def.body.iter().enumerate() {
+            match stmt {
+                Stmt::Expr(ast::StmtExpr { value, .. }) if i == 0 => {
+                    if let Some(Value::String(doc)) = expr_value(value, &class.attrs) {
+                        class.doc = Some(doc.trim().to_string());
+                    }
+                }
+                Stmt::Assign(assign) => {
+                    let Some(value) = expr_value(&assign.value, &class.attrs) else {
+                        continue;
+                    };
+                    for target in &assign.targets {
+                        if let Expr::Name(name) = target {
+                            class.attrs.insert(name.id.to_string(), value.clone());
+                        }
+                    }
+                }
+                Stmt::AnnAssign(assign) => {
+                    let (Expr::Name(name), Some(ty)) =
+                        (assign.target.as_ref(), expr_name(&assign.annotation))
+                    else {
+                        continue;
+                    };
+                    match &assign.value {
+                        Some(value) => {
+                            if let Some(value) = expr_value(value, &class.attrs) {
+                                class.attrs.insert(name.id.to_string(), value);
+                            }
+                        }
+                        None => class.fields.push((name.id.to_string(), ty)),
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        classes.insert(class_def.name.to_string(), class);
+    }
+}
+
+// Walks up the inheritance chain of `name`, returning the archipelago base
+// option kind and the attributes merged from the most generic class down
+fn resolve(name: &str, classes: &BTreeMap<String, Class>, depth: usize) -> Option<(String, Class)> {
+    if BASE_KINDS.contains(&name) {
+        return Some((name.to_string(), Class::default()));
+    }
+    if depth > 16 {
+        return None;
+    }
+
+    let class = classes.get(name)?;
+    let (kind, mut merged) = class
+        .bases
+        .iter()
+        .find_map(|base| resolve(base, classes, depth + 1))?;
+    merged.attrs.extend(class.attrs.clone());
+    merged.doc = class.doc.clone().or(merged.doc);
+
+    Some((kind, merged))
+}
+
+fn is_options_container(name: &str, classes: &BTreeMap<String, Class>, depth: usize) -> bool {
+    if OPTIONS_CONTAINERS.contains(&name) {
+        return true;
+    }
+
+    depth < 16
+        && classes.get(name).is_some_and(|class| {
+            class
+                .bases
+                .iter()
+                .any(|base| is_options_container(base, classes, depth + 1))
+        })
+}
+
+fn schema(name: &str, class_name: &str, classes: &BTreeMap<String, Class>) -> Option<OptionSchema> {
+    let (kind, class) = resolve(class_name, classes, 0)?;
+    let int_attrs = |prefix: &str| {
+        class
+            .attrs
+            .iter()
+            .filter_map(|(key, value)| {
+                Some((key.strip_prefix(prefix)?.to_string(), value.as_i64()?))
+            })
+            .collect::<BTreeMap<_, _>>()
+    };
+
+    let default = class.attrs.get("default").cloned().or(match kind.as_str() {
+        "Toggle" | "DeathLink" => Some(Value::from(0)),
+        "DefaultOnToggle" => Some(Value::from(1)),
+        _ => None,
+    });
+
+    Some(OptionSchema {
+        name: name.to_string(),
+        class: class_name.to_string(),
+        display_name: class
+            .attrs
+            .get("display_name")
+            .and_then(Value::as_str)
+            .map(str::to_string),
+        description: class.doc,
+        default,
+        choices: int_attrs("option_"),
+        range_start: class.attrs.get("range_start").and_then(Value::as_i64),
+        range_end: class.attrs.get("range_end").and_then(Value::as_i64),
+        special_range_names: class
+            .attrs
+            .get("special_range_names")
+            .and_then(Value::as_object)
+            .map(|names| {
+                names
+                    .iter()
+                    .filter_map(|(name, value)| Some((name.clone(), value.as_i64()?)))
+                    .collect()
+            })
+            .unwrap_or_default(),
+        kind,
+    })
+}
+
+// Extracts the options of a world. Options are taken from the fields of its
+// `PerGameCommonOptions` dataclass, or from every option class defined by the
+// world if it doesn't have one.
+pub fn extract_options(path: &Path) -> Result<Vec<OptionSchema>> {
+    let sources =
+        apworld::package_files(path, |name| name.ends_with(".py") && !junk::is_junk(name))?;
+
+    let mut classes = BTreeMap::new();
+    for (file, content) in &sources {
+        parse_classes(&String::from_utf8_lossy(content), file, &mut classes);
+    }
+
+    let containers = classes
+        .iter()
+        .filter(|(name, class)| !class.fields.is_empty() && is_options_container(name, &classes, 0))
+        .collect::<Vec<_>>();
+
+    if containers.is_empty() {
+        return Ok(classes
+            .keys()
+            .filter_map(|class_name| schema(class_name, class_name, &classes))
+            .collect());
+    }
+
+    Ok(containers
+        .iter()
+        .flat_map(|(_, container)| container.fields.iter())
+        .filter_map(|(name, class_name)| schema(name, class_name, &classes))
+        .collect())
+}
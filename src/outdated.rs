@@ -0,0 +1,180 @@
+use crate::Result;
+use git2::{Direction, Remote};
+use reqwest::{header, Client};
+use serde::Serialize;
+
+use crate::{
+    github,
+    version::{self, VersionBump},
+    WorldVersion,
+};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamSource {
+    Release,
+    Tag,
+    Headers,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum UpstreamStatus {
+    UpToDate {
+        latest: String,
+    },
+    Outdated {
+        latest: String,
+        bump: VersionBump,
+    },
+    // Only the headers of the download could be read, there's no version to
+    // compare against
+    Unversioned {
+        last_modified: Option<String>,
+        etag: Option<String>,
+    },
+    Unknown {
+        error: String,
+    },
+}
+
+impl UpstreamStatus {
+    pub fn is_outdated(&self) -> bool {
+        matches!(self, UpstreamStatus::Outdated { .. })
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct UpstreamCheck {
+    pub world: String,
+    pub version: Option<String>,
+    pub source: UpstreamSource,
+    // Where the latest version can be found
+    pub url: String,
+    pub status: UpstreamStatus,
+}
+
+fn compare(current: Option<&str>, latest: String) -> UpstreamStatus {
+    let Some(current) = current else {
+        return UpstreamStatus::Outdated {
+            latest,
+            bump: VersionBump::Unknown,
+        };
+    };
+
+    match version::classify(current, &latest) {
+        VersionBump::Equivalent | VersionBump::Downgrade => UpstreamStatus::UpToDate { latest },
+        VersionBump::Unknown
+            if WorldVersion::from(current) >= WorldVersion::from(latest.as_str()) =>
+        {
+            UpstreamStatus::UpToDate { latest }
+        }
+        bump => UpstreamStatus::Outdated { latest, bump },
+    }
+}
+
+// The highest version among the tags of a git repository
+fn latest_tag(url: &str) -> Result<Option<String>> {
+    let mut remote = Remote::create_detached(url)?;
+    remote.connect(Direction::Fetch)?;
+    let latest = remote
+        .list()?
+        .iter()
+        .filter_map(|head| head.name().strip_prefix("refs/tags/"))
+        .filter(|tag| !tag.ends_with("^{}") && version::is_version(tag))
+        .max_by_key(|tag| WorldVersion::from(*tag))
+        .map(str::to_string);
+
+    Ok(latest)
+}
+
+async fn headers(client: &Client, url: &str) -> UpstreamStatus {
+    let response = match client.head(url).send().await {
+        Ok(response) if response.status().is_success() => response,
+        Ok(response) => {
+            return UpstreamStatus::Unknown {
+                error: response.status().to_string(),
+            }
+        }
+        Err(e) => {
+            return UpstreamStatus::Unknown {
+                error: e.to_string(),
+            }
+        }
+    };
+
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    };
+    UpstreamStatus::Unversioned {
+        last_modified: header(header::LAST_MODIFIED),
+        etag: header(header::ETAG),
+    }
+}
+
+// Looks for the latest version of a world. The latest github release of the
+// first github repository among `links` wins, then the highest version tag of
+// a git repository, and when neither is available the headers of `download`
+// are reported instead. Returns `None` when there's nowhere to look.
+pub async fn check(
+    client: &Client,
+    token: Option<&str>,
+    world: &str,
+    version: Option<&str>,
+    links: &[String],
+    download: Option<&str>,
+) -> Option<UpstreamCheck> {
+    let result = |source, url: String, status| {
+        Some(UpstreamCheck {
+            world: world.to_string(),
+            version: version.map(str::to_string),
+            source,
+            url,
+            status,
+        })
+    };
+
+    let mut git_url = links.iter().find(|link| link.ends_with(".git")).cloned();
+    if let Some((owner, repo)) = links
+        .iter()
+        .find_map(|link| github::owning_repository(link))
+    {
+        if let Ok(release) = github::latest_release(client, token, &owner, &repo).await {
+            return result(
+                UpstreamSource::Release,
+                release.url,
+                compare(version, release.tag),
+            );
+        }
+        // Repositories without any release may still tag their versions
+        git_url = Some(format!("https://github.com/{}/{}.git", owner, repo));
+    }
+
+    if let Some(git_url) = git_url {
+        let error = match latest_tag(&git_url) {
+            Ok(Some(tag)) => {
+                return result(UpstreamSource::Tag, git_url, compare(version, tag));
+            }
+            Ok(None) => "No release nor version tag".to_string(),
+            Err(e) => e.to_string(),
+        };
+        if download.is_none() {
+            return result(
+                UpstreamSource::Tag,
+                git_url,
+                UpstreamStatus::Unknown { error },
+            );
+        }
+    }
+
+    let download = download?;
+    result(
+        UpstreamSource::Headers,
+        download.to_string(),
+        headers(client, download).await,
+    )
+}
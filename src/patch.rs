@@ -0,0 +1,604 @@
+use crate::{Error, Result};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::WorldVersion;
+#[cfg(feature = "refresh")]
+use crate::{
+    bail,
+    diff::{self, DiffOptions, FileChangeKind},
+    sandbox, scratch_dir_beside,
+};
+#[cfg(feature = "refresh")]
+use std::process::{Command, Output};
+use std::{
+    collections::BTreeSet,
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Clone)]
+pub struct Patch {
+    pub path: String,
+    // The world version this patch was upstreamed in. The patch only applies
+    // to versions strictly lower than this one.
+    pub before: Option<String>,
+}
+
+impl Patch {
+    pub fn applies_to(&self, version: Option<&str>) -> bool {
+        match (&self.before, version) {
+            (Some(before), Some(version)) => {
+                WorldVersion::from(version) < WorldVersion::from(before.as_str())
+            }
+            _ => true,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Patch {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum RawPatch {
+            Path(String),
+            Conditional {
+                path: String,
+                before: Option<String>,
+            },
+        }
+
+        Ok(match RawPatch::deserialize(d)? {
+            RawPatch::Path(path) => Patch { path, before: None },
+            RawPatch::Conditional { path, before } => Patch { path, before },
+        })
+    }
+}
+
+// Written back the way it's usually written, a plain path unless it has a
+// `before`
+impl Serialize for Patch {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Conditional<'a> {
+            path: &'a str,
+            before: &'a str,
+        }
+
+        match &self.before {
+            Some(before) => Conditional {
+                path: &self.path,
+                before,
+            }
+            .serialize(s),
+            None => self.path.serialize(s),
+        }
+    }
+}
+
+// Fuzzy patches carry the hunks that needed fuzz, failed ones the output of
+// `patch`
+#[derive(Serialize, Debug)]
+#[serde(tag = "kind", content = "output", rename_all = "snake_case")]
+pub enum PatchStatus {
+    Clean,
+    Fuzzy(Vec<String>),
+    Failed(String),
+}
+
+impl PatchStatus {
+    pub fn is_clean(&self) -> bool {
+        matches!(self, PatchStatus::Clean)
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PatchCheck {
+    pub patch: String,
+    pub status: PatchStatus,
+}
+
+// Rejects patches touching files outside of the directory they're applied to
+#[cfg(feature = "refresh")]
+fn validate_paths(patch: &Path) -> Result<()> {
+    let content = String::from_utf8_lossy(&std::fs::read(patch).map_err(|e| Error::io(patch, e))?)
+        .into_owned();
+    for line in headers(&content) {
+        let path = if let Some(header) = line
+            .strip_prefix("--- ")
+            .or_else(|| line.strip_prefix("+++ "))
+        {
+            // The raw path is checked too, `/etc/passwd` would otherwise
+            // become `etc/passwd` once its first component is dropped
+            let raw = header.split('\t').next().unwrap_or_default().trim();
+            if raw != "/dev/null" && !is_header_path(raw) {
+                Some(raw.to_string())
+            } else {
+                strip_header_path(header)
+            }
+        } else {
+            ["rename from ", "rename to ", "copy from ", "copy to "]
+                .iter()
+                .find_map(|prefix| line.strip_prefix(prefix))
+                .map(str::to_string)
+        };
+
+        if let Some(path) = path {
+            if !sandbox::is_contained(Path::new(&path)) {
+                bail!(
+                    "Patch {} touches {} which is outside of the world",
+                    patch.to_string_lossy(),
+                    path
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "refresh")]
+fn run(dir: &Path, patch: &Path) -> Result<Output> {
+    // `patch -d` changes directory before reading the input file
+    let patch = std::fs::canonicalize(patch).map_err(|e| Error::io(patch, e))?;
+    validate_paths(&patch)?;
+    tracing::debug!(
+        "Applying {} to {}",
+        patch.to_string_lossy(),
+        dir.to_string_lossy()
+    );
+
+    let tmp_dir = scratch_dir_beside(dir)?;
+    let mut command = Command::new("patch");
+    command
+        .arg("-p1")
+        .arg("--forward")
+        .arg("--batch")
+        .arg("--no-backup-if-mismatch")
+        .arg("--reject-file=-")
+        .arg("-d")
+        .arg(dir)
+        .arg("-i")
+        .arg(&patch)
+        .env("TMPDIR", tmp_dir.path());
+    sandbox::restrict(&mut command, &[dir, tmp_dir.path()])?;
+
+    Ok(command.output()?)
+}
+
+#[cfg(feature = "refresh")]
+pub fn apply(dir: &Path, patch: &Path) -> Result<()> {
+    let output = run(dir, patch)?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to apply patch {}:\n{}",
+            patch.to_string_lossy(),
+            String::from_utf8_lossy(&output.stdout)
+        );
+    }
+
+    Ok(())
+}
+
+// Applies the patch like `apply` would but reports fuzzy hunks and failures
+// instead of erroring out.
+#[cfg(feature = "refresh")]
+pub fn check(dir: &Path, patch: &Path) -> Result<PatchStatus> {
+    let output = run(dir, patch)?;
+    let stdout = String::from_utf8_lossy(&output.stdout);
+
+    if !output.status.success() {
+        return Ok(PatchStatus::Failed(stdout.into_owned()));
+    }
+
+    let fuzzy_hunks = stdout
+        .lines()
+        .filter(|line| line.contains("with fuzz"))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    if !fuzzy_hunks.is_empty() {
+        return Ok(PatchStatus::Fuzzy(fuzzy_hunks));
+    }
+
+    Ok(PatchStatus::Clean)
+}
+
+// Two patches touching the same lines of the same file
+#[derive(Serialize, Debug)]
+pub struct PatchConflict {
+    pub file: String,
+    pub first: String,
+    pub first_lines: Range<usize>,
+    pub second: String,
+    pub second_lines: Range<usize>,
+}
+
+struct Hunk {
+    file: String,
+    // The lines the hunk replaces, as written in the patch
+    lines: Range<usize>,
+    // The lines it puts in their place, as written in the patch
+    new_lines: Range<usize>,
+    // `lines` in the file before any of the patches, see `find_conflicts`
+    original: Range<usize>,
+}
+
+impl Hunk {
+    fn new(file: &str, lines: Range<usize>, new_lines: Range<usize>) -> Self {
+        Self {
+            file: file.into(),
+            original: lines.clone(),
+            lines,
+            new_lines,
+        }
+    }
+
+    // Where `lines`, lines of the file once the hunk is applied, were before.
+    // Lines the hunk touched map to all of the lines it replaced.
+    fn unapply(&self, lines: &Range<usize>) -> Range<usize> {
+        let shift = |line: usize| line + self.lines.len() - self.new_lines.len();
+        let start = match lines.start {
+            line if line >= self.new_lines.end => shift(line),
+            line if line >= self.new_lines.start => self.lines.start,
+            line => line,
+        };
+        let end = match lines.end {
+            line if line > self.new_lines.end => shift(line),
+            line if line > self.new_lines.start => self.lines.end,
+            line => line,
+        };
+        start..end
+    }
+}
+
+// Whether a header path is relative and never goes up, `../x/y` would look
+// fine once stripped to `x/y`
+#[cfg(feature = "refresh")]
+fn is_header_path(path: &str) -> bool {
+    !path.starts_with('/')
+        && Path::new(path)
+            .components()
+            .all(|c| !matches!(c, std::path::Component::ParentDir))
+}
+
+fn strip_header_path(header: &str) -> Option<String> {
+    let path = header.split('\t').next()?.trim();
+    if path == "/dev/null" {
+        return None;
+    }
+
+    Some(
+        path.split_once('/')
+            .map_or(path, |(_, path)| path)
+            .to_string(),
+    )
+}
+
+fn parse_range(range: &str) -> Option<Range<usize>> {
+    let (start, count) = range.split_once(',').unwrap_or((range, "1"));
+    let (start, count) = (start.parse::<usize>().ok()?, count.parse::<usize>().ok()?);
+    Some(start..start + count)
+}
+
+// The lines of a patch outside of hunk bodies, where file headers and hunk
+// ranges are. A removed line can very well start with "-- ".
+fn headers(content: &str) -> impl Iterator<Item = &str> {
+    // Lines of the current hunk still to go through, on the old and new side
+    let mut remaining = (0usize, 0usize);
+
+    content.lines().filter(move |line| {
+        if remaining != (0, 0) {
+            match line.chars().next() {
+                Some('-') => remaining.0 = remaining.0.saturating_sub(1),
+                Some('+') => remaining.1 = remaining.1.saturating_sub(1),
+                Some('\\') => {}
+                _ => {
+                    remaining.0 = remaining.0.saturating_sub(1);
+                    remaining.1 = remaining.1.saturating_sub(1);
+                }
+            }
+            return false;
+        }
+
+        if let Some(ranges) = line.strip_prefix("@@ -") {
+            let mut ranges = ranges.split(' ');
+            let old = ranges.next().and_then(parse_range);
+            let new = ranges
+                .next()
+                .and_then(|range| range.strip_prefix('+'))
+                .and_then(parse_range);
+            if let (Some(old), Some(new)) = (old, new) {
+                remaining = (old.len(), new.len());
+            }
+        }
+        true
+    })
+}
+
+fn parse_hunks(content: &str) -> Vec<Hunk> {
+    let mut hunks = vec![];
+    let mut old_file = None;
+    let mut file = None;
+
+    for line in headers(content) {
+        if let Some(header) = line.strip_prefix("--- ") {
+            old_file = strip_header_path(header);
+        } else if let Some(header) = line.strip_prefix("+++ ") {
+            let new_file = strip_header_path(header);
+            // File creations and deletions touch the whole file
+            let is_whole_file = old_file.is_none() || new_file.is_none();
+            file = new_file.or(old_file.take());
+            if let (true, Some(file)) = (is_whole_file, &file) {
+                hunks.push(Hunk::new(file, 0..usize::MAX, 0..usize::MAX));
+            }
+        } else if let Some(ranges) = line.strip_prefix("@@ -") {
+            let mut ranges = ranges.split(' ');
+            let old = ranges.next().and_then(parse_range);
+            let new = ranges
+                .next()
+                .and_then(|range| range.strip_prefix('+'))
+                .and_then(parse_range);
+            let (Some(old), Some(new)) = (old, new) else {
+                continue;
+            };
+            let Some(file) = &file else {
+                continue;
+            };
+
+            // An empty range is the position lines get inserted after
+            let touched = |range: Range<usize>| range.start..range.end.max(range.start + 1);
+            hunks.push(Hunk::new(file, touched(old), touched(new)));
+        }
+    }
+
+    hunks
+}
+
+// Finds hunks from different patches touching overlapping lines. Patches are
+// given as (name, content) pairs in application order.
+pub fn find_conflicts(patches: &[(String, String)]) -> Vec<PatchConflict> {
+    let mut hunks = patches
+        .iter()
+        .map(|(name, content)| (name, parse_hunks(content)))
+        .collect::<Vec<_>>();
+    // Later patches are written against the file earlier ones produced, their
+    // lines are brought back to the file before any patch to compare them
+    for i in 1..hunks.len() {
+        let (earlier, later) = hunks.split_at_mut(i);
+        for hunk in &mut later[0].1 {
+            if hunk.lines.end == usize::MAX {
+                continue;
+            }
+            for (_, earlier_hunks) in earlier.iter().rev() {
+                let same_file = earlier_hunks
+                    .iter()
+                    .filter(|earlier| earlier.file == hunk.file && earlier.lines.end != usize::MAX);
+                // Hunks further down the file are undone first so positions
+                // above them stay valid
+                let mut same_file = same_file.collect::<Vec<_>>();
+                same_file.sort_by_key(|earlier| std::cmp::Reverse(earlier.new_lines.start));
+                for earlier in same_file {
+                    hunk.original = earlier.unapply(&hunk.original);
+                }
+            }
+        }
+    }
+
+    let mut conflicts = vec![];
+    for (i, (first, first_hunks)) in hunks.iter().enumerate() {
+        for (second, second_hunks) in &hunks[i + 1..] {
+            for a in first_hunks {
+                for b in second_hunks {
+                    if a.file == b.file
+                        && a.original.start < b.original.end
+                        && b.original.start < a.original.end
+                    {
+                        conflicts.push(PatchConflict {
+                            file: a.file.clone(),
+                            first: first.to_string(),
+                            first_lines: a.lines.clone(),
+                            second: second.to_string(),
+                            second_lines: b.lines.clone(),
+                        });
+                    }
+                }
+            }
+        }
+    }
+
+    conflicts
+}
+
+pub fn collect_files(root: &Path, dir: &Path, files: &mut BTreeSet<PathBuf>) -> Result<()> {
+    if !dir.exists() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let path = entry.path();
+        if entry
+            .file_type()
+            .map_err(|e| Error::io(entry.path(), e))?
+            .is_dir()
+        {
+            collect_files(root, &path, files)?;
+        } else {
+            files.insert(path.strip_prefix(root)?.to_path_buf());
+        }
+    }
+
+    Ok(())
+}
+
+// Produces a `patch -p1` compatible unified diff turning `original` into
+// `modified`. Junk files like python bytecode caches are ignored.
+#[cfg(feature = "refresh")]
+pub fn generate(original: &Path, modified: &Path) -> Result<String> {
+    let mut diff = String::new();
+    for change in diff::diff_dirs(original, modified, &DiffOptions::default())? {
+        let Some(file_diff) = change.diff else {
+            let root = match change.kind {
+                FileChangeKind::Removed => original,
+                _ => modified,
+            };
+            bail!(
+                "{} is a binary file and can't be diffed, use a transform instead",
+                root.join(&change.path).to_string_lossy()
+            );
+        };
+        diff.push_str(&file_diff);
+    }
+
+    Ok(diff)
+}
+
+#[cfg(all(test, feature = "refresh"))]
+mod tests {
+    use super::*;
+
+    const PATCH: &str = "\
+--- a/a.py
++++ b/a.py
+@@ -1,3 +1,3 @@
+ one
+-two
++deux
+ three
+";
+
+    // A world with `a.py` in it, and a directory next to it for patches and
+    // files the world shouldn't reach
+    fn world() -> (tempfile::TempDir, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let world = tmp.path().join("world");
+        std::fs::create_dir_all(&world).unwrap();
+        std::fs::write(world.join("a.py"), "one\ntwo\nthree\n").unwrap();
+        (tmp, world)
+    }
+
+    fn write_patch(tmp: &tempfile::TempDir, content: &str) -> PathBuf {
+        let path = tmp.path().join("world.patch");
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn read(world: &Path) -> String {
+        std::fs::read_to_string(world.join("a.py")).unwrap()
+    }
+
+    #[test]
+    fn applies_patches() {
+        let (tmp, world) = world();
+        apply(&world, &write_patch(&tmp, PATCH)).unwrap();
+        assert_eq!(read(&world), "one\ndeux\nthree\n");
+    }
+
+    #[test]
+    fn fails_on_patches_that_dont_apply() {
+        let (tmp, world) = world();
+        let patch = write_patch(&tmp, &PATCH.replace("-two", "-zwei"));
+        assert!(apply(&world, &patch).is_err());
+        assert_eq!(read(&world), "one\ntwo\nthree\n");
+    }
+
+    #[test]
+    fn checks_patches() {
+        let (tmp, world) = world();
+        let patch = write_patch(&tmp, PATCH);
+        assert!(matches!(check(&world, &patch).unwrap(), PatchStatus::Clean));
+        // Applied already, `--forward` refuses to apply it again
+        assert!(matches!(
+            check(&world, &patch).unwrap(),
+            PatchStatus::Failed(_)
+        ));
+    }
+
+    #[test]
+    fn reports_fuzzy_hunks() {
+        let (tmp, world) = world();
+        std::fs::write(world.join("a.py"), "a\nb\nc\nd\ne\nf\ng\n").unwrap();
+        let patch = write_patch(
+            &tmp,
+            "--- a/a.py\n+++ b/a.py\n@@ -1,7 +1,7 @@\n X\n b\n c\n-d\n+D\n e\n f\n g\n",
+        );
+        let PatchStatus::Fuzzy(hunks) = check(&world, &patch).unwrap() else {
+            panic!("the first line of context doesn't match");
+        };
+        assert_eq!(hunks.len(), 1);
+        assert!(!PatchStatus::Fuzzy(hunks).is_clean());
+    }
+
+    #[test]
+    fn generates_patches_that_apply() {
+        let (tmp, world) = world();
+        let modified = tmp.path().join("modified");
+        std::fs::create_dir_all(&modified).unwrap();
+        std::fs::write(modified.join("a.py"), "one\n2\nthree\nfour\n").unwrap();
+        std::fs::write(modified.join("b.py"), "new\n").unwrap();
+
+        let patch = write_patch(&tmp, &generate(&world, &modified).unwrap());
+        apply(&world, &patch).unwrap();
+        assert_eq!(read(&world), "one\n2\nthree\nfour\n");
+        assert_eq!(
+            std::fs::read_to_string(world.join("b.py")).unwrap(),
+            "new\n"
+        );
+    }
+
+    #[test]
+    fn rejects_paths_outside_of_the_world() {
+        let (tmp, world) = world();
+        std::fs::write(tmp.path().join("outside.py"), "one\ntwo\nthree\n").unwrap();
+        let patches = [
+            PATCH.replace("a/a.py", "/etc/passwd"),
+            PATCH.replace("a/a.py", "../a/b"),
+            PATCH.replace("a/a.py", "a/../outside.py"),
+            PATCH.replace("b/a.py", "b/../outside.py"),
+            format!(
+                "diff --git a/a.py b/a.py\nrename from a.py\nrename to ../a.py\n{}",
+                PATCH
+            ),
+        ];
+        for content in patches {
+            let patch = write_patch(&tmp, &content);
+            assert!(validate_paths(&patch).is_err(), "{}", content);
+            assert!(apply(&world, &patch).is_err(), "{}", content);
+        }
+        assert_eq!(read(&world), "one\ntwo\nthree\n");
+        assert_eq!(
+            std::fs::read_to_string(tmp.path().join("outside.py")).unwrap(),
+            "one\ntwo\nthree\n"
+        );
+    }
+
+    #[test]
+    fn ignores_header_like_lines_in_hunks() {
+        let (tmp, world) = world();
+        std::fs::write(world.join("a.py"), "one\n-- /etc/passwd\nthree\n").unwrap();
+        // Removing `-- /etc/passwd` and adding `++ /etc/shadow` gives lines
+        // looking like file headers
+        let patch = write_patch(
+            &tmp,
+            "--- a/a.py\n+++ b/a.py\n@@ -1,3 +1,3 @@\n one\n--- /etc/passwd\n+++ /etc/shadow\n three\n",
+        );
+        validate_paths(&patch).unwrap();
+        apply(&world, &patch).unwrap();
+        assert_eq!(read(&world), "one\n++ /etc/shadow\nthree\n");
+    }
+
+    #[test]
+    fn finds_patches_touching_the_same_lines() {
+        let other = PATCH.replace("+deux", "+zwei");
+        let unrelated = PATCH.replace("a.py", "b.py");
+        let conflicts = find_conflicts(&[
+            ("first.patch".into(), PATCH.into()),
+            ("second.patch".into(), other),
+            ("third.patch".into(), unrelated),
+        ]);
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].file, "a.py");
+        assert_eq!(conflicts[0].first, "first.patch");
+        assert_eq!(conflicts[0].second, "second.patch");
+    }
+}
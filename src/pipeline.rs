@@ -0,0 +1,432 @@
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "refresh")]
+use std::path::Path;
+use std::{collections::BTreeMap, path::PathBuf, sync::Mutex, time::SystemTime};
+
+use crate::{bail, AppliedPatch, Index, Result, WorldManifest, WorldOrigin, WorldVersion};
+#[cfg(feature = "refresh")]
+use crate::{
+    copy_dir_all, delete_file_or_dir, manifest, syntax, CancellationToken, Error, LicensePolicy,
+    SyntaxPolicy,
+};
+
+// A world of the index with everything it's going to be fetched and patched
+// with, from `Index::resolve_world`
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ResolvedWorld {
+    pub key: String,
+    pub origin: WorldOrigin,
+    pub version: WorldVersion,
+    // Where url worlds get downloaded from
+    pub url: Option<String>,
+    pub patches: Vec<AppliedPatch>,
+    pub dependencies: Vec<String>,
+    // The hash the fetched artifact must have, set it to pin a known one.
+    // It's the hash of the world before patches, unlike the manifest's.
+    pub sha256: Option<String>,
+}
+
+// A world as its origin has it, before patches, from `Index::fetch_world`.
+// It can be installed into any number of destinations.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FetchedArtifact {
+    pub resolved: ResolvedWorld,
+    // Holds the world, and its dependencies for supported worlds
+    pub dir: PathBuf,
+    // The `.apworld` file or the package directory of the world in `dir`
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct InstalledWorld {
+    pub key: String,
+    pub path: PathBuf,
+    pub manifest: WorldManifest,
+}
+
+// Worlds resolved so far, reused as long as their patch files keep the same
+// modification time so patches aren't hashed again on every refresh
+#[derive(Debug, Default)]
+pub(crate) struct ResolutionCache {
+    worlds: Mutex<BTreeMap<String, CachedResolution>>,
+}
+
+#[derive(Debug)]
+struct CachedResolution {
+    patches_modified: Vec<Option<SystemTime>>,
+    resolved: ResolvedWorld,
+}
+
+impl ResolutionCache {
+    fn get(&self, key: &str, modified: &[Option<SystemTime>]) -> Option<ResolvedWorld> {
+        match self.worlds.lock().unwrap().get(key) {
+            Some(cached) if cached.patches_modified == modified => Some(cached.resolved.clone()),
+            _ => None,
+        }
+    }
+
+    fn insert(&self, key: &str, modified: Vec<Option<SystemTime>>, resolved: ResolvedWorld) {
+        let cached = CachedResolution {
+            patches_modified: modified,
+            resolved,
+        };
+        self.worlds.lock().unwrap().insert(key.into(), cached);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.worlds.lock().unwrap().clear();
+    }
+}
+
+// Where the artifact of a world cached by an earlier refresh is described
+#[cfg(feature = "refresh")]
+fn cached_artifact_path(cache: &Path, key: &str) -> PathBuf {
+    cache.join(format!("{}.json", key))
+}
+
+// The artifact an earlier refresh kept in `cache`, if it was fetched from the
+// same url for the same version and hasn't been touched since
+#[cfg(feature = "refresh")]
+pub(crate) fn cached_artifact(cache: &Path, resolved: &ResolvedWorld) -> Option<FetchedArtifact> {
+    let content = std::fs::read(cached_artifact_path(cache, &resolved.key)).ok()?;
+    let artifact = serde_json::from_slice::<FetchedArtifact>(&content).ok()?;
+    if artifact.resolved.url != resolved.url
+        || artifact.resolved.version != resolved.version
+        || resolved
+            .sha256
+            .as_ref()
+            .is_some_and(|sha256| *sha256 != artifact.sha256)
+        || manifest::content_sha256(&artifact.path).ok()? != artifact.sha256
+    {
+        return None;
+    }
+
+    Some(FetchedArtifact {
+        resolved: resolved.clone(),
+        ..artifact
+    })
+}
+
+#[cfg(feature = "refresh")]
+pub(crate) fn cache_artifact(cache: &Path, artifact: &FetchedArtifact) -> Result<()> {
+    let path = cached_artifact_path(cache, &artifact.resolved.key);
+    std::fs::write(&path, serde_json::to_vec_pretty(artifact)?).map_err(|e| Error::io(path, e))
+}
+
+impl Index {
+    // The cache directory when `resolved` can be reused from it: url worlds
+    // with a declared version, a url alone could serve anything over time
+    #[cfg(feature = "refresh")]
+    pub(crate) fn artifact_cache(&self, resolved: &ResolvedWorld) -> Option<&Path> {
+        let world = self.worlds.get(&resolved.key)?;
+        if resolved.url.is_none() || world.declared_version().is_none() {
+            return None;
+        }
+
+        self.dirs.cache()
+    }
+
+    pub fn resolve_world(&self, key: &str) -> Result<ResolvedWorld> {
+        let Some(world) = self.worlds.get(key) else {
+            bail!("No world named {} in the index", key);
+        };
+        let index_dir = self.index_dir()?;
+        let patch_files = world
+            .active_patches()
+            .map(|patch| (patch, index_dir.join(&patch.path)))
+            .collect::<Vec<_>>();
+        let modified = patch_files
+            .iter()
+            .map(|(_, file)| std::fs::metadata(file).and_then(|m| m.modified()).ok())
+            .collect::<Vec<_>>();
+        // The world itself may have been changed since
+        let cached = self.resolutions.get(key, &modified).filter(|resolved| {
+            resolved.origin == world.origin
+                && resolved.version == world.version()
+                && resolved.dependencies == world.dependencies
+                && resolved
+                    .patches
+                    .iter()
+                    .map(|patch| &patch.path)
+                    .eq(patch_files.iter().map(|(patch, _)| &patch.path))
+        });
+        if let Some(resolved) = cached {
+            return Ok(resolved);
+        }
+
+        let patches = patch_files
+            .iter()
+            .map(|(patch, file)| AppliedPatch::new(&patch.path, file))
+            .collect::<Result<Vec<_>>>()?;
+        let resolved = ResolvedWorld {
+            key: key.into(),
+            origin: world.origin.clone(),
+            version: world.version(),
+            url: world.origin.url().map(ToString::to_string),
+            patches,
+            dependencies: world.dependencies.clone(),
+            sha256: None,
+        };
+        self.resolutions.insert(key, modified, resolved.clone());
+
+        Ok(resolved)
+    }
+
+    // Forgets the worlds resolved so far, they get resolved again when needed
+    pub fn clear_resolutions(&self) {
+        self.resolutions.clear();
+    }
+
+    // Fetches a resolved world into `dir/<key>`, replacing what's there.
+    // Supported worlds need a checkout of archipelago, made on the way.
+    #[cfg(feature = "refresh")]
+    pub async fn fetch_world(
+        &self,
+        resolved: &ResolvedWorld,
+        dir: &Path,
+    ) -> Result<FetchedArtifact> {
+        let ap_dir = self.dirs.tempdir()?;
+        if resolved.origin.is_supported() {
+            self.checkout_archipelago_at(
+                ap_dir.path(),
+                &self.common.archipelago_version,
+                &CancellationToken::new(),
+            )?;
+        }
+
+        self.fetch_in(resolved, dir, ap_dir.path()).await
+    }
+
+    #[cfg(feature = "refresh")]
+    pub(crate) async fn fetch_in(
+        &self,
+        resolved: &ResolvedWorld,
+        dir: &Path,
+        ap_dir: &Path,
+    ) -> Result<FetchedArtifact> {
+        let Some(world) = self.worlds.get(&resolved.key) else {
+            bail!("No world named {} in the index", resolved.key);
+        };
+        let artifact_dir = dir.join(&resolved.key);
+        if artifact_dir.exists() {
+            std::fs::remove_dir_all(&artifact_dir).map_err(|e| Error::io(&artifact_dir, e))?;
+        }
+        std::fs::create_dir_all(&artifact_dir).map_err(|e| Error::io(&artifact_dir, e))?;
+
+        let path = Self::world_destination(&resolved.key, world, &artifact_dir)?;
+        world
+            .fetch_verified(
+                &self.downloader,
+                &resolved.key,
+                &path,
+                ap_dir,
+                self.index_dir()?,
+            )
+            .await?;
+        let path = world.package_path(&path);
+        let sha256 = manifest::content_sha256(&path)?;
+        if let Some(expected) = &resolved.sha256 {
+            if *expected != sha256 {
+                bail!(
+                    "{} doesn't have the expected hash, expected {} but got {}",
+                    resolved.key,
+                    expected,
+                    sha256
+                );
+            }
+        }
+
+        Ok(FetchedArtifact {
+            resolved: resolved.clone(),
+            dir: artifact_dir,
+            path,
+            sha256,
+        })
+    }
+
+    // Installs a fetched world into `destination`, patched and checked. The
+    // artifact is left as is and the destination's manifest isn't touched.
+    #[cfg(feature = "refresh")]
+    pub fn install_world(
+        &self,
+        artifact: &FetchedArtifact,
+        destination: &Path,
+    ) -> Result<InstalledWorld> {
+        let key = &artifact.resolved.key;
+        let Some(world) = self.worlds.get(key) else {
+            bail!("No world named {} in the index", key);
+        };
+        std::fs::create_dir_all(destination).map_err(|e| Error::io(destination, e))?;
+        for entry in std::fs::read_dir(&artifact.dir).map_err(|e| Error::io(&artifact.dir, e))? {
+            let target =
+                destination.join(entry.map_err(|e| Error::io(&artifact.dir, e))?.file_name());
+            if target.exists() {
+                delete_file_or_dir(&target)?;
+            }
+        }
+        copy_dir_all(&artifact.dir, destination)?;
+
+        let index_dir = self.index_dir()?;
+        let world_dest = Self::world_destination(key, world, destination)?;
+        world.prepare(key, &world_dest, index_dir, &self.common)?;
+        world.validate(key, &world_dest)?;
+        world.resolve_info(&world_dest)?;
+        if self.common.license_policy == LicensePolicy::Error
+            && !world.is_supported()
+            && world.license().is_none()
+        {
+            bail!("{} doesn't contain a license", key);
+        }
+
+        let path = world.package_path(&world_dest);
+        // Catches broken uploads now rather than when generating
+        if self.common.syntax_policy != SyntaxPolicy::Ignore {
+            let errors = syntax::check_syntax(&path)?;
+            let errors = errors.iter().map(ToString::to_string).collect::<Vec<_>>();
+            match (self.common.syntax_policy, errors.is_empty()) {
+                (_, true) => {}
+                (SyntaxPolicy::Error, false) => {
+                    bail!("{} has syntax errors:\n  {}", key, errors.join("\n  "))
+                }
+                (_, false) => {
+                    for error in &errors {
+                        tracing::warn!("{} has a syntax error: {}", key, error);
+                    }
+                }
+            }
+        }
+
+        let mut manifest = world.manifest(index_dir)?;
+        manifest.sha256 = Some(manifest::content_sha256(&path)?);
+
+        Ok(InstalledWorld {
+            key: key.clone(),
+            path,
+            manifest,
+        })
+    }
+}
+
+#[cfg(all(test, feature = "refresh"))]
+mod tests {
+    use super::*;
+
+    const WORLD: &str = "from worlds.AutoWorld import World\n\n\
+class OkWorld(World):\n    game = \"Ok Game\"\n";
+
+    // An index in `dir` with a local world
+    fn index(dir: &Path) -> Index {
+        std::fs::create_dir_all(dir.join("ok")).unwrap();
+        std::fs::write(dir.join("ok/__init__.py"), WORLD).unwrap();
+        std::fs::write(dir.join("ok/LICENSE"), "MIT\n").unwrap();
+        let index_file = dir.join("index.toml");
+        std::fs::write(
+            &index_file,
+            "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+             archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+             required_global_files = []\n\n\
+             [worlds.ok]\nname = \"Ok Game\"\nversion = \"1.0\"\nlocal = \"ok\"\n",
+        )
+        .unwrap();
+
+        let mut index = Index::new(&index_file).unwrap();
+        index.set_temp_dir(&dir.join("tmp"));
+        index
+    }
+
+    #[tokio::test]
+    async fn installs_an_artifact_into_several_destinations() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index = index(tmp.path());
+        let resolved = index.resolve_world("ok").unwrap();
+        assert_eq!(resolved.url, None);
+        let artifact = index
+            .fetch_world(&resolved, &tmp.path().join("artifacts"))
+            .await
+            .unwrap();
+        assert_eq!(artifact.dir, tmp.path().join("artifacts/ok"));
+
+        for destination in ["first", "second"].map(|name| tmp.path().join(name)) {
+            let installed = index.install_world(&artifact, &destination).unwrap();
+            assert_eq!(installed.path, destination.join("ok"));
+            assert_eq!(installed.manifest.game.as_deref(), Some("Ok Game"));
+            assert_eq!(
+                std::fs::read_to_string(installed.path.join("__init__.py")).unwrap(),
+                WORLD
+            );
+            // No manifest, that's for the refresh to write
+            assert!(!destination.join(".manifest.toml").exists());
+        }
+        assert!(artifact.path.join("__init__.py").exists());
+    }
+
+    #[tokio::test]
+    async fn reuses_cached_artifacts_only_for_the_same_download() {
+        let tmp = tempfile::tempdir().unwrap();
+        let index = index(tmp.path());
+        let cache = tmp.path().join("cache");
+        std::fs::create_dir_all(&cache).unwrap();
+        let mut resolved = index.resolve_world("ok").unwrap();
+        resolved.url = Some("https://example.com/ok.apworld".into());
+        let artifact = index.fetch_world(&resolved, &cache).await.unwrap();
+        assert!(cached_artifact(&cache, &resolved).is_none());
+        cache_artifact(&cache, &artifact).unwrap();
+
+        let cached = cached_artifact(&cache, &resolved).unwrap();
+        assert_eq!(cached.sha256, artifact.sha256);
+        let mut pinned = resolved.clone();
+        pinned.sha256 = Some(artifact.sha256.clone());
+        assert!(cached_artifact(&cache, &pinned).is_some());
+
+        let mut other = resolved.clone();
+        other.version = "1.1".into();
+        assert!(cached_artifact(&cache, &other).is_none());
+        let mut other = resolved.clone();
+        other.url = Some("https://example.com/other.apworld".into());
+        assert!(cached_artifact(&cache, &other).is_none());
+        let mut other = resolved.clone();
+        other.sha256 = Some("0".repeat(64));
+        assert!(cached_artifact(&cache, &other).is_none());
+
+        // Touched since it was cached
+        std::fs::write(artifact.path.join("__init__.py"), "").unwrap();
+        assert!(cached_artifact(&cache, &resolved).is_none());
+    }
+
+    #[test]
+    fn reuses_resolutions_until_patches_change() {
+        let tmp = tempfile::tempdir().unwrap();
+        index(tmp.path());
+        let index_file = tmp.path().join("index.toml");
+        let content = std::fs::read_to_string(&index_file).unwrap();
+        std::fs::write(&index_file, content + "patches = [\"ok.patch\"]\n").unwrap();
+        let patch = tmp.path().join("ok.patch");
+        let write_patch = |content: &str, modified: SystemTime| {
+            std::fs::write(&patch, content).unwrap();
+            std::fs::File::options()
+                .write(true)
+                .open(&patch)
+                .unwrap()
+                .set_modified(modified)
+                .unwrap();
+        };
+        let sha256 = |index: &Index| index.resolve_world("ok").unwrap().patches[0].sha256.clone();
+
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        write_patch("first", modified);
+        let index = Index::new(&index_file).unwrap();
+        let first = sha256(&index);
+
+        // Clones share what was resolved, and the patch looks the same
+        write_patch("second", modified);
+        assert_eq!(sha256(&index.clone()), first);
+
+        index.clear_resolutions();
+        let second = sha256(&index);
+        assert_ne!(second, first);
+
+        write_patch("third", modified + std::time::Duration::from_secs(1));
+        assert_ne!(sha256(&index), second);
+    }
+}
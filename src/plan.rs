@@ -0,0 +1,298 @@
+use serde::Serialize;
+use std::{
+    collections::BTreeSet,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    manifest::Manifest, CancellationToken, Index, RefreshSummary, Result, WorldOrigin, WorldVersion,
+};
+
+// How a world of the plan compares with what the destination has
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PlannedChange {
+    // Not in the destination yet
+    Add,
+    // Deployed with another version, origin or patches
+    Update,
+    // Deployed with a newer version, going back to an older one
+    Downgrade,
+    // Deployed the same way already, refreshed again all the same
+    Unchanged,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedWorld {
+    pub key: String,
+    pub origin: WorldOrigin,
+    pub version: WorldVersion,
+    // The version in the destination's manifest
+    pub deployed_version: Option<WorldVersion>,
+    pub patches: Vec<String>,
+    pub change: PlannedChange,
+}
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum RefreshAction {
+    CheckoutArchipelago { repo: String, version: String },
+    InstallWorld(PlannedWorld),
+    // A world of the destination that won't be in it anymore
+    RemoveWorld { key: String, version: WorldVersion },
+    CopyGlobalFile { path: String },
+    WriteTemplates { dir: PathBuf },
+    ReplaceDestination { destination: PathBuf },
+}
+
+// What a refresh would do, from `Index::plan_refresh`, to show or confirm it
+// before running it with `execute`
+#[derive(Serialize, Debug)]
+pub struct RefreshPlan<'a> {
+    #[serde(skip)]
+    index: &'a Index,
+    destination: PathBuf,
+    actions: Vec<RefreshAction>,
+}
+
+impl<'a> RefreshPlan<'a> {
+    pub(crate) fn new(index: &'a Index, destination: &Path) -> Result<Self> {
+        let deployed = Manifest::load_with(index.fs.as_ref(), destination)
+            .ok()
+            .map(|manifest| manifest.worlds)
+            .unwrap_or_default();
+
+        let mut actions = vec![RefreshAction::CheckoutArchipelago {
+            repo: index.common.archipelago_repo.to_string(),
+            version: index.common.archipelago_version.clone(),
+        }];
+        for (key, world) in &index.worlds {
+            let patches = world.active_patch_names();
+            let version = world.version();
+            let deployed_world = deployed.get(key);
+            let deployed_version =
+                deployed_world.map(|deployed| WorldVersion::from(deployed.version.as_str()));
+            let change = match (deployed_world, &deployed_version) {
+                (Some(deployed), Some(deployed_version))
+                    if deployed_version.as_str() == version.as_str()
+                        && deployed.origin == world.origin
+                        && deployed
+                            .patches
+                            .iter()
+                            .map(|patch| &patch.path)
+                            .eq(&patches) =>
+                {
+                    PlannedChange::Unchanged
+                }
+                (_, Some(deployed_version)) if deployed_version.is_downgrade_to(&version) => {
+                    PlannedChange::Downgrade
+                }
+                (_, Some(_)) => PlannedChange::Update,
+                _ => PlannedChange::Add,
+            };
+            actions.push(RefreshAction::InstallWorld(PlannedWorld {
+                key: key.clone(),
+                origin: world.origin.clone(),
+                version,
+                deployed_version,
+                patches,
+                change,
+            }));
+        }
+        for (key, deployed) in &deployed {
+            if !index.worlds.contains_key(key) {
+                actions.push(RefreshAction::RemoveWorld {
+                    key: key.clone(),
+                    version: deployed.version.as_str().into(),
+                });
+            }
+        }
+        for path in &index.common.required_global_files {
+            actions.push(RefreshAction::CopyGlobalFile { path: path.clone() });
+        }
+        if let Some(templates_dir) = &index.common.templates_dir {
+            actions.push(RefreshAction::WriteTemplates {
+                dir: index.index_dir()?.join(templates_dir),
+            });
+        }
+        actions.push(RefreshAction::ReplaceDestination {
+            destination: destination.into(),
+        });
+
+        Ok(Self {
+            index,
+            destination: destination.into(),
+            actions,
+        })
+    }
+
+    pub fn destination(&self) -> &Path {
+        &self.destination
+    }
+
+    pub fn actions(&self) -> &[RefreshAction] {
+        &self.actions
+    }
+
+    pub fn worlds(&self) -> impl Iterator<Item = &PlannedWorld> {
+        self.actions.iter().filter_map(|action| match action {
+            RefreshAction::InstallWorld(world) => Some(world),
+            _ => None,
+        })
+    }
+
+    // Keeps the worlds `keep` returns true for. The others are left out of
+    // the destination, removing them if they were deployed.
+    pub fn retain_worlds(&mut self, mut keep: impl FnMut(&PlannedWorld) -> bool) {
+        let mut removed = vec![];
+        self.actions.retain(|action| match action {
+            RefreshAction::InstallWorld(world) if !keep(world) => {
+                if let Some(version) = &world.deployed_version {
+                    removed.push(RefreshAction::RemoveWorld {
+                        key: world.key.clone(),
+                        version: version.clone(),
+                    });
+                }
+                false
+            }
+            _ => true,
+        });
+
+        let position = self
+            .actions
+            .iter()
+            .position(|action| {
+                !matches!(
+                    action,
+                    RefreshAction::CheckoutArchipelago { .. } | RefreshAction::InstallWorld(_)
+                )
+            })
+            .unwrap_or(self.actions.len());
+        self.actions.splice(position..position, removed);
+    }
+
+    pub async fn execute(self) -> Result<RefreshSummary> {
+        self.execute_with(&CancellationToken::new()).await
+    }
+
+    pub async fn execute_with(self, cancel: &CancellationToken) -> Result<RefreshSummary> {
+        let keys = self
+            .worlds()
+            .map(|world| world.key.clone())
+            .collect::<BTreeSet<_>>();
+        let built = self.index.dirs.tempdir()?;
+        let summary = self
+            .index
+            .refresh_staged(built.path(), &keys, cancel)
+            .await?;
+
+        cancel.check()?;
+        self.index.deploy(built.path(), &self.destination, cancel)?;
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryFs, Vfs};
+    use std::sync::Arc;
+
+    fn world(key: &str, version: &str) -> String {
+        format!(
+            "[worlds.{key}]\nname = \"{key}\"\nurl = \"https://example.com/{key}.apworld\"\n\
+             version = \"{version}\"\n"
+        )
+    }
+
+    fn planned(plan: &RefreshPlan) -> Vec<(String, PlannedChange)> {
+        plan.worlds()
+            .map(|world| (world.key.clone(), world.change))
+            .collect()
+    }
+
+    #[test]
+    fn compares_worlds_with_the_destination() {
+        let index = format!(
+            "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+             archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+             required_global_files = [\"LICENSE\"]\n\n{}{}{}{}",
+            world("added", "1.0"),
+            world("downgraded", "1.5"),
+            world("unchanged", "1.0"),
+            world("updated", "1.1"),
+        );
+        let mut index = Index::from_str(&index, Path::new("/srv/index")).unwrap();
+        let deployed = [
+            "downgraded 2.0",
+            "removed 1.0",
+            "unchanged 1.0",
+            "updated 1.0",
+        ]
+        .iter()
+        .map(|world| {
+            let (key, version) = world.split_once(' ').unwrap();
+            format!(
+                "[worlds.{key}]\nname = \"{key}\"\ngame = \"{key}\"\nversion = \"{version}\"\n\
+                     url = \"https://example.com/{key}.apworld\"\n"
+            )
+        })
+        .collect::<String>();
+        let fs = Arc::new(MemoryFs::new());
+        fs.write(
+            Path::new("/worlds/.manifest.toml"),
+            format!("archipelago_version = \"0.5.0\"\n\n{}", deployed).as_bytes(),
+        )
+        .unwrap();
+        index.set_filesystem(fs);
+
+        let mut plan = index.plan_refresh(Path::new("/worlds")).unwrap();
+        assert_eq!(
+            planned(&plan),
+            [
+                ("added".into(), PlannedChange::Add),
+                ("downgraded".into(), PlannedChange::Downgrade),
+                ("unchanged".into(), PlannedChange::Unchanged),
+                ("updated".into(), PlannedChange::Update),
+            ]
+        );
+        assert!(matches!(
+            plan.actions().first(),
+            Some(RefreshAction::CheckoutArchipelago { version, .. }) if version == "0.5.0"
+        ));
+        assert!(matches!(
+            plan.actions().last(),
+            Some(RefreshAction::ReplaceDestination { destination }) if destination == Path::new("/worlds")
+        ));
+
+        // Left out worlds that were deployed get removed, before the global
+        // files get copied
+        plan.retain_worlds(|world| world.key != "added" && world.key != "updated");
+        let actions = plan
+            .actions()
+            .iter()
+            .map(|action| match action {
+                RefreshAction::CheckoutArchipelago { .. } => "checkout".into(),
+                RefreshAction::InstallWorld(world) => format!("install {}", world.key),
+                RefreshAction::RemoveWorld { key, version } => {
+                    format!("remove {} {}", key, version)
+                }
+                RefreshAction::CopyGlobalFile { path } => format!("copy {}", path),
+                RefreshAction::WriteTemplates { .. } => "templates".into(),
+                RefreshAction::ReplaceDestination { .. } => "replace".into(),
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            actions,
+            [
+                "checkout",
+                "install downgraded",
+                "install unchanged",
+                "remove updated 1.0",
+                "remove removed 1.0",
+                "copy LICENSE",
+                "replace",
+            ]
+        );
+    }
+}
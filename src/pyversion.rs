@@ -0,0 +1,232 @@
+use regex::Regex;
+use rustpython_parser::{
+    ast::{self, ExceptHandler, Ranged, Stmt},
+    parse, Mode,
+};
+use serde::Serialize;
+use std::path::Path;
+
+#[cfg(feature = "refresh")]
+use crate::Error;
+use crate::{apworld, junk, Result, WorldVersion};
+
+// Modules and names added to the standard library, with the python version
+// that introduced them. An empty name stands for the whole module.
+const STDLIB_ADDITIONS: &[(&str, &str, &str)] = &[
+    ("zoneinfo", "", "3.9"),
+    ("graphlib", "", "3.9"),
+    ("functools", "cache", "3.9"),
+    ("itertools", "pairwise", "3.10"),
+    ("typing", "TypeAlias", "3.10"),
+    ("typing", "ParamSpec", "3.10"),
+    ("typing", "Concatenate", "3.10"),
+    ("typing", "TypeGuard", "3.10"),
+    ("tomllib", "", "3.11"),
+    ("typing", "Self", "3.11"),
+    ("typing", "LiteralString", "3.11"),
+    ("typing", "Never", "3.11"),
+    ("typing", "assert_never", "3.11"),
+    ("typing", "reveal_type", "3.11"),
+    ("typing", "TypeVarTuple", "3.11"),
+    ("typing", "Unpack", "3.11"),
+    ("typing", "Required", "3.11"),
+    ("typing", "NotRequired", "3.11"),
+    ("typing", "dataclass_transform", "3.11"),
+    ("enum", "StrEnum", "3.11"),
+    ("enum", "ReprEnum", "3.11"),
+    ("enum", "verify", "3.11"),
+    ("datetime", "UTC", "3.11"),
+    ("itertools", "batched", "3.12"),
+    ("typing", "override", "3.12"),
+    ("typing", "TypeAliasType", "3.12"),
+];
+
+// Features spotted in the source text rather than in the syntax tree
+const SOURCE_PATTERNS: &[(&str, &str, &str)] = &[
+    (
+        r"\.removeprefix\(|\.removesuffix\(",
+        "str.removeprefix",
+        "3.9",
+    ),
+    (
+        r"@(?:dataclasses\.)?dataclass\([^)]*\b(?:slots|kw_only)\s*=",
+        "dataclass slots/kw_only",
+        "3.10",
+    ),
+];
+
+// The newest python feature used by a world
+#[derive(Serialize, Debug, Clone)]
+pub struct PythonRequirement {
+    pub version: String,
+    pub feature: String,
+    pub file: String,
+    pub line: usize,
+}
+
+fn line_of(source: &str, offset: usize) -> usize {
+    source[..offset.min(source.len())].matches('\n').count() + 1
+}
+
+struct Finder<'a> {
+    file: &'a str,
+    source: &'a str,
+    found: Option<PythonRequirement>,
+}
+
+impl Finder<'_> {
+    fn require(&mut self, version: &str, feature: String, offset: usize) {
+        let is_newer = self.found.as_ref().is_none_or(|found| {
+            WorldVersion::from(version) > WorldVersion::from(found.version.as_str())
+        });
+        if is_newer {
+            self.found = Some(PythonRequirement {
+                version: version.to_string(),
+                feature,
+                file: self.file.to_string(),
+                line: line_of(self.source, offset),
+            });
+        }
+    }
+
+    fn import(&mut self, module: &str, name: &str, offset: usize) {
+        let additions = STDLIB_ADDITIONS
+            .iter()
+            .filter(|(added_module, added_name, _)| {
+                *added_module == module && (added_name.is_empty() || *added_name == name)
+            });
+        for (module, name, version) in additions {
+            let feature = if name.is_empty() {
+                module.to_string()
+            } else {
+                format!("{}.{}", module, name)
+            };
+            self.require(version, feature, offset);
+        }
+    }
+
+    fn visit_all(&mut self, body: &[Stmt]) {
+        for stmt in body {
+            self.visit(stmt);
+        }
+    }
+
+    fn visit(&mut self, stmt: &Stmt) {
+        let offset = u32::from(stmt.range().start()) as usize;
+        match stmt {
+            Stmt::Match(stmt) => {
+                self.require("3.10", "match statement".into(), offset);
+                for case in &stmt.cases {
+                    self.visit_all(&case.body);
+                }
+            }
+            Stmt::TryStar(stmt) => {
+                self.require("3.11", "except*".into(), offset);
+                self.visit_all(&stmt.body);
+                self.visit_all(&stmt.orelse);
+                self.visit_all(&stmt.finalbody);
+            }
+            Stmt::TypeAlias(_) => self.require("3.12", "type statement".into(), offset),
+            Stmt::FunctionDef(ast::StmtFunctionDef {
+                body, type_params, ..
+            })
+            | Stmt::AsyncFunctionDef(ast::StmtAsyncFunctionDef {
+                body, type_params, ..
+            })
+            | Stmt::ClassDef(ast::StmtClassDef {
+                body, type_params, ..
+            }) => {
+                if !type_params.is_empty() {
+                    self.require("3.12", "type parameters".into(), offset);
+                }
+                self.visit_all(body);
+            }
+            Stmt::Import(stmt) => {
+                for alias in &stmt.names {
+                    let (module, name) = alias.name.rsplit_once('.').unwrap_or((&alias.name, ""));
+                    self.import(module, name, offset);
+                    self.import(&alias.name, "", offset);
+                }
+            }
+            Stmt::ImportFrom(stmt) => {
+                let Some(module) = &stmt.module else {
+                    return;
+                };
+                self.import(module, "", offset);
+                for alias in &stmt.names {
+                    self.import(module, &alias.name, offset);
+                }
+            }
+            Stmt::For(ast::StmtFor { body, orelse, .. })
+            | Stmt::AsyncFor(ast::StmtAsyncFor { body, orelse, .. })
+            | Stmt::While(ast::StmtWhile { body, orelse, .. })
+            | Stmt::If(ast::StmtIf { body, orelse, .. }) => {
+                self.visit_all(body);
+                self.visit_all(orelse);
+            }
+            Stmt::With(ast::StmtWith { body, .. })
+            | Stmt::AsyncWith(ast::StmtAsyncWith { body, .. }) => self.visit_all(body),
+            Stmt::Try(stmt) => {
+                self.visit_all(&stmt.body);
+                for ExceptHandler::ExceptHandler(handler) in &stmt.handlers {
+                    self.visit_all(&handler.body);
+                }
+                self.visit_all(&stmt.orelse);
+                self.visit_all(&stmt.finalbody);
+            }
+            _ => {}
+        }
+    }
+}
+
+// Infers the minimum python version a world needs from the syntax and
+// standard library features it uses. Returns `None` when nothing newer than
+// python 3.8 was found.
+pub fn minimum_python(path: &Path) -> Result<Option<PythonRequirement>> {
+    let sources =
+        apworld::package_files(path, |name| name.ends_with(".py") && !junk::is_junk(name))?;
+    let patterns = SOURCE_PATTERNS
+        .iter()
+        .map(|(pattern, feature, version)| Ok((Regex::new(pattern)?, *feature, *version)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut found: Option<PythonRequirement> = None;
+    for (file, content) in &sources {
+        let source = String::from_utf8_lossy(content);
+        let mut finder = Finder {
+            file,
+            source: &source,
+            found: found.take(),
+        };
+
+        if let Ok(ast::Mod::Module(module)) = parse(&source, Mode::Module, file) {
+            finder.visit_all(&module.body);
+        }
+        for (pattern, feature, version) in &patterns {
+            if let Some(m) = pattern.find(&source) {
+                finder.require(version, feature.to_string(), m.start());
+            }
+        }
+
+        found = finder.found;
+    }
+
+    Ok(found)
+}
+
+// The oldest python version an archipelago checkout accepts, as enforced by
+// its `ModuleUpdate.py`
+#[cfg(feature = "refresh")]
+pub fn archipelago_minimum_python(ap_dir: &Path) -> Result<Option<String>> {
+    let module_update = ap_dir.join("ModuleUpdate.py");
+    if !module_update.exists() {
+        return Ok(None);
+    }
+
+    let source =
+        std::fs::read_to_string(&module_update).map_err(|e| Error::io(module_update, e))?;
+    let re = Regex::new(r"sys\.version_info\s*<\s*\(\s*(\d+)\s*,\s*(\d+)")?;
+    Ok(re
+        .captures(&source)
+        .map(|captures| format!("{}.{}", &captures[1], &captures[2])))
+}
@@ -0,0 +1,297 @@
+use http::Uri;
+#[cfg(feature = "http")]
+use reqwest::Client;
+#[cfg(feature = "refresh")]
+use std::borrow::Cow;
+use std::{collections::BTreeMap, future::Future, path::Path, pin::Pin, sync::Arc};
+#[cfg(feature = "refresh")]
+use tracing::Instrument;
+
+#[cfg(feature = "refresh")]
+use crate::bail;
+#[cfg(any(feature = "http", feature = "blocking"))]
+use crate::Error;
+use crate::Result;
+
+pub type ResolveFuture<'a> = Pin<Box<dyn Future<Output = Result<()>> + Send + 'a>>;
+
+// Fetches the worlds whose url has a scheme registered for it with
+// `Index::register_resolver`, like an internal artifact store or a server
+// needing its own authentication. `fetch` writes the apworld `uri` points to
+// at `destination`, it then goes through validation, patches and transforms
+// like any downloaded world.
+pub trait OriginResolver: Send + Sync {
+    fn fetch<'a>(&'a self, uri: &'a Uri, destination: &'a Path) -> ResolveFuture<'a>;
+}
+
+// How far a download went, reported after every chunk received
+#[derive(Debug, Clone)]
+pub struct DownloadProgress<'a> {
+    pub url: &'a str,
+    pub received: u64,
+    // From the `Content-Length` of the response, when the server sent one
+    pub total: Option<u64>,
+}
+
+pub(crate) type ProgressCallback = Arc<dyn Fn(&DownloadProgress) + Send + Sync>;
+
+// Returns the url to use instead of the one given, if any
+pub(crate) type UrlRewrite = Arc<dyn Fn(&str) -> Option<String> + Send + Sync>;
+
+// Downloads urls, through the resolver registered for their scheme if there's
+// one. The client is also used for the github API.
+#[derive(Clone, Default)]
+pub(crate) struct Downloader {
+    resolvers: BTreeMap<String, Arc<dyn OriginResolver>>,
+    #[cfg(feature = "http")]
+    client: Client,
+    #[cfg(feature = "http")]
+    github_token: Option<String>,
+    // Set by the `_blocking` methods, downloads then happen on the calling
+    // thread without needing an async runtime
+    #[cfg(feature = "blocking")]
+    blocking_client: Option<reqwest::blocking::Client>,
+    progress: Option<ProgressCallback>,
+    rewrite: Option<UrlRewrite>,
+}
+
+impl std::fmt::Debug for Downloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut debug = f.debug_struct("Downloader");
+        debug.field("resolvers", &self.resolvers.keys().collect::<Vec<_>>());
+        #[cfg(feature = "http")]
+        debug
+            .field("client", &self.client)
+            .field("github_token", &self.github_token.is_some());
+        #[cfg(feature = "blocking")]
+        debug.field("blocking_client", &self.blocking_client);
+        debug
+            .field("progress", &self.progress.is_some())
+            .field("rewrite", &self.rewrite.is_some())
+            .finish()
+    }
+}
+
+impl Downloader {
+    pub(crate) fn register(&mut self, scheme: &str, resolver: Arc<dyn OriginResolver>) {
+        self.resolvers.insert(scheme.to_ascii_lowercase(), resolver);
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn set_client(&mut self, client: Client) {
+        self.client = client;
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn set_github_token(&mut self, token: String) {
+        self.github_token = Some(token);
+    }
+
+    // The token sent to the github API, `GITHUB_TOKEN` unless one was set
+    #[cfg(feature = "http")]
+    pub(crate) fn github_token(&self) -> Option<String> {
+        self.github_token
+            .clone()
+            .or_else(|| std::env::var("GITHUB_TOKEN").ok())
+            .filter(|token| !token.is_empty())
+    }
+
+    #[cfg(feature = "blocking")]
+    pub(crate) fn set_blocking_client(&mut self, client: reqwest::blocking::Client) {
+        self.blocking_client = Some(client);
+    }
+
+    pub(crate) fn set_progress(&mut self, progress: ProgressCallback) {
+        self.progress = Some(progress);
+    }
+
+    pub(crate) fn set_rewrite(&mut self, rewrite: UrlRewrite) {
+        self.rewrite = Some(rewrite);
+    }
+
+    // Where `url` actually gets fetched from
+    #[cfg(feature = "refresh")]
+    pub(crate) fn rewrite<'a>(&self, url: &'a str) -> Cow<'a, str> {
+        match self.rewrite.as_ref().and_then(|rewrite| rewrite(url)) {
+            Some(rewritten) => {
+                tracing::debug!("Fetching {} from {}", url, rewritten);
+                Cow::Owned(rewritten)
+            }
+            None => Cow::Borrowed(url),
+        }
+    }
+
+    #[cfg(feature = "http")]
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    #[cfg(feature = "refresh")]
+    fn resolver(&self, uri: &Uri) -> Option<&dyn OriginResolver> {
+        let scheme = uri.scheme_str()?.to_ascii_lowercase();
+        self.resolvers
+            .get(&scheme)
+            .map(|resolver| resolver.as_ref())
+    }
+
+    // Whether `uri` is fetched by a resolver rather than over HTTP
+    #[cfg(feature = "http")]
+    pub(crate) fn resolves(&self, uri: &Uri) -> bool {
+        self.resolver(uri).is_some()
+    }
+
+    #[cfg(feature = "refresh")]
+    pub(crate) async fn fetch(&self, url: &str, destination: &Path) -> Result<()> {
+        let url = self.rewrite(url);
+        let url = url.as_ref();
+        let span = tracing::info_span!("download", url, bytes = tracing::field::Empty);
+        self.download(url, destination).instrument(span).await
+    }
+
+    #[cfg(feature = "refresh")]
+    async fn download(&self, url: &str, destination: &Path) -> Result<()> {
+        let uri = url.parse::<Uri>()?;
+        if let Some(resolver) = self.resolver(&uri) {
+            return resolver.fetch(&uri, destination).await;
+        }
+
+        #[cfg(feature = "blocking")]
+        if let Some(client) = &self.blocking_client {
+            return self.download_blocking(client, url, destination);
+        }
+        self.download_http(url, destination).await
+    }
+
+    #[cfg(any(feature = "http", feature = "blocking"))]
+    fn report_progress(&self, url: &str, received: u64, total: Option<u64>) {
+        if let Some(progress) = &self.progress {
+            progress(&DownloadProgress {
+                url,
+                received,
+                total,
+            });
+        }
+    }
+
+    #[cfg(feature = "http")]
+    async fn download_http(&self, url: &str, destination: &Path) -> Result<()> {
+        use std::io::Write;
+
+        let mut response = self.client.get(url).send().await?;
+        if !response.status().is_success() {
+            bail!("The server answered {}", response.status());
+        }
+
+        let total = response.content_length();
+        let mut file = std::fs::File::create(destination).map_err(|e| Error::io(destination, e))?;
+        let mut received = 0;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk)
+                .map_err(|e| Error::io(destination, e))?;
+            received += chunk.len() as u64;
+            self.report_progress(url, received, total);
+        }
+        tracing::Span::current().record("bytes", received);
+
+        Ok(())
+    }
+
+    #[cfg(all(not(feature = "http"), feature = "refresh"))]
+    async fn download_http(&self, url: &str, _destination: &Path) -> Result<()> {
+        bail!(
+            "Can't download {}, apwm was built without the http feature",
+            url
+        )
+    }
+
+    #[cfg(feature = "blocking")]
+    fn download_blocking(
+        &self,
+        client: &reqwest::blocking::Client,
+        url: &str,
+        destination: &Path,
+    ) -> Result<()> {
+        use std::io::{Read, Write};
+
+        let mut response = client.get(url).send()?;
+        if !response.status().is_success() {
+            bail!("The server answered {}", response.status());
+        }
+
+        let total = response.content_length();
+        let mut file = std::fs::File::create(destination).map_err(|e| Error::io(destination, e))?;
+        let mut buffer = vec![0; 64 * 1024];
+        let mut received = 0;
+        loop {
+            let read = response.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read])
+                .map_err(|e| Error::io(destination, e))?;
+            received += read as u64;
+            self.report_progress(url, received, total);
+        }
+        tracing::Span::current().record("bytes", received);
+
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "refresh"))]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // Writes the uri it was asked for as the apworld
+    #[derive(Default)]
+    struct Store {
+        fetched: Mutex<Vec<String>>,
+    }
+
+    impl OriginResolver for Store {
+        fn fetch<'a>(&'a self, uri: &'a Uri, destination: &'a Path) -> ResolveFuture<'a> {
+            Box::pin(async move {
+                self.fetched.lock().unwrap().push(uri.to_string());
+                std::fs::write(destination, uri.to_string())
+                    .map_err(|e| crate::Error::io(destination, e))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn fetches_registered_schemes_through_their_resolver() {
+        let tmp = tempfile::tempdir().unwrap();
+        let destination = tmp.path().join("ok.apworld");
+        let store = Arc::new(Store::default());
+        let mut downloader = Downloader::default();
+        downloader.register("Store", store.clone());
+        downloader.set_rewrite(Arc::new(|url: &str| {
+            url.strip_prefix("https://mirror.example.com/")
+                .map(|path| format!("store://artifacts/{}", path))
+        }));
+
+        downloader
+            .fetch("STORE://artifacts/ok.apworld", &destination)
+            .await
+            .unwrap();
+        downloader
+            .fetch("https://mirror.example.com/other.apworld", &destination)
+            .await
+            .unwrap();
+        assert_eq!(
+            *store.fetched.lock().unwrap(),
+            [
+                "STORE://artifacts/ok.apworld",
+                "store://artifacts/other.apworld"
+            ]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&destination).unwrap(),
+            "store://artifacts/other.apworld"
+        );
+        assert!(downloader
+            .resolver(&"https://example.com".parse().unwrap())
+            .is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use anyhow::anyhow;
+
+use crate::{CancellationToken, Error, Result};
+use git2::{
+    build::RepoBuilder, AutotagOption, Commit, FetchOptions, ObjectType, Oid, RemoteCallbacks,
+    Repository, Tree,
+};
+use serde::Serialize;
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+const SYMLINK_MODE: i32 = 0o120000;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RevisionInfo {
+    pub id: String,
+    pub summary: String,
+    pub author: String,
+    // Commit time, in seconds since the unix epoch
+    pub time: u64,
+}
+
+impl RevisionInfo {
+    fn new(commit: &Commit) -> Self {
+        Self {
+            id: commit.id().to_string(),
+            summary: commit.summary().unwrap_or_default().to_string(),
+            author: commit.author().name().unwrap_or_default().to_string(),
+            time: commit.time().seconds().max(0) as u64,
+        }
+    }
+}
+
+// The git repository an index file lives in, the directory of the index in
+// it and the name of the index file
+fn open_index_repository(index_file: &Path) -> Result<(Repository, PathBuf, OsString)> {
+    let index_file = std::fs::canonicalize(index_file).map_err(|e| Error::io(index_file, e))?;
+    let index_dir = index_file
+        .parent()
+        .ok_or_else(|| anyhow!("Index file doesn't have a parent dir"))?;
+    let file_name = index_file
+        .file_name()
+        .ok_or_else(|| anyhow!("{} isn't a file", index_file.to_string_lossy()))?;
+
+    let repo = Repository::discover(index_dir)?;
+    let workdir = repo
+        .workdir()
+        .ok_or_else(|| anyhow!("The index isn't in a git checkout"))?;
+    let prefix = index_dir
+        .strip_prefix(std::fs::canonicalize(workdir).map_err(|e| Error::io(workdir, e))?)?
+        .to_path_buf();
+
+    Ok((repo, prefix, file_name.to_os_string()))
+}
+
+// The id of the index directory in `tree`, if it exists there
+fn index_tree_id(tree: &Tree, prefix: &Path) -> Option<Oid> {
+    if prefix.as_os_str().is_empty() {
+        return Some(tree.id());
+    }
+
+    Some(tree.get_path(prefix).ok()?.id())
+}
+
+fn export_tree(repo: &Repository, tree: &Tree, destination: &Path) -> Result<()> {
+    std::fs::create_dir_all(destination).map_err(|e| Error::io(destination, e))?;
+    for entry in tree.iter() {
+        let Some(name) = entry.name() else {
+            continue;
+        };
+        match entry.kind() {
+            Some(ObjectType::Tree) => {
+                let subtree = entry.to_object(repo)?.peel_to_tree()?;
+                export_tree(repo, &subtree, &destination.join(name))?;
+            }
+            // Symlinks could point anywhere, they're never needed by an index
+            Some(ObjectType::Blob) if entry.filemode() != SYMLINK_MODE => {
+                let blob = entry.to_object(repo)?.peel_to_blob()?;
+                std::fs::write(destination.join(name), blob.content())
+                    .map_err(|e| Error::io(destination.join(name), e))?;
+            }
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+// Writes the index as it was at `revision` of the git repository it lives in
+// to `destination`, along with everything next to it like patches and local
+// worlds. Returns the path of the exported index file.
+pub fn export_revision(index_file: &Path, revision: &str, destination: &Path) -> Result<PathBuf> {
+    let (repo, prefix, file_name) = open_index_repository(index_file)?;
+
+    let mut tree = repo.revparse_single(revision)?.peel_to_tree()?;
+    if !prefix.as_os_str().is_empty() {
+        tree = tree.get_path(&prefix)?.to_object(&repo)?.peel_to_tree()?;
+    }
+    export_tree(&repo, &tree, destination)?;
+
+    Ok(destination.join(file_name))
+}
+
+pub fn describe_revision(index_file: &Path, revision: &str) -> Result<RevisionInfo> {
+    let (repo, _, _) = open_index_repository(index_file)?;
+    let commit = repo.revparse_single(revision)?.peel_to_commit()?;
+    Ok(RevisionInfo::new(&commit))
+}
+
+// The commits reachable from `to` but not from `from` that changed the index
+// directory, oldest first
+pub fn commits_between(index_file: &Path, from: &str, to: &str) -> Result<Vec<RevisionInfo>> {
+    let (repo, prefix, _) = open_index_repository(index_file)?;
+    let mut revwalk = repo.revwalk()?;
+    revwalk.set_sorting(git2::Sort::TOPOLOGICAL | git2::Sort::REVERSE)?;
+    revwalk.push(repo.revparse_single(to)?.peel_to_commit()?.id())?;
+    revwalk.hide(repo.revparse_single(from)?.peel_to_commit()?.id())?;
+
+    let mut commits = vec![];
+    for id in revwalk {
+        let commit = repo.find_commit(id?)?;
+        let current = index_tree_id(&commit.tree()?, &prefix);
+        let previous = match commit.parents().next() {
+            Some(parent) => index_tree_id(&parent.tree()?, &prefix),
+            None => None,
+        };
+        if current != previous {
+            commits.push(RevisionInfo::new(&commit));
+        }
+    }
+
+    Ok(commits)
+}
+
+// Clones `repo` into `destination` and checks `version` out
+pub(crate) fn checkout(
+    repo: &str,
+    version: &str,
+    destination: &Path,
+    cancel: &CancellationToken,
+) -> Result<()> {
+    // Cloning blocks, returning false from a callback is the only way to stop
+    // it early
+    let mut callbacks = RemoteCallbacks::new();
+    callbacks.transfer_progress(|_| !cancel.is_cancelled());
+    let mut fetch_opts = FetchOptions::new();
+    fetch_opts.download_tags(AutotagOption::All);
+    fetch_opts.remote_callbacks(callbacks);
+
+    let repo = RepoBuilder::new()
+        .fetch_options(fetch_opts)
+        .clone(repo, destination)
+        .map_err(|e| match cancel.is_cancelled() {
+            true => Error::Cancelled,
+            false => e.into(),
+        })?;
+    let git_ref = repo.resolve_reference_from_short_name(version)?;
+    let tag = git_ref.peel_to_commit()?;
+
+    repo.checkout_tree(tag.as_object(), None)?;
+
+    Ok(())
+}
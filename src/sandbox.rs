@@ -0,0 +1,75 @@
+#[cfg(feature = "refresh")]
+use crate::{bail, Error, Result};
+use std::path::{Component, Path};
+#[cfg(feature = "refresh")]
+use std::process::Command;
+
+// Whether `path` is relative and points below the directory it's joined to.
+// "" and "." are the directory itself, which isn't below it.
+pub fn is_contained(path: &Path) -> bool {
+    path.components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+        && path
+            .components()
+            .any(|component| matches!(component, Component::Normal(_)))
+}
+
+#[cfg(feature = "refresh")]
+pub fn ensure_no_symlinks(dir: &Path) -> Result<()> {
+    for entry in std::fs::read_dir(dir).map_err(|e| Error::io(dir, e))? {
+        let entry = entry.map_err(|e| Error::io(dir, e))?;
+        let ty = entry.file_type().map_err(|e| Error::io(entry.path(), e))?;
+        if ty.is_symlink() {
+            bail!(
+                "Refusing to process symlink {}",
+                entry.path().to_string_lossy()
+            );
+        }
+        if ty.is_dir() {
+            ensure_no_symlinks(&entry.path())?;
+        }
+    }
+
+    Ok(())
+}
+
+// Restricts `command` to reading the filesystem and writing below `writable`
+#[cfg(all(feature = "sandbox", target_os = "linux"))]
+pub fn restrict(command: &mut Command, writable: &[&Path]) -> Result<()> {
+    use landlock::{
+        path_beneath_rules, Access, AccessFs, Ruleset, RulesetAttr, RulesetCreatedAttr, ABI,
+    };
+    use std::os::unix::process::CommandExt;
+
+    let abi = ABI::V1;
+    let ruleset = Ruleset::default()
+        .handle_access(AccessFs::from_all(abi))
+        .and_then(|ruleset| ruleset.create())
+        .and_then(|ruleset| ruleset.add_rules(path_beneath_rules(["/"], AccessFs::from_read(abi))))
+        .and_then(|ruleset| {
+            ruleset.add_rules(path_beneath_rules(writable, AccessFs::from_all(abi)))
+        })
+        .map_err(anyhow::Error::from)?;
+
+    let mut ruleset = Some(ruleset);
+    // SAFETY: restricting ourselves only issues syscalls on an already
+    // created ruleset, it doesn't allocate or take locks.
+    unsafe {
+        command.pre_exec(move || {
+            if let Some(ruleset) = ruleset.take() {
+                ruleset.restrict_self().map_err(std::io::Error::other)?;
+            }
+            Ok(())
+        });
+    }
+
+    Ok(())
+}
+
+#[cfg(all(
+    not(all(feature = "sandbox", target_os = "linux")),
+    feature = "refresh"
+))]
+pub fn restrict(_command: &mut Command, _writable: &[&Path]) -> Result<()> {
+    Ok(())
+}
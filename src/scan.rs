@@ -0,0 +1,103 @@
+use regex::Regex;
+use serde::Serialize;
+use std::{fmt, path::Path};
+
+use crate::{apworld, junk, Result};
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum FindingKind {
+    Network,
+    Subprocess,
+    DynamicCode,
+    FileWrite,
+    Obfuscation,
+}
+
+impl fmt::Display for FindingKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FindingKind::Network => "network",
+            FindingKind::Subprocess => "subprocess",
+            FindingKind::DynamicCode => "dynamic code",
+            FindingKind::FileWrite => "file write",
+            FindingKind::Obfuscation => "obfuscation",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Finding {
+    pub file: String,
+    pub line: usize,
+    pub kind: FindingKind,
+    pub snippet: String,
+}
+
+const RULES: &[(FindingKind, &str)] = &[
+    (
+        FindingKind::Network,
+        r"^\s*(import|from)\s+(requests|urllib|urllib3|http\.client|socket|aiohttp|httpx|websockets)\b",
+    ),
+    (FindingKind::Network, r"\burlopen\s*\("),
+    (FindingKind::Subprocess, r"^\s*(import|from)\s+subprocess\b"),
+    (
+        FindingKind::Subprocess,
+        r"\bos\.(system|popen|exec\w*|spawn\w*|startfile)\s*\(",
+    ),
+    (FindingKind::DynamicCode, r"(^|[^\w.])(exec|eval)\s*\("),
+    (FindingKind::DynamicCode, r"\b__import__\s*\("),
+    (FindingKind::DynamicCode, r"\b(marshal|pickle)\.loads?\s*\("),
+    (
+        FindingKind::FileWrite,
+        r#"\bopen\s*\([^)]*,\s*(mode\s*=\s*)?["'][^"']*[wax+][^"']*["']"#,
+    ),
+    (
+        FindingKind::FileWrite,
+        r"\b(shutil\.(rmtree|move|copy\w*)|os\.(remove|unlink|rename|rmdir|makedirs))\s*\(",
+    ),
+    (
+        FindingKind::Obfuscation,
+        r"\b(b64decode|b85decode|a85decode)\s*\(",
+    ),
+    (
+        FindingKind::Obfuscation,
+        r"\bcodecs\.decode\s*\([^)]*rot.?13",
+    ),
+];
+
+// Looks for risky patterns in the python sources of a world. This is only
+// meant to triage worlds for reviewers, there will be false positives.
+pub fn scan(path: &Path) -> Result<Vec<Finding>> {
+    let rules = RULES
+        .iter()
+        .map(|(kind, rule)| (*kind, Regex::new(rule).unwrap()))
+        .collect::<Vec<_>>();
+    let sources =
+        apworld::package_files(path, |name| name.ends_with(".py") && !junk::is_junk(name))?;
+
+    let mut findings = vec![];
+    for (file, content) in sources {
+        let source = String::from_utf8_lossy(&content);
+        for (i, line) in source.lines().enumerate() {
+            if line.trim_start().starts_with('#') {
+                continue;
+            }
+
+            for (kind, rule) in &rules {
+                if rule.is_match(line) {
+                    findings.push(Finding {
+                        file: file.clone(),
+                        line: i + 1,
+                        kind: *kind,
+                        snippet: line.trim().to_string(),
+                    });
+                    break;
+                }
+            }
+        }
+    }
+
+    Ok(findings)
+}
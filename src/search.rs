@@ -0,0 +1,49 @@
+use serde::Serialize;
+use std::path::PathBuf;
+
+use crate::World;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SearchHit {
+    // The directory of the index the world was found in
+    pub index: PathBuf,
+    pub key: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub tags: Vec<String>,
+    pub score: u32,
+}
+
+// Scores how well a world matches every term of a query, matches on the key
+// and name counting more than ones on tags, and those more than ones on the
+// description. Returns `None` if a term doesn't match anywhere.
+pub fn score(key: &str, world: &World, terms: &[String]) -> Option<u32> {
+    let fields = [
+        (key.to_lowercase(), 4),
+        (world.name.to_lowercase(), 4),
+        (world.tags.join(" ").to_lowercase(), 2),
+        (
+            world.description.clone().unwrap_or_default().to_lowercase(),
+            1,
+        ),
+    ];
+
+    let mut score = 0;
+    for term in terms {
+        let term_score = fields
+            .iter()
+            .filter(|(field, _)| field.contains(term.as_str()))
+            .map(|(_, weight)| weight)
+            .sum::<u32>();
+        if term_score == 0 {
+            return None;
+        }
+        score += term_score;
+    }
+
+    Some(score)
+}
+
+pub fn terms(query: &str) -> Vec<String> {
+    query.split_whitespace().map(str::to_lowercase).collect()
+}
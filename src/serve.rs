@@ -0,0 +1,503 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{bail, sandbox, Error, Index, Manifest, Result, WorldFilter, WorldListing};
+
+// Connections sending a bigger request head get dropped
+const MAX_HEAD_SIZE: usize = 16 * 1024;
+
+#[derive(Serialize)]
+struct ServedIndex {
+    archipelago_version: String,
+    worlds: Vec<WorldListing>,
+}
+
+// Serves an index, and the destination and mirror it was refreshed and
+// mirrored into, over HTTP:
+// - `/index.json`: the worlds of the index, read again on every request
+// - `/manifest.json`: the manifest of the destination
+// - `/worlds/<path>`: the files of the destination
+// - `/mirror/<path>`: the files of the mirror, `mirror.toml` included
+pub struct Server {
+    index_toml: PathBuf,
+    destination: Option<PathBuf>,
+    mirror: Option<PathBuf>,
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+enum Body {
+    Bytes(Vec<u8>),
+    File(PathBuf),
+}
+
+struct Resource {
+    content_type: &'static str,
+    etag: String,
+    len: u64,
+    body: Body,
+}
+
+impl Resource {
+    fn json(value: &impl Serialize) -> Result<Self> {
+        let content = serde_json::to_vec_pretty(value)?;
+        Ok(Self {
+            content_type: "application/json",
+            etag: format!("\"{:.16}\"", format!("{:x}", Sha256::digest(&content))),
+            len: content.len() as u64,
+            body: Body::Bytes(content),
+        })
+    }
+
+    // Only regular files below `root` get served, without following
+    // symlinks out of it
+    fn file(root: &Path, path: &str) -> Result<Option<Self>> {
+        let Some(path) = percent_decode(path) else {
+            return Ok(None);
+        };
+        if path.is_empty() || !sandbox::is_contained(Path::new(&path)) {
+            return Ok(None);
+        }
+        let Ok(file) = std::fs::canonicalize(root.join(&path)) else {
+            return Ok(None);
+        };
+        if !file.starts_with(std::fs::canonicalize(root).map_err(|e| Error::io(root, e))?)
+            || !file.is_file()
+        {
+            return Ok(None);
+        }
+
+        let metadata = std::fs::metadata(&file).map_err(|e| Error::io(&file, e))?;
+        let modified = metadata
+            .modified()
+            .map_err(|e| Error::io(&file, e))?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let content_type = match file.extension().and_then(|ext| ext.to_str()) {
+            Some("json") => "application/json",
+            Some("apworld" | "zip") => "application/zip",
+            Some("toml") => "application/toml",
+            _ => "application/octet-stream",
+        };
+
+        Ok(Some(Self {
+            content_type,
+            etag: format!("\"{:x}-{:x}\"", metadata.len(), modified),
+            len: metadata.len(),
+            body: Body::File(file),
+        }))
+    }
+}
+
+enum RangeRequest {
+    Full,
+    Partial(Range<u64>),
+    Unsatisfiable,
+}
+
+// Only single byte ranges are supported, others get the whole resource as
+// allowed by RFC 9110, same for invalid ranges
+fn parse_range(header: &str, len: u64) -> RangeRequest {
+    let Some(range) = header.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    let Some((start, end)) = range.split_once('-') else {
+        return RangeRequest::Full;
+    };
+    if range.contains(',') {
+        return RangeRequest::Full;
+    }
+
+    match (start.trim(), end.trim()) {
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => RangeRequest::Unsatisfiable,
+            Ok(suffix) if len > 0 => RangeRequest::Partial(len.saturating_sub(suffix)..len),
+            Ok(_) => RangeRequest::Unsatisfiable,
+            Err(_) => RangeRequest::Full,
+        },
+        (start, end) => {
+            let Ok(start) = start.parse::<u64>() else {
+                return RangeRequest::Full;
+            };
+            let end = match end {
+                "" => len,
+                end => match end.parse::<u64>() {
+                    Ok(end) if end >= start => (end + 1).min(len),
+                    _ => return RangeRequest::Full,
+                },
+            };
+            if start >= len {
+                return RangeRequest::Unsatisfiable;
+            }
+            RangeRequest::Partial(start..end)
+        }
+    }
+}
+
+fn etag_matches(header: &str, etag: &str) -> bool {
+    header
+        .split(',')
+        .map(|tag| tag.trim().trim_start_matches("W/"))
+        .any(|tag| tag == "*" || tag == etag)
+}
+
+fn percent_decode(path: &str) -> Option<String> {
+    let mut bytes = vec![];
+    let mut input = path.bytes();
+    while let Some(byte) = input.next() {
+        if byte != b'%' {
+            bytes.push(byte);
+            continue;
+        }
+        let hex = [input.next()?, input.next()?];
+        bytes.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+    }
+
+    String::from_utf8(bytes).ok()
+}
+
+async fn read_request(stream: &mut TcpStream) -> Result<Request> {
+    let mut head = vec![];
+    let mut chunk = [0; 4096];
+    while !head.windows(4).any(|window| window == b"\r\n\r\n") {
+        if head.len() > MAX_HEAD_SIZE {
+            bail!("Request head too big");
+        }
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            bail!("Connection closed before the end of the request");
+        }
+        head.extend_from_slice(&chunk[..read]);
+    }
+
+    let head = String::from_utf8_lossy(&head);
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let (Some(method), Some(target)) = (request_line.next(), request_line.next()) else {
+        bail!("Invalid request line");
+    };
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_string(), value.trim().to_string()))
+        .collect();
+
+    Ok(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        headers,
+    })
+}
+
+async fn write_head(
+    stream: &mut TcpStream,
+    status: &str,
+    headers: &[(&str, String)],
+) -> Result<()> {
+    let mut head = format!("HTTP/1.1 {}\r\nConnection: close\r\n", status);
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes()).await?;
+    Ok(())
+}
+
+async fn write_body(stream: &mut TcpStream, body: &Body, range: Range<u64>) -> Result<()> {
+    match body {
+        Body::Bytes(content) => {
+            stream
+                .write_all(&content[range.start as usize..range.end as usize])
+                .await?
+        }
+        Body::File(path) => {
+            let mut file = tokio::fs::File::open(path).await?;
+            file.seek(SeekFrom::Start(range.start)).await?;
+            tokio::io::copy(&mut file.take(range.end - range.start), stream).await?;
+        }
+    }
+
+    Ok(())
+}
+
+impl Server {
+    pub fn new(
+        index_toml: &Path,
+        destination: Option<&Path>,
+        mirror: Option<&Path>,
+    ) -> Result<Self> {
+        // Fail early on a broken index rather than on every request
+        Index::new(index_toml)?;
+
+        Ok(Self {
+            index_toml: index_toml.into(),
+            destination: destination.map(Into::into),
+            mirror: mirror.map(Into::into),
+        })
+    }
+
+    fn resource(&self, path: &str) -> Result<Option<Resource>> {
+        if path == "/index.json" {
+            let index = Index::new(&self.index_toml)?;
+            return Resource::json(&ServedIndex {
+                archipelago_version: index.common.archipelago_version.clone(),
+                worlds: index.list(&WorldFilter::default()),
+            })
+            .map(Some);
+        }
+
+        if let Some(destination) = &self.destination {
+            if path == "/manifest.json" {
+                return Resource::json(&Manifest::load(destination)?).map(Some);
+            }
+            if let Some(path) = path.strip_prefix("/worlds/") {
+                return Resource::file(destination, path);
+            }
+        }
+        if let (Some(mirror), Some(path)) = (&self.mirror, path.strip_prefix("/mirror/")) {
+            return Resource::file(mirror, path);
+        }
+
+        Ok(None)
+    }
+
+    async fn handle(&self, stream: &mut TcpStream) -> Result<()> {
+        let request = read_request(stream).await?;
+        if request.method != "GET" && request.method != "HEAD" {
+            tracing::info!("{} {} 405", request.method, request.path);
+            let headers = [
+                ("Allow", "GET, HEAD".into()),
+                ("Content-Length", "0".into()),
+            ];
+            return write_head(stream, "405 Method Not Allowed", &headers).await;
+        }
+
+        let resource = match self.resource(&request.path) {
+            Ok(Some(resource)) => resource,
+            Ok(None) => {
+                tracing::info!("{} {} 404", request.method, request.path);
+                let headers = [("Content-Length", "0".into())];
+                return write_head(stream, "404 Not Found", &headers).await;
+            }
+            Err(e) => {
+                tracing::warn!("{} {} 500: {}", request.method, request.path, e);
+                let headers = [("Content-Length", "0".into())];
+                return write_head(stream, "500 Internal Server Error", &headers).await;
+            }
+        };
+
+        if request
+            .header("If-None-Match")
+            .is_some_and(|header| etag_matches(header, &resource.etag))
+        {
+            tracing::info!("{} {} 304", request.method, request.path);
+            return write_head(stream, "304 Not Modified", &[("ETag", resource.etag)]).await;
+        }
+
+        // A range is only for the version of the resource the client has
+        let range = match request.header("Range") {
+            Some(range)
+                if request
+                    .header("If-Range")
+                    .is_none_or(|if_range| if_range == resource.etag) =>
+            {
+                parse_range(range, resource.len)
+            }
+            _ => RangeRequest::Full,
+        };
+        let mut headers = vec![
+            ("Content-Type", resource.content_type.to_string()),
+            ("ETag", resource.etag.clone()),
+            ("Accept-Ranges", "bytes".into()),
+        ];
+        let (status, range) = match range {
+            RangeRequest::Full => ("200 OK", 0..resource.len),
+            RangeRequest::Partial(range) => {
+                headers.push((
+                    "Content-Range",
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, resource.len),
+                ));
+                ("206 Partial Content", range)
+            }
+            RangeRequest::Unsatisfiable => {
+                tracing::info!("{} {} 416", request.method, request.path);
+                headers.push(("Content-Range", format!("bytes */{}", resource.len)));
+                headers.push(("Content-Length", "0".into()));
+                return write_head(stream, "416 Range Not Satisfiable", &headers).await;
+            }
+        };
+        headers.push(("Content-Length", (range.end - range.start).to_string()));
+
+        tracing::info!("{} {} {}", request.method, request.path, status);
+        write_head(stream, status, &headers).await?;
+        if request.method == "GET" {
+            write_body(stream, &resource.body, range).await?;
+        }
+
+        Ok(())
+    }
+
+    // Serves requests until the listener fails, one request per connection
+    pub async fn run(self, listener: TcpListener) -> Result<()> {
+        let server = Arc::new(self);
+        loop {
+            let (mut stream, peer) = listener.accept().await?;
+            let server = server.clone();
+            tokio::spawn(async move {
+                if let Err(e) = server.handle(&mut stream).await {
+                    tracing::debug!("{}: {}", peer, e);
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_single_byte_ranges() {
+        let partial = |header| match parse_range(header, 10) {
+            RangeRequest::Partial(range) => Some(range),
+            _ => None,
+        };
+        assert_eq!(partial("bytes=2-5"), Some(2..6));
+        assert_eq!(partial("bytes=2-"), Some(2..10));
+        assert_eq!(partial("bytes=4-100"), Some(4..10));
+        assert_eq!(partial("bytes=-3"), Some(7..10));
+        assert_eq!(partial("bytes=-30"), Some(0..10));
+        for header in ["bytes=0-1,4-5", "bytes=5-2", "items=0-1", "bytes=a-"] {
+            assert!(matches!(parse_range(header, 10), RangeRequest::Full));
+        }
+        for header in ["bytes=10-", "bytes=-0"] {
+            assert!(matches!(
+                parse_range(header, 10),
+                RangeRequest::Unsatisfiable
+            ));
+        }
+    }
+
+    #[test]
+    fn matches_etags() {
+        assert!(etag_matches("\"a\", \"b\"", "\"b\""));
+        assert!(etag_matches("W/\"b\"", "\"b\""));
+        assert!(etag_matches("*", "\"b\""));
+        assert!(!etag_matches("\"a\"", "\"b\""));
+    }
+
+    // A server for a destination with `ok.apworld`, and the response to a
+    // request made to it
+    async fn get(path: &str, headers: &[(&str, &str)]) -> String {
+        let tmp = tempfile::tempdir().unwrap();
+        let index_toml = tmp.path().join("index.toml");
+        std::fs::write(
+            &index_toml,
+            "[common]\narchipelago_repo = \"https://github.com/ArchipelagoMW/Archipelago\"\n\
+             archipelago_version = \"0.5.0\"\nhomepage = \"https://example.com\"\n\
+             required_global_files = []\n\n[worlds]\n",
+        )
+        .unwrap();
+        let destination = tmp.path().join("worlds");
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::write(destination.join("ok.apworld"), "0123456789").unwrap();
+        std::fs::write(tmp.path().join("secret.txt"), "secret").unwrap();
+
+        let server = Server::new(&index_toml, Some(&destination), None).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(server.run(listener));
+
+        let mut stream = TcpStream::connect(address).await.unwrap();
+        let mut request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n", path);
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    fn etag(response: &str) -> &str {
+        response
+            .lines()
+            .find_map(|line| line.strip_prefix("ETag: "))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_files_by_range() {
+        let response = get("/worlds/ok.apworld", &[]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Content-Type: application/zip\r\n"));
+        assert!(response.ends_with("\r\n\r\n0123456789"));
+
+        let response = get("/worlds/ok.apworld", &[("Range", "bytes=2-5")]).await;
+        assert!(response.starts_with("HTTP/1.1 206 Partial Content\r\n"));
+        assert!(response.contains("Content-Range: bytes 2-5/10\r\n"));
+        assert!(response.ends_with("\r\n\r\n2345"));
+
+        let response = get("/worlds/ok.apworld", &[("Range", "bytes=10-")]).await;
+        assert!(response.starts_with("HTTP/1.1 416 Range Not Satisfiable\r\n"));
+        assert!(response.contains("Content-Range: bytes */10\r\n"));
+
+        // The range is for another version of the file
+        let headers = [("Range", "bytes=2-5"), ("If-Range", "\"stale\"")];
+        let response = get("/worlds/ok.apworld", &headers).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    }
+
+    #[tokio::test]
+    async fn answers_matching_etags_with_not_modified() {
+        let response = get("/index.json", &[]).await;
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        let etag = etag(&response).to_string();
+        let response = get("/index.json", &[("If-None-Match", &etag)]).await;
+        assert!(response.starts_with("HTTP/1.1 304 Not Modified\r\n"));
+        assert_eq!(self::etag(&response), etag);
+    }
+
+    #[tokio::test]
+    async fn only_serves_files_of_the_destination() {
+        for path in [
+            "/worlds/../secret.txt",
+            "/worlds/%2e%2e/secret.txt",
+            "/worlds/missing.apworld",
+            "/mirror/mirror.toml",
+        ] {
+            let response = get(path, &[]).await;
+            assert!(
+                response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+                "{}",
+                path
+            );
+        }
+    }
+}
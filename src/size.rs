@@ -0,0 +1,54 @@
+use serde::Serialize;
+use std::path::Path;
+
+use crate::{apworld, Error, Result};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FileSize {
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SizeReport {
+    pub installed_size: u64,
+    // Size of the `.apworld` file, worlds deployed as directories don't have one
+    pub compressed_size: Option<u64>,
+    pub largest_files: Vec<FileSize>,
+}
+
+pub fn size_report(path: &Path, top: usize) -> Result<SizeReport> {
+    let mut files = apworld::package_file_sizes(path)?
+        .into_iter()
+        .map(|(path, size)| FileSize { path, size })
+        .collect::<Vec<_>>();
+    files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+
+    let compressed_size = if path.is_file() {
+        Some(
+            std::fs::metadata(path)
+                .map_err(|e| Error::io(path, e))?
+                .len(),
+        )
+    } else {
+        None
+    };
+
+    Ok(SizeReport {
+        installed_size: files.iter().map(|file| file.size).sum(),
+        compressed_size,
+        largest_files: files.into_iter().take(top).collect(),
+    })
+}
+
+// The size a world takes on disk once deployed: the size of the `.apworld`
+// file, or the size of all the files of a world deployed as a directory
+pub fn deployed_size(path: &Path) -> Result<u64> {
+    if path.is_file() {
+        return Ok(std::fs::metadata(path)
+            .map_err(|e| Error::io(path, e))?
+            .len());
+    }
+
+    Ok(apworld::package_file_sizes(path)?.values().sum())
+}
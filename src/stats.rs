@@ -0,0 +1,115 @@
+use serde::Serialize;
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{size, Manifest, OriginKind, Result, WorldOrigin};
+
+#[derive(Serialize, Debug, Clone)]
+pub struct WorldSize {
+    pub world: String,
+    pub size: u64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SizeStats {
+    // Worlds that were found in the destination
+    pub deployed: usize,
+    pub total: u64,
+    pub average: u64,
+    pub largest: Vec<WorldSize>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct IndexStats {
+    pub worlds: usize,
+    pub by_origin: BTreeMap<OriginKind, usize>,
+    pub patched: usize,
+    pub supported: usize,
+    // Worlds that aren't part of archipelago, from a URL or the index itself
+    pub external: usize,
+    // Only known with a refreshed destination
+    pub sizes: Option<SizeStats>,
+}
+
+impl IndexStats {
+    // The share of supported worlds, between 0 and 1
+    pub fn supported_ratio(&self) -> f64 {
+        if self.worlds == 0 {
+            return 0.;
+        }
+
+        self.supported as f64 / self.worlds as f64
+    }
+}
+
+fn size_stats<'a>(
+    worlds: impl Iterator<Item = (&'a str, &'a WorldOrigin)>,
+    destination: &Path,
+    top: usize,
+) -> Result<SizeStats> {
+    let mut sizes = vec![];
+    for (name, origin) in worlds {
+        let path = origin.deployed_path(name, destination)?;
+        if !path.exists() {
+            continue;
+        }
+        sizes.push(WorldSize {
+            world: name.to_string(),
+            size: size::deployed_size(&path)?,
+        });
+    }
+    sizes.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.world.cmp(&b.world)));
+
+    let total = sizes.iter().map(|world| world.size).sum::<u64>();
+    Ok(SizeStats {
+        deployed: sizes.len(),
+        total,
+        average: total.checked_div(sizes.len() as u64).unwrap_or(0),
+        largest: sizes.into_iter().take(top).collect(),
+    })
+}
+
+// Summarizes worlds given as their key, origin and whether they're patched.
+// Sizes are read from `destination` when given, keeping the `top` largest
+// worlds.
+pub fn compute<'a>(
+    worlds: impl Iterator<Item = (&'a str, &'a WorldOrigin, bool)> + Clone,
+    destination: Option<&Path>,
+    top: usize,
+) -> Result<IndexStats> {
+    let mut by_origin = BTreeMap::new();
+    for (_, origin, _) in worlds.clone() {
+        *by_origin.entry(OriginKind::from(origin)).or_default() += 1;
+    }
+    let count = |kind| by_origin.get(&kind).copied().unwrap_or(0);
+
+    let sizes = match destination {
+        Some(destination) => Some(size_stats(
+            worlds.clone().map(|(name, origin, _)| (name, origin)),
+            destination,
+            top,
+        )?),
+        None => None,
+    };
+
+    Ok(IndexStats {
+        worlds: worlds.clone().count(),
+        patched: worlds.filter(|(_, _, patched)| *patched).count(),
+        supported: count(OriginKind::Supported),
+        external: count(OriginKind::Url) + count(OriginKind::Local),
+        by_origin,
+        sizes,
+    })
+}
+
+// Statistics of a refreshed destination, read from its manifest
+pub fn destination_stats(destination: &Path, top: usize) -> Result<IndexStats> {
+    let manifest = Manifest::load(destination)?;
+    compute(
+        manifest
+            .worlds
+            .iter()
+            .map(|(name, world)| (name.as_str(), &world.origin, !world.patches.is_empty())),
+        Some(destination),
+        top,
+    )
+}
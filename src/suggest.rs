@@ -0,0 +1,40 @@
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<_>>();
+    let mut previous = (0..=b.len()).collect::<Vec<_>>();
+
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(a_char != *b_char);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+// Candidates close enough to `name` to be what was meant, closest first
+pub fn near_misses<'a>(name: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<&'a str> {
+    let name = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+
+    let mut matches = candidates
+        .into_iter()
+        .filter_map(|candidate| {
+            let lowercase = candidate.to_lowercase();
+            let distance = if lowercase.contains(&name) || name.contains(&lowercase) {
+                0
+            } else {
+                edit_distance(&name, &lowercase)
+            };
+            (distance <= max_distance).then_some((distance, candidate))
+        })
+        .collect::<Vec<_>>();
+    matches.sort();
+
+    matches
+        .into_iter()
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
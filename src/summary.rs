@@ -0,0 +1,75 @@
+use serde::{Serialize, Serializer};
+use std::time::Duration;
+
+#[cfg(feature = "refresh")]
+use crate::Error;
+use crate::WorldVersion;
+
+// What a refresh did, from `Index::refresh_into`, to build reports,
+// notifications or metrics from. Worlds are in key order.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RefreshSummary {
+    pub archipelago_version: String,
+    pub installed: Vec<RefreshedWorld>,
+    // Worlds of the index left out of the refresh, like with
+    // `RefreshPlan::retain_worlds`
+    pub skipped: Vec<String>,
+    // Only filled in the summary of an `Error::Refresh`, the destination isn't
+    // replaced when a world fails
+    pub failed: Vec<FailedWorld>,
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RefreshedWorld {
+    pub key: String,
+    pub version: WorldVersion,
+    // The hash of the installed world, patches included
+    pub sha256: Option<String>,
+    // Whether it came from the cache directory instead of being fetched
+    pub cached: bool,
+    #[serde(serialize_with = "serialize_secs")]
+    pub duration: Duration,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FailedWorld {
+    pub key: String,
+    pub version: WorldVersion,
+    // The error and its causes
+    pub error: String,
+}
+
+impl FailedWorld {
+    #[cfg(feature = "refresh")]
+    pub(crate) fn new(key: &str, version: WorldVersion, error: &Error) -> Self {
+        let mut message = error.to_string();
+        let mut source = std::error::Error::source(error);
+        while let Some(cause) = source {
+            message.push_str(": ");
+            message.push_str(&cause.to_string());
+            source = cause.source();
+        }
+
+        Self {
+            key: key.into(),
+            version,
+            error: message,
+        }
+    }
+}
+
+impl RefreshSummary {
+    pub(crate) fn failed_keys(&self) -> String {
+        self.failed
+            .iter()
+            .map(|world| world.key.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+fn serialize_secs<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(duration.as_secs_f64())
+}
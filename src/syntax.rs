@@ -0,0 +1,60 @@
+use rustpython_parser::{parse, Mode};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::{apworld, junk, Result};
+
+// What refreshes do with worlds whose python files don't parse
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SyntaxPolicy {
+    Ignore,
+    #[default]
+    Warn,
+    Error,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct SyntaxError {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+// Parses every python file of a world, returning the syntax errors found
+pub fn check_syntax(path: &Path) -> Result<Vec<SyntaxError>> {
+    let sources =
+        apworld::package_files(path, |name| name.ends_with(".py") && !junk::is_junk(name))?;
+
+    let mut errors = vec![];
+    for (file, content) in sources {
+        let source = String::from_utf8_lossy(&content);
+        let Err(error) = parse(&source, Mode::Module, &file) else {
+            continue;
+        };
+
+        let offset = (u32::from(error.offset) as usize).min(source.len());
+        let before = &source[..offset];
+        let line = before.matches('\n').count() + 1;
+        let column = offset - before.rfind('\n').map_or(0, |i| i + 1) + 1;
+        errors.push(SyntaxError {
+            file,
+            line,
+            column,
+            message: error.error.to_string(),
+        });
+    }
+
+    Ok(errors)
+}
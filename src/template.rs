@@ -0,0 +1,140 @@
+use serde_json::Value;
+use std::fmt::Write;
+
+use crate::options::OptionSchema;
+
+// YAML is a superset of JSON, so JSON scalars make for correctly quoted YAML
+fn yaml_value(value: &Value) -> String {
+    value.to_string()
+}
+
+// Words YAML would read as something else than a string when left unquoted
+const YAML_KEYWORDS: &[&str] = &["true", "false", "yes", "no", "on", "off", "null", "~"];
+
+fn yaml_key(key: &str) -> String {
+    let is_plain = !key.is_empty()
+        && !key.starts_with('-')
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        && !YAML_KEYWORDS.contains(&key.to_lowercase().as_str());
+    if is_plain {
+        return key.to_string();
+    }
+
+    yaml_value(&Value::from(key))
+}
+
+fn choice_name(option: &OptionSchema) -> Option<String> {
+    match option.default.as_ref()? {
+        Value::String(name) => Some(name.clone()),
+        Value::Number(n) => option
+            .choices
+            .iter()
+            .find(|(_, value)| Some(**value) == n.as_i64())
+            .map(|(name, _)| name.clone()),
+        _ => None,
+    }
+}
+
+// The weighted values for an option, the default one getting all the weight
+fn weights(option: &OptionSchema) -> Option<Vec<(String, u32)>> {
+    match option.kind.as_str() {
+        "Toggle" | "DefaultOnToggle" | "DeathLink" => {
+            let enabled = option.default.as_ref().and_then(Value::as_i64) == Some(1)
+                || option.default == Some(Value::Bool(true));
+            Some(vec![
+                ("false".to_string(), if enabled { 0 } else { 50 }),
+                ("true".to_string(), if enabled { 50 } else { 0 }),
+            ])
+        }
+        "Choice" | "TextChoice" if !option.choices.is_empty() => {
+            let default = choice_name(option);
+            Some(
+                option
+                    .choices
+                    .keys()
+                    .map(|name| {
+                        let weight = if Some(name) == default.as_ref() {
+                            50
+                        } else {
+                            0
+                        };
+                        (name.clone(), weight)
+                    })
+                    .collect(),
+            )
+        }
+        "Range" | "NamedRange" => {
+            let default = option
+                .default
+                .as_ref()
+                .and_then(Value::as_i64)
+                .or(option.range_start)?;
+            let mut weights = vec![(default.to_string(), 50)];
+            weights.extend(
+                ["random", "random-low", "random-high"]
+                    .iter()
+                    .map(|name| (name.to_string(), 0)),
+            );
+            weights.extend(
+                option
+                    .special_range_names
+                    .keys()
+                    .filter(|name| !name.starts_with("random"))
+                    .map(|name| (name.clone(), 0)),
+            );
+            Some(weights)
+        }
+        _ => None,
+    }
+}
+
+// Renders a player YAML template for a world with every option set to its
+// default value. Options without a known default are left out.
+pub fn render(game: &str, archipelago_version: &str, options: &[OptionSchema]) -> String {
+    let mut template = String::new();
+    let _ = writeln!(template, "name: Player{{number}}");
+    let _ = writeln!(
+        template,
+        "description: {}",
+        yaml_key(&format!("Default {} template", game))
+    );
+    let _ = writeln!(template, "game: {}", yaml_key(game));
+    let _ = writeln!(template, "requires:");
+    let _ = writeln!(template, "  version: {}", archipelago_version);
+    let _ = writeln!(template);
+    let _ = writeln!(template, "{}:", yaml_key(game));
+
+    for option in options {
+        let weights = weights(option);
+        if weights.is_none() && option.default.is_none() {
+            continue;
+        }
+
+        let _ = writeln!(template, "  {}:", yaml_key(&option.name));
+        let description = option
+            .display_name
+            .iter()
+            .chain(option.description.iter())
+            .flat_map(|text| text.lines())
+            .map(str::trim);
+        for line in description {
+            let _ = writeln!(template, "    #{}", format!(" {}", line).trim_end());
+        }
+
+        match (weights, &option.default) {
+            (Some(weights), _) => {
+                for (value, weight) in weights {
+                    let _ = writeln!(template, "    {}: {}", yaml_key(&value), weight);
+                }
+            }
+            (None, default) => {
+                let default = default.clone().unwrap_or_default();
+                let _ = writeln!(template, "    {}", yaml_value(&default));
+            }
+        }
+    }
+
+    template
+}
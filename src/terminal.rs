@@ -0,0 +1,212 @@
+use std::fmt::Write;
+
+use crate::{
+    diff::{
+        DiffStats, FileChange, FileChangeKind, IndexDiff, VersionChange, WorldChange, WorldDiff,
+    },
+    version::VersionBump,
+};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[31m";
+const GREEN: &str = "\x1b[32m";
+const YELLOW: &str = "\x1b[33m";
+const CYAN: &str = "\x1b[36m";
+
+struct Painter {
+    colored: bool,
+}
+
+impl Painter {
+    fn paint(&self, style: &str, text: &str) -> String {
+        if !self.colored || text.is_empty() {
+            return text.to_string();
+        }
+
+        format!("{}{}{}", style, text, RESET)
+    }
+
+    fn diff_line(&self, line: &str) -> String {
+        let style = if line.starts_with("+++ ") || line.starts_with("--- ") {
+            BOLD
+        } else if line.starts_with("@@") {
+            CYAN
+        } else if line.starts_with('+') {
+            GREEN
+        } else if line.starts_with('-') {
+            RED
+        } else {
+            return line.to_string();
+        };
+
+        self.paint(style, line)
+    }
+}
+
+impl IndexDiff {
+    // Renders the diff as plain text for a terminal, with the content changes
+    // as unified diffs. With `colored`, world names, added and removed lines
+    // and downgrades are highlighted with ANSI escape codes.
+    pub fn to_text(&self, colored: bool) -> String {
+        let renderer = TextRenderer::new(colored);
+        if self.is_empty() {
+            return "No changes\n".to_string();
+        }
+
+        let mut text = String::new();
+        if let Some(version) = &self.archipelago_version {
+            text.push_str(&renderer.archipelago(version));
+        }
+        for (key, world_diff) in &self.worlds {
+            text.push_str(&renderer.world(key, world_diff));
+        }
+
+        text
+    }
+}
+
+// Renders diffs as plain text one piece at a time, for destination diffs
+// streamed through `visit_destination_changes`
+pub struct TextRenderer {
+    painter: Painter,
+}
+
+impl TextRenderer {
+    pub fn new(colored: bool) -> Self {
+        Self {
+            painter: Painter { colored },
+        }
+    }
+
+    pub fn archipelago(&self, version: &VersionChange) -> String {
+        format!(
+            "{}: {} -> {}\n",
+            self.painter.paint(BOLD, "archipelago"),
+            version.old,
+            version.new
+        )
+    }
+
+    // The changes of a world, with the content diffs of its files
+    pub fn world(&self, key: &str, world_diff: &WorldDiff) -> String {
+        let painter = &self.painter;
+        let mut text = String::new();
+        let name = painter.paint(BOLD, key);
+        for change in &world_diff.changes {
+            match change {
+                WorldChange::Added { version, origin } => {
+                    let _ = writeln!(
+                        text,
+                        "{}: {}, version {} from {}",
+                        name,
+                        painter.paint(GREEN, "added"),
+                        version,
+                        origin
+                    );
+                }
+                WorldChange::Removed { version, origin } => {
+                    let _ = writeln!(
+                        text,
+                        "{}: {}, was version {} from {}",
+                        name,
+                        painter.paint(RED, "removed"),
+                        version,
+                        origin
+                    );
+                }
+                WorldChange::VersionChanged { old, new, bump } => {
+                    let bump = match bump {
+                        VersionBump::Downgrade => painter.paint(RED, &bump.to_string()),
+                        VersionBump::Major => painter.paint(YELLOW, &bump.to_string()),
+                        _ => bump.to_string(),
+                    };
+                    let _ = writeln!(text, "{}: version {} -> {} ({})", name, old, new, bump);
+                }
+                WorldChange::OriginChanged { old, new } => {
+                    let _ = writeln!(text, "{}: origin {} -> {}", name, old, new);
+                }
+                WorldChange::PatchesChanged { old, new } => {
+                    let _ = writeln!(
+                        text,
+                        "{}: patches [{}] -> [{}]",
+                        name,
+                        old.join(", "),
+                        new.join(", ")
+                    );
+                }
+                WorldChange::Reuploaded {
+                    version,
+                    old_sha256,
+                    new_sha256,
+                } => {
+                    let _ = writeln!(
+                        text,
+                        "{}: {} ({}, {:.12} -> {:.12})",
+                        name,
+                        painter.paint(YELLOW, "content changed without a version bump"),
+                        version,
+                        old_sha256,
+                        new_sha256
+                    );
+                }
+                WorldChange::Updated { files } => {
+                    text.push_str(&self.stats(key, &world_diff.stats()));
+                    for file in files {
+                        text.push_str(&self.file_line(file));
+                    }
+                    for file in files {
+                        text.push_str(&self.file_diff(file));
+                    }
+                }
+            }
+        }
+        if let Some(changelog) = &world_diff.changelog {
+            let _ = writeln!(text, "{}: changelog", name);
+            for line in changelog.lines() {
+                let _ = writeln!(text, "{}", format!("    {}", line).trim_end());
+            }
+        }
+
+        text
+    }
+
+    pub fn stats(&self, key: &str, stats: &DiffStats) -> String {
+        format!("{}: {}\n", self.painter.paint(BOLD, key), stats)
+    }
+
+    // A file changed in a world, its summary line followed by its diff
+    pub fn file(&self, file: &FileChange) -> String {
+        self.file_line(file) + &self.file_diff(file)
+    }
+
+    fn file_line(&self, file: &FileChange) -> String {
+        let kind = match file.kind {
+            FileChangeKind::Added => self.painter.paint(GREEN, "added"),
+            FileChangeKind::Removed => self.painter.paint(RED, "removed"),
+            FileChangeKind::Modified => "modified".to_string(),
+        };
+        format!(
+            "    {} {} (+{} -{})\n",
+            kind, file.path, file.insertions, file.deletions
+        )
+    }
+
+    fn file_diff(&self, file: &FileChange) -> String {
+        let mut text = String::new();
+        match (&file.diff, &file.binary) {
+            (Some(diff), _) => {
+                for line in diff.lines() {
+                    let _ = writeln!(text, "{}", self.painter.diff_line(line));
+                }
+            }
+            (None, Some(binary)) => {
+                let _ = writeln!(text, "Binary file {} differs: {}", file.path, binary);
+            }
+            // Only stats were asked for
+            (None, None) => {}
+        }
+
+        text
+    }
+}
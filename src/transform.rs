@@ -0,0 +1,270 @@
+use regex::Regex;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::path::{Path, PathBuf};
+
+use crate::{bail, copy_file_or_dir, delete_file_or_dir, sandbox, Error, Result};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum Transform {
+    Add {
+        path: PathBuf,
+        source: PathBuf,
+    },
+    Replace {
+        path: PathBuf,
+        source: PathBuf,
+    },
+    Delete {
+        path: PathBuf,
+    },
+    Substitute {
+        path: PathBuf,
+        #[serde(
+            serialize_with = "serialize_regex",
+            deserialize_with = "deserialize_regex"
+        )]
+        pattern: Regex,
+        replacement: String,
+    },
+}
+
+fn serialize_regex<S: Serializer>(regex: &Regex, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_str(regex.as_str())
+}
+
+fn deserialize_regex<'de, D: Deserializer<'de>>(d: D) -> Result<Regex, D::Error> {
+    let pattern = String::deserialize(d)?;
+    Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+fn target_path(dir: &Path, path: &Path) -> Result<PathBuf> {
+    if !sandbox::is_contained(path) {
+        bail!(
+            "Transform path {} must be relative to the world directory",
+            path.to_string_lossy()
+        );
+    }
+
+    Ok(dir.join(path))
+}
+
+fn copy_source(target: &Path, index_dir: &Path, source: &Path) -> Result<()> {
+    if !sandbox::is_contained(source) {
+        bail!(
+            "Transform source {} must be relative to the index directory",
+            source.to_string_lossy()
+        );
+    }
+    if !index_dir.join(source).exists() {
+        bail!(
+            "Transform source {} doesn't exist",
+            source.to_string_lossy()
+        );
+    }
+
+    copy_file_or_dir(target, index_dir, source)
+}
+
+impl Transform {
+    // The file of the index the transform copies into the world, if any
+    pub fn source(&self) -> Option<&Path> {
+        match self {
+            Transform::Add { source, .. } | Transform::Replace { source, .. } => Some(source),
+            Transform::Delete { .. } | Transform::Substitute { .. } => None,
+        }
+    }
+
+    pub fn apply(&self, dir: &Path, index_dir: &Path) -> Result<()> {
+        match self {
+            Transform::Add { path, source } => {
+                let target = target_path(dir, path)?;
+                if target.exists() {
+                    bail!("Cannot add {}, it already exists", path.to_string_lossy());
+                }
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| Error::io(parent, e))?;
+                }
+                copy_source(&target, index_dir, source)
+            }
+            Transform::Replace { path, source } => {
+                let target = target_path(dir, path)?;
+                if !target.exists() {
+                    bail!(
+                        "Cannot replace {}, it doesn't exist",
+                        path.to_string_lossy()
+                    );
+                }
+                copy_source(&target, index_dir, source)
+            }
+            Transform::Delete { path } => delete_file_or_dir(&target_path(dir, path)?),
+            Transform::Substitute {
+                path,
+                pattern,
+                replacement,
+            } => {
+                let target = target_path(dir, path)?;
+                let content =
+                    std::fs::read_to_string(&target).map_err(|e| Error::io(&target, e))?;
+                if !pattern.is_match(&content) {
+                    bail!(
+                        "Pattern {} doesn't match anything in {}",
+                        pattern,
+                        path.to_string_lossy()
+                    );
+                }
+                let content = pattern.replace_all(&content, replacement.as_str());
+                std::fs::write(&target, content.as_bytes()).map_err(|e| Error::io(&target, e))?;
+
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(feature = "refresh")]
+pub fn apply_all(dir: &Path, index_dir: &Path, transforms: &[Transform]) -> Result<()> {
+    for transform in transforms {
+        transform.apply(dir, index_dir)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A world directory with an `__init__.py` and an index directory with a
+    // `source.txt`, side by side
+    fn dirs() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let world = tmp.path().join("world");
+        let index = tmp.path().join("index");
+        std::fs::create_dir_all(&world).unwrap();
+        std::fs::create_dir_all(&index).unwrap();
+        std::fs::write(world.join("__init__.py"), "game = \"Old\"\n").unwrap();
+        std::fs::write(index.join("source.txt"), "from the index\n").unwrap();
+        (tmp, world, index)
+    }
+
+    fn add(path: &str) -> Transform {
+        Transform::Add {
+            path: path.into(),
+            source: "source.txt".into(),
+        }
+    }
+
+    #[test]
+    fn adds_files_from_the_index() {
+        let (_tmp, world, index) = dirs();
+        add("data/extra.txt").apply(&world, &index).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(world.join("data/extra.txt")).unwrap(),
+            "from the index\n"
+        );
+        assert!(add("data/extra.txt").apply(&world, &index).is_err());
+    }
+
+    #[test]
+    fn needs_an_existing_source() {
+        let (_tmp, world, index) = dirs();
+        let transform = Transform::Add {
+            path: "extra.txt".into(),
+            source: "missing.txt".into(),
+        };
+        assert!(transform.apply(&world, &index).is_err());
+        assert!(!world.join("extra.txt").exists());
+    }
+
+    #[test]
+    fn only_replaces_existing_files() {
+        let (_tmp, world, index) = dirs();
+        let replace = |path: &str| Transform::Replace {
+            path: path.into(),
+            source: "source.txt".into(),
+        };
+        assert!(replace("missing.py").apply(&world, &index).is_err());
+        replace("__init__.py").apply(&world, &index).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(world.join("__init__.py")).unwrap(),
+            "from the index\n"
+        );
+    }
+
+    #[test]
+    fn deletes_files() {
+        let (_tmp, world, index) = dirs();
+        let transform = Transform::Delete {
+            path: "__init__.py".into(),
+        };
+        transform.apply(&world, &index).unwrap();
+        assert!(!world.join("__init__.py").exists());
+    }
+
+    #[test]
+    fn substitutes_patterns() {
+        let (_tmp, world, index) = dirs();
+        let substitute = |pattern: &str| Transform::Substitute {
+            path: "__init__.py".into(),
+            pattern: Regex::new(pattern).unwrap(),
+            replacement: "game = \"New $1\"".into(),
+        };
+        substitute(r#"game = "(\w+)""#)
+            .apply(&world, &index)
+            .unwrap();
+        assert_eq!(
+            std::fs::read_to_string(world.join("__init__.py")).unwrap(),
+            "game = \"New Old\"\n"
+        );
+        assert!(substitute("missing").apply(&world, &index).is_err());
+    }
+
+    #[test]
+    fn stays_inside_the_world() {
+        let (tmp, world, index) = dirs();
+        for path in [
+            "../escape.txt",
+            "/tmp/escape.txt",
+            "data/../../escape.txt",
+            "",
+        ] {
+            assert!(add(path).apply(&world, &index).is_err(), "{}", path);
+        }
+        assert!(!tmp.path().join("escape.txt").exists());
+
+        let transform = Transform::Delete {
+            path: "../index/source.txt".into(),
+        };
+        assert!(transform.apply(&world, &index).is_err());
+        assert!(index.join("source.txt").exists());
+
+        std::fs::write(tmp.path().join("secret.txt"), "secret\n").unwrap();
+        for source in ["../secret.txt", "/tmp/secret.txt", "data/../../secret.txt"] {
+            let transform = Transform::Add {
+                path: "secret.txt".into(),
+                source: source.into(),
+            };
+            assert!(transform.apply(&world, &index).is_err(), "{}", source);
+        }
+        assert!(!world.join("secret.txt").exists());
+    }
+
+    #[test]
+    fn reads_transforms_from_the_index() {
+        let transform: Transform = toml::from_str(
+            r#"
+            op = "substitute"
+            path = "__init__.py"
+            pattern = "Old"
+            replacement = "New"
+            "#,
+        )
+        .unwrap();
+        let Transform::Substitute { pattern, .. } = &transform else {
+            panic!("{:?}", transform);
+        };
+        assert_eq!(pattern.as_str(), "Old");
+        assert_eq!(transform.source(), None);
+    }
+}
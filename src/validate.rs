@@ -0,0 +1,101 @@
+use regex::Regex;
+use std::{collections::BTreeSet, fmt, path::Path};
+
+use crate::{apworld, layout, LayoutProblem, Result};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StructureIssue {
+    AbsolutePath(String),
+    EscapingPath(String),
+    NoTopLevelDirectory,
+    MultipleTopLevelEntries(Vec<String>),
+    TopLevelMismatch { expected: String, found: String },
+    BadLayout(LayoutProblem),
+    MissingInit,
+    NoWorldSubclass,
+}
+
+impl fmt::Display for StructureIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StructureIssue::AbsolutePath(path) => write!(f, "entry {} has an absolute path", path),
+            StructureIssue::EscapingPath(path) => {
+                write!(f, "entry {} points outside of the archive", path)
+            }
+            StructureIssue::NoTopLevelDirectory => write!(f, "no top-level directory"),
+            StructureIssue::MultipleTopLevelEntries(entries) => {
+                write!(f, "multiple top-level entries: {}", entries.join(", "))
+            }
+            StructureIssue::TopLevelMismatch { expected, found } => write!(
+                f,
+                "top-level directory is {} but should be {}",
+                found, expected
+            ),
+            StructureIssue::BadLayout(problem) => write!(f, "{}", problem),
+            StructureIssue::MissingInit => write!(f, "no __init__.py in the world"),
+            StructureIssue::NoWorldSubclass => write!(f, "no World subclass found"),
+        }
+    }
+}
+
+fn check_entries(apworld: &Path, expected_name: &str) -> Result<Vec<StructureIssue>> {
+    let mut issues = vec![];
+    let mut top_level = BTreeSet::new();
+
+    for name in apworld::entry_names(apworld)? {
+        if name.starts_with('/') || name.starts_with('\\') || name.contains(":\\") {
+            issues.push(StructureIssue::AbsolutePath(name));
+            continue;
+        }
+        if name.split(['/', '\\']).any(|component| component == "..") {
+            issues.push(StructureIssue::EscapingPath(name));
+            continue;
+        }
+
+        match name.split_once('/') {
+            Some((dir, _)) => top_level.insert(dir.to_string()),
+            None => top_level.insert(name),
+        };
+    }
+
+    let top_level = top_level.into_iter().collect::<Vec<_>>();
+    match top_level.as_slice() {
+        [] => issues.push(StructureIssue::NoTopLevelDirectory),
+        [found] if found != expected_name => issues.push(StructureIssue::TopLevelMismatch {
+            expected: expected_name.to_string(),
+            found: found.clone(),
+        }),
+        [_] => {}
+        _ => issues.push(StructureIssue::MultipleTopLevelEntries(top_level)),
+    }
+
+    Ok(issues)
+}
+
+// Checks that `path`, an `.apworld` file or a world package directory, looks
+// like something Archipelago can load as the `expected_name` world.
+pub fn validate_structure(path: &Path, expected_name: &str) -> Result<Vec<StructureIssue>> {
+    let mut issues = vec![];
+    if path.is_file() {
+        if let Some(problem) = layout::diagnose(path)? {
+            // Everything else would just be noise caused by the bad layout
+            return Ok(vec![StructureIssue::BadLayout(problem)]);
+        }
+        issues.extend(check_entries(path, expected_name)?);
+    }
+
+    let sources = apworld::package_files(path, |name| name.ends_with(".py"))?;
+    if !sources.contains_key("__init__.py") {
+        issues.push(StructureIssue::MissingInit);
+    }
+
+    let world_class = Regex::new(r"(?m)^class\s+\w+\s*\([^)]*\bWorld\b[^)]*\)\s*:").unwrap();
+    let has_world = sources
+        .values()
+        .any(|source| world_class.is_match(&String::from_utf8_lossy(source)));
+    if !has_world {
+        issues.push(StructureIssue::NoWorldSubclass);
+    }
+
+    Ok(issues)
+}
@@ -0,0 +1,124 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{apworld, junk, Result};
+
+// A library worlds tend to vendor, identified by a marker file. `versions`
+// maps known releases to their tree hash so modified copies can be spotted.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct VendoredLibraryDefinition {
+    pub marker: String,
+    #[serde(default)]
+    pub versions: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "status", content = "version", rename_all = "snake_case")]
+pub enum VendoredStatus {
+    Known(String),
+    Modified,
+    Unregistered,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VendoredLibrary {
+    pub library: String,
+    pub path: String,
+    pub declared_version: Option<String>,
+    pub hash: String,
+    pub status: VendoredStatus,
+}
+
+pub fn builtin_definitions() -> BTreeMap<String, VendoredLibraryDefinition> {
+    [
+        ("requests", "requests/__init__.py"),
+        ("urllib3", "urllib3/__init__.py"),
+        ("certifi", "certifi/__init__.py"),
+        ("charset_normalizer", "charset_normalizer/__init__.py"),
+        ("idna", "idna/__init__.py"),
+        ("yaml", "yaml/__init__.py"),
+        ("websockets", "websockets/__init__.py"),
+        ("typing_extensions", "typing_extensions.py"),
+    ]
+    .into_iter()
+    .map(|(name, marker)| {
+        (
+            name.to_string(),
+            VendoredLibraryDefinition {
+                marker: marker.to_string(),
+                versions: BTreeMap::new(),
+            },
+        )
+    })
+    .collect()
+}
+
+// Hashes a set of files so that the result only depends on their relative
+// paths and contents
+fn tree_hash<'a>(files: impl Iterator<Item = (&'a str, &'a [u8])>) -> String {
+    let mut hasher = Sha256::new();
+    for (name, content) in files {
+        hasher.update(name.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(Sha256::digest(content));
+        hasher.update(b"\n");
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+fn declared_version(files: &BTreeMap<String, Vec<u8>>, root: &str) -> Option<String> {
+    let re = Regex::new(r#"(?m)^__version__\s*=\s*["']([^"']+)["']"#).unwrap();
+    ["__version__.py", "__init__.py", "version.py", ""]
+        .iter()
+        .filter_map(|file| files.get(&format!("{}{}", root, file)))
+        .find_map(|content| {
+            re.captures(&String::from_utf8_lossy(content))
+                .map(|captures| captures[1].to_string())
+        })
+}
+
+pub fn find_vendored(
+    path: &Path,
+    definitions: &BTreeMap<String, VendoredLibraryDefinition>,
+) -> Result<Vec<VendoredLibrary>> {
+    let files = apworld::package_files(path, |name| !junk::is_junk(name))?;
+
+    let mut found = vec![];
+    for (library, definition) in definitions {
+        let marker_suffix = format!("/{}", definition.marker);
+        for name in files.keys() {
+            if *name != definition.marker && !name.ends_with(&marker_suffix) {
+                continue;
+            }
+
+            // Single file libraries are their own root
+            let root = match definition.marker.rsplit_once('/') {
+                Some((_, file)) => name.trim_end_matches(file).to_string(),
+                None => name.clone(),
+            };
+            let library_files = files
+                .iter()
+                .filter(|(file, _)| file.starts_with(&root))
+                .map(|(file, content)| (&file[root.len()..], content.as_slice()));
+            let hash = tree_hash(library_files);
+
+            let status = match definition.versions.iter().find(|(_, h)| **h == hash) {
+                Some((version, _)) => VendoredStatus::Known(version.clone()),
+                None if definition.versions.is_empty() => VendoredStatus::Unregistered,
+                None => VendoredStatus::Modified,
+            };
+
+            found.push(VendoredLibrary {
+                library: library.clone(),
+                path: root.clone(),
+                declared_version: declared_version(&files, &root),
+                hash,
+                status,
+            });
+        }
+    }
+
+    Ok(found)
+}
@@ -0,0 +1,112 @@
+use crate::{Error, Result};
+use serde::Serialize;
+use std::{collections::BTreeSet, path::Path};
+
+use crate::manifest::{self, Manifest};
+
+// Files a refresh writes next to the worlds
+const BOOKKEEPING_FILES: &[&str] = &[".manifest.toml", ".last_refresh"];
+
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DeploymentIssue {
+    Missing {
+        world: String,
+        path: String,
+    },
+    Modified {
+        world: String,
+        path: String,
+        expected: String,
+        actual: String,
+    },
+    // Neither a world, one of its dependencies nor a global file
+    Extraneous {
+        path: String,
+    },
+}
+
+impl std::fmt::Display for DeploymentIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DeploymentIssue::Missing { world, path } => {
+                write!(f, "{}: {} is missing", world, path)
+            }
+            DeploymentIssue::Modified {
+                world,
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{}: {} was modified, expected sha256 {:.12} but found {:.12}",
+                world, path, expected, actual
+            ),
+            DeploymentIssue::Extraneous { path } => write!(f, "{} isn't part of the index", path),
+        }
+    }
+}
+
+// Checks that a destination holds exactly what `expected` describes: every
+// world is present with the recorded hash and nothing else was added. Worlds
+// recorded without a hash are only checked for presence.
+pub fn verify_destination(destination: &Path, expected: &Manifest) -> Result<Vec<DeploymentIssue>> {
+    let mut issues = vec![];
+    let mut known = BOOKKEEPING_FILES
+        .iter()
+        .map(|name| name.to_string())
+        .chain(expected.global_files.iter().cloned())
+        .collect::<BTreeSet<_>>();
+
+    for (name, world) in &expected.worlds {
+        let path = world.origin.deployed_path(name, destination)?;
+        let relative = path
+            .strip_prefix(destination)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .into_owned();
+        known.insert(relative.clone());
+        known.extend(world.dependencies.iter().cloned());
+
+        if !path.exists() {
+            issues.push(DeploymentIssue::Missing {
+                world: name.clone(),
+                path: relative,
+            });
+            continue;
+        }
+
+        let Some(expected) = &world.sha256 else {
+            continue;
+        };
+        let actual = manifest::content_sha256(&path)?;
+        if *expected != actual {
+            issues.push(DeploymentIssue::Modified {
+                world: name.clone(),
+                path: relative,
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    let mut extraneous = std::fs::read_dir(destination)
+        .map_err(|e| Error::io(destination, e))?
+        .map(|entry| {
+            Ok(entry
+                .map_err(|e| Error::io(destination, e))?
+                .file_name()
+                .to_string_lossy()
+                .into_owned())
+        })
+        .collect::<Result<Vec<_>>>()?;
+    extraneous.retain(|name| !known.contains(name));
+    extraneous.sort();
+    issues.extend(
+        extraneous
+            .into_iter()
+            .map(|path| DeploymentIssue::Extraneous { path }),
+    );
+
+    Ok(issues)
+}
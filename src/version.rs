@@ -0,0 +1,403 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::cmp::Ordering;
+
+// Compares pre-release tags and versions that aren't semver component by
+// component, numerically when both components are numbers and
+// lexicographically otherwise
+fn cmp_components(a: &str, b: &str) -> Ordering {
+    let components = |version: &str| -> Vec<String> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '_', '+'])
+            .map(str::to_string)
+            .collect()
+    };
+
+    let a = components(a);
+    let b = components(b);
+    for (a, b) in a.iter().zip(b.iter()) {
+        let ordering = match (a.parse::<u64>(), b.parse::<u64>()) {
+            (Ok(a), Ok(b)) => a.cmp(&b),
+            _ => a.cmp(b),
+        };
+        if ordering != Ordering::Equal {
+            return ordering;
+        }
+    }
+
+    a.len().cmp(&b.len())
+}
+
+// How a version change looks when versions are read as loose semver
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum VersionBump {
+    Major,
+    Minor,
+    Patch,
+    // Moving between pre-releases of the same version
+    Prerelease,
+    // From a pre-release to the matching stable release
+    Stabilized,
+    Downgrade,
+    // Written differently but the same version, like "v1.0" and "1.0.0"
+    Equivalent,
+    // At least one of the versions can't be read as semver
+    Unknown,
+}
+
+impl std::fmt::Display for VersionBump {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let description = match self {
+            VersionBump::Major => "major bump",
+            VersionBump::Minor => "minor bump",
+            VersionBump::Patch => "patch bump",
+            VersionBump::Prerelease => "new pre-release",
+            VersionBump::Stabilized => "pre-release to stable",
+            VersionBump::Downgrade => "DOWNGRADE",
+            VersionBump::Equivalent => "same version",
+            VersionBump::Unknown => "unknown change",
+        };
+        write!(f, "{}", description)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct LooseVersion {
+    numbers: Vec<u64>,
+    pre: Option<String>,
+}
+
+impl LooseVersion {
+    // Reads "1.2.3", "v1.2", "1.2.3-beta.1" or "1.2.3b1" style versions. Build
+    // metadata after a `+` is ignored.
+    fn parse(version: &str) -> Option<Self> {
+        let version = version.trim().trim_start_matches(['v', 'V']);
+        let version = version.split('+').next()?;
+
+        let end = version
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(version.len());
+        let (numbers, pre) = version.split_at(end);
+        let numbers = numbers
+            .trim_end_matches('.')
+            .split('.')
+            .map(|n| n.parse().ok())
+            .collect::<Option<Vec<u64>>>()?;
+        let pre = pre.trim_start_matches(['-', '.', '_']);
+
+        Some(Self {
+            numbers,
+            pre: (!pre.is_empty()).then(|| pre.to_string()),
+        })
+    }
+
+    fn number(&self, i: usize) -> u64 {
+        self.numbers.get(i).copied().unwrap_or(0)
+    }
+
+    // Missing components count as 0 and a pre-release comes before its
+    // stable release
+    fn cmp(&self, other: &Self) -> Ordering {
+        let len = self.numbers.len().max(other.numbers.len());
+        (0..len)
+            .map(|i| self.number(i).cmp(&other.number(i)))
+            .find(|ordering| ordering.is_ne())
+            .unwrap_or_else(|| match (&self.pre, &other.pre) {
+                (None, None) => Ordering::Equal,
+                (None, Some(_)) => Ordering::Greater,
+                (Some(_), None) => Ordering::Less,
+                (Some(a), Some(b)) => cmp_components(a, b),
+            })
+    }
+}
+
+// The version of a world, read as loose semver when it looks like it and kept
+// as an opaque string otherwise. Versions compare semantically, "1.10" after
+// "1.9", "1.0-beta" before "1.0" and "v1.0" equal to "1.0.0". `as_str` tells
+// versions written differently apart.
+#[derive(Debug, Clone)]
+pub struct WorldVersion {
+    raw: String,
+    parsed: Option<LooseVersion>,
+}
+
+impl WorldVersion {
+    pub fn new(version: impl Into<String>) -> Self {
+        let raw = version.into();
+        let parsed = LooseVersion::parse(&raw);
+        Self { raw, parsed }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    pub fn is_semver(&self) -> bool {
+        self.parsed.is_some()
+    }
+
+    pub fn major(&self) -> Option<u64> {
+        Some(self.parsed.as_ref()?.number(0))
+    }
+
+    pub fn minor(&self) -> Option<u64> {
+        Some(self.parsed.as_ref()?.number(1))
+    }
+
+    pub fn patch(&self) -> Option<u64> {
+        Some(self.parsed.as_ref()?.number(2))
+    }
+
+    pub fn pre(&self) -> Option<&str> {
+        self.parsed.as_ref()?.pre.as_deref()
+    }
+
+    // How going from this version to `new` looks
+    pub fn bump_to(&self, new: &WorldVersion) -> VersionBump {
+        classify(&self.raw, &new.raw)
+    }
+
+    pub fn is_downgrade_to(&self, new: &WorldVersion) -> bool {
+        self.bump_to(new) == VersionBump::Downgrade
+    }
+
+    // Replaces `{version}` in `template` with the version as written, and
+    // `{major}`, `{minor}` and `{patch}` with its components when it's semver
+    pub fn expand(&self, template: &str) -> String {
+        let mut expanded = template.replace("{version}", &self.raw);
+        let components = [
+            ("{major}", self.major()),
+            ("{minor}", self.minor()),
+            ("{patch}", self.patch()),
+        ];
+        for (placeholder, component) in components {
+            if let Some(component) = component {
+                expanded = expanded.replace(placeholder, &component.to_string());
+            }
+        }
+        expanded
+    }
+}
+
+// Replaces `old` with `new` in `url` where it's a whole token, like the
+// `1.0` of `/v1.0/` or `world-1.0.apworld`, and not the start of `1.0.1`.
+// Nothing's replaced if `old` isn't in `url`, or is also part of a longer
+// version there, as there's no telling which one is the world's version.
+#[cfg(feature = "http")]
+pub(crate) fn replace_version(url: &str, old: &str, new: &str) -> Option<String> {
+    let bytes = url.as_bytes();
+    let starts_token = |start: usize| match start.checked_sub(1).map(|i| bytes[i]) {
+        None => true,
+        // A `v` prefix, as long as it isn't the end of a word
+        Some(b'v' | b'V') => start < 2 || !bytes[start - 2].is_ascii_alphanumeric(),
+        Some(c) => !c.is_ascii_alphanumeric() && c != b'.',
+    };
+    let ends_token = |end: usize| match bytes.get(end) {
+        None => true,
+        Some(c) if c.is_ascii_alphanumeric() => false,
+        Some(b'.' | b'-' | b'_' | b'+') => !bytes.get(end + 1).is_some_and(u8::is_ascii_digit),
+        Some(_) => true,
+    };
+
+    let mut replaced = String::new();
+    let mut last = 0;
+    for (start, _) in url.match_indices(old) {
+        if !starts_token(start) || !ends_token(start + old.len()) {
+            return None;
+        }
+        replaced.push_str(&url[last..start]);
+        replaced.push_str(new);
+        last = start + old.len();
+    }
+    if last == 0 {
+        return None;
+    }
+    replaced.push_str(&url[last..]);
+
+    Some(replaced)
+}
+
+impl std::fmt::Display for WorldVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.raw)
+    }
+}
+
+impl From<&str> for WorldVersion {
+    fn from(version: &str) -> Self {
+        Self::new(version)
+    }
+}
+
+impl From<String> for WorldVersion {
+    fn from(version: String) -> Self {
+        Self::new(version)
+    }
+}
+
+impl PartialEq for WorldVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for WorldVersion {}
+
+impl PartialOrd for WorldVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for WorldVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (&self.parsed, &other.parsed) {
+            (Some(a), Some(b)) => a.cmp(b),
+            _ => cmp_components(&self.raw, &other.raw),
+        }
+    }
+}
+
+impl Serialize for WorldVersion {
+    fn serialize<S: Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(&self.raw)
+    }
+}
+
+impl<'de> Deserialize<'de> for WorldVersion {
+    fn deserialize<D: Deserializer<'de>>(d: D) -> Result<Self, D::Error> {
+        Ok(Self::new(String::deserialize(d)?))
+    }
+}
+
+// Whether `version` reads as a loose semver version, to tell release tags
+// apart from other tags
+#[cfg(all(feature = "git", feature = "http"))]
+pub fn is_version(version: &str) -> bool {
+    LooseVersion::parse(version).is_some()
+}
+
+pub fn classify(old: &str, new: &str) -> VersionBump {
+    let (Some(old), Some(new)) = (LooseVersion::parse(old), LooseVersion::parse(new)) else {
+        return VersionBump::Unknown;
+    };
+
+    let len = old.numbers.len().max(new.numbers.len());
+    let first_difference = (0..len).find(|&i| old.number(i) != new.number(i));
+    if let Some(i) = first_difference {
+        if new.number(i) < old.number(i) {
+            return VersionBump::Downgrade;
+        }
+        return match i {
+            0 => VersionBump::Major,
+            1 => VersionBump::Minor,
+            _ => VersionBump::Patch,
+        };
+    }
+
+    match (&old.pre, &new.pre) {
+        (None, None) => VersionBump::Equivalent,
+        (Some(_), None) => VersionBump::Stabilized,
+        // A pre-release comes before its stable release
+        (None, Some(_)) => VersionBump::Downgrade,
+        (Some(old), Some(new)) => match cmp_components(old, new) {
+            Ordering::Less => VersionBump::Prerelease,
+            Ordering::Equal => VersionBump::Equivalent,
+            Ordering::Greater => VersionBump::Downgrade,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> WorldVersion {
+        WorldVersion::new(version)
+    }
+
+    #[test]
+    fn compares_numbers_numerically() {
+        assert!(version("1.10") > version("1.9"));
+        assert!(version("2.0") > version("1.99.99"));
+        assert!(version("1.0.1") > version("1.0"));
+    }
+
+    #[test]
+    fn puts_prereleases_before_their_release() {
+        assert!(version("1.0-beta") < version("1.0"));
+        assert!(version("1.0b1") < version("1.0"));
+        assert!(version("1.0-beta.2") < version("1.0-beta.10"));
+        assert!(version("1.0-rc1") > version("0.9"));
+    }
+
+    #[test]
+    fn equates_versions_written_differently() {
+        assert_eq!(version("v1.0"), version("1.0.0"));
+        assert_eq!(version("1.0+build.5"), version("1.0"));
+        assert_ne!(version("v1.0").as_str(), version("1.0.0").as_str());
+    }
+
+    #[test]
+    fn compares_opaque_versions_component_by_component() {
+        assert!(!version("alpha-3").is_semver());
+        assert!(version("alpha-10") > version("alpha-3"));
+        assert!(version("beta") > version("alpha-10"));
+    }
+
+    #[test]
+    fn sorts_versions() {
+        let mut versions = ["1.10", "1.0", "v1.2", "1.0-rc1", "1.9.1"].map(version);
+        versions.sort();
+        assert_eq!(
+            versions.map(|version| version.to_string()),
+            ["1.0-rc1", "1.0", "v1.2", "1.9.1", "1.10"]
+        );
+    }
+
+    #[test]
+    fn classifies_bumps() {
+        let bump = |old: &str, new: &str| version(old).bump_to(&version(new));
+        assert_eq!(bump("1.0", "2.0"), VersionBump::Major);
+        assert_eq!(bump("1.0", "1.1"), VersionBump::Minor);
+        assert_eq!(bump("1.0.0", "1.0.1"), VersionBump::Patch);
+        assert_eq!(bump("1.0-rc1", "1.0-rc2"), VersionBump::Prerelease);
+        assert_eq!(bump("1.0-rc1", "1.0"), VersionBump::Stabilized);
+        assert_eq!(bump("1.1", "1.0"), VersionBump::Downgrade);
+        assert_eq!(bump("1.0", "1.0-rc1"), VersionBump::Downgrade);
+        assert_eq!(bump("v1.0", "1.0.0"), VersionBump::Equivalent);
+        assert_eq!(bump("alpha", "beta"), VersionBump::Unknown);
+        assert!(version("1.1").is_downgrade_to(&version("1.0.9")));
+    }
+
+    #[cfg(feature = "http")]
+    #[test]
+    fn replaces_whole_versions_in_urls() {
+        let replace = |url: &str| replace_version(url, "1.0", "1.1");
+        assert_eq!(
+            replace("https://example.com/v1.0/world-1.0.apworld").as_deref(),
+            Some("https://example.com/v1.1/world-1.1.apworld")
+        );
+        assert_eq!(
+            replace("https://example.com/1.0/world_1.0").as_deref(),
+            Some("https://example.com/1.1/world_1.1")
+        );
+        assert_eq!(replace("https://example.com/1.0.1/world-1.0.apworld"), None);
+        assert_eq!(replace("https://example.com/world-21.0.apworld"), None);
+        assert_eq!(replace("https://example.com/world-2.1.0.apworld"), None);
+        assert_eq!(replace("https://example.com/world.apworld"), None);
+    }
+
+    #[test]
+    fn expands_templates() {
+        assert_eq!(
+            version("1.2.3").expand("v{version}/{major}.{minor}.{patch}"),
+            "v1.2.3/1.2.3"
+        );
+        assert_eq!(
+            version("nightly").expand("{version}-{major}"),
+            "nightly-{major}"
+        );
+    }
+}
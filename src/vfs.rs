@@ -0,0 +1,740 @@
+#[cfg(feature = "refresh")]
+use crate::WorkDirs;
+use crate::{Clock, Error, Result};
+use serde::Serialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+// Where refreshes deploy to, set with `Index::set_filesystem`. Worlds are
+// still downloaded, patched and checked in a scratch directory on disk, then
+// staged on the filesystem and moved over the destination, so tests can
+// refresh into memory and dry runs can tell what would change.
+pub trait Vfs: Send + Sync + std::fmt::Debug {
+    fn exists(&self, path: &Path) -> bool;
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+    fn modified(&self, path: &Path) -> Result<SystemTime>;
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()>;
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+    fn remove_file(&self, path: &Path) -> Result<()>;
+    fn remove_dir_all(&self, path: &Path) -> Result<()>;
+
+    // Copies `from`, a file of the real filesystem, to `to`
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.write(to, &std::fs::read(from).map_err(|e| Error::io(from, e))?)
+    }
+
+    // Moves `from` to `to`, both on this filesystem. Filesystems only
+    // implementing the file operations can only move files.
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.write(to, &self.read(from)?)?;
+        self.remove_file(from)
+    }
+
+    // Where files written from now on get their modification time from, set
+    // by `Index::set_clock` and `Index::set_filesystem`. Filesystems that
+    // can't choose it keep the time they write files at.
+    fn set_clock(&self, _clock: Arc<dyn Clock>) {}
+
+    // An empty directory on this filesystem to stage the content of
+    // `destination` in, next to it unless `dirs` says otherwise
+    #[cfg(feature = "refresh")]
+    fn staging_dir(&self, destination: &Path, _dirs: &WorkDirs) -> Result<PathBuf> {
+        let name = destination.file_name().unwrap_or_default();
+        let path = destination.with_file_name(format!(".{}.staging", name.to_string_lossy()));
+        if self.exists(&path) {
+            self.remove_dir_all(&path)?;
+        }
+        self.create_dir_all(&path)?;
+        Ok(path)
+    }
+
+    // Copies `built`, a directory of the real filesystem, into `staging`
+    #[cfg(feature = "refresh")]
+    fn stage(&self, built: &Path, staging: &Path) -> Result<()> {
+        copy_staged(self, built, staging)
+    }
+
+    // Replaces `destination` with `staged`, made by `staging_dir`
+    #[cfg(feature = "refresh")]
+    fn install(&self, staged: &Path, destination: &Path) -> Result<()> {
+        if self.exists(destination) {
+            self.remove_dir_all(destination)?;
+        }
+        self.rename(staged, destination)
+    }
+}
+
+// A directory made by `Vfs::staging_dir`, removed when dropped unless it got
+// installed, so failed and cancelled refreshes don't leave it behind
+#[cfg(feature = "refresh")]
+pub(crate) struct StagingDir {
+    fs: Arc<dyn Vfs>,
+    path: PathBuf,
+}
+
+#[cfg(feature = "refresh")]
+impl StagingDir {
+    pub(crate) fn new(fs: Arc<dyn Vfs>, destination: &Path, dirs: &WorkDirs) -> Result<Self> {
+        let path = fs.staging_dir(destination, dirs)?;
+        Ok(Self { fs, path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(feature = "refresh")]
+impl Drop for StagingDir {
+    fn drop(&mut self) {
+        if self.fs.exists(&self.path) {
+            if let Err(e) = self.fs.remove_dir_all(&self.path) {
+                tracing::warn!("Couldn't remove {}: {}", self.path.to_string_lossy(), e);
+            }
+        }
+    }
+}
+
+#[cfg(feature = "refresh")]
+fn copy_staged(fs: &(impl Vfs + ?Sized), staged: &Path, destination: &Path) -> Result<()> {
+    fs.create_dir_all(destination)?;
+    for (relative, file) in staged_entries(staged)? {
+        let path = destination.join(relative);
+        match file {
+            Some(file) => fs.copy(&file, &path)?,
+            None => fs.create_dir_all(&path)?,
+        }
+    }
+
+    Ok(())
+}
+
+// Every entry below `dir`, a directory of the real filesystem, parents first,
+// with the path of the files to read them from. Directories have no file.
+#[cfg(feature = "refresh")]
+fn staged_entries(dir: &Path) -> Result<Vec<(PathBuf, Option<PathBuf>)>> {
+    let mut entries = vec![];
+    let mut pending = vec![PathBuf::new()];
+    while let Some(relative) = pending.pop() {
+        for entry in
+            std::fs::read_dir(dir.join(&relative)).map_err(|e| Error::io(dir.join(&relative), e))?
+        {
+            let entry = entry.map_err(|e| Error::io(dir.join(&relative), e))?;
+            let path = relative.join(entry.file_name());
+            if entry
+                .file_type()
+                .map_err(|e| Error::io(entry.path(), e))?
+                .is_dir()
+            {
+                entries.push((path.clone(), None));
+                pending.push(path);
+            } else {
+                entries.push((path, Some(entry.path())));
+            }
+        }
+    }
+    entries.sort();
+
+    Ok(entries)
+}
+
+// The filesystem of the machine, used unless another one is set
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RealFs;
+
+impl Vfs for RealFs {
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        std::fs::read(path).map_err(|e| Error::io(path, e))
+    }
+
+    fn modified(&self, path: &Path) -> Result<SystemTime> {
+        std::fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| Error::io(path, e))
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        std::fs::write(path, content).map_err(|e| Error::io(path, e))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::create_dir_all(path).map_err(|e| Error::io(path, e))
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        std::fs::remove_file(path).map_err(|e| Error::io(path, e))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        std::fs::remove_dir_all(path).map_err(|e| Error::io(path, e))
+    }
+
+    // Keeps the modification time of `from`, like the one refreshes stamp
+    // `.last_refresh` with
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::copy(from, to).map_err(|e| Error::io(to, e))?;
+        let modified = std::fs::metadata(from)
+            .and_then(|metadata| metadata.modified())
+            .map_err(|e| Error::io(from, e))?;
+        std::fs::File::options()
+            .write(true)
+            .open(to)
+            .and_then(|file| file.set_modified(modified))
+            .map_err(|e| Error::io(to, e))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        std::fs::rename(from, to).map_err(|e| Error::io(to, e))
+    }
+
+    // Next to the destination, so it can be renamed over it, or in the
+    // staging directory of `dirs`
+    #[cfg(feature = "refresh")]
+    fn staging_dir(&self, destination: &Path, dirs: &WorkDirs) -> Result<PathBuf> {
+        let staging = match &dirs.staging {
+            Some(dir) => crate::staging_dir_in(dir)?,
+            None => crate::staging_dir(destination)?,
+        };
+        Ok(staging.keep())
+    }
+
+    // The previous deployment is moved aside until the new one is in place,
+    // and moved back if it can't be
+    #[cfg(feature = "refresh")]
+    fn install(&self, staged: &Path, destination: &Path) -> Result<()> {
+        let previous = match destination.exists() {
+            true => {
+                let dir = crate::scratch_dir_beside(destination)?;
+                let path = dir.path().join("previous");
+                std::fs::rename(destination, &path).map_err(|e| Error::io(destination, e))?;
+                Some((dir, path))
+            }
+            false => None,
+        };
+
+        let installed = match std::fs::rename(staged, destination) {
+            // Staged in a staging directory set on another filesystem
+            Err(e) if e.kind() == std::io::ErrorKind::CrossesDevices => {
+                copy_staged(self, staged, destination)
+            }
+            result => result.map_err(|e| Error::io(destination, e)),
+        };
+        if installed.is_err() {
+            // What a failed copy left behind
+            if destination.exists() {
+                self.remove_dir_all(destination)?;
+            }
+            if let Some((dir, path)) = previous {
+                if let Err(e) = std::fs::rename(&path, destination) {
+                    // Kept for it to be moved back by hand
+                    let dir = dir.keep();
+                    tracing::error!(
+                        "Couldn't move the previous content of {} back, it's in {}",
+                        destination.to_string_lossy(),
+                        dir.join("previous").to_string_lossy()
+                    );
+                    return Err(Error::io(destination, e));
+                }
+            }
+        }
+
+        installed
+    }
+}
+
+#[derive(Debug, Clone)]
+enum MemoryEntry {
+    File {
+        content: Vec<u8>,
+        modified: SystemTime,
+    },
+    Dir,
+}
+
+// A filesystem living in memory, for tests. Parents are created implicitly.
+// Files are stamped with the clock of the index it's set on.
+#[derive(Debug, Default)]
+pub struct MemoryFs {
+    entries: Mutex<BTreeMap<PathBuf, MemoryEntry>>,
+    clock: Mutex<Option<Arc<dyn Clock>>>,
+}
+
+impl MemoryFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Every file, with its content
+    pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        self.entries
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(path, entry)| match entry {
+                MemoryEntry::File { content, .. } => Some((path.clone(), content.clone())),
+                MemoryEntry::Dir => None,
+            })
+            .collect()
+    }
+
+    fn not_found(path: &Path) -> Error {
+        Error::io(path, std::io::ErrorKind::NotFound.into())
+    }
+}
+
+impl Vfs for MemoryFs {
+    fn exists(&self, path: &Path) -> bool {
+        self.entries.lock().unwrap().contains_key(path)
+    }
+
+    fn set_clock(&self, clock: Arc<dyn Clock>) {
+        *self.clock.lock().unwrap() = Some(clock);
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        match self.entries.lock().unwrap().get(path) {
+            Some(MemoryEntry::File { content, .. }) => Ok(content.clone()),
+            _ => Err(Self::not_found(path)),
+        }
+    }
+
+    fn modified(&self, path: &Path) -> Result<SystemTime> {
+        match self.entries.lock().unwrap().get(path) {
+            Some(MemoryEntry::File { modified, .. }) => Ok(*modified),
+            _ => Err(Self::not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let file = MemoryEntry::File {
+            content: content.to_vec(),
+            modified: match &*self.clock.lock().unwrap() {
+                Some(clock) => clock.now(),
+                None => SystemTime::now(),
+            },
+        };
+        self.entries.lock().unwrap().insert(path.into(), file);
+        Ok(())
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        for ancestor in path.ancestors() {
+            if ancestor.as_os_str().is_empty() {
+                continue;
+            }
+            if let Some(MemoryEntry::File { .. }) = entries.get(ancestor) {
+                return Err(Error::io(
+                    ancestor,
+                    std::io::ErrorKind::AlreadyExists.into(),
+                ));
+            }
+            entries.insert(ancestor.into(), MemoryEntry::Dir);
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(path) {
+            Some(MemoryEntry::File { .. }) => {
+                entries.remove(path);
+                Ok(())
+            }
+            _ => Err(Self::not_found(path)),
+        }
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(path) {
+            return Err(Self::not_found(path));
+        }
+        entries.retain(|entry, _| !entry.starts_with(path));
+        Ok(())
+    }
+
+    // Moves directories along with everything below them
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        if let Some(parent) = to.parent() {
+            self.create_dir_all(parent)?;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if !entries.contains_key(from) {
+            return Err(Self::not_found(from));
+        }
+        entries.retain(|entry, _| !entry.starts_with(to));
+        let moved = entries
+            .keys()
+            .filter(|entry| entry.starts_with(from))
+            .cloned()
+            .collect::<Vec<_>>();
+        for entry in moved {
+            if let Some(value) = entries.remove(&entry) {
+                let relative = entry.strip_prefix(from)?;
+                entries.insert(to.join(relative), value);
+            }
+        }
+        Ok(())
+    }
+}
+
+// What a `DryRunFs` was asked to do, serialized with an `action` tag for
+// tools showing it for approval
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum FsAction {
+    Write {
+        path: PathBuf,
+        size: u64,
+    },
+    // `from` is a file of the real filesystem, like a staged world
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+        size: u64,
+    },
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    CreateDir {
+        path: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+}
+
+impl std::fmt::Display for FsAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FsAction::Write { path, size } => {
+                write!(f, "write {} ({} bytes)", path.to_string_lossy(), size)
+            }
+            FsAction::Copy { to, size, .. } => {
+                write!(f, "copy {} ({} bytes)", to.to_string_lossy(), size)
+            }
+            FsAction::Rename { from, to } => write!(
+                f,
+                "rename {} to {}",
+                from.to_string_lossy(),
+                to.to_string_lossy()
+            ),
+            FsAction::CreateDir { path } => write!(f, "create {}", path.to_string_lossy()),
+            FsAction::RemoveFile { path } => write!(f, "remove {}", path.to_string_lossy()),
+            FsAction::RemoveDir { path } => {
+                write!(f, "remove {} and its content", path.to_string_lossy())
+            }
+        }
+    }
+}
+
+// Reads from the real filesystem and records mutations instead of doing them.
+// Set it with `Index::set_filesystem` to get what a refresh or a sync would
+// do to the destination, file by file.
+#[derive(Debug, Default)]
+pub struct DryRunFs {
+    actions: Mutex<Vec<FsAction>>,
+}
+
+impl DryRunFs {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // What would have been done, in order
+    pub fn actions(&self) -> Vec<FsAction> {
+        self.actions.lock().unwrap().clone()
+    }
+
+    // Forgets the actions recorded so far
+    pub fn clear(&self) {
+        self.actions.lock().unwrap().clear();
+    }
+
+    fn record(&self, action: FsAction) -> Result<()> {
+        self.actions.lock().unwrap().push(action);
+        Ok(())
+    }
+}
+
+impl Vfs for DryRunFs {
+    fn exists(&self, path: &Path) -> bool {
+        RealFs.exists(path)
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        RealFs.read(path)
+    }
+
+    fn modified(&self, path: &Path) -> Result<SystemTime> {
+        RealFs.modified(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> Result<()> {
+        self.record(FsAction::Write {
+            path: path.into(),
+            size: content.len() as u64,
+        })
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        self.record(FsAction::CreateDir { path: path.into() })
+    }
+
+    fn remove_file(&self, path: &Path) -> Result<()> {
+        self.record(FsAction::RemoveFile { path: path.into() })
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> Result<()> {
+        self.record(FsAction::RemoveDir { path: path.into() })
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let size = std::fs::metadata(from)
+            .map_err(|e| Error::io(from, e))?
+            .len();
+        self.record(FsAction::Copy {
+            from: from.into(),
+            to: to.into(),
+            size,
+        })
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.record(FsAction::Rename {
+            from: from.into(),
+            to: to.into(),
+        })
+    }
+}
+
+#[cfg(all(test, feature = "refresh"))]
+mod tests {
+    use super::*;
+    #[cfg(feature = "git")]
+    use crate::Index;
+    use crate::ManualClock;
+    use std::time::Duration;
+
+    // A deployment with `old.txt` and a staged directory with `new.txt`,
+    // side by side
+    fn deployment() -> (tempfile::TempDir, PathBuf, PathBuf) {
+        let tmp = tempfile::tempdir().unwrap();
+        let (destination, staged) = (tmp.path().join("worlds"), tmp.path().join("staged"));
+        std::fs::create_dir_all(&destination).unwrap();
+        std::fs::create_dir_all(&staged).unwrap();
+        std::fs::write(destination.join("old.txt"), "old").unwrap();
+        std::fs::write(staged.join("new.txt"), "new").unwrap();
+        (tmp, destination, staged)
+    }
+
+    #[test]
+    fn replaces_the_previous_deployment() {
+        let (tmp, destination, staged) = deployment();
+        RealFs.install(&staged, &destination).unwrap();
+        assert!(destination.join("new.txt").exists());
+        assert!(!destination.join("old.txt").exists());
+        let mut left = std::fs::read_dir(tmp.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect::<Vec<_>>();
+        left.sort();
+        assert_eq!(left, ["worlds"]);
+    }
+
+    #[test]
+    fn keeps_the_previous_deployment_when_installing_fails() {
+        let (tmp, destination, _) = deployment();
+        let missing = tmp.path().join("missing");
+        assert!(RealFs.install(&missing, &destination).is_err());
+        assert_eq!(std::fs::read(destination.join("old.txt")).unwrap(), b"old");
+    }
+
+    #[test]
+    fn removes_staging_directories_left_uninstalled() {
+        let fs: Arc<dyn Vfs> = Arc::new(MemoryFs::new());
+        let staging =
+            StagingDir::new(fs.clone(), Path::new("/worlds"), &WorkDirs::default()).unwrap();
+        let path = staging.path().to_path_buf();
+        assert_eq!(path, Path::new("/.worlds.staging"));
+        fs.write(&path.join("a.txt"), b"a").unwrap();
+        drop(staging);
+        assert!(!fs.exists(&path));
+    }
+
+    #[cfg(feature = "git")]
+    const WORLD: &str = "from worlds.AutoWorld import World\n\n\
+class OkWorld(World):\n    game = \"Ok Game\"\n";
+
+    // An index with a local world, next to the archipelago repository it
+    // checks out
+    #[cfg(feature = "git")]
+    fn index(dir: &Path) -> Index {
+        let archipelago = dir.join("archipelago");
+        std::fs::create_dir_all(archipelago.join("worlds")).unwrap();
+        std::fs::write(archipelago.join("worlds/__init__.py"), "").unwrap();
+        let repo = git2::Repository::init(&archipelago).unwrap();
+        let mut git_index = repo.index().unwrap();
+        git_index.add_path(Path::new("worlds/__init__.py")).unwrap();
+        let tree = repo.find_tree(git_index.write_tree().unwrap()).unwrap();
+        let signature = git2::Signature::now("apwm", "apwm@localhost").unwrap();
+        let commit = repo
+            .commit(Some("HEAD"), &signature, &signature, "init", &tree, &[])
+            .unwrap();
+        let commit = repo.find_object(commit, None).unwrap();
+        repo.tag_lightweight("0.5.0", &commit, false).unwrap();
+
+        std::fs::create_dir_all(dir.join("ok")).unwrap();
+        std::fs::write(dir.join("ok/__init__.py"), WORLD).unwrap();
+        std::fs::write(dir.join("ok/LICENSE"), "MIT\n").unwrap();
+        let index_file = dir.join("index.toml");
+        std::fs::write(
+            &index_file,
+            format!(
+                r#"
+                [common]
+                archipelago_repo = "file://localhost{}"
+                archipelago_version = "0.5.0"
+                homepage = "https://example.com"
+                required_global_files = []
+
+                [worlds.ok]
+                name = "Ok Game"
+                version = "1.0"
+                local = "ok"
+                "#,
+                archipelago.to_string_lossy()
+            ),
+        )
+        .unwrap();
+
+        let mut index = Index::new(&index_file).unwrap();
+        index.set_temp_dir(&dir.join("tmp"));
+        index
+    }
+
+    fn epoch(seconds: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(seconds)
+    }
+
+    #[test]
+    fn stamps_writes_with_its_clock() {
+        let fs = MemoryFs::new();
+        let clock = Arc::new(ManualClock::new(epoch(1000)));
+        fs.set_clock(clock.clone());
+        fs.write(Path::new("/a/b.txt"), b"b").unwrap();
+        clock.advance(Duration::from_secs(60));
+        fs.rename(Path::new("/a"), Path::new("/c")).unwrap();
+
+        assert!(!fs.exists(Path::new("/a/b.txt")));
+        assert_eq!(fs.read(Path::new("/c/b.txt")).unwrap(), b"b");
+        assert_eq!(fs.modified(Path::new("/c/b.txt")).unwrap(), epoch(1000));
+    }
+
+    #[cfg(feature = "git")]
+    #[tokio::test]
+    async fn refreshes_into_memory() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut index = index(tmp.path());
+        let fs = Arc::new(MemoryFs::new());
+        let clock = Arc::new(ManualClock::new(epoch(1_000_000)));
+        index.set_filesystem(fs.clone());
+        index.set_clock(clock.clone());
+
+        let destination = tmp.path().join("worlds");
+        assert!(index.should_refresh(&destination));
+        let summary = index.refresh_into(&destination).await.unwrap();
+        assert_eq!(summary.installed.len(), 1);
+        assert_eq!(summary.installed[0].key, "ok");
+        assert!(!destination.exists());
+
+        // Staged next to the destination and moved over it
+        let files = fs.files();
+        assert!(files.keys().all(|path| path.starts_with(&destination)));
+        let world = destination.join("ok").join("__init__.py");
+        assert_eq!(files[&world], WORLD.as_bytes());
+        assert!(files.contains_key(&destination.join(".manifest.toml")));
+        assert_eq!(fs.modified(&world).unwrap(), epoch(1_000_000));
+
+        assert_eq!(index.refresh_age(&destination), Some(Duration::ZERO));
+        clock.advance(Duration::from_secs(3600));
+        assert_eq!(
+            index.refresh_age(&destination),
+            Some(Duration::from_secs(3600))
+        );
+
+        // Stale once the index changes after the refresh
+        let index_file = tmp.path().join("index.toml");
+        clock.set_modified(&index_file, epoch(1_000_000) - Duration::from_secs(60));
+        assert!(!index.should_refresh(&destination));
+        clock.set_modified(&index_file, epoch(1_000_060));
+        assert!(index.should_refresh(&destination));
+    }
+
+    #[cfg(feature = "git")]
+    #[tokio::test]
+    async fn records_a_dry_run() {
+        let tmp = tempfile::tempdir().unwrap();
+        let mut index = index(tmp.path());
+        let fs = Arc::new(DryRunFs::new());
+        index.set_filesystem(fs.clone());
+        index.set_clock(Arc::new(ManualClock::new(epoch(1_000_000))));
+
+        let destination = tmp.path().join("worlds");
+        index.refresh_into(&destination).await.unwrap();
+        assert!(!destination.exists());
+
+        // The destination gets staged file by file next to it, then renamed
+        let actions = fs.actions();
+        let staging = tmp.path().join(".worlds.staging");
+        let copied = actions
+            .iter()
+            .filter_map(|action| match action {
+                FsAction::Copy { to, size, .. } => {
+                    Some((to.strip_prefix(&staging).unwrap().to_path_buf(), *size))
+                }
+                _ => None,
+            })
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(
+            copied.keys().collect::<Vec<_>>(),
+            [
+                ".last_refresh",
+                ".manifest.toml",
+                "ok/LICENSE",
+                "ok/__init__.py"
+            ]
+            .map(Path::new)
+        );
+        assert_eq!(copied[Path::new("ok/__init__.py")], WORLD.len() as u64);
+        assert!(actions.contains(&FsAction::CreateDir {
+            path: staging.join("ok")
+        }));
+        assert_eq!(
+            actions.last(),
+            Some(&FsAction::Rename {
+                from: staging,
+                to: destination
+            })
+        );
+
+        fs.clear();
+        assert!(fs.actions().is_empty());
+    }
+}